@@ -1,18 +1,10 @@
-﻿
-
-
-
-
-
-
-
-
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut prost_build = prost_build::Config::new();
-    prost_build.btree_map(&["."]);
-    prost_build.out_dir("src/proto");
-    prost_build.compile_protos(&["message/hello_world.proto"], &["message"])?;
+﻿fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // 不依赖系统安装的 protoc
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .btree_map(["."])
+        .out_dir("src/proto")
+        .compile_protos(&["message/hello_world.proto", "message/csc.proto"], &["message"])?;
 
     Ok(())
 }
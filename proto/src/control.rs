@@ -0,0 +1,211 @@
+use std::pin::Pin;
+use std::time::{Instant, SystemTime};
+
+use futures_util::stream::{self, Stream};
+use message::{AlarmMsg, AlarmSeverity, MessageType, MotorCommand, MotorMsg, MoveDirection, Msg, MsgBuilder, TelemetryMsg};
+use subsystem::{CenterHandle, Health, QueueConfig, QueuePolicy, Subscription};
+use tonic::{Request, Response, Status};
+
+use crate::csc::control_server::{Control, ControlServer};
+use crate::csc::{
+    Alarm, AlarmRequest, CommandReply, Direction, EnableRequest, InfoReply, InfoRequest, MoveRequest, Session,
+    SessionsReply, SessionsRequest, Severity, StopRequest, Subsystem, Telemetry, TelemetryRequest,
+};
+
+/// 会话在总线上的订阅都以此开头
+const SESSION_PREFIX: &str = "session/";
+
+const STREAM_QUEUE_SIZE: usize = 1024;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// The [`Control`] service on a running center: commands are published on
+/// its bus as coming from `grpc`, streams follow the bus, and sessions are the
+/// bus subscriptions of connected clients. A client that reads its stream too
+/// slowly loses telemetry and alarms rather than holding up the bus.
+pub struct ControlService {
+    center: CenterHandle,
+    started: Instant,
+}
+
+impl ControlService {
+    pub fn new(center: CenterHandle) -> Self {
+        Self {
+            center,
+            started: Instant::now(),
+        }
+    }
+
+    /// Wraps the service for `tonic::transport::Server::add_service`.
+    pub fn into_server(self) -> ControlServer<Self> {
+        ControlServer::new(self)
+    }
+
+    async fn command(&self, msg_type: MessageType, motor: MotorMsg) -> Result<Response<CommandReply>, Status> {
+        let mut msg = MsgBuilder::new().msg_type(msg_type).data(Box::new(motor)).build().unwrap();
+        msg.info.source = Some("grpc".to_string());
+        let id = msg.get_uid().to_string();
+        self.center
+            .publish(msg)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(CommandReply { id }))
+    }
+}
+
+/// Ends when the client cancels the call, which drops the subscription.
+fn follow<T, F>(subscription: Subscription, convert: F) -> ResponseStream<T>
+where
+    T: Send + 'static,
+    F: FnMut(&Msg) -> Option<T> + Send + 'static,
+{
+    let stream = stream::unfold((subscription, convert), |(subscription, mut convert)| async move {
+        loop {
+            let msg = subscription.recv().await?;
+            if let Some(item) = convert(&msg) {
+                return Some((Ok(item), (subscription, convert)));
+            }
+        }
+    });
+    Box::pin(stream)
+}
+
+fn severity(severity: AlarmSeverity) -> Severity {
+    match severity {
+        AlarmSeverity::Info => Severity::Info,
+        AlarmSeverity::Warning => Severity::Warning,
+        AlarmSeverity::Critical => Severity::Critical,
+    }
+}
+
+fn health(health: Health) -> String {
+    match health {
+        Health::Healthy => "healthy".to_string(),
+        Health::Degraded(reason) => format!("degraded: {}", reason),
+        Health::Unhealthy(reason) => format!("unhealthy: {}", reason),
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    type StreamTelemetryStream = ResponseStream<Telemetry>;
+    type StreamAlarmsStream = ResponseStream<Alarm>;
+
+    async fn r#move(&self, request: Request<MoveRequest>) -> Result<Response<CommandReply>, Status> {
+        let request = request.into_inner();
+        let direction = match request.direction() {
+            Direction::Up => MoveDirection::Up,
+            Direction::Down => MoveDirection::Down,
+        };
+        self.command(MessageType::Move, MotorMsg::new(request.motor, direction)).await
+    }
+
+    async fn stop(&self, request: Request<StopRequest>) -> Result<Response<CommandReply>, Status> {
+        let motor = MotorMsg::new(request.into_inner().motor, MoveDirection::Up).command(MotorCommand::Stop);
+        self.command(MessageType::Stop, motor).await
+    }
+
+    async fn enable(&self, request: Request<EnableRequest>) -> Result<Response<CommandReply>, Status> {
+        let request = request.into_inner();
+        let command = if request.enable { MotorCommand::Enable } else { MotorCommand::Disable };
+        let motor = MotorMsg::new(request.motor, MoveDirection::Up).command(command);
+        self.command(MessageType::Move, motor).await
+    }
+
+    async fn stream_telemetry(
+        &self,
+        request: Request<TelemetryRequest>,
+    ) -> Result<Response<Self::StreamTelemetryStream>, Status> {
+        let device = request.into_inner().device;
+        // 落后的客户端只拿到每个设备每组的最新值
+        let subscription = self.center.bus().subscribe_with(
+            "grpc/telemetry",
+            [MessageType::Telemetry],
+            QueueConfig::new(STREAM_QUEUE_SIZE, QueuePolicy::LatestPerKey),
+        );
+        let stream = follow(subscription, move |msg| {
+            let telemetry = msg.get_data::<TelemetryMsg>()?;
+            if !device.is_empty() && telemetry.device != device {
+                return None;
+            }
+            Some(Telemetry {
+                device: telemetry.device,
+                group: telemetry.group,
+                values: telemetry.values,
+                time: Some(SystemTime::now().into()),
+            })
+        });
+        Ok(Response::new(stream))
+    }
+
+    async fn stream_alarms(&self, request: Request<AlarmRequest>) -> Result<Response<Self::StreamAlarmsStream>, Status> {
+        let min_severity = request.into_inner().min_severity();
+        let subscription = self.center.bus().subscribe_with(
+            "grpc/alarms",
+            [MessageType::Alarm],
+            QueueConfig::new(STREAM_QUEUE_SIZE, QueuePolicy::DropOldest),
+        );
+        let stream = follow(subscription, move |msg| {
+            let alarm = msg.get_data::<AlarmMsg>()?;
+            let severity = severity(alarm.severity);
+            if severity < min_severity {
+                return None;
+            }
+            Some(Alarm {
+                source: alarm.source,
+                severity: severity.into(),
+                text: alarm.text,
+                topic: msg.topic().into_owned(),
+                time: Some(SystemTime::now().into()),
+            })
+        });
+        Ok(Response::new(stream))
+    }
+
+    async fn list_sessions(&self, _request: Request<SessionsRequest>) -> Result<Response<SessionsReply>, Status> {
+        let sessions = self
+            .center
+            .queue_stats()
+            .into_iter()
+            .filter_map(|stats| {
+                let peer = stats.label.strip_prefix(SESSION_PREFIX)?;
+                let (transport, peer) = match peer.split_once('/') {
+                    Some((transport @ ("ws" | "unix"), peer)) => (transport, peer),
+                    _ => ("tcp", peer),
+                };
+                Some(Session {
+                    transport: transport.to_string(),
+                    peer: peer.to_string(),
+                    queued: stats.depth as u64,
+                    dropped: stats.dropped,
+                    id: stats.label,
+                })
+            })
+            .collect();
+        Ok(Response::new(SessionsReply { sessions }))
+    }
+
+    async fn get_info(&self, _request: Request<InfoRequest>) -> Result<Response<InfoReply>, Status> {
+        let mut healths = self
+            .center
+            .health()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let mut subsystems: Vec<Subsystem> = self
+            .center
+            .states()
+            .into_iter()
+            .map(|(id, state)| Subsystem {
+                id: id.as_str().to_string(),
+                state: state.to_string(),
+                health: healths.remove(&id).map(health).unwrap_or_default(),
+            })
+            .collect();
+        subsystems.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Response::new(InfoReply {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            subsystems,
+        }))
+    }
+}
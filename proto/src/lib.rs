@@ -1 +1,13 @@
-﻿
\ No newline at end of file
+﻿//! gRPC API of the CSC server, built from `message/*.proto`.
+
+pub mod hello {
+    include!("proto/hello.rs");
+}
+
+pub mod csc {
+    include!("proto/csc.rs");
+}
+
+mod control;
+
+pub use control::*;
@@ -0,0 +1,98 @@
+use message::{MotorCommand, MotorMsg, MoveDirection};
+
+use crate::config::{DriveConfig, SdoWrite, ValueKind};
+
+pub const CONTROLWORD: u16 = 0x6040;
+pub const STATUSWORD: u16 = 0x6041;
+pub const MODES_OF_OPERATION: u16 = 0x6060;
+pub const POSITION_ACTUAL: u16 = 0x6064;
+pub const VELOCITY_ACTUAL: u16 = 0x606C;
+pub const TARGET_POSITION: u16 = 0x607A;
+pub const TARGET_VELOCITY: u16 = 0x60FF;
+
+pub const PROFILE_POSITION_MODE: i64 = 1;
+pub const PROFILE_VELOCITY_MODE: i64 = 3;
+pub const HOMING_MODE: i64 = 6;
+
+/// Controlword commands of the CiA 402 state machine.
+pub mod control {
+    /// Switch on disabled → ready to switch on, or power stage off from any later state.
+    pub const SHUTDOWN: i64 = 0x0006;
+    pub const SWITCH_ON: i64 = 0x0007;
+    pub const ENABLE_OPERATION: i64 = 0x000F;
+    /// 按急停减速度停下，之后需重新使能
+    pub const QUICK_STOP: i64 = 0x0002;
+    /// Operation enabled with bit 4 set: starts homing in homing mode.
+    pub const START_HOMING: i64 = 0x001F;
+    /// 位置模式下第 4 位为新设定点，第 5 位立即生效
+    pub const NEW_SETPOINT: i64 = 0x003F;
+    /// As [`NEW_SETPOINT`], with bit 6 making the target relative.
+    pub const NEW_RELATIVE_SETPOINT: i64 = 0x007F;
+}
+
+/// Statusword bits.
+pub mod status {
+    /// Bits telling the state machine state apart.
+    pub const STATE_MASK: u16 = 0x006F;
+    pub const OPERATION_ENABLED: u16 = 0x0027;
+    pub const FAULT: u16 = 0x0008;
+    pub const TARGET_REACHED: u16 = 0x0400;
+}
+
+fn controlword(value: i64) -> SdoWrite {
+    SdoWrite::new(CONTROLWORD, 0, ValueKind::U16, value)
+}
+
+impl DriveConfig {
+    /// SDO writes carrying out `command` on the drive:
+    ///
+    /// - `Enable` walks the state machine to operation enabled,
+    /// - `Disable` shuts the power stage off,
+    /// - `Move` selects profile velocity mode and sets the target velocity,
+    ///   signed by direction,
+    /// - `Stop` is a quick stop; the drive has to be enabled again afterwards,
+    /// - `Home` selects homing mode and starts the drive's homing method,
+    /// - `MoveTo` and `MoveBy` select profile position mode, set the target
+    ///   in drive units and start the move.
+    pub fn writes(&self, motor: &MotorMsg) -> Vec<SdoWrite> {
+        match motor.command {
+            MotorCommand::Enable => vec![
+                controlword(control::SHUTDOWN),
+                controlword(control::SWITCH_ON),
+                controlword(control::ENABLE_OPERATION),
+            ],
+            MotorCommand::Disable => vec![controlword(control::SHUTDOWN)],
+            MotorCommand::Move => {
+                let velocity = match motor.direction {
+                    MoveDirection::Up => self.velocity as i64,
+                    MoveDirection::Down => -(self.velocity as i64),
+                };
+                vec![
+                    SdoWrite::new(MODES_OF_OPERATION, 0, ValueKind::I8, PROFILE_VELOCITY_MODE),
+                    SdoWrite::new(TARGET_VELOCITY, 0, ValueKind::I32, velocity),
+                    controlword(control::ENABLE_OPERATION),
+                ]
+            }
+            MotorCommand::Stop => vec![controlword(control::QUICK_STOP)],
+            // 第 4 位的上升沿启动回零
+            MotorCommand::Home => vec![
+                SdoWrite::new(MODES_OF_OPERATION, 0, ValueKind::I8, HOMING_MODE),
+                controlword(control::ENABLE_OPERATION),
+                controlword(control::START_HOMING),
+            ],
+            MotorCommand::MoveTo | MotorCommand::MoveBy => {
+                let start = match motor.command {
+                    MotorCommand::MoveTo => control::NEW_SETPOINT,
+                    _ => control::NEW_RELATIVE_SETPOINT,
+                };
+                let target = (motor.target / self.scale).round() as i64;
+                vec![
+                    SdoWrite::new(MODES_OF_OPERATION, 0, ValueKind::I8, PROFILE_POSITION_MODE),
+                    SdoWrite::new(TARGET_POSITION, 0, ValueKind::I32, target),
+                    controlword(control::ENABLE_OPERATION),
+                    controlword(start),
+                ]
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use subsystem::BoxError;
+
+/// CANopen nodes on one CAN interface and how they map to the bus, read
+/// from a JSON file:
+///
+/// ```json
+/// {
+///   "interface": "can0",
+///   "sdo_timeout_ms": 500,
+///   "nodes": [{
+///     "node": 1,
+///     "name": "drive1",
+///     "heartbeat_ms": 1000,
+///     "sdo": [{ "index": "0x6083", "kind": "u32", "value": 2000 }],
+///     "pdos": [{
+///       "name": "status", "cob_id": "0x181", "tpdo": 1,
+///       "points": [
+///         { "name": "statusword", "object": "0x6041", "kind": "u16" },
+///         { "name": "position", "object": "0x6064", "kind": "i32", "scale": 0.001 }
+///       ]
+///     }],
+///     "drive": { "motor": 1, "velocity": 5000 }
+///   }]
+/// }
+/// ```
+///
+/// Indices and COB-IDs may be numbers or `0x` strings. PDO points are packed
+/// in order from the first byte. With `tpdo` set, that transmit PDO of the
+/// node is mapped to the points' objects over SDO before the node is started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanopenConfig {
+    pub interface: String,
+    /// 单次 SDO 请求等待应答的时间
+    #[serde(default = "default_sdo_timeout_ms")]
+    pub sdo_timeout_ms: u64,
+    /// Publish every received frame as a `CanFrame` message on `can/{interface}`.
+    #[serde(default)]
+    pub raw: bool,
+    pub nodes: Vec<NodeConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeConfig {
+    /// Node id, 1 to 127.
+    pub node: u8,
+    /// Used as the telemetry device and in topics.
+    pub name: String,
+    /// Raise an alarm when no heartbeat arrives for this long.
+    #[serde(default)]
+    pub heartbeat_ms: Option<u64>,
+    /// Put the node in operational state once configured.
+    #[serde(default = "default_start")]
+    pub start: bool,
+    /// Written in order before the node is started.
+    #[serde(default)]
+    pub sdo: Vec<SdoWrite>,
+    #[serde(default)]
+    pub pdos: Vec<PdoConfig>,
+    #[serde(default)]
+    pub drive: Option<DriveConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueKind {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+impl ValueKind {
+    pub fn size(self) -> usize {
+        match self {
+            ValueKind::U8 | ValueKind::I8 => 1,
+            ValueKind::U16 | ValueKind::I16 => 2,
+            ValueKind::U32 | ValueKind::I32 => 4,
+        }
+    }
+
+    /// Little endian bytes of `value`, truncated to the size of the kind.
+    pub fn encode(self, value: i64) -> Vec<u8> {
+        value.to_le_bytes()[..self.size()].to_vec()
+    }
+
+    pub fn decode(self, data: &[u8]) -> Option<i64> {
+        let data = data.get(..self.size())?;
+        let value = match self {
+            ValueKind::U8 => data[0] as i64,
+            ValueKind::I8 => data[0] as i8 as i64,
+            ValueKind::U16 => u16::from_le_bytes([data[0], data[1]]) as i64,
+            ValueKind::I16 => i16::from_le_bytes([data[0], data[1]]) as i64,
+            ValueKind::U32 => u32::from_le_bytes(data.try_into().unwrap()) as i64,
+            ValueKind::I32 => i32::from_le_bytes(data.try_into().unwrap()) as i64,
+        };
+        Some(value)
+    }
+}
+
+/// One expedited SDO write.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SdoWrite {
+    #[serde(deserialize_with = "hex_u16")]
+    pub index: u16,
+    #[serde(default)]
+    pub subindex: u8,
+    pub kind: ValueKind,
+    pub value: i64,
+}
+
+impl SdoWrite {
+    pub fn new(index: u16, subindex: u8, kind: ValueKind, value: i64) -> Self {
+        Self {
+            index,
+            subindex,
+            kind,
+            value,
+        }
+    }
+}
+
+/// A process data object received from the node, published as one
+/// telemetry message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdoConfig {
+    pub name: String,
+    #[serde(deserialize_with = "hex_u32")]
+    pub cob_id: u32,
+    /// Transmit PDO number, 1 to 4, to map on the node at startup.
+    #[serde(default)]
+    pub tpdo: Option<u8>,
+    pub points: Vec<PdoPoint>,
+}
+
+/// One value in a PDO: `raw * scale + bias`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdoPoint {
+    pub name: String,
+    /// Object mapped to this place, needed when the PDO is mapped at startup.
+    #[serde(default, deserialize_with = "hex_u16_opt")]
+    pub object: Option<u16>,
+    #[serde(default)]
+    pub subindex: u8,
+    pub kind: ValueKind,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub bias: f64,
+}
+
+/// A CiA 402 drive run by `MotorMsg` commands for `motor`: in profile
+/// velocity mode for moves, in profile position mode for `MoveTo` and `MoveBy`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveConfig {
+    pub motor: i32,
+    /// Target velocity of a move, in drive units; moves down run at the negated value.
+    pub velocity: i32,
+    /// Units of a `MoveTo` or `MoveBy` target per drive position unit, as
+    /// for PDO points.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_sdo_timeout_ms() -> u64 {
+    500
+}
+
+fn default_start() -> bool {
+    true
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Int(u64),
+    Text(String),
+}
+
+fn parse_number(number: Number) -> Result<u64, String> {
+    match number {
+        Number::Int(value) => Ok(value),
+        Number::Text(text) => match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).map_err(|e| format!("{}: {}", text, e)),
+            None => text.parse().map_err(|e| format!("{}: {}", text, e)),
+        },
+    }
+}
+
+fn hex_u32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let value = parse_number(Number::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+    u32::try_from(value).map_err(serde::de::Error::custom)
+}
+
+fn hex_u16<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    let value = parse_number(Number::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+    u16::try_from(value).map_err(serde::de::Error::custom)
+}
+
+fn hex_u16_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    hex_u16(deserializer).map(Some)
+}
+
+impl CanopenConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks node ids, that every PDO fits in a frame and that mapped PDOs
+    /// name their objects.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        let mut seen = Vec::new();
+        for node in &self.nodes {
+            if !(1..=127).contains(&node.node) {
+                return Err(format!("{}: node id {} is not between 1 and 127", node.name, node.node).into());
+            }
+            if seen.contains(&node.node) {
+                return Err(format!("{}: node id {} is used twice", node.name, node.node).into());
+            }
+            seen.push(node.node);
+            if node.heartbeat_ms == Some(0) {
+                return Err(format!("{}: heartbeat must be greater than zero", node.name).into());
+            }
+            for pdo in &node.pdos {
+                let at = format!("{}/{}", node.name, pdo.name);
+                if pdo.cob_id > 0x7FF {
+                    return Err(format!("{}: COB-ID 0x{:X} is not an 11-bit id", at, pdo.cob_id).into());
+                }
+                if pdo.size() > 8 {
+                    return Err(format!("{}: points take {} bytes, more than a frame", at, pdo.size()).into());
+                }
+                if let Some(tpdo) = pdo.tpdo {
+                    if !(1..=4).contains(&tpdo) {
+                        return Err(format!("{}: TPDO {} is not between 1 and 4", at, tpdo).into());
+                    }
+                    if let Some(point) = pdo.points.iter().find(|point| point.object.is_none()) {
+                        return Err(format!("{}: {} needs an object to map", at, point.name).into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PdoConfig {
+    /// Bytes the points take up.
+    pub fn size(&self) -> usize {
+        self.points.iter().map(|point| point.kind.size()).sum()
+    }
+}
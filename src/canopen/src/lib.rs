@@ -0,0 +1,21 @@
+//! CANopen for the CSC over Linux SocketCAN: raw frames, NMT, expedited
+//! SDO, PDOs as telemetry, heartbeat consumer and CiA 402 drives driven by
+//! motor commands, plus a virtual bus and node simulator to test with.
+//!
+//! Linux only: on other targets the crate is empty.
+#![cfg(target_os = "linux")]
+
+mod cia402;
+mod config;
+mod master;
+mod pdo;
+mod protocol;
+mod simulator;
+mod socket;
+
+pub use cia402::*;
+pub use config::*;
+pub use master::*;
+pub use protocol::*;
+pub use simulator::*;
+pub use socket::*;
@@ -0,0 +1,398 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use message::{AlarmMsg, AlarmSeverity, CanFrameMsg, MessageType, MotorCommand, MotorMsg, Msg, MsgBuilder, TelemetryMsg};
+use subsystem::{BoxError, Clock, Context, Health, Publisher, SubSystem, SystemClock};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+use tokio::task::JoinHandle;
+
+use crate::config::{CanopenConfig, NodeConfig, SdoWrite};
+use crate::protocol::{nmt, parse_heartbeat, sdo_download, sdo_transfer, NmtCommand, NmtState, SdoError, SdoReply};
+use crate::socket::{CanInterface, CanSocket};
+
+/// 接口读取失败后重试前的等待
+const RETRY: Duration = Duration::from_secs(1);
+
+/// What the bus task is asked to do.
+enum Request {
+    Motor(MotorMsg),
+    Frame(CanFrameMsg),
+}
+
+/// Stops the bus task, which hands the request queue back.
+type BusTask = (oneshot::Sender<()>, JoinHandle<UnboundedReceiver<Request>>);
+
+/// A CANopen master on one SocketCAN interface.
+///
+/// On start every node is configured over SDO (PDO mapping, then the
+/// configured writes) and put in operational state. Received PDOs are
+/// published as a [`TelemetryMsg`] on `telemetry/{node}/{pdo}`. Heartbeats
+/// are watched: a node that misses its heartbeat raises a critical alarm on
+/// `alarm/canopen/{node}` until it is heard again, and a node that boots up
+/// again is configured and started again.
+///
+/// [`MotorMsg`] commands for a configured drive become CiA 402 controlword
+/// and setpoint writes; `CanFrame` messages are sent on the bus as they are.
+pub struct CanopenMaster {
+    config: Arc<CanopenConfig>,
+    interface: Option<Arc<dyn CanInterface>>,
+    clock: Arc<dyn Clock>,
+    requests: UnboundedSender<Request>,
+    pending: Option<UnboundedReceiver<Request>>,
+    lost: Arc<Mutex<BTreeSet<String>>>,
+    task: Option<BusTask>,
+}
+
+impl CanopenMaster {
+    pub fn new(config: CanopenConfig) -> Self {
+        let (requests, pending) = unbounded_channel();
+        Self {
+            config: Arc::new(config),
+            interface: None,
+            clock: Arc::new(SystemClock),
+            requests,
+            pending: Some(pending),
+            lost: Arc::new(Mutex::new(BTreeSet::new())),
+            task: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Uses `interface` instead of opening the configured SocketCAN interface.
+    pub fn with_interface(mut self, interface: impl CanInterface + 'static) -> Self {
+        self.interface = Some(Arc::new(interface));
+        self
+    }
+}
+
+/// The bus task.
+struct Bus {
+    config: Arc<CanopenConfig>,
+    interface: Arc<dyn CanInterface>,
+    clock: Arc<dyn Clock>,
+    publisher: Publisher,
+    sdo_timeout: Duration,
+    /// 每个节点最近一次心跳的时间
+    seen: HashMap<u8, DateTime<Utc>>,
+    lost: Arc<Mutex<BTreeSet<String>>>,
+    /// SDO 传输期间收到的其它帧
+    skipped: VecDeque<CanFrameMsg>,
+    /// 重新上电、等待重新配置的节点
+    rebooted: Vec<u8>,
+}
+
+impl Bus {
+    async fn run(
+        mut self,
+        mut requests: UnboundedReceiver<Request>,
+        mut stop: oneshot::Receiver<()>,
+    ) -> UnboundedReceiver<Request> {
+        let config = self.config.clone();
+        let now = self.clock.now();
+        for node in &config.nodes {
+            self.seen.insert(node.node, now);
+        }
+        tokio::select! {
+            _ = &mut stop => return requests,
+            _ = async {
+                for node in &config.nodes {
+                    self.configure(node).await;
+                }
+                self.settle().await;
+            } => {}
+        }
+
+        loop {
+            let deadline = self.next_deadline();
+            tokio::select! {
+                // 指令优先于收帧
+                biased;
+                _ = &mut stop => break,
+                request = requests.recv() => match request {
+                    Some(Request::Motor(motor)) => self.command(motor).await,
+                    Some(Request::Frame(frame)) => {
+                        if let Err(e) = self.interface.send(&frame).await {
+                            eprintln!("Failed to send CAN frame 0x{:X}: {}", frame.id, e);
+                        }
+                    }
+                    None => break,
+                },
+                frame = self.interface.recv() => match frame {
+                    Ok(frame) => self.frame(frame).await,
+                    Err(e) => {
+                        eprintln!("Failed to read from {}: {}", self.config.interface, e);
+                        tokio::select! {
+                            _ = &mut stop => break,
+                            _ = self.clock.sleep(RETRY) => {}
+                        }
+                    }
+                },
+                _ = sleep_until(&*self.clock, deadline) => self.check_heartbeats().await,
+            }
+            self.settle().await;
+        }
+        requests
+    }
+
+    /// Handles frames that arrived during SDO transfers and configures
+    /// nodes that booted up meanwhile.
+    async fn settle(&mut self) {
+        loop {
+            while let Some(frame) = self.skipped.pop_front() {
+                self.frame(frame).await;
+            }
+            let Some(node) = self.rebooted.pop() else {
+                break;
+            };
+            let config = self.config.clone();
+            if let Some(node) = config.nodes.iter().find(|config| config.node == node) {
+                self.configure(node).await;
+            }
+        }
+    }
+
+    /// Maps the node's PDOs, does its SDO writes and starts it, stopping at
+    /// the first failed write.
+    async fn configure(&mut self, node: &NodeConfig) {
+        let writes = node.pdos.iter().flat_map(|pdo| pdo.mapping()).chain(node.sdo.iter().cloned());
+        for write in writes {
+            if let Err(e) = self.write(node.node, &write).await {
+                let text = format!("configuring {} failed at 0x{:04X}:{}: {}", node.name, write.index, write.subindex, e);
+                self.alarm(node, AlarmSeverity::Warning, text).await;
+                return;
+            }
+        }
+        if node.start {
+            if let Err(e) = self.interface.send(&nmt(NmtCommand::Start, node.node)).await {
+                eprintln!("Failed to start CANopen node {}: {}", node.name, e);
+            }
+        }
+    }
+
+    async fn write(&mut self, node: u8, write: &SdoWrite) -> Result<(), SdoError> {
+        let request = sdo_download(node, write.index, write.subindex, &write.kind.encode(write.value))?;
+        let mut skipped = Vec::new();
+        let result = sdo_transfer(&*self.interface, request, self.sdo_timeout, &mut skipped).await;
+        self.skipped.extend(skipped);
+        match result? {
+            SdoReply::Downloaded => Ok(()),
+            SdoReply::Uploaded(_) => Err(SdoError::Protocol("upload answered a download".to_string())),
+        }
+    }
+
+    /// Carries out a motor command on the drive mapped to it.
+    async fn command(&mut self, motor: MotorMsg) {
+        let config = self.config.clone();
+        let Some((node, drive)) = config
+            .nodes
+            .iter()
+            .find_map(|node| Some((node, node.drive.as_ref().filter(|drive| drive.motor == motor.id)?)))
+        else {
+            eprintln!("No CANopen drive for motor {} {:?}", motor.id, motor.command);
+            return;
+        };
+        for write in drive.writes(&motor) {
+            let Err(e) = self.write(node.node, &write).await else {
+                continue;
+            };
+            let severity = match motor.command {
+                MotorCommand::Stop => AlarmSeverity::Critical,
+                _ => AlarmSeverity::Warning,
+            };
+            let text = format!("motor {} {:?} failed: {}", motor.id, motor.command, e);
+            self.alarm(node, severity, text).await;
+            break;
+        }
+    }
+
+    async fn frame(&mut self, frame: CanFrameMsg) {
+        if self.config.raw {
+            let msg = MsgBuilder::new()
+                .msg_type(MessageType::CanFrame)
+                .topic(format!("can/{}", self.config.interface))
+                .data(Box::new(frame.clone()))
+                .build()
+                .unwrap();
+            self.publisher.publish(msg).await;
+        }
+        if let Some((node, state)) = parse_heartbeat(&frame) {
+            self.heartbeat(node, state).await;
+            return;
+        }
+        if frame.extended {
+            return;
+        }
+        let config = self.config.clone();
+        for node in &config.nodes {
+            for pdo in node.pdos.iter().filter(|pdo| pdo.cob_id == frame.id) {
+                self.publish_telemetry(pdo.telemetry(&node.name, &frame.data)).await;
+            }
+        }
+    }
+
+    async fn heartbeat(&mut self, node: u8, state: NmtState) {
+        let config = self.config.clone();
+        let Some(node) = config.nodes.iter().find(|config| config.node == node) else {
+            return;
+        };
+        self.seen.insert(node.node, self.clock.now());
+        let recovered = self.lost.lock().unwrap().remove(&node.name);
+        if recovered {
+            let text = format!("{} heartbeat is back ({:?})", node.name, state);
+            self.alarm(node, AlarmSeverity::Info, text).await;
+        }
+        if state == NmtState::BootUp {
+            let text = format!("{} booted up, configuring it again", node.name);
+            self.alarm(node, AlarmSeverity::Warning, text).await;
+            self.rebooted.push(node.node);
+        }
+    }
+
+    /// When the next node that is still heard from is due to miss its heartbeat.
+    fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        let lost = self.lost.lock().unwrap();
+        self.config
+            .nodes
+            .iter()
+            .filter(|node| !lost.contains(&node.name))
+            .filter_map(|node| Some(self.seen[&node.node] + Duration::from_millis(node.heartbeat_ms?)))
+            .min()
+    }
+
+    async fn check_heartbeats(&mut self) {
+        let config = self.config.clone();
+        let now = self.clock.now();
+        for node in &config.nodes {
+            let Some(heartbeat_ms) = node.heartbeat_ms else {
+                continue;
+            };
+            if self.seen[&node.node] + Duration::from_millis(heartbeat_ms) > now {
+                continue;
+            }
+            if self.lost.lock().unwrap().insert(node.name.clone()) {
+                let text = format!("no heartbeat from {} for {} ms", node.name, heartbeat_ms);
+                self.alarm(node, AlarmSeverity::Critical, text).await;
+            }
+        }
+    }
+
+    async fn publish_telemetry(&self, telemetry: TelemetryMsg) {
+        let msg = MsgBuilder::new()
+            .msg_type(MessageType::Telemetry)
+            .topic(format!("telemetry/{}/{}", telemetry.device, telemetry.group))
+            .data(Box::new(telemetry))
+            .build()
+            .unwrap();
+        self.publisher.publish(msg).await;
+    }
+
+    async fn alarm(&self, node: &NodeConfig, severity: AlarmSeverity, text: String) {
+        eprintln!("CANopen alarm on {}: {}", node.name, text);
+        let source = format!("{}/{}", self.publisher.source(), node.name);
+        let msg = alarm_msg(&node.name, AlarmMsg::new(source, severity, text));
+        self.publisher.publish(msg).await;
+    }
+}
+
+fn alarm_msg(node: &str, alarm: AlarmMsg) -> Msg {
+    MsgBuilder::new()
+        .msg_type(MessageType::Alarm)
+        .topic(format!("alarm/canopen/{}", node))
+        .data(Box::new(alarm))
+        .build()
+        .unwrap()
+}
+
+async fn sleep_until(clock: &dyn Clock, deadline: Option<DateTime<Utc>>) {
+    match deadline {
+        Some(deadline) => clock.sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[async_trait]
+impl SubSystem for CanopenMaster {
+    /// Opens the interface, so a missing interface fails the start.
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let requests = self.pending.take().ok_or("request queue was lost when the bus task failed")?;
+        let interface = match &self.interface {
+            Some(interface) => interface.clone(),
+            None => match CanSocket::open(&self.config.interface) {
+                Ok(socket) => Arc::new(socket),
+                Err(e) => {
+                    self.pending = Some(requests);
+                    return Err(format!("{}: {}", self.config.interface, e).into());
+                }
+            },
+        };
+        self.lost.lock().unwrap().clear();
+        let bus = Bus {
+            config: self.config.clone(),
+            interface,
+            clock: self.clock.clone(),
+            publisher: ctx.publisher(),
+            sdo_timeout: Duration::from_millis(self.config.sdo_timeout_ms),
+            seen: HashMap::new(),
+            lost: self.lost.clone(),
+            skipped: VecDeque::new(),
+            rebooted: Vec::new(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.task = Some((stop, tokio::spawn(bus.run(requests, stopped))));
+        Ok(())
+    }
+
+    /// Queues the [`MotorMsg`] of a move or stop, or the [`CanFrameMsg`] of
+    /// a `CanFrame` message, for the bus task.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        let request = match msg.get_msg_type() {
+            MessageType::Move | MessageType::Stop => {
+                let Some(mut motor) = msg.get_data::<MotorMsg>() else {
+                    return Ok(());
+                };
+                if msg.get_msg_type() == MessageType::Stop {
+                    motor.command = MotorCommand::Stop;
+                }
+                Request::Motor(motor)
+            }
+            MessageType::CanFrame => {
+                let Some(frame) = msg.get_data::<CanFrameMsg>() else {
+                    return Ok(());
+                };
+                Request::Frame(frame)
+            }
+            _ => return Ok(()),
+        };
+        let _ = self.requests.send(request);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.task.take() {
+            let _ = stop.send(());
+            self.pending = Some(task.await?);
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        let lost = self.lost.lock().unwrap();
+        if lost.is_empty() {
+            return Health::Healthy;
+        }
+        let nodes: Vec<&str> = lost.iter().map(String::as_str).collect();
+        Health::Degraded(format!("no heartbeat from {}", nodes.join(", ")))
+    }
+
+    fn rollup(&mut self) {}
+}
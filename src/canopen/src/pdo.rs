@@ -0,0 +1,49 @@
+use message::TelemetryMsg;
+
+use crate::config::{PdoConfig, SdoWrite, ValueKind};
+
+/// TPDO 通信参数从 0x1800 开始，映射参数从 0x1A00 开始
+const TPDO_COMMUNICATION: u16 = 0x1800;
+const TPDO_MAPPING: u16 = 0x1A00;
+/// COB-ID 的第 31 位置位表示 PDO 无效
+const PDO_INVALID: i64 = 0x8000_0000;
+
+impl PdoConfig {
+    /// The scaled values of a received PDO, skipping points past the end of `data`.
+    pub fn telemetry(&self, device: &str, data: &[u8]) -> TelemetryMsg {
+        let mut telemetry = TelemetryMsg::new(device, &self.name);
+        let mut at = 0;
+        for point in &self.points {
+            if let Some(raw) = data.get(at..).and_then(|rest| point.kind.decode(rest)) {
+                telemetry = telemetry.value(&point.name, raw as f64 * point.scale + point.bias);
+            }
+            at += point.kind.size();
+        }
+        telemetry
+    }
+
+    /// SDO writes mapping the transmit PDO to the points' objects, in the
+    /// order CiA 301 asks for: invalidate, clear, map, count, validate.
+    /// Empty unless `tpdo` is set.
+    pub fn mapping(&self) -> Vec<SdoWrite> {
+        let Some(tpdo) = self.tpdo else {
+            return Vec::new();
+        };
+        let communication = TPDO_COMMUNICATION + tpdo as u16 - 1;
+        let mapping = TPDO_MAPPING + tpdo as u16 - 1;
+        let cob_id = self.cob_id as i64;
+        let mut writes = vec![
+            SdoWrite::new(communication, 1, ValueKind::U32, cob_id | PDO_INVALID),
+            SdoWrite::new(mapping, 0, ValueKind::U8, 0),
+        ];
+        for (entry, point) in self.points.iter().enumerate() {
+            let object = point.object.unwrap_or_default() as i64;
+            let bits = (point.kind.size() * 8) as i64;
+            let value = object << 16 | (point.subindex as i64) << 8 | bits;
+            writes.push(SdoWrite::new(mapping, entry as u8 + 1, ValueKind::U32, value));
+        }
+        writes.push(SdoWrite::new(mapping, 0, ValueKind::U8, self.points.len() as i64));
+        writes.push(SdoWrite::new(communication, 1, ValueKind::U32, cob_id));
+        writes
+    }
+}
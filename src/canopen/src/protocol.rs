@@ -0,0 +1,250 @@
+use std::fmt;
+use std::time::Duration;
+
+use message::CanFrameMsg;
+use tokio::time::{timeout_at, Instant};
+
+use crate::socket::CanInterface;
+
+/// COB-ID of NMT commands from the master.
+pub const NMT_ID: u32 = 0x000;
+pub const SDO_RESPONSE_BASE: u32 = 0x580;
+pub const SDO_REQUEST_BASE: u32 = 0x600;
+pub const HEARTBEAT_BASE: u32 = 0x700;
+
+/// Command specifiers of NMT node control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtCommand {
+    Start = 0x01,
+    Stop = 0x02,
+    PreOperational = 0x80,
+    ResetNode = 0x81,
+    ResetCommunication = 0x82,
+}
+
+/// Tells `node`, or every node if 0, to change state.
+pub fn nmt(command: NmtCommand, node: u8) -> CanFrameMsg {
+    CanFrameMsg::new(NMT_ID, [command as u8, node])
+}
+
+/// NMT state reported in heartbeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtState {
+    /// 节点刚上电或复位，只发一次
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+}
+
+impl NmtState {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte & 0x7F {
+            0x00 => Some(NmtState::BootUp),
+            0x04 => Some(NmtState::Stopped),
+            0x05 => Some(NmtState::Operational),
+            0x7F => Some(NmtState::PreOperational),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            NmtState::BootUp => 0x00,
+            NmtState::Stopped => 0x04,
+            NmtState::Operational => 0x05,
+            NmtState::PreOperational => 0x7F,
+        }
+    }
+}
+
+pub fn heartbeat(node: u8, state: NmtState) -> CanFrameMsg {
+    CanFrameMsg::new(HEARTBEAT_BASE + node as u32, [state.to_byte()])
+}
+
+/// The node and state of a heartbeat or boot-up frame.
+pub fn parse_heartbeat(frame: &CanFrameMsg) -> Option<(u8, NmtState)> {
+    let node = frame.id.checked_sub(HEARTBEAT_BASE)?;
+    if frame.extended || !(1..=127).contains(&node) || frame.data.len() != 1 {
+        return None;
+    }
+    Some((node as u8, NmtState::from_byte(frame.data[0])?))
+}
+
+/// Why an SDO transfer failed.
+#[derive(Debug)]
+pub enum SdoError {
+    Timeout(Duration),
+    /// The node aborted the transfer with this abort code.
+    Abort(u32),
+    /// 超过 4 字节的对象需要分段传输，这里不支持
+    Segmented,
+    Protocol(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for SdoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SdoError::Timeout(after) => write!(f, "no SDO response within {} ms", after.as_millis()),
+            SdoError::Abort(code) => write!(f, "SDO abort 0x{:08X} ({})", code, abort_text(*code)),
+            SdoError::Segmented => write!(f, "segmented SDO transfers are not supported"),
+            SdoError::Protocol(reason) => write!(f, "bad SDO response: {}", reason),
+            SdoError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SdoError {}
+
+impl From<std::io::Error> for SdoError {
+    fn from(e: std::io::Error) -> Self {
+        SdoError::Io(e)
+    }
+}
+
+/// Meaning of the abort codes drives commonly send.
+pub fn abort_text(code: u32) -> &'static str {
+    match code {
+        0x0504_0000 => "SDO protocol timed out",
+        0x0504_0001 => "command specifier not valid",
+        0x0601_0000 => "unsupported access to an object",
+        0x0601_0001 => "attempt to read a write only object",
+        0x0601_0002 => "attempt to write a read only object",
+        0x0602_0000 => "object does not exist",
+        0x0604_0041 => "object cannot be mapped to the PDO",
+        0x0604_0042 => "PDO length exceeded",
+        0x0607_0010 => "data type does not match",
+        0x0609_0011 => "sub-index does not exist",
+        0x0609_0030 => "value range exceeded",
+        0x0800_0000 => "general error",
+        0x0800_0021 => "not allowed because of local control",
+        0x0800_0022 => "not allowed in the present device state",
+        _ => "unknown",
+    }
+}
+
+/// Multiplexer of an SDO frame: index little endian, then sub-index.
+fn mux(index: u16, subindex: u8) -> [u8; 3] {
+    let [low, high] = index.to_le_bytes();
+    [low, high, subindex]
+}
+
+/// Expedited download (write) of up to 4 bytes.
+pub fn sdo_download(node: u8, index: u16, subindex: u8, data: &[u8]) -> Result<CanFrameMsg, SdoError> {
+    if data.is_empty() || data.len() > 4 {
+        return Err(SdoError::Segmented);
+    }
+    let mut frame = [0u8; 8];
+    // ccs=1, e=1, s=1, n = 未用字节数
+    frame[0] = 0x23 | (((4 - data.len()) as u8) << 2);
+    frame[1..4].copy_from_slice(&mux(index, subindex));
+    frame[4..4 + data.len()].copy_from_slice(data);
+    Ok(CanFrameMsg::new(SDO_REQUEST_BASE + node as u32, frame))
+}
+
+pub fn sdo_upload(node: u8, index: u16, subindex: u8) -> CanFrameMsg {
+    let mut frame = [0u8; 8];
+    frame[0] = 0x40;
+    frame[1..4].copy_from_slice(&mux(index, subindex));
+    CanFrameMsg::new(SDO_REQUEST_BASE + node as u32, frame)
+}
+
+pub fn sdo_abort(node: u8, index: u16, subindex: u8, code: u32, from_server: bool) -> CanFrameMsg {
+    let mut frame = [0u8; 8];
+    frame[0] = 0x80;
+    frame[1..4].copy_from_slice(&mux(index, subindex));
+    frame[4..8].copy_from_slice(&code.to_le_bytes());
+    let base = if from_server { SDO_RESPONSE_BASE } else { SDO_REQUEST_BASE };
+    CanFrameMsg::new(base + node as u32, frame)
+}
+
+/// What a node answered to an SDO request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SdoReply {
+    Downloaded,
+    Uploaded(Vec<u8>),
+}
+
+/// The answer to a request for `index`/`subindex` on `node`, or `None` if
+/// `frame` is something else.
+pub fn parse_sdo_reply(node: u8, index: u16, subindex: u8, frame: &CanFrameMsg) -> Option<Result<SdoReply, SdoError>> {
+    if frame.extended || frame.id != SDO_RESPONSE_BASE + node as u32 || frame.data.len() != 8 {
+        return None;
+    }
+    if frame.data[1..4] != mux(index, subindex) {
+        return None;
+    }
+    let command = frame.data[0];
+    let reply = match command >> 5 {
+        // scs=3: 下载确认
+        3 => Ok(SdoReply::Downloaded),
+        2 if command & 0x02 == 0 => Err(SdoError::Segmented),
+        2 => {
+            let len = if command & 0x01 != 0 { 4 - ((command >> 2) & 0x03) as usize } else { 4 };
+            Ok(SdoReply::Uploaded(frame.data[4..4 + len].to_vec()))
+        }
+        4 => Err(SdoError::Abort(u32::from_le_bytes(frame.data[4..8].try_into().unwrap()))),
+        _ => Err(SdoError::Protocol(format!("unexpected command byte 0x{:02X}", command))),
+    };
+    Some(reply)
+}
+
+/// Sends `request` and waits for the matching reply. Frames that arrive
+/// meanwhile are kept in `skipped` for the caller to handle.
+pub async fn sdo_transfer(
+    interface: &dyn CanInterface,
+    request: CanFrameMsg,
+    after: Duration,
+    skipped: &mut Vec<CanFrameMsg>,
+) -> Result<SdoReply, SdoError> {
+    let node = (request.id - SDO_REQUEST_BASE) as u8;
+    let index = u16::from_le_bytes([request.data[1], request.data[2]]);
+    let subindex = request.data[3];
+    interface.send(&request).await?;
+    let deadline = Instant::now() + after;
+    loop {
+        let frame = match timeout_at(deadline, interface.recv()).await {
+            Ok(frame) => frame?,
+            Err(_) => {
+                // 告知节点放弃本次传输
+                let _ = interface.send(&sdo_abort(node, index, subindex, 0x0504_0000, false)).await;
+                return Err(SdoError::Timeout(after));
+            }
+        };
+        match parse_sdo_reply(node, index, subindex, &frame) {
+            Some(reply) => return reply,
+            None => skipped.push(frame),
+        }
+    }
+}
+
+/// Reads an object of up to 4 bytes from `node`.
+pub async fn sdo_read(
+    interface: &dyn CanInterface,
+    node: u8,
+    index: u16,
+    subindex: u8,
+    after: Duration,
+) -> Result<Vec<u8>, SdoError> {
+    match sdo_transfer(interface, sdo_upload(node, index, subindex), after, &mut Vec::new()).await? {
+        SdoReply::Uploaded(data) => Ok(data),
+        SdoReply::Downloaded => Err(SdoError::Protocol("download confirmed for an upload".to_string())),
+    }
+}
+
+/// Writes an object of up to 4 bytes on `node`.
+pub async fn sdo_write(
+    interface: &dyn CanInterface,
+    node: u8,
+    index: u16,
+    subindex: u8,
+    data: &[u8],
+    after: Duration,
+) -> Result<(), SdoError> {
+    let request = sdo_download(node, index, subindex, data)?;
+    match sdo_transfer(interface, request, after, &mut Vec::new()).await? {
+        SdoReply::Downloaded => Ok(()),
+        SdoReply::Uploaded(_) => Err(SdoError::Protocol("upload answered a download".to_string())),
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use message::CanFrameMsg;
+
+use crate::protocol::{heartbeat, sdo_abort, NmtState, NMT_ID, SDO_REQUEST_BASE, SDO_RESPONSE_BASE};
+use crate::socket::CanInterface;
+
+#[derive(Debug)]
+struct NodeState {
+    nmt: NmtState,
+    objects: BTreeMap<(u16, u8), Vec<u8>>,
+    /// 收到的每次 SDO 写入，按顺序
+    writes: Vec<(u16, u8, Vec<u8>)>,
+}
+
+/// A CANopen node for testing masters without hardware: an object
+/// dictionary served over expedited SDO, NMT state control, boot-up and
+/// heartbeat.
+pub struct NodeSimulator {
+    node: u8,
+    heartbeat: Option<Duration>,
+    read_only: BTreeSet<(u16, u8)>,
+    state: Arc<Mutex<NodeState>>,
+}
+
+impl NodeSimulator {
+    pub fn new(node: u8) -> Self {
+        Self {
+            node,
+            heartbeat: None,
+            read_only: BTreeSet::new(),
+            state: Arc::new(Mutex::new(NodeState {
+                nmt: NmtState::BootUp,
+                objects: BTreeMap::new(),
+                writes: Vec::new(),
+            })),
+        }
+    }
+
+    /// Sends a heartbeat this often. No heartbeat by default.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    pub fn object(self, index: u16, subindex: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.state.lock().unwrap().objects.insert((index, subindex), data.into());
+        self
+    }
+
+    /// Aborts writes to the object.
+    pub fn read_only(mut self, index: u16, subindex: u8) -> Self {
+        self.read_only.insert((index, subindex));
+        self
+    }
+
+    pub fn handle(&self) -> NodeHandle {
+        NodeHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Boots up and serves requests until the interface fails.
+    pub async fn run(self, interface: impl CanInterface) {
+        self.boot(&interface).await;
+        let mut ticker = self.heartbeat.map(tokio::time::interval);
+        loop {
+            let tick = async {
+                match &mut ticker {
+                    Some(ticker) => {
+                        ticker.tick().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = tick => {
+                    let state = self.state.lock().unwrap().nmt;
+                    let _ = interface.send(&heartbeat(self.node, state)).await;
+                }
+                frame = interface.recv() => match frame {
+                    Ok(frame) => self.frame(&interface, frame).await,
+                    Err(_) => return,
+                },
+            }
+        }
+    }
+
+    async fn boot(&self, interface: &impl CanInterface) {
+        let _ = interface.send(&heartbeat(self.node, NmtState::BootUp)).await;
+        self.state.lock().unwrap().nmt = NmtState::PreOperational;
+    }
+
+    async fn frame(&self, interface: &impl CanInterface, frame: CanFrameMsg) {
+        if frame.extended {
+            return;
+        }
+        if frame.id == NMT_ID && frame.data.len() == 2 && (frame.data[1] == 0 || frame.data[1] == self.node) {
+            let next = match frame.data[0] {
+                0x01 => NmtState::Operational,
+                0x02 => NmtState::Stopped,
+                0x80 => NmtState::PreOperational,
+                0x81 | 0x82 => return self.boot(interface).await,
+                _ => return,
+            };
+            self.state.lock().unwrap().nmt = next;
+            return;
+        }
+        if frame.id != SDO_REQUEST_BASE + self.node as u32 || frame.data.len() != 8 {
+            return;
+        }
+        let reply = self.sdo(&frame.data);
+        if let Some(reply) = reply {
+            let _ = interface.send(&reply).await;
+        }
+    }
+
+    fn sdo(&self, request: &[u8]) -> Option<CanFrameMsg> {
+        let mut state = self.state.lock().unwrap();
+        // 停止状态下不响应 SDO
+        if state.nmt == NmtState::Stopped {
+            return None;
+        }
+        let index = u16::from_le_bytes([request[1], request[2]]);
+        let subindex = request[3];
+        let abort = |code| Some(sdo_abort(self.node, index, subindex, code, true));
+        let mut reply = [0u8; 8];
+        reply[1..4].copy_from_slice(&request[1..4]);
+        match request[0] >> 5 {
+            // 下载，仅支持快速传输
+            1 if request[0] & 0x02 != 0 => {
+                if self.read_only.contains(&(index, subindex)) {
+                    return abort(0x0601_0002);
+                }
+                let len = if request[0] & 0x01 != 0 { 4 - ((request[0] >> 2) & 0x03) as usize } else { 4 };
+                let data = request[4..4 + len].to_vec();
+                state.objects.insert((index, subindex), data.clone());
+                state.writes.push((index, subindex, data));
+                reply[0] = 0x60;
+            }
+            2 => {
+                let Some(data) = state.objects.get(&(index, subindex)) else {
+                    return abort(0x0602_0000);
+                };
+                if data.is_empty() || data.len() > 4 {
+                    return abort(0x0504_0001);
+                }
+                reply[0] = 0x43 | (((4 - data.len()) as u8) << 2);
+                reply[4..4 + data.len()].copy_from_slice(data);
+            }
+            // 主站放弃传输
+            4 => return None,
+            _ => return abort(0x0504_0001),
+        }
+        Some(CanFrameMsg::new(SDO_RESPONSE_BASE + self.node as u32, reply))
+    }
+}
+
+/// Looks into a running [`NodeSimulator`].
+#[derive(Clone)]
+pub struct NodeHandle {
+    state: Arc<Mutex<NodeState>>,
+}
+
+impl NodeHandle {
+    pub fn nmt_state(&self) -> NmtState {
+        self.state.lock().unwrap().nmt
+    }
+
+    pub fn object(&self, index: u16, subindex: u8) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().objects.get(&(index, subindex)).cloned()
+    }
+
+    /// Changes an object the way the node itself would, such as a statusword.
+    pub fn set_object(&self, index: u16, subindex: u8, data: impl Into<Vec<u8>>) {
+        self.state.lock().unwrap().objects.insert((index, subindex), data.into());
+    }
+
+    /// Every SDO write received, in order.
+    pub fn writes(&self) -> Vec<(u16, u8, Vec<u8>)> {
+        self.state.lock().unwrap().writes.clone()
+    }
+}
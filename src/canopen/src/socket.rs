@@ -0,0 +1,241 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use message::CanFrameMsg;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{broadcast, Mutex};
+
+/// 经典 CAN 帧最多 8 字节数据
+pub const MAX_DATA: usize = 8;
+
+/// A CAN bus frames are sent to and received from.
+#[async_trait]
+pub trait CanInterface: Send + Sync {
+    async fn send(&self, frame: &CanFrameMsg) -> io::Result<()>;
+
+    /// The next frame sent by another node. Frames sent through this
+    /// interface are not received back.
+    async fn recv(&self) -> io::Result<CanFrameMsg>;
+}
+
+/// `struct can_frame` from `linux/can.h`.
+#[repr(C, align(8))]
+#[derive(Default)]
+struct RawFrame {
+    can_id: u32,
+    len: u8,
+    pad: u8,
+    res0: u8,
+    len8_dlc: u8,
+    data: [u8; MAX_DATA],
+}
+
+/// A raw Linux SocketCAN socket bound to one interface such as `can0` or
+/// `vcan0`.
+pub struct CanSocket {
+    fd: AsyncFd<OwnedFd>,
+    interface: String,
+}
+
+impl CanSocket {
+    pub fn open(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no CAN interface {}", interface),
+            ));
+        }
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_CAN,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_can = unsafe { mem::zeroed() };
+        addr.can_family = libc::AF_CAN as libc::sa_family_t;
+        addr.can_ifindex = index as libc::c_int;
+        let bound = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            interface: interface.to_string(),
+        })
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+}
+
+#[async_trait]
+impl CanInterface for CanSocket {
+    async fn send(&self, frame: &CanFrameMsg) -> io::Result<()> {
+        if frame.data.len() > MAX_DATA {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CAN frame carries at most {} bytes", MAX_DATA),
+            ));
+        }
+        let mut raw = RawFrame {
+            can_id: frame.id,
+            len: frame.data.len() as u8,
+            ..Default::default()
+        };
+        if frame.extended {
+            raw.can_id |= libc::CAN_EFF_FLAG;
+        }
+        raw.data[..frame.data.len()].copy_from_slice(&frame.data);
+        loop {
+            let mut guard = self.fd.writable().await?;
+            let result = guard.try_io(|fd| {
+                let written = unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        &raw as *const RawFrame as *const libc::c_void,
+                        mem::size_of::<RawFrame>(),
+                    )
+                };
+                if written < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+
+    async fn recv(&self) -> io::Result<CanFrameMsg> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let mut raw = RawFrame::default();
+            let result = guard.try_io(|fd| {
+                let read = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        &mut raw as *mut RawFrame as *mut libc::c_void,
+                        mem::size_of::<RawFrame>(),
+                    )
+                };
+                if read < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(read as usize)
+            });
+            let read = match result {
+                Ok(read) => read?,
+                Err(_would_block) => continue,
+            };
+            if read != mem::size_of::<RawFrame>() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "short CAN frame"));
+            }
+            // 远程帧和错误帧不当数据帧处理
+            if raw.can_id & (libc::CAN_RTR_FLAG | libc::CAN_ERR_FLAG) != 0 {
+                continue;
+            }
+            let extended = raw.can_id & libc::CAN_EFF_FLAG != 0;
+            let id = if extended {
+                raw.can_id & libc::CAN_EFF_MASK
+            } else {
+                raw.can_id & libc::CAN_SFF_MASK
+            };
+            let len = (raw.len as usize).min(MAX_DATA);
+            return Ok(CanFrameMsg {
+                id,
+                extended,
+                data: raw.data[..len].to_vec(),
+            });
+        }
+    }
+}
+
+/// An in-process CAN bus, like a `vcan` interface without needing one.
+/// Every [`port`](VirtualCan::port) sees the frames sent by the others.
+#[derive(Clone)]
+pub struct VirtualCan {
+    bus: broadcast::Sender<(usize, CanFrameMsg)>,
+    ports: Arc<AtomicUsize>,
+}
+
+impl VirtualCan {
+    pub fn new() -> Self {
+        let (bus, _) = broadcast::channel(1024);
+        Self {
+            bus,
+            ports: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn port(&self) -> VirtualPort {
+        VirtualPort {
+            id: self.ports.fetch_add(1, Ordering::Relaxed),
+            bus: self.bus.clone(),
+            receiver: Mutex::new(self.bus.subscribe()),
+        }
+    }
+}
+
+impl Default for VirtualCan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct VirtualPort {
+    id: usize,
+    bus: broadcast::Sender<(usize, CanFrameMsg)>,
+    receiver: Mutex<broadcast::Receiver<(usize, CanFrameMsg)>>,
+}
+
+#[async_trait]
+impl CanInterface for VirtualPort {
+    async fn send(&self, frame: &CanFrameMsg) -> io::Result<()> {
+        if frame.data.len() > MAX_DATA {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CAN frame carries at most {} bytes", MAX_DATA),
+            ));
+        }
+        // 没有其它端口时帧直接丢掉，与真实总线一样
+        let _ = self.bus.send((self.id, frame.clone()));
+        Ok(())
+    }
+
+    async fn recv(&self) -> io::Result<CanFrameMsg> {
+        let mut receiver = self.receiver.lock().await;
+        loop {
+            match receiver.recv().await {
+                Ok((from, frame)) if from != self.id => return Ok(frame),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    eprintln!("Virtual CAN port {} missed {} frames", self.id, missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "virtual CAN bus closed"));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+#![cfg(target_os = "linux")]
+
+use std::time::Duration;
+
+use canopen::{
+    heartbeat, nmt, sdo_read, CanInterface, CanSocket, CanopenConfig, CanopenMaster, NmtCommand, NmtState,
+    NodeHandle, NodeSimulator, SdoError, VirtualCan, VirtualPort,
+};
+use message::{AlarmSeverity, CanFrameMsg, MessageType, MotorCommand, MotorMsg, MoveDirection, MsgBuilder};
+use subsystem::{Health, SystemId};
+use testkit::{expect, Harness, EXPECT};
+
+/// A drive on node 1 reporting statusword and position in TPDO 1, moved at
+/// 5000 units by motor 1 commands.
+const MASTER: &str = r#"{
+    "interface": "vcan0",
+    "sdo_timeout_ms": 500,
+    "nodes": [{
+        "node": 1,
+        "name": "drive1",
+        "heartbeat_ms": 1000,
+        "sdo": [{ "index": "0x6083", "kind": "u32", "value": 2000 }],
+        "pdos": [{
+            "name": "status", "cob_id": "0x181", "tpdo": 1,
+            "points": [
+                { "name": "statusword", "object": "0x6041", "kind": "u16" },
+                { "name": "position", "object": "0x6064", "kind": "i32", "scale": 0.001 }
+            ]
+        }],
+        "drive": { "motor": 1, "velocity": 5000 }
+    }]
+}"#;
+
+/// SDO writes configuring node 1 from [`MASTER`].
+fn configuration() -> Vec<(u16, u8, Vec<u8>)> {
+    vec![
+        (0x1800, 1, 0x8000_0181u32.to_le_bytes().to_vec()),
+        (0x1A00, 0, vec![0]),
+        (0x1A00, 1, 0x6041_0010u32.to_le_bytes().to_vec()),
+        (0x1A00, 2, 0x6064_0020u32.to_le_bytes().to_vec()),
+        (0x1A00, 0, vec![2]),
+        (0x1800, 1, 0x181u32.to_le_bytes().to_vec()),
+        (0x6083, 0, 2000u32.to_le_bytes().to_vec()),
+    ]
+}
+
+struct Bench {
+    harness: Harness,
+    node: NodeHandle,
+    /// Stands for other devices on the bus.
+    port: VirtualPort,
+}
+
+async fn start(config: &str, simulator: NodeSimulator) -> Bench {
+    let config: CanopenConfig = serde_json::from_str(config).unwrap();
+    config.validate().unwrap();
+    let bus = VirtualCan::new();
+    let node = simulator.handle();
+    tokio::spawn(simulator.run(bus.port()));
+    // 让模拟节点先发出上电报文，主站不会收到
+    tokio::task::yield_now().await;
+
+    let mut harness = Harness::new();
+    let master = CanopenMaster::new(config).with_clock(harness.clock()).with_interface(bus.port());
+    harness.register(
+        "canopen",
+        master,
+        vec![MessageType::Move, MessageType::Stop, MessageType::CanFrame],
+    );
+    let port = bus.port();
+    harness.start().await;
+    Bench { harness, node, port }
+}
+
+#[tokio::test]
+async fn configures_and_starts_nodes() {
+    let mut bench = start(MASTER, NodeSimulator::new(1)).await;
+
+    let node = bench.node.clone();
+    bench.harness.wait_until(|_| node.nmt_state() == NmtState::Operational).await;
+    assert_eq!(node.writes(), configuration());
+    assert!(bench.harness.alarms("canopen").is_empty());
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn pdos_become_telemetry() {
+    let mut bench = start(MASTER, NodeSimulator::new(1)).await;
+    let node = bench.node.clone();
+    bench.harness.wait_until(|_| node.nmt_state() == NmtState::Operational).await;
+
+    let pdo = CanFrameMsg::new(0x181, [0x37, 0x02, 0x10, 0x27, 0x00, 0x00]);
+    bench.port.send(&pdo).await.unwrap();
+    bench.harness.wait_until(|h| h.telemetry("canopen").len() == 1).await;
+    let status = &bench.harness.telemetry("canopen")[0];
+    assert_eq!((status.device.as_str(), status.group.as_str()), ("drive1", "status"));
+    assert_eq!(status.values["statusword"], 0x0237 as f64);
+    assert_eq!(status.values["position"], 10.0);
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn motor_commands_write_controlword_and_setpoint() {
+    let mut bench = start(MASTER, NodeSimulator::new(1)).await;
+    let node = bench.node.clone();
+    let configured = configuration().len();
+    bench.harness.wait_until(|_| node.writes().len() == configured).await;
+
+    let enable = MotorMsg::new(1, MoveDirection::Up).command(MotorCommand::Enable);
+    bench.harness.inject_data(MessageType::Move, enable).await;
+    bench.harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    // Stop 类型的消息按急停处理
+    bench.harness.inject_data(MessageType::Stop, MotorMsg::new(1, MoveDirection::Down)).await;
+    bench.harness.wait_until(|_| node.writes().len() == configured + 7).await;
+
+    let commands: Vec<_> = node.writes().into_iter().skip(configured).collect();
+    assert_eq!(
+        commands,
+        [
+            (0x6040, 0, vec![0x06, 0x00]),
+            (0x6040, 0, vec![0x07, 0x00]),
+            (0x6040, 0, vec![0x0F, 0x00]),
+            (0x6060, 0, vec![3]),
+            (0x60FF, 0, (-5000i32).to_le_bytes().to_vec()),
+            (0x6040, 0, vec![0x0F, 0x00]),
+            (0x6040, 0, vec![0x02, 0x00]),
+        ]
+    );
+    assert!(bench.harness.alarms("canopen").is_empty());
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn missed_heartbeat_alarms_until_heard_again() {
+    let mut bench = start(MASTER, NodeSimulator::new(1)).await;
+    let node = bench.node.clone();
+    bench.harness.wait_until(|_| node.nmt_state() == NmtState::Operational).await;
+
+    bench.harness.wait_for_sleepers(1).await;
+    bench.harness.advance(Duration::from_secs(1)).await;
+    bench.harness.wait_until(|h| h.alarms("canopen").len() == 1).await;
+    assert_eq!(bench.harness.alarms("canopen")[0].severity, AlarmSeverity::Critical);
+    let health = bench.harness.center().health().await;
+    assert!(matches!(health[&SystemId::from("canopen")], Health::Degraded(_)));
+
+    bench.port.send(&heartbeat(1, NmtState::Operational)).await.unwrap();
+    bench.harness.wait_until(|h| h.alarms("canopen").len() == 2).await;
+    assert_eq!(bench.harness.alarms("canopen")[1].severity, AlarmSeverity::Info);
+    let health = bench.harness.center().health().await;
+    assert_eq!(health[&SystemId::from("canopen")], Health::Healthy);
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn rebooted_node_is_configured_again() {
+    let mut bench = start(MASTER, NodeSimulator::new(1)).await;
+    let node = bench.node.clone();
+    let configured = configuration().len();
+    bench.harness.wait_until(|_| node.nmt_state() == NmtState::Operational).await;
+
+    bench.port.send(&nmt(NmtCommand::ResetNode, 1)).await.unwrap();
+    bench.harness.wait_until(|_| node.writes().len() == 2 * configured).await;
+    bench.harness.wait_until(|_| node.nmt_state() == NmtState::Operational).await;
+    let alarms = bench.harness.alarms("canopen");
+    assert_eq!(alarms.len(), 1);
+    assert!(alarms[0].text.contains("booted up"));
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn sdo_abort_stops_configuration() {
+    let simulator = NodeSimulator::new(1).read_only(0x6083, 0);
+    let mut bench = start(MASTER, simulator).await;
+
+    bench.harness.wait_until(|h| h.alarms("canopen").len() == 1).await;
+    let alarm = &bench.harness.alarms("canopen")[0];
+    assert_eq!(alarm.severity, AlarmSeverity::Warning);
+    assert!(alarm.text.contains("0x6083"), "{}", alarm.text);
+    assert!(alarm.text.contains("0x06010002"), "{}", alarm.text);
+    assert_eq!(bench.node.nmt_state(), NmtState::PreOperational);
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn sdo_reads_objects() {
+    let bus = VirtualCan::new();
+    let simulator = NodeSimulator::new(5).object(0x1000, 0, 0x0002_0192u32.to_le_bytes());
+    tokio::spawn(simulator.run(bus.port()));
+    let port = bus.port();
+
+    let device_type = sdo_read(&port, 5, 0x1000, 0, EXPECT).await.unwrap();
+    assert_eq!(device_type, 0x0002_0192u32.to_le_bytes());
+    let missing = sdo_read(&port, 5, 0x2000, 0, EXPECT).await;
+    assert!(matches!(missing, Err(SdoError::Abort(0x0602_0000))));
+    let silent = sdo_read(&port, 6, 0x1000, 0, Duration::from_millis(50)).await;
+    assert!(matches!(silent, Err(SdoError::Timeout(_))));
+}
+
+#[tokio::test]
+async fn raw_frames_pass_both_ways() {
+    let config = MASTER.replace(r#""sdo_timeout_ms": 500,"#, r#""sdo_timeout_ms": 500, "raw": true,"#);
+    let mut bench = start(&config, NodeSimulator::new(1)).await;
+    let node = bench.node.clone();
+    bench.harness.wait_until(|_| node.nmt_state() == NmtState::Operational).await;
+
+    let frame = CanFrameMsg::new(0x321, [1, 2, 3]);
+    let msg = MsgBuilder::new().msg_type(MessageType::CanFrame).data(Box::new(frame.clone())).build().unwrap();
+    bench.harness.inject(msg).await;
+    let sent = expect(async {
+        loop {
+            let received = bench.port.recv().await.unwrap();
+            if received.id == 0x321 {
+                return received;
+            }
+        }
+    }).await;
+    assert_eq!(sent, frame);
+
+    let frame = CanFrameMsg::new(0x7FF, [9]);
+    bench.port.send(&frame).await.unwrap();
+    bench
+        .harness
+        .wait_until(|h| {
+            h.emitted_of("canopen", MessageType::CanFrame)
+                .iter()
+                .filter_map(|msg| msg.get_data::<CanFrameMsg>())
+                .any(|received| received == frame)
+        })
+        .await;
+    let raw = bench.harness.emitted_of("canopen", MessageType::CanFrame);
+    assert_eq!(raw[0].topic(), "can/vcan0");
+    bench.harness.stop().await;
+}
+
+/// Needs a `vcan0` interface, so it only runs when asked for:
+///
+/// ```sh
+/// sudo modprobe vcan
+/// sudo ip link add dev vcan0 type vcan && sudo ip link set up vcan0
+/// cargo test -p canopen --test canopen -- --ignored
+/// ```
+#[tokio::test]
+#[ignore = "needs a vcan0 interface"]
+async fn socketcan_exchanges_frames_on_vcan() {
+    let sender = CanSocket::open("vcan0").expect("vcan0 is up");
+    let receiver = CanSocket::open("vcan0").unwrap();
+
+    let frame = CanFrameMsg::new(0x123, [0xDE, 0xAD]);
+    sender.send(&frame).await.unwrap();
+    assert_eq!(expect(receiver.recv()).await.unwrap(), frame);
+    let mut extended = CanFrameMsg::new(0x1ABC_DEF0, []);
+    extended.extended = true;
+    sender.send(&extended).await.unwrap();
+    assert_eq!(expect(receiver.recv()).await.unwrap(), extended);
+}
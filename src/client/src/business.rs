@@ -1,38 +1,73 @@
 ﻿// src/business_logic.rs
+use std::net::{AddrParseError, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+
 use crate::event::{self, Event, EventManager};
 use message::{MessageType, MotorMsg, MsgBuilder};
-use tokio::{
-    io::AsyncWriteExt,
-    net::TcpStream,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-};
+#[cfg(unix)]
+use subsystem::UnixSystem;
+use subsystem::{CenterHandle, CenterSubsystem, TcpSystem, WsSystem};
+use tokio::sync::mpsc::Receiver;
+
+/// How the client reaches the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    Tcp(SocketAddr),
+    /// A `ws://` URL, for sites where only HTTP gets through
+    WebSocket(String),
+    /// The server's Unix domain socket, when running on the same box
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Transport {
+    /// A `ws://` URL picks WebSocket and `unix:<path>` the Unix socket, anything
+    /// else must be a TCP address.
+    pub fn parse(target: &str) -> Result<Self, AddrParseError> {
+        if target.starts_with("ws://") {
+            return Ok(Transport::WebSocket(target.to_string()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = target.strip_prefix("unix:") {
+            return Ok(Transport::Unix(PathBuf::from(path)));
+        }
+        Ok(Transport::Tcp(target.parse()?))
+    }
+}
 
 pub struct BusinessLogic {
-    server_receiver: UnboundedReceiver<Box<dyn Event>>,
-    stream: Option<TcpStream>,
+    server_receiver: Receiver<Box<dyn Event>>,
+    center: CenterHandle,
 }
 
 impl BusinessLogic {
-    pub async fn new(server_receiver: UnboundedReceiver<Box<dyn Event>>) -> Self {
-        let mut stream = None;
-        if let Ok(appstream) = tokio::net::TcpStream::connect("127.0.0.1:8080").await {
-            stream = Some(appstream);
-        }
+    pub async fn new(server_receiver: Receiver<Box<dyn Event>>, center: CenterHandle) -> Self {
         Self {
             server_receiver,
-            stream: stream,
+            center,
         }
     }
 
+    /// Registers the link to the server as subsystem `"link"`, which sends it
+    /// the commands published here and publishes what the server sends back.
+    pub fn connect(center: &mut CenterSubsystem, transport: Transport) {
+        let commands = vec![MessageType::Move, MessageType::Stop];
+        match transport {
+            Transport::Tcp(addr) => center.register("link", TcpSystem::new(addr), commands),
+            Transport::WebSocket(url) => center.register("link", WsSystem::new(url), commands),
+            #[cfg(unix)]
+            Transport::Unix(path) => center.register("link", UnixSystem::new(path), commands),
+        };
+    }
+
     pub async fn run(&mut self) {
         let mut events = EventManager::new();
-        let buf = [0; 1024];
-        loop {
-            if let Ok(event) = self.server_receiver.try_recv() {
-                println!("event is {:?}", event);
-                events.add_event(event);
-            }
-            if let Some(event) = events.pop() {
+        // 等事件时让出线程，内嵌的 center 和它共用运行时
+        while let Some(event) = self.server_receiver.recv().await {
+            println!("event is {:?}", event);
+            events.add_event(event);
+            while let Some(event) = events.pop() {
                 if let event::EventType::MotorEvent = event.get_type() {
                     let motor_msg = MotorMsg::default();
                     let msg = MsgBuilder::new()
@@ -40,13 +75,9 @@ impl BusinessLogic {
                         .data(Box::new(motor_msg))
                         .build()
                         .unwrap();
-                    if let Some(stream) = &mut self.stream {
-                        let msg = serde_json::to_string(&msg).unwrap();
-                        println!("msg send is {}", msg);
-                        if let Err(e) = stream.write_all(msg.as_bytes()).await {
-                            println!("Failed to write to socket; err = {:?}", e);
-                            return;
-                        }
+                    // 经 link 子系统发往服务端，本地子系统同样能收到
+                    if self.center.publish(msg).await.is_err() {
+                        return;
                     }
                 }
             }
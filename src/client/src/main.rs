@@ -1,4 +1,4 @@
-use business::BusinessLogic;
+use business::{BusinessLogic, Transport};
 use crossbeam::channel::Receiver;
 use crossbeam::channel::Sender;
 use eframe::egui;
@@ -8,13 +8,18 @@ use event::Event;
 use event::EventManager;
 use router::Route;
 use router::Router;
-use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::sync::mpsc::UnboundedSender;
-use ui::render_navbar;
+use tokio::sync::mpsc::Receiver as EventReceiver;
+use tokio::sync::mpsc::Sender as EventSender;
+use ui::{render_connect, render_navbar, ConnectDialog};
 use ui::{render_home, render_settings, render_profile, render_not_found};
-use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::channel;
+use tokio::sync::oneshot;
 use std::error::Error;
 use std::fmt;
+use subsystem::CenterSubsystem;
+
+/// UI 与业务线程之间事件队列的容量，满了 UI 事件会被丢弃而不是无限堆积
+const EVENT_QUEUE_SIZE: usize = 64;
 
 mod router;
 mod event;
@@ -24,9 +29,10 @@ mod widgets;
 
 struct ClientApp {
     router: Router,
-    sender: UnboundedSender<Box<dyn Event>>,
-    receiver: UnboundedReceiver<Box<dyn Event>>,
+    sender: EventSender<Box<dyn Event>>,
+    receiver: EventReceiver<Box<dyn Event>>,
     events: EventManager,
+    connect: ConnectDialog,
 }
 
 impl App for ClientApp {
@@ -44,17 +50,19 @@ impl App for ClientApp {
                 _ => render_not_found(self, ctx, frame), // Default case for unknown routes
             }
         });
+        render_connect(self, ctx);
     }
 }
 
 impl ClientApp {
-    pub fn new(router: Router, sender:UnboundedSender<Box<dyn Event>> , receiver:UnboundedReceiver<Box<dyn Event>> ) -> Self {
+    pub fn new(router: Router, sender:EventSender<Box<dyn Event>> , receiver:EventReceiver<Box<dyn Event>>, connect: ConnectDialog) -> Self {
         let events = EventManager::new();
         Self {
             router,
             sender,
             receiver,
             events,
+            connect,
         }
     }
 }
@@ -90,21 +98,51 @@ fn app_setup(cc: &CreationContext) {
 async fn main() {
     let native_options = eframe::NativeOptions::default();
 
-    let (ui_sender, server_receiver) = unbounded_channel::<Box<dyn Event>>();
-    let (server_sender, ui_receiver) = unbounded_channel::<Box<dyn Event>>();
+    let (ui_sender, server_receiver) = channel::<Box<dyn Event>>(EVENT_QUEUE_SIZE);
+    let (server_sender, ui_receiver) = channel::<Box<dyn Event>>(EVENT_QUEUE_SIZE);
+
+    // 第一个参数选择服务端地址，ws:// 开头走 WebSocket，unix: 开头走本地 socket；不给则在连接对话框里选
+    let (connect_sender, connect_receiver) = oneshot::channel::<Transport>();
+    let mut connect = ConnectDialog::new(connect_sender);
+    if let Some(target) = std::env::args().nth(1) {
+        connect.choose(Transport::parse(&target).expect("invalid server address"));
+    }
+    let (quit_sender, mut quit_receiver) = oneshot::channel::<()>();
+
+    // 选定服务端后才启动中心，运行中的中心不能再注册子系统
+    let client = tokio::spawn(async move {
+        let transport = tokio::select! {
+            Ok(transport) = connect_receiver => transport,
+            _ = &mut quit_receiver => return,
+        };
+        let mut center = CenterSubsystem::new();
+        BusinessLogic::connect(&mut center, transport);
+        let center_handle = center.handle();
+        let center_task = tokio::spawn(async move { center.run().await });
 
-    let handle = tokio::spawn(async move {
-        let mut business_logic = BusinessLogic::new(server_receiver).await;
-        business_logic.run().await;
+        let handle = tokio::spawn({
+            let center_handle = center_handle.clone();
+            async move {
+                let mut business_logic = BusinessLogic::new(server_receiver, center_handle).await;
+                business_logic.run().await;
+            }
+        });
+
+        let _ = quit_receiver.await;
+        center_handle.shutdown();
+        let _ = center_task.await;
     });
 
     eframe::run_native(
         "Task Manager",
         native_options,
-        Box::new(|cc| {
+        Box::new(move |cc| {
             app_setup(cc);
-            Ok(Box::new(ClientApp::new(Router::new(), ui_sender, ui_receiver)))
+            Ok(Box::new(ClientApp::new(Router::new(), ui_sender, ui_receiver, connect)))
         }),
     )
     .expect("Failed to run native application");
+
+    let _ = quit_sender.send(());
+    let _ = client.await;
 }
\ No newline at end of file
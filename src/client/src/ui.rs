@@ -2,9 +2,11 @@
 mod profile;
 mod setting;
 mod error;
+mod connect;
 
 use eframe::egui;
 
+pub use self::connect::{render_connect, ConnectDialog};
 pub use self::{home::render_home, profile::render_profile, setting::render_settings, error::render_not_found};
 
 
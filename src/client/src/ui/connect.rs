@@ -0,0 +1,140 @@
+use std::io;
+use std::time::Duration;
+
+use crossbeam::channel::{unbounded, Receiver, TryRecvError};
+use subsystem::{discover, discovery_targets, Advertised, DiscoveredServer, TransportKind};
+use tokio::sync::oneshot;
+
+use crate::business::Transport;
+use crate::ClientApp;
+
+/// 等待服务端应答的时间
+const SCAN_WAIT: Duration = Duration::from_secs(1);
+
+/// Lets the user pick the server to connect to, among those answering
+/// discovery on the local network or by typing its address. Closes once a
+/// server is chosen.
+pub struct ConnectDialog {
+    chosen: Option<oneshot::Sender<Transport>>,
+    servers: Vec<DiscoveredServer>,
+    scan: Option<Receiver<io::Result<Vec<DiscoveredServer>>>>,
+    scanned: bool,
+    address: String,
+    error: Option<String>,
+}
+
+impl ConnectDialog {
+    pub fn new(chosen: oneshot::Sender<Transport>) -> Self {
+        Self {
+            chosen: Some(chosen),
+            servers: Vec::new(),
+            scan: None,
+            scanned: false,
+            address: "127.0.0.1:8080".to_string(),
+            error: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.chosen.is_some()
+    }
+
+    pub fn choose(&mut self, transport: Transport) {
+        if let Some(chosen) = self.chosen.take() {
+            let _ = chosen.send(transport);
+        }
+    }
+
+    fn scan(&mut self, ctx: &egui::Context) {
+        let (sender, receiver) = unbounded();
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let _ = sender.send(discover(&discovery_targets(), SCAN_WAIT).await);
+            ctx.request_repaint();
+        });
+        self.scan = Some(receiver);
+        self.scanned = true;
+    }
+
+    fn poll(&mut self) {
+        let Some(receiver) = &self.scan else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(Ok(servers)) => {
+                self.servers = servers;
+                self.error = None;
+            }
+            Ok(Err(e)) => self.error = Some(format!("Scan failed: {}", e)),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {}
+        }
+        self.scan = None;
+    }
+}
+
+fn transport(endpoint: &Advertised) -> Option<Transport> {
+    match endpoint.transport {
+        TransportKind::Tcp => Some(Transport::Tcp(endpoint.addr)),
+        TransportKind::WebSocket => Some(Transport::WebSocket(format!("ws://{}", endpoint.addr))),
+        // gRPC 留给其它工具，客户端不走
+        TransportKind::Grpc => None,
+    }
+}
+
+pub fn render_connect(app: &mut ClientApp, ctx: &egui::Context) {
+    let dialog = &mut app.connect;
+    if !dialog.is_open() {
+        return;
+    }
+    dialog.poll();
+    if !dialog.scanned {
+        dialog.scan(ctx);
+    }
+
+    let mut chosen = None;
+    egui::Window::new("Connect").collapsible(false).resizable(false).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Servers on the network:");
+            if dialog.scan.is_some() {
+                ui.spinner();
+            } else if ui.button("Scan").clicked() {
+                dialog.scan(ctx);
+            }
+        });
+        if dialog.servers.is_empty() && dialog.scan.is_none() {
+            ui.label("No server answered.");
+        }
+        for server in &dialog.servers {
+            ui.separator();
+            ui.label(format!("{} {} ({})", server.info.name, server.info.version, server.from.ip()));
+            ui.horizontal(|ui| {
+                for endpoint in &server.info.endpoints {
+                    let Some(transport) = transport(endpoint) else {
+                        continue;
+                    };
+                    if ui.button(format!("{:?} {}", endpoint.transport, endpoint.addr)).clicked() {
+                        chosen = Some(transport);
+                    }
+                }
+            });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut dialog.address);
+            if ui.button("Connect").clicked() {
+                match Transport::parse(dialog.address.trim()) {
+                    Ok(transport) => chosen = Some(transport),
+                    Err(e) => dialog.error = Some(format!("Invalid address: {}", e)),
+                }
+            }
+        });
+        if let Some(error) = &dialog.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+    if let Some(transport) = chosen {
+        dialog.choose(transport);
+    }
+}
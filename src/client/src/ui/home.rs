@@ -45,7 +45,9 @@ pub fn render_home(app: &mut ClientApp, ctx: &egui::Context, frame: &mut eframe:
         // });
         if ui.button("event").clicked(){
             let home_event = Box::new(HomeEvent::new(123));
-            app.sender.send(home_event).unwrap();
+            if let Err(e) = app.sender.try_send(home_event) {
+                println!("Dropped UI event: {}", e);
+            }
         }
 
         ui.add(CustomButton::new("custom"));
@@ -75,7 +77,9 @@ pub fn render_home(app: &mut ClientApp, ctx: &egui::Context, frame: &mut eframe:
         });
 
         if ui.button("motor").clicked(){
-            app.sender.send(Box::new(MotorEvent::default())).unwrap();
+            if let Err(e) = app.sender.try_send(Box::new(MotorEvent::default())) {
+                println!("Dropped UI event: {}", e);
+            }
         }
 
         // 快捷链接
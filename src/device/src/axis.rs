@@ -0,0 +1,71 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use message::MoveDirection;
+
+/// Why an axis command failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AxisError {
+    /// The driver can't do this on its drive.
+    Unsupported(&'static str),
+    /// The drive refused, for example a move while disabled or past a limit.
+    Rejected(String),
+    /// The bus or the drive didn't answer properly.
+    Link(String),
+}
+
+impl fmt::Display for AxisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AxisError::Unsupported(what) => write!(f, "{} is not supported by the drive", what),
+            AxisError::Rejected(reason) => write!(f, "rejected: {}", reason),
+            AxisError::Link(reason) => write!(f, "link failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AxisError {}
+
+/// What an axis reports about itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AxisStatus {
+    pub enabled: bool,
+    pub moving: bool,
+    /// Actual position, in the axis units.
+    pub position: f64,
+    /// 驱动器报告的故障
+    pub fault: Option<String>,
+}
+
+/// One motion axis, whatever drive and bus it is on.
+///
+/// Positions and distances are in the units of the axis (mm, degrees, ...);
+/// the driver scales them for its drive. A new drive vendor only needs an
+/// implementation of this trait to be run by the
+/// [`DeviceSystem`](crate::DeviceSystem).
+#[async_trait]
+pub trait Axis: Send {
+    async fn enable(&mut self) -> Result<(), AxisError>;
+
+    async fn disable(&mut self) -> Result<(), AxisError>;
+
+    async fn move_absolute(&mut self, position: f64) -> Result<(), AxisError>;
+
+    /// Moves by `distance` from the actual position; negative moves down.
+    async fn move_relative(&mut self, distance: f64) -> Result<(), AxisError> {
+        let status = self.status().await?;
+        self.move_absolute(status.position + distance).await
+    }
+
+    /// Moves at jog speed until stopped.
+    async fn jog(&mut self, direction: MoveDirection) -> Result<(), AxisError>;
+
+    async fn stop(&mut self) -> Result<(), AxisError>;
+
+    /// Runs the homing sequence of the drive.
+    async fn home(&mut self) -> Result<(), AxisError> {
+        Err(AxisError::Unsupported("homing"))
+    }
+
+    async fn status(&mut self) -> Result<AxisStatus, AxisError>;
+}
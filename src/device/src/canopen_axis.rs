@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use canopen::{
+    sdo_read, sdo_write, status, CanInterface, CanSocket, DriveConfig, SdoError, ValueKind, POSITION_ACTUAL, STATUSWORD,
+    VELOCITY_ACTUAL,
+};
+use message::{MotorCommand, MotorMsg, MoveDirection};
+use serde::{Deserialize, Serialize};
+
+use crate::axis::{Axis, AxisError, AxisStatus};
+
+/// A CiA 402 drive on a CANopen bus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanopenAxisConfig {
+    /// SocketCAN interface, such as `can0`.
+    pub interface: String,
+    pub node: u8,
+    #[serde(default = "default_sdo_timeout_ms")]
+    pub sdo_timeout_ms: u64,
+    /// Target velocity of a jog, in drive units.
+    pub velocity: i32,
+    /// Axis units per drive position unit.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_sdo_timeout_ms() -> u64 {
+    500
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl From<SdoError> for AxisError {
+    fn from(e: SdoError) -> Self {
+        match e {
+            SdoError::Abort(_) => AxisError::Rejected(e.to_string()),
+            e => AxisError::Link(e.to_string()),
+        }
+    }
+}
+
+/// Runs a CiA 402 drive over expedited SDO, with the same controlword and
+/// setpoint writes as the drives of a [`CanopenMaster`](canopen::CanopenMaster).
+///
+/// A stop is a quick stop, so the drive has to be enabled again afterwards.
+/// The interface is opened on first use.
+pub struct CanopenAxis {
+    config: CanopenAxisConfig,
+    drive: DriveConfig,
+    interface: Option<Arc<dyn CanInterface>>,
+}
+
+impl CanopenAxis {
+    pub fn new(config: CanopenAxisConfig) -> Self {
+        let drive = DriveConfig {
+            motor: 0,
+            velocity: config.velocity,
+            scale: config.scale,
+        };
+        Self {
+            config,
+            drive,
+            interface: None,
+        }
+    }
+
+    /// Uses `interface` instead of opening the configured SocketCAN interface.
+    pub fn with_interface(mut self, interface: impl CanInterface + 'static) -> Self {
+        self.interface = Some(Arc::new(interface));
+        self
+    }
+
+    fn interface(&mut self) -> Result<Arc<dyn CanInterface>, AxisError> {
+        if let Some(interface) = &self.interface {
+            return Ok(interface.clone());
+        }
+        let socket = CanSocket::open(&self.config.interface)
+            .map_err(|e| AxisError::Link(format!("{}: {}", self.config.interface, e)))?;
+        let interface: Arc<dyn CanInterface> = Arc::new(socket);
+        self.interface = Some(interface.clone());
+        Ok(interface)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.config.sdo_timeout_ms)
+    }
+
+    /// Does the drive writes for `motor`, stopping at the first failure.
+    async fn run(&mut self, motor: MotorMsg) -> Result<(), AxisError> {
+        let interface = self.interface()?;
+        for write in self.drive.writes(&motor) {
+            let data = write.kind.encode(write.value);
+            sdo_write(&*interface, self.config.node, write.index, write.subindex, &data, self.timeout()).await?;
+        }
+        Ok(())
+    }
+
+    async fn command(&mut self, command: MotorCommand) -> Result<(), AxisError> {
+        self.run(MotorMsg::new(self.drive.motor, MoveDirection::Up).command(command)).await
+    }
+
+    async fn read(&mut self, index: u16, kind: ValueKind) -> Result<i64, AxisError> {
+        let interface = self.interface()?;
+        let data = sdo_read(&*interface, self.config.node, index, 0, self.timeout()).await?;
+        kind.decode(&data)
+            .ok_or_else(|| AxisError::Link(format!("0x{:04X} returned {} bytes", index, data.len())))
+    }
+}
+
+#[async_trait]
+impl Axis for CanopenAxis {
+    async fn enable(&mut self) -> Result<(), AxisError> {
+        self.command(MotorCommand::Enable).await
+    }
+
+    async fn disable(&mut self) -> Result<(), AxisError> {
+        self.command(MotorCommand::Disable).await
+    }
+
+    async fn move_absolute(&mut self, position: f64) -> Result<(), AxisError> {
+        let motor = MotorMsg::new(self.drive.motor, MoveDirection::Up).command(MotorCommand::MoveTo);
+        self.run(motor.target(position)).await
+    }
+
+    /// Leaves the distance to the drive as a relative setpoint.
+    async fn move_relative(&mut self, distance: f64) -> Result<(), AxisError> {
+        let motor = MotorMsg::new(self.drive.motor, MoveDirection::Up).command(MotorCommand::MoveBy);
+        self.run(motor.target(distance)).await
+    }
+
+    async fn jog(&mut self, direction: MoveDirection) -> Result<(), AxisError> {
+        self.run(MotorMsg::new(self.drive.motor, direction)).await
+    }
+
+    async fn stop(&mut self) -> Result<(), AxisError> {
+        self.command(MotorCommand::Stop).await
+    }
+
+    async fn home(&mut self) -> Result<(), AxisError> {
+        self.command(MotorCommand::Home).await
+    }
+
+    async fn status(&mut self) -> Result<AxisStatus, AxisError> {
+        let word = self.read(STATUSWORD, ValueKind::U16).await? as u16;
+        let position = self.read(POSITION_ACTUAL, ValueKind::I32).await?;
+        let velocity = self.read(VELOCITY_ACTUAL, ValueKind::I32).await?;
+        let enabled = word & status::STATE_MASK == status::OPERATION_ENABLED;
+        let fault = (word & status::FAULT != 0).then(|| format!("drive fault, statusword 0x{:04X}", word));
+        Ok(AxisStatus {
+            enabled,
+            moving: enabled && velocity != 0,
+            position: position as f64 * self.config.scale,
+            fault,
+        })
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use subsystem::{BoxError, Clock};
+
+use crate::axis::Axis;
+#[cfg(target_os = "linux")]
+use crate::canopen_axis::{CanopenAxis, CanopenAxisConfig};
+use crate::modbus_axis::{ModbusAxis, ModbusAxisConfig};
+use crate::simulated::{SimulatedAxis, SimulatedConfig};
+
+/// The axes a [`DeviceSystem`](crate::DeviceSystem) runs and the driver of
+/// each, read from a JSON file:
+///
+/// ```json
+/// {
+///   "status_interval_ms": 500,
+///   "axes": [
+///     { "motor": 1, "name": "lift", "driver": { "simulated": { "speed": 20.0, "min": 0.0, "max": 400.0 } } },
+///     { "motor": 2, "name": "gate", "driver": { "modbus": {
+///       "transport": { "tcp": "192.168.0.20:502" }, "unit": 1,
+///       "command": 0, "codes": { "enable": 1, "disable": 2, "stop": 3, "jog_up": 4, "jog_down": 5, "move_absolute": 6 },
+///       "target": 2, "status": 10, "position": 12, "scale": 0.01
+///     } } },
+///     { "motor": 3, "name": "table", "driver": { "canopen": { "interface": "can0", "node": 2, "velocity": 5000, "scale": 0.001 } } }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// How often the status of every axis is read and published.
+    #[serde(default = "default_status_interval_ms")]
+    pub status_interval_ms: u64,
+    pub axes: Vec<AxisConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AxisConfig {
+    /// Motor id that `MotorMsg` commands address the axis by.
+    pub motor: i32,
+    /// Used as the telemetry device and in topics.
+    pub name: String,
+    pub driver: DriverConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriverConfig {
+    Simulated(SimulatedConfig),
+    Modbus(ModbusAxisConfig),
+    /// Linux only.
+    #[cfg(target_os = "linux")]
+    Canopen(CanopenAxisConfig),
+}
+
+fn default_status_interval_ms() -> u64 {
+    1000
+}
+
+impl DeviceConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that motor ids and names are unique and every driver can work.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if self.status_interval_ms == 0 {
+            return Err("status interval must be greater than zero".into());
+        }
+        let mut motors = HashSet::new();
+        let mut names = HashSet::new();
+        for axis in &self.axes {
+            if axis.name.is_empty() || axis.name.contains('/') {
+                return Err(format!("motor {}: name must be non-empty and without '/'", axis.motor).into());
+            }
+            if !motors.insert(axis.motor) {
+                return Err(format!("motor {} is configured twice", axis.motor).into());
+            }
+            if !names.insert(&axis.name) {
+                return Err(format!("axis name {} is used twice", axis.name).into());
+            }
+            axis.driver.validate().map_err(|e| format!("{}: {}", axis.name, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl DriverConfig {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            DriverConfig::Simulated(sim) => {
+                if sim.speed <= 0.0 {
+                    return Err("speed must be greater than zero".to_string());
+                }
+                if let (Some(min), Some(max)) = (sim.min, sim.max) {
+                    if min > max {
+                        return Err(format!("min {} is above max {}", min, max));
+                    }
+                }
+            }
+            DriverConfig::Modbus(modbus) => {
+                if modbus.scale == 0.0 {
+                    return Err("scale must not be zero".to_string());
+                }
+                let bits = modbus.bits;
+                if [bits.enabled, bits.moving, bits.fault].iter().any(|bit| *bit > 15) {
+                    return Err("status bits are numbered 0 to 15".to_string());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            DriverConfig::Canopen(canopen) => {
+                if !(1..=127).contains(&canopen.node) {
+                    return Err(format!("node {} is not between 1 and 127", canopen.node));
+                }
+                if canopen.scale == 0.0 {
+                    return Err("scale must not be zero".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the driver. Links are opened on first use, so this can't fail.
+    pub fn open(&self, clock: Arc<dyn Clock>) -> Box<dyn Axis> {
+        match self {
+            DriverConfig::Simulated(config) => Box::new(SimulatedAxis::new(config.clone()).with_shared_clock(clock)),
+            DriverConfig::Modbus(config) => Box::new(ModbusAxis::new(config.clone())),
+            #[cfg(target_os = "linux")]
+            DriverConfig::Canopen(config) => Box::new(CanopenAxis::new(config.clone())),
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use message::{AlarmMsg, AlarmSeverity, MessageType, MotorCommand, MotorMsg, Msg, MsgBuilder, TelemetryMsg};
+use subsystem::{BoxError, Clock, Context, Health, Publisher, SubSystem, SystemClock};
+use tokio::sync::{
+    mpsc::{channel, error::TrySendError, Receiver, Sender},
+    oneshot, watch,
+};
+use tokio::task::JoinHandle;
+
+use crate::axis::{Axis, AxisStatus};
+use crate::config::DeviceConfig;
+
+/// Commands an axis can have waiting before further ones are dropped.
+const COMMAND_QUEUE_SIZE: usize = 16;
+
+/// A queued command with the number of stops requested before it.
+type Queued = (u64, MotorMsg);
+
+/// An axis with the command queue its task works through.
+type Parked = (Box<dyn Axis>, Receiver<Queued>);
+
+/// Stops an axis task, which hands the axis and its queue back.
+type AxisTask = (oneshot::Sender<()>, JoinHandle<Parked>);
+
+struct Slot {
+    motor: i32,
+    name: String,
+    commands: Sender<Queued>,
+    /// 急停不排队，计数加一即通知轴任务
+    stops: watch::Sender<u64>,
+    parked: Option<Parked>,
+    task: Option<AxisTask>,
+}
+
+impl Slot {
+    fn new(motor: i32, name: String, axis: Box<dyn Axis>) -> Self {
+        let (commands, pending) = channel(COMMAND_QUEUE_SIZE);
+        Self {
+            motor,
+            name,
+            commands,
+            stops: watch::Sender::new(0),
+            parked: Some((axis, pending)),
+            task: None,
+        }
+    }
+
+    /// Commands waiting for the axis task.
+    fn backlog(&self) -> usize {
+        COMMAND_QUEUE_SIZE - self.commands.capacity()
+    }
+}
+
+/// Runs motion axes through their drivers.
+///
+/// [`MotorMsg`] commands of `Move` and `Stop` messages are carried out on
+/// the axis configured for their motor id: `Move` jogs, `MoveTo` and
+/// `MoveBy` move to a position or by a distance, and `Stop`, `Enable`,
+/// `Disable` and `Home` do what they say. Each axis has its own task, so a
+/// slow drive doesn't hold up the others.
+///
+/// A stop skips the queue: it cuts short the command the axis is carrying
+/// out and drops the ones still waiting. Other commands queue up to 16 per
+/// axis; beyond that they are dropped with an alarm, and an axis with half
+/// of that waiting makes the subsystem degraded.
+///
+/// The status of every axis is read on the configured interval and right
+/// after each command, and published as a [`TelemetryMsg`] on
+/// `telemetry/{axis}/axis`. Failed commands, drive faults and failing status
+/// reads are published as alarms on `alarm/device/{axis}`, a problem once
+/// and again when it clears.
+pub struct DeviceSystem {
+    config: Arc<DeviceConfig>,
+    clock: Arc<dyn Clock>,
+    added: Vec<(i32, String, Box<dyn Axis>)>,
+    slots: Vec<Slot>,
+    /// 每个有问题的轴及原因
+    problems: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl DeviceSystem {
+    pub fn new(config: DeviceConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            clock: Arc::new(SystemClock),
+            added: Vec::new(),
+            slots: Vec::new(),
+            problems: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Adds an axis that isn't in the config, such as one with a driver of
+    /// its own.
+    pub fn with_axis(mut self, motor: i32, name: impl Into<String>, axis: impl Axis + 'static) -> Self {
+        self.added.push((motor, name.into(), Box::new(axis)));
+        self
+    }
+}
+
+/// The task of one axis.
+struct Runner {
+    motor: i32,
+    name: String,
+    axis: Box<dyn Axis>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    publisher: Publisher,
+    problems: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl Runner {
+    async fn run(
+        mut self,
+        mut commands: Receiver<Queued>,
+        mut stops: watch::Receiver<u64>,
+        mut stop: oneshot::Receiver<()>,
+    ) -> Parked {
+        let mut due = self.clock.now();
+        // 停止期间收到的急停已经过时
+        let mut stopped = *stops.borrow_and_update();
+        loop {
+            tokio::select! {
+                // 急停优先于指令，指令优先于读状态
+                biased;
+                _ = &mut stop => break,
+                Ok(()) = stops.changed() => {
+                    stopped = *stops.borrow_and_update();
+                    self.halt().await;
+                    due = self.clock.now();
+                }
+                command = commands.recv() => match command {
+                    // 急停之前排队的指令作废
+                    Some((before, motor)) if before < stopped => {
+                        eprintln!("Device {} dropped motor {} {:?} after a stop", self.name, motor.id, motor.command);
+                    }
+                    Some((_, motor)) => {
+                        let preempted = tokio::select! {
+                            biased;
+                            Ok(()) = stops.changed() => true,
+                            _ = self.command(motor) => false,
+                        };
+                        if preempted {
+                            stopped = *stops.borrow_and_update();
+                            self.halt().await;
+                        }
+                        due = self.clock.now();
+                    }
+                    None => break,
+                },
+                _ = self.clock.sleep_until(due) => {
+                    self.report().await;
+                    // 落后时不补读，从现在重新计
+                    let now = self.clock.now();
+                    due = if due + self.interval > now { due + self.interval } else { now + self.interval };
+                }
+            }
+        }
+        (self.axis, commands)
+    }
+
+    async fn command(&mut self, motor: MotorMsg) {
+        let result = match motor.command {
+            MotorCommand::Move => self.axis.jog(motor.direction).await,
+            MotorCommand::MoveTo => self.axis.move_absolute(motor.target).await,
+            MotorCommand::MoveBy => self.axis.move_relative(motor.target).await,
+            MotorCommand::Stop => return self.halt().await,
+            MotorCommand::Enable => self.axis.enable().await,
+            MotorCommand::Disable => self.axis.disable().await,
+            MotorCommand::Home => self.axis.home().await,
+        };
+        if let Err(e) = result {
+            let text = format!("motor {} {:?} failed: {}", motor.id, motor.command, e);
+            self.alarm(AlarmSeverity::Warning, text).await;
+        }
+    }
+
+    /// Stops the axis; a stop that fails is critical.
+    async fn halt(&mut self) {
+        if let Err(e) = self.axis.stop().await {
+            let text = format!("motor {} {:?} failed: {}", self.motor, MotorCommand::Stop, e);
+            self.alarm(AlarmSeverity::Critical, text).await;
+        }
+    }
+
+    async fn report(&mut self) {
+        let problem = match self.axis.status().await {
+            Ok(status) => {
+                self.publish_status(&status).await;
+                status.fault
+            }
+            Err(e) => Some(format!("reading status failed: {}", e)),
+        };
+        self.problem(problem).await;
+    }
+
+    /// Alarms when the axis' problem changes.
+    async fn problem(&mut self, problem: Option<String>) {
+        let previous = {
+            let mut problems = self.problems.lock().unwrap();
+            match &problem {
+                Some(reason) => problems.insert(self.name.clone(), reason.clone()),
+                None => problems.remove(&self.name),
+            }
+        };
+        if previous == problem {
+            return;
+        }
+        match problem {
+            Some(reason) => self.alarm(AlarmSeverity::Warning, reason).await,
+            None => self.alarm(AlarmSeverity::Info, format!("{} recovered", self.name)).await,
+        }
+    }
+
+    async fn publish_status(&mut self, status: &AxisStatus) {
+        let flag = |on: bool| if on { 1.0 } else { 0.0 };
+        let telemetry = TelemetryMsg::new(&self.name, "axis")
+            .value("position", status.position)
+            .value("enabled", flag(status.enabled))
+            .value("moving", flag(status.moving))
+            .value("fault", flag(status.fault.is_some()));
+        let msg = MsgBuilder::new()
+            .msg_type(MessageType::Telemetry)
+            .topic(format!("telemetry/{}/axis", self.name))
+            .data(Box::new(telemetry))
+            .build()
+            .unwrap();
+        self.publisher.publish(msg).await;
+    }
+
+    async fn alarm(&mut self, severity: AlarmSeverity, text: String) {
+        eprintln!("Device alarm on {}: {}", self.name, text);
+        let msg = alarm_msg(self.publisher.source().as_str(), &self.name, severity, text);
+        self.publisher.publish(msg).await;
+    }
+}
+
+fn alarm_msg(system: &str, axis: &str, severity: AlarmSeverity, text: String) -> Msg {
+    MsgBuilder::new()
+        .msg_type(MessageType::Alarm)
+        .topic(format!("alarm/device/{}", axis))
+        .data(Box::new(AlarmMsg::new(format!("{}/{}", system, axis), severity, text)))
+        .build()
+        .unwrap()
+}
+
+#[async_trait]
+impl SubSystem for DeviceSystem {
+    /// Starts a task per axis. Drivers are created on the first start, on
+    /// the clock set by then.
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        if self.slots.is_empty() {
+            for axis in &self.config.axes {
+                let driver = axis.driver.open(self.clock.clone());
+                self.slots.push(Slot::new(axis.motor, axis.name.clone(), driver));
+            }
+            for (motor, name, axis) in self.added.drain(..) {
+                self.slots.push(Slot::new(motor, name, axis));
+            }
+        }
+        self.problems.lock().unwrap().clear();
+        let interval = Duration::from_millis(self.config.status_interval_ms);
+        for slot in &mut self.slots {
+            let (axis, commands) = slot
+                .parked
+                .take()
+                .ok_or_else(|| format!("axis {} was lost when its task failed", slot.name))?;
+            let runner = Runner {
+                motor: slot.motor,
+                name: slot.name.clone(),
+                axis,
+                clock: self.clock.clone(),
+                interval,
+                publisher: ctx.publisher(),
+                problems: self.problems.clone(),
+            };
+            let (stop, stopped) = oneshot::channel();
+            let stops = slot.stops.subscribe();
+            slot.task = Some((stop, tokio::spawn(runner.run(commands, stops, stopped))));
+        }
+        Ok(())
+    }
+
+    /// Queues the [`MotorMsg`] of a move for the axis it addresses, or
+    /// signals it to stop. Other motor ids are left to other subsystems.
+    async fn exec(&mut self, msg: &Msg, ctx: &mut Context) -> Result<(), BoxError> {
+        if !matches!(msg.get_msg_type(), MessageType::Move | MessageType::Stop) {
+            return Ok(());
+        }
+        let Some(mut motor) = msg.get_data::<MotorMsg>() else {
+            return Ok(());
+        };
+        if msg.get_msg_type() == MessageType::Stop {
+            motor.command = MotorCommand::Stop;
+        }
+        let Some(slot) = self.slots.iter().find(|slot| slot.motor == motor.id) else {
+            return Ok(());
+        };
+        if motor.command == MotorCommand::Stop {
+            slot.stops.send_modify(|stops| *stops += 1);
+            return Ok(());
+        }
+        let (id, command) = (motor.id, motor.command);
+        let reason = match slot.commands.try_send((*slot.stops.borrow(), motor)) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(_)) => "command queue is full",
+            Err(TrySendError::Closed(_)) => "axis task has stopped",
+        };
+        let text = format!("motor {} {:?} dropped: {}", id, command, reason);
+        eprintln!("Device alarm on {}: {}", slot.name, text);
+        ctx.publish(alarm_msg(ctx.source().as_str(), &slot.name, AlarmSeverity::Warning, text));
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        for slot in &mut self.slots {
+            if let Some((stop, task)) = slot.task.take() {
+                let _ = stop.send(());
+                slot.parked = Some(task.await?);
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        let mut problems: Vec<String> =
+            self.problems.lock().unwrap().iter().map(|(name, reason)| format!("{}: {}", name, reason)).collect();
+        for slot in &self.slots {
+            let backlog = slot.backlog();
+            if backlog >= COMMAND_QUEUE_SIZE / 2 {
+                problems.push(format!("{}: {} commands waiting", slot.name, backlog));
+            }
+        }
+        if problems.is_empty() {
+            return Health::Healthy;
+        }
+        Health::Degraded(problems.join(", "))
+    }
+
+    fn rollup(&mut self) {}
+}
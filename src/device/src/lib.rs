@@ -0,0 +1,20 @@
+//! Motion devices for the CSC: the [`Axis`] trait every drive driver
+//! implements, drivers for Modbus, CANopen and simulated drives, and a
+//! subsystem that carries out motor commands on the axis they address.
+//! The CANopen driver is only built on Linux.
+
+mod axis;
+#[cfg(target_os = "linux")]
+mod canopen_axis;
+mod config;
+mod devices;
+mod modbus_axis;
+mod simulated;
+
+pub use axis::*;
+#[cfg(target_os = "linux")]
+pub use canopen_axis::*;
+pub use config::*;
+pub use devices::*;
+pub use modbus_axis::*;
+pub use simulated::*;
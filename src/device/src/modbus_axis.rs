@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use message::MoveDirection;
+use modbus::{connect, encode_value, BlockData, PointConfig, Transport, ValueKind, WordOrder};
+use serde::{Deserialize, Serialize};
+use tokio::time::{error::Elapsed, timeout};
+use tokio_modbus::client::Context as ModbusContext;
+use tokio_modbus::prelude::{Reader, Slave, SlaveContext, Writer};
+
+use crate::axis::{Axis, AxisError, AxisStatus};
+
+/// A drive run through holding registers, as most Modbus servo and stepper
+/// drives are: a command register taking command codes, a target register
+/// pair read by moves, a status register and an actual position pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModbusAxisConfig {
+    pub transport: Transport,
+    pub unit: u8,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Register the command codes are written to.
+    pub command: u16,
+    pub codes: CommandCodes,
+    /// First of the two registers holding the target of a move, as an i32.
+    pub target: u16,
+    /// Register holding the status bits.
+    pub status: u16,
+    #[serde(default)]
+    pub bits: StatusBits,
+    /// First of the two registers holding the actual position, as an i32.
+    pub position: u16,
+    #[serde(default)]
+    pub word_order: WordOrder,
+    /// Axis units per drive position unit.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+/// Values written to the command register. Commands without a code are
+/// not supported by the drive, except relative moves, which are then made
+/// absolute from the actual position.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandCodes {
+    #[serde(default)]
+    pub enable: Option<u16>,
+    #[serde(default)]
+    pub disable: Option<u16>,
+    #[serde(default)]
+    pub stop: Option<u16>,
+    #[serde(default)]
+    pub jog_up: Option<u16>,
+    #[serde(default)]
+    pub jog_down: Option<u16>,
+    #[serde(default)]
+    pub home: Option<u16>,
+    #[serde(default)]
+    pub move_absolute: Option<u16>,
+    #[serde(default)]
+    pub move_relative: Option<u16>,
+}
+
+/// Bit numbers in the status register.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StatusBits {
+    pub enabled: u8,
+    pub moving: u8,
+    pub fault: u8,
+}
+
+impl Default for StatusBits {
+    fn default() -> Self {
+        Self {
+            enabled: 0,
+            moving: 1,
+            fault: 2,
+        }
+    }
+}
+
+fn default_timeout_ms() -> u64 {
+    500
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// Runs a register mapped drive over Modbus RTU or TCP. The link is opened
+/// on first use and again after it fails, each time within `timeout_ms`.
+pub struct ModbusAxis {
+    config: ModbusAxisConfig,
+    link: Option<ModbusContext>,
+}
+
+impl ModbusAxis {
+    pub fn new(config: ModbusAxisConfig) -> Self {
+        Self { config, link: None }
+    }
+
+    async fn link(&mut self) -> Result<&mut ModbusContext, AxisError> {
+        if self.link.is_none() {
+            let ctx = match timeout(self.timeout(), connect(&self.config.transport)).await {
+                Ok(Ok(ctx)) => ctx,
+                Ok(Err(e)) => return Err(AxisError::Link(format!("{}: {}", self.config.transport, e))),
+                Err(_) => {
+                    let reason = format!("{}: not open within {} ms", self.config.transport, self.config.timeout_ms);
+                    return Err(AxisError::Link(reason));
+                }
+            };
+            self.link = Some(ctx);
+        }
+        let ctx = self.link.as_mut().unwrap();
+        ctx.set_slave(Slave(self.config.unit));
+        Ok(ctx)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.config.timeout_ms)
+    }
+
+    fn check<T>(&mut self, result: Result<tokio_modbus::Result<T>, Elapsed>) -> Result<T, AxisError> {
+        match result {
+            Err(_) => Err(AxisError::Link(format!("no response within {} ms", self.config.timeout_ms))),
+            Ok(Ok(Ok(value))) => Ok(value),
+            Ok(Ok(Err(code))) => Err(AxisError::Rejected(format!("exception response {:?}", code))),
+            Ok(Err(tokio_modbus::Error::Protocol(e))) => Err(AxisError::Link(e.to_string())),
+            Ok(Err(tokio_modbus::Error::Transport(e))) => {
+                // 链路断了，下次重新打开
+                self.link = None;
+                Err(AxisError::Link(e.to_string()))
+            }
+        }
+    }
+
+    async fn write(&mut self, address: u16, values: &[u16]) -> Result<(), AxisError> {
+        let after = self.timeout();
+        let ctx = self.link().await?;
+        let result = timeout(after, ctx.write_multiple_registers(address, values)).await;
+        self.check(result)
+    }
+
+    async fn read(&mut self, address: u16, count: u16) -> Result<Vec<u16>, AxisError> {
+        let after = self.timeout();
+        let ctx = self.link().await?;
+        let result = timeout(after, ctx.read_holding_registers(address, count)).await;
+        self.check(result)
+    }
+
+    async fn command(&mut self, code: Option<u16>, what: &'static str) -> Result<(), AxisError> {
+        let code = code.ok_or(AxisError::Unsupported(what))?;
+        self.write(self.config.command, &[code]).await
+    }
+
+    /// Writes the target, then the command code that starts the move.
+    async fn go(&mut self, target: f64, code: Option<u16>, what: &'static str) -> Result<(), AxisError> {
+        let code = code.ok_or(AxisError::Unsupported(what))?;
+        let registers = encode_value(target, ValueKind::I32, self.config.word_order, self.config.scale, 0.0);
+        self.write(self.config.target, &registers).await?;
+        self.write(self.config.command, &[code]).await
+    }
+}
+
+#[async_trait]
+impl Axis for ModbusAxis {
+    async fn enable(&mut self) -> Result<(), AxisError> {
+        self.command(self.config.codes.enable, "enable").await
+    }
+
+    async fn disable(&mut self) -> Result<(), AxisError> {
+        self.command(self.config.codes.disable, "disable").await
+    }
+
+    async fn move_absolute(&mut self, position: f64) -> Result<(), AxisError> {
+        self.go(position, self.config.codes.move_absolute, "absolute moves").await
+    }
+
+    async fn move_relative(&mut self, distance: f64) -> Result<(), AxisError> {
+        if self.config.codes.move_relative.is_some() {
+            return self.go(distance, self.config.codes.move_relative, "relative moves").await;
+        }
+        let status = self.status().await?;
+        self.move_absolute(status.position + distance).await
+    }
+
+    async fn jog(&mut self, direction: MoveDirection) -> Result<(), AxisError> {
+        match direction {
+            MoveDirection::Up => self.command(self.config.codes.jog_up, "jogging up").await,
+            MoveDirection::Down => self.command(self.config.codes.jog_down, "jogging down").await,
+        }
+    }
+
+    async fn stop(&mut self) -> Result<(), AxisError> {
+        self.command(self.config.codes.stop, "stop").await
+    }
+
+    async fn home(&mut self) -> Result<(), AxisError> {
+        self.command(self.config.codes.home, "homing").await
+    }
+
+    async fn status(&mut self) -> Result<AxisStatus, AxisError> {
+        let word = self.read(self.config.status, 1).await?.first().copied();
+        let word = word.ok_or_else(|| AxisError::Link("short status read".to_string()))?;
+        let words = self.read(self.config.position, 2).await?;
+        let point = PointConfig {
+            name: "position".to_string(),
+            offset: 0,
+            kind: ValueKind::I32,
+            word_order: self.config.word_order,
+            scale: self.config.scale,
+            bias: 0.0,
+        };
+        let position = point
+            .read(&BlockData::Words(words))
+            .ok_or_else(|| AxisError::Link("short position read".to_string()))?;
+        let bit = |n: u8| word & (1 << n) != 0;
+        let bits = self.config.bits;
+        Ok(AxisStatus {
+            enabled: bit(bits.enabled),
+            moving: bit(bits.moving),
+            position,
+            fault: bit(bits.fault).then(|| format!("drive fault, status 0x{:04X}", word)),
+        })
+    }
+}
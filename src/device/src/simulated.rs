@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use message::MoveDirection;
+use serde::{Deserialize, Serialize};
+use subsystem::{Clock, SystemClock};
+
+use crate::axis::{Axis, AxisError, AxisStatus};
+
+/// How a [`SimulatedAxis`] moves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulatedConfig {
+    /// Speed of every move, in units per second.
+    pub speed: f64,
+    /// Soft limits; jogs stop there and moves beyond them are refused.
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Position at start.
+    #[serde(default)]
+    pub position: f64,
+}
+
+/// An axis without hardware, moving at constant speed on its clock. Homing
+/// moves it to 0.
+pub struct SimulatedAxis {
+    config: SimulatedConfig,
+    clock: Arc<dyn Clock>,
+    enabled: bool,
+    /// 当前运动的起点、起始时间和终点
+    origin: f64,
+    started: DateTime<Utc>,
+    target: f64,
+}
+
+impl SimulatedAxis {
+    pub fn new(config: SimulatedConfig) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let position = config.position;
+        Self {
+            config,
+            started: clock.now(),
+            clock,
+            enabled: false,
+            origin: position,
+            target: position,
+        }
+    }
+
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        self.with_shared_clock(Arc::new(clock))
+    }
+
+    pub(crate) fn with_shared_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    fn position(&self) -> f64 {
+        let elapsed = (self.clock.now() - self.started).to_std().unwrap_or_default();
+        let travelled = elapsed.as_secs_f64() * self.config.speed;
+        if self.target >= self.origin {
+            (self.origin + travelled).min(self.target)
+        } else {
+            (self.origin - travelled).max(self.target)
+        }
+    }
+
+    fn go(&mut self, target: f64) -> Result<(), AxisError> {
+        if !self.enabled {
+            return Err(AxisError::Rejected("axis is disabled".to_string()));
+        }
+        self.origin = self.position();
+        self.started = self.clock.now();
+        self.target = target;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Axis for SimulatedAxis {
+    async fn enable(&mut self) -> Result<(), AxisError> {
+        self.enabled = true;
+        Ok(())
+    }
+
+    async fn disable(&mut self) -> Result<(), AxisError> {
+        self.stop().await?;
+        self.enabled = false;
+        Ok(())
+    }
+
+    async fn move_absolute(&mut self, position: f64) -> Result<(), AxisError> {
+        let below = self.config.min.is_some_and(|min| position < min);
+        let above = self.config.max.is_some_and(|max| position > max);
+        if below || above {
+            return Err(AxisError::Rejected(format!("{} is outside the limits", position)));
+        }
+        self.go(position)
+    }
+
+    async fn jog(&mut self, direction: MoveDirection) -> Result<(), AxisError> {
+        let end = match direction {
+            MoveDirection::Up => self.config.max.unwrap_or(f64::INFINITY),
+            MoveDirection::Down => self.config.min.unwrap_or(f64::NEG_INFINITY),
+        };
+        self.go(end)
+    }
+
+    async fn stop(&mut self) -> Result<(), AxisError> {
+        self.origin = self.position();
+        self.started = self.clock.now();
+        self.target = self.origin;
+        Ok(())
+    }
+
+    async fn home(&mut self) -> Result<(), AxisError> {
+        self.go(0.0)
+    }
+
+    async fn status(&mut self) -> Result<AxisStatus, AxisError> {
+        let position = self.position();
+        Ok(AxisStatus {
+            enabled: self.enabled,
+            moving: position != self.target,
+            position,
+            fault: None,
+        })
+    }
+}
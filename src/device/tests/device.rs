@@ -0,0 +1,466 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+#[cfg(target_os = "linux")]
+use canopen::{NodeSimulator, VirtualCan};
+#[cfg(target_os = "linux")]
+use device::{CanopenAxis, CanopenAxisConfig};
+use device::{
+    Axis, AxisError, AxisStatus, DeviceConfig, DeviceSystem, ModbusAxis, ModbusAxisConfig, SimulatedAxis,
+    SimulatedConfig,
+};
+use message::{AlarmSeverity, MessageType, MotorCommand, MotorMsg, MoveDirection};
+use modbus::{Simulator, SimulatorConfig, Table};
+use subsystem::{Health, MockClock, SystemId};
+use testkit::{expect, Harness};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::Notify;
+
+/// A lift on motor 1 and a gate on motor 2, both simulated.
+const DEVICES: &str = r#"{
+    "status_interval_ms": 1000,
+    "axes": [
+        { "motor": 1, "name": "lift", "driver": { "simulated": { "speed": 10.0, "min": 0.0, "max": 400.0 } } },
+        { "motor": 2, "name": "gate", "driver": { "simulated": { "speed": 5.0 } } }
+    ]
+}"#;
+
+/// The latest status published for `device` as (position, enabled, moving).
+fn last(harness: &Harness, device: &str) -> Option<(f64, f64, f64)> {
+    let mut telemetry = harness.telemetry("devices");
+    telemetry.retain(|telemetry| telemetry.device == device);
+    let telemetry = telemetry.pop()?;
+    Some((telemetry.values["position"], telemetry.values["enabled"], telemetry.values["moving"]))
+}
+
+fn motor(id: i32, command: MotorCommand) -> MotorMsg {
+    MotorMsg::new(id, MoveDirection::Up).command(command)
+}
+
+#[tokio::test]
+async fn simulated_axis_moves_at_its_speed() {
+    let clock = MockClock::default();
+    let config = SimulatedConfig {
+        speed: 10.0,
+        min: Some(-50.0),
+        max: Some(100.0),
+        position: 0.0,
+    };
+    let mut axis = SimulatedAxis::new(config).with_clock(clock.clone());
+    let disabled = axis.move_absolute(20.0).await;
+    assert_eq!(disabled, Err(AxisError::Rejected("axis is disabled".to_string())));
+
+    axis.enable().await.unwrap();
+    axis.move_absolute(20.0).await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    let status = axis.status().await.unwrap();
+    assert_eq!((status.position, status.moving, status.enabled), (10.0, true, true));
+    clock.advance(Duration::from_secs(5));
+    let status = axis.status().await.unwrap();
+    assert_eq!((status.position, status.moving), (20.0, false));
+
+    axis.move_relative(-5.0).await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(axis.status().await.unwrap().position, 15.0);
+    assert!(matches!(axis.move_absolute(150.0).await, Err(AxisError::Rejected(_))));
+
+    // 点动停在软限位
+    axis.jog(MoveDirection::Up).await.unwrap();
+    clock.advance(Duration::from_secs(60));
+    let status = axis.status().await.unwrap();
+    assert_eq!((status.position, status.moving), (100.0, false));
+    axis.jog(MoveDirection::Down).await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    axis.stop().await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    let status = axis.status().await.unwrap();
+    assert_eq!((status.position, status.moving), (90.0, false));
+
+    axis.home().await.unwrap();
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(axis.status().await.unwrap().position, 0.0);
+    axis.disable().await.unwrap();
+    assert!(!axis.status().await.unwrap().enabled);
+}
+
+#[tokio::test]
+async fn commands_reach_the_axis_of_their_motor() {
+    let config: DeviceConfig = serde_json::from_str(DEVICES).unwrap();
+    config.validate().unwrap();
+    let mut harness = Harness::new();
+    let devices = DeviceSystem::new(config).with_clock(harness.clock());
+    harness.register("devices", devices, vec![MessageType::Move, MessageType::Stop]);
+    harness.start().await;
+    harness.wait_until(|h| last(h, "lift").is_some() && last(h, "gate").is_some()).await;
+    assert_eq!(last(&harness, "lift"), Some((0.0, 0.0, 0.0)));
+    let topic = harness.emitted_of("devices", MessageType::Telemetry)[0].topic().to_string();
+    assert!(topic.starts_with("telemetry/") && topic.ends_with("/axis"), "{}", topic);
+
+    harness.inject_data(MessageType::Move, motor(1, MotorCommand::Enable)).await;
+    harness.inject_data(MessageType::Move, motor(1, MotorCommand::MoveTo).target(30.0)).await;
+    harness.wait_until(|h| last(h, "lift") == Some((0.0, 1.0, 1.0))).await;
+    harness.wait_for_sleepers(2).await;
+    harness.advance(Duration::from_secs(3)).await;
+    harness.wait_until(|h| last(h, "lift") == Some((30.0, 1.0, 0.0))).await;
+
+    harness.inject_data(MessageType::Move, motor(1, MotorCommand::MoveBy).target(-10.0)).await;
+    harness.wait_for_sleepers(2).await;
+    harness.advance(Duration::from_secs(1)).await;
+    harness.wait_until(|h| last(h, "lift") == Some((20.0, 1.0, 0.0))).await;
+
+    harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.wait_for_sleepers(2).await;
+    harness.advance(Duration::from_secs(1)).await;
+    // Stop 消息不管带的是什么指令都是停止
+    harness.inject_data(MessageType::Stop, MotorMsg::new(1, MoveDirection::Up)).await;
+    harness.wait_until(|h| last(h, "lift") == Some((10.0, 1.0, 0.0))).await;
+
+    // 其它电机号留给别的子系统
+    harness.inject_data(MessageType::Move, motor(9, MotorCommand::Enable)).await;
+    assert_eq!(last(&harness, "gate"), Some((0.0, 0.0, 0.0)));
+    assert!(harness.alarms("devices").is_empty());
+
+    harness.inject_data(MessageType::Move, motor(2, MotorCommand::MoveTo).target(5.0)).await;
+    harness.wait_until(|h| !h.alarms("devices").is_empty()).await;
+    let alarm = &harness.alarms("devices")[0];
+    assert_eq!(alarm.severity, AlarmSeverity::Warning);
+    assert_eq!(alarm.text, "motor 2 MoveTo failed: rejected: axis is disabled");
+    assert_eq!(alarm.source, "devices/gate");
+    assert_eq!(harness.emitted_of("devices", MessageType::Alarm)[0].topic(), "alarm/device/gate");
+    harness.stop().await;
+}
+
+/// A driver written for the test: only absolute moves and status, with a
+/// status read that can be made to fail.
+#[derive(Clone, Default)]
+struct Scripted {
+    moves: Arc<Mutex<Vec<f64>>>,
+    broken: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl Axis for Scripted {
+    async fn enable(&mut self) -> Result<(), AxisError> {
+        Ok(())
+    }
+
+    async fn disable(&mut self) -> Result<(), AxisError> {
+        Ok(())
+    }
+
+    async fn move_absolute(&mut self, position: f64) -> Result<(), AxisError> {
+        self.moves.lock().unwrap().push(position);
+        Ok(())
+    }
+
+    async fn jog(&mut self, _direction: MoveDirection) -> Result<(), AxisError> {
+        Err(AxisError::Unsupported("jogging"))
+    }
+
+    async fn stop(&mut self) -> Result<(), AxisError> {
+        Ok(())
+    }
+
+    async fn status(&mut self) -> Result<AxisStatus, AxisError> {
+        if self.broken.load(Ordering::SeqCst) {
+            return Err(AxisError::Link("no response within 500 ms".to_string()));
+        }
+        Ok(AxisStatus {
+            enabled: true,
+            moving: false,
+            position: 10.0,
+            fault: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn a_new_driver_only_implements_the_trait() {
+    let driver = Scripted::default();
+    let config: DeviceConfig = serde_json::from_str(r#"{ "status_interval_ms": 1000, "axes": [] }"#).unwrap();
+    let mut harness = Harness::new();
+    let devices = DeviceSystem::new(config).with_clock(harness.clock()).with_axis(7, "press", driver.clone());
+    harness.register("devices", devices, vec![MessageType::Move, MessageType::Stop]);
+    harness.start().await;
+    harness.wait_until(|h| last(h, "press").is_some()).await;
+
+    // 相对移动默认按实际位置换算成绝对移动
+    harness.inject_data(MessageType::Move, motor(7, MotorCommand::MoveBy).target(5.0)).await;
+    harness.inject_data(MessageType::Move, motor(7, MotorCommand::Home)).await;
+    harness.wait_until(|h| h.alarms("devices").len() == 1).await;
+    assert_eq!(*driver.moves.lock().unwrap(), [15.0]);
+    assert_eq!(harness.alarms("devices")[0].text, "motor 7 Home failed: homing is not supported by the drive");
+
+    driver.broken.store(true, Ordering::SeqCst);
+    for _ in 0..2 {
+        harness.wait_for_sleepers(1).await;
+        harness.advance(Duration::from_secs(1)).await;
+    }
+    harness.wait_until(|h| h.alarms("devices").len() == 2).await;
+    harness.wait_for_sleepers(1).await;
+    let alarm = &harness.alarms("devices")[1];
+    assert_eq!(alarm.text, "reading status failed: link failed: no response within 500 ms");
+    let health = harness.center().health().await;
+    assert!(matches!(&health[&SystemId::from("devices")], Health::Degraded(reason) if reason.starts_with("press: ")));
+
+    driver.broken.store(false, Ordering::SeqCst);
+    harness.advance(Duration::from_secs(1)).await;
+    harness.wait_until(|h| h.alarms("devices").len() == 3).await;
+    let alarm = &harness.alarms("devices")[2];
+    assert_eq!((alarm.severity, alarm.text.as_str()), (AlarmSeverity::Info, "press recovered"));
+    let health = harness.center().health().await;
+    assert_eq!(health[&SystemId::from("devices")], Health::Healthy);
+    harness.stop().await;
+}
+
+/// A driver whose moves only finish when released, counting stops.
+#[derive(Clone, Default)]
+struct Held {
+    moves: Arc<Mutex<Vec<f64>>>,
+    stops: Arc<AtomicUsize>,
+    release: Arc<Notify>,
+}
+
+#[async_trait]
+impl Axis for Held {
+    async fn enable(&mut self) -> Result<(), AxisError> {
+        Ok(())
+    }
+
+    async fn disable(&mut self) -> Result<(), AxisError> {
+        Ok(())
+    }
+
+    async fn move_absolute(&mut self, position: f64) -> Result<(), AxisError> {
+        self.moves.lock().unwrap().push(position);
+        self.release.notified().await;
+        Ok(())
+    }
+
+    async fn jog(&mut self, _direction: MoveDirection) -> Result<(), AxisError> {
+        Err(AxisError::Unsupported("jogging"))
+    }
+
+    async fn stop(&mut self) -> Result<(), AxisError> {
+        self.stops.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn status(&mut self) -> Result<AxisStatus, AxisError> {
+        Ok(AxisStatus::default())
+    }
+}
+
+async fn held(driver: &Held) -> Harness {
+    let config: DeviceConfig = serde_json::from_str(r#"{ "status_interval_ms": 1000, "axes": [] }"#).unwrap();
+    let mut harness = Harness::new();
+    let devices = DeviceSystem::new(config).with_clock(harness.clock()).with_axis(3, "crane", driver.clone());
+    harness.register("devices", devices, vec![MessageType::Move, MessageType::Stop]);
+    harness.start().await;
+    harness.wait_until(|h| last(h, "crane").is_some()).await;
+    harness
+}
+
+#[tokio::test]
+async fn a_stop_cuts_short_the_move_and_drops_queued_ones() {
+    let driver = Held::default();
+    let mut harness = held(&driver).await;
+    let moves = driver.moves.clone();
+
+    harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(10.0)).await;
+    harness.wait_until(|_| *moves.lock().unwrap() == [10.0]).await;
+    harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(20.0)).await;
+    harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(30.0)).await;
+    harness.inject_data(MessageType::Stop, MotorMsg::new(3, MoveDirection::Up)).await;
+    let stops = driver.stops.clone();
+    harness.wait_until(|_| stops.load(Ordering::SeqCst) == 1).await;
+
+    // 急停之后的指令照常执行
+    harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(40.0)).await;
+    harness.wait_until(|_| *moves.lock().unwrap() == [10.0, 40.0]).await;
+    assert!(harness.alarms("devices").is_empty());
+    driver.release.notify_one();
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn a_full_command_queue_drops_commands_and_degrades_health() {
+    let driver = Held::default();
+    let mut harness = held(&driver).await;
+    let moves = driver.moves.clone();
+    harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(0.0)).await;
+    harness.wait_until(|_| moves.lock().unwrap().len() == 1).await;
+
+    for target in 1..=16 {
+        harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(target as f64)).await;
+    }
+    assert!(harness.alarms("devices").is_empty());
+    let health = harness.center().health().await;
+    assert_eq!(health[&SystemId::from("devices")], Health::Degraded("crane: 16 commands waiting".to_string()));
+    harness.inject_data(MessageType::Move, motor(3, MotorCommand::MoveTo).target(17.0)).await;
+    let alarms = harness.alarms("devices");
+    assert_eq!(alarms.len(), 1);
+    assert_eq!(alarms[0].text, "motor 3 MoveTo dropped: command queue is full");
+    assert_eq!(alarms[0].source, "devices/crane");
+
+    harness.inject_data(MessageType::Stop, MotorMsg::new(3, MoveDirection::Up)).await;
+    let stops = driver.stops.clone();
+    harness.wait_until(|_| stops.load(Ordering::SeqCst) == 1).await;
+    harness.settle().await;
+    let health = harness.center().health().await;
+    assert_eq!(health[&SystemId::from("devices")], Health::Healthy);
+    assert_eq!(moves.lock().unwrap().len(), 1);
+    harness.stop().await;
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn canopen_axis_runs_a_cia402_drive() {
+    let bus = VirtualCan::new();
+    let simulator = NodeSimulator::new(2)
+        .object(0x6041, 0, 0x0627u16.to_le_bytes())
+        .object(0x6064, 0, 2500i32.to_le_bytes())
+        .object(0x606C, 0, 0i32.to_le_bytes());
+    let drive = simulator.handle();
+    tokio::spawn(simulator.run(bus.port()));
+    tokio::task::yield_now().await;
+
+    let config: CanopenAxisConfig =
+        serde_json::from_str(r#"{ "interface": "vcan0", "node": 2, "velocity": 3000, "scale": 0.001 }"#).unwrap();
+    let mut axis = CanopenAxis::new(config).with_interface(bus.port());
+    let controlword = |value: u16| (0x6040, 0, value.to_le_bytes().to_vec());
+
+    axis.enable().await.unwrap();
+    assert_eq!(drive.writes(), [controlword(0x06), controlword(0x07), controlword(0x0F)]);
+    axis.move_absolute(1.5).await.unwrap();
+    axis.move_relative(-0.25).await.unwrap();
+    let moves: Vec<_> = drive.writes().into_iter().skip(3).collect();
+    assert_eq!(
+        moves,
+        [
+            (0x6060, 0, vec![1]),
+            (0x607A, 0, 1500i32.to_le_bytes().to_vec()),
+            controlword(0x0F),
+            controlword(0x3F),
+            (0x6060, 0, vec![1]),
+            (0x607A, 0, (-250i32).to_le_bytes().to_vec()),
+            controlword(0x0F),
+            controlword(0x7F),
+        ]
+    );
+    axis.jog(MoveDirection::Down).await.unwrap();
+    let jog: Vec<_> = drive.writes().into_iter().skip(11).collect();
+    assert_eq!(jog, [(0x6060, 0, vec![3]), (0x60FF, 0, (-3000i32).to_le_bytes().to_vec()), controlword(0x0F)]);
+
+    let status = axis.status().await.unwrap();
+    assert_eq!(status, AxisStatus { enabled: true, moving: false, position: 2.5, fault: None });
+    drive.set_object(0x606C, 0, (-3000i32).to_le_bytes());
+    assert!(axis.status().await.unwrap().moving);
+    drive.set_object(0x6041, 0, 0x0008u16.to_le_bytes());
+    let status = axis.status().await.unwrap();
+    assert!(!status.enabled && !status.moving);
+    assert_eq!(status.fault.as_deref(), Some("drive fault, statusword 0x0008"));
+
+    // 节点拒绝写入时是驱动器拒绝，不是链路故障
+    let locked = NodeSimulator::new(3).read_only(0x6040, 0);
+    tokio::spawn(locked.run(bus.port()));
+    tokio::task::yield_now().await;
+    let config: CanopenAxisConfig = serde_json::from_str(r#"{ "interface": "vcan0", "node": 3, "velocity": 3000 }"#).unwrap();
+    let mut axis = CanopenAxis::new(config).with_interface(bus.port());
+    assert!(matches!(axis.enable().await, Err(AxisError::Rejected(_))));
+}
+
+/// Command codes in holding register 0, the target in 2-3, status bits in
+/// 10 and the actual position in 12-13, in 0.01 units.
+const MODBUS_AXIS: &str = r#"{
+    "transport": { "tcp": "127.0.0.1:0" },
+    "unit": 1,
+    "command": 0,
+    "codes": { "enable": 1, "disable": 2, "stop": 3, "jog_up": 4, "jog_down": 5, "move_absolute": 6 },
+    "target": 2,
+    "status": 10,
+    "position": 12,
+    "scale": 0.01
+}"#;
+
+#[tokio::test]
+async fn modbus_axis_writes_command_and_target_registers() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let registers = r#"{ "slaves": [{ "unit": 1, "holding": { "0": [0, 0, 0, 0], "10": [0, 0, 0, 0] } }] }"#;
+    let simulator = Simulator::new(serde_json::from_str::<SimulatorConfig>(registers).unwrap());
+    let drive = simulator.handle();
+    tokio::spawn(simulator.serve(listener));
+    let config: ModbusAxisConfig = serde_json::from_str(&MODBUS_AXIS.replace("127.0.0.1:0", &addr.to_string())).unwrap();
+    let mut axis = ModbusAxis::new(config);
+    let holding = |address| drive.get(1, Table::Holding, address).unwrap();
+
+    axis.enable().await.unwrap();
+    assert_eq!(holding(0), 1);
+    axis.move_absolute(12.5).await.unwrap();
+    assert_eq!((holding(2), holding(3), holding(0)), (0, 1250, 6));
+
+    // 没有相对移动的指令码，按实际位置换算
+    drive.set(1, Table::Holding, 10, &[0b011, 0, 0, 500]);
+    axis.move_relative(-2.0).await.unwrap();
+    assert_eq!((holding(3), holding(0)), (300, 6));
+    let status = axis.status().await.unwrap();
+    assert_eq!(status, AxisStatus { enabled: true, moving: true, position: 5.0, fault: None });
+
+    axis.jog(MoveDirection::Down).await.unwrap();
+    assert_eq!(holding(0), 5);
+    assert_eq!(axis.home().await, Err(AxisError::Unsupported("homing")));
+    drive.set(1, Table::Holding, 10, &[0b100]);
+    assert_eq!(axis.status().await.unwrap().fault.as_deref(), Some("drive fault, status 0x0004"));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn modbus_axis_gives_up_opening_the_link_after_its_timeout() {
+    // 积压队列满了的监听端口不再应答握手
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _queued = TcpStream::connect(addr).await.unwrap();
+    let mut config: ModbusAxisConfig =
+        serde_json::from_str(&MODBUS_AXIS.replace("127.0.0.1:0", &addr.to_string())).unwrap();
+    config.timeout_ms = 100;
+    let mut axis = ModbusAxis::new(config);
+    let status = expect(axis.status()).await;
+    assert_eq!(status, Err(AxisError::Link(format!("{}: not open within 100 ms", addr))));
+}
+
+#[test]
+fn bad_configs_are_rejected() {
+    let bad = [
+        r#"{ "status_interval_ms": 0, "axes": [] }"#,
+        r#"{ "axes": [{ "motor": 1, "name": "", "driver": { "simulated": { "speed": 1.0 } } }] }"#,
+        r#"{ "axes": [
+            { "motor": 1, "name": "a", "driver": { "simulated": { "speed": 1.0 } } },
+            { "motor": 1, "name": "b", "driver": { "simulated": { "speed": 1.0 } } }
+        ] }"#,
+        r#"{ "axes": [
+            { "motor": 1, "name": "a", "driver": { "simulated": { "speed": 1.0 } } },
+            { "motor": 2, "name": "a", "driver": { "simulated": { "speed": 1.0 } } }
+        ] }"#,
+        r#"{ "axes": [{ "motor": 1, "name": "a", "driver": { "simulated": { "speed": 0.0 } } }] }"#,
+        r#"{ "axes": [{ "motor": 1, "name": "a", "driver": { "simulated": { "speed": 1.0, "min": 5.0, "max": 1.0 } } }] }"#,
+    ];
+    for json in bad {
+        let config: DeviceConfig = serde_json::from_str(json).unwrap();
+        assert!(config.validate().is_err(), "{}", json);
+    }
+    let unknown = r#"{ "axes": [{ "motor": 1, "name": "a", "driver": { "stepper": {} } }] }"#;
+    assert!(serde_json::from_str::<DeviceConfig>(unknown).is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn bad_canopen_config_is_rejected() {
+    let json = r#"{ "axes": [{ "motor": 1, "name": "a", "driver": { "canopen": { "interface": "can0", "node": 0, "velocity": 1 } } }] }"#;
+    let config: DeviceConfig = serde_json::from_str(json).unwrap();
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,21 @@
+//! Wire framing shared by every stream transport: one JSON encoded `Msg` per line.
+
+use crate::Msg;
+
+pub const FRAME_DELIMITER: u8 = b'\n';
+
+/// Longest frame a transport accepts from a peer, delimiter excluded.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Encodes `msg` as a single frame, delimiter included.
+pub fn encode_frame(msg: &Msg) -> serde_json::Result<Vec<u8>> {
+    let mut frame = serde_json::to_vec(msg)?;
+    frame.push(FRAME_DELIMITER);
+    Ok(frame)
+}
+
+/// Decodes one frame, with or without its delimiter.
+pub fn decode_frame(frame: &[u8]) -> serde_json::Result<Msg> {
+    let frame = frame.strip_suffix(&[FRAME_DELIMITER]).unwrap_or(frame);
+    serde_json::from_slice(frame)
+}
@@ -1,8 +1,12 @@
 ﻿
+mod frame;
 mod msg;
 mod message;
 mod message_build;
+mod msg_queue;
 
+pub use frame::{*};
 pub use message::{*};
 pub use message_build::{*};
-pub use msg::{*};
\ No newline at end of file
+pub use msg::{*};
+pub use msg_queue::{*};
\ No newline at end of file
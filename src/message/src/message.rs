@@ -1,15 +1,29 @@
 ﻿pub(crate) mod quit;
 mod motor;
+mod subscribe;
+mod alarm;
+mod schedule;
+mod connection;
+mod telemetry;
+mod can;
+mod setpoint;
 
 
 pub use quit::{*};
 pub use motor::{*};
+pub use subscribe::{*};
+pub use alarm::{*};
+pub use schedule::{*};
+pub use connection::{*};
+pub use telemetry::{*};
+pub use can::{*};
+pub use setpoint::{*};
 
 
 use std::fmt::Debug;
 
 
-pub trait Message:Debug + Send {
+pub trait Message:Debug + Send + Sync {
     fn encode(&self) -> Vec<u8>;
     fn decode(data: &[u8]) -> Option<Self> where Self: Sized;
 }
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlarmSeverity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// Sent with `MessageType::Alarm`, usually on a topic under `alarm/`,
+/// e.g. `alarm/subsystem/tcp` when a subsystem gives up restarting.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct AlarmMsg {
+    /// 触发告警的子系统或设备
+    pub source: String,
+    pub severity: AlarmSeverity,
+    pub text: String,
+}
+
+impl Message for AlarmMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl AlarmMsg {
+    pub fn new(source: impl Into<String>, severity: AlarmSeverity, text: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            severity,
+            text: text.into(),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// Sent with `MessageType::CanFrame`: one classic CAN frame as seen on or
+/// sent to a bus.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct CanFrameMsg {
+    /// 11 位标准帧 id，`extended` 时为 29 位
+    pub id: u32,
+    #[serde(default)]
+    pub extended: bool,
+    /// At most 8 bytes.
+    pub data: Vec<u8>,
+}
+
+impl Message for CanFrameMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl CanFrameMsg {
+    pub fn new(id: u32, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            id,
+            extended: false,
+            data: data.into(),
+        }
+    }
+}
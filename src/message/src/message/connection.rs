@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    #[default]
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Sent with `MessageType::Connection` by a transport whenever its link changes
+/// state, on the topic `connection/{id}`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ConnectionMsg {
+    /// 对端地址
+    pub peer: String,
+    pub state: LinkState,
+    /// Why the link went down.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Message for ConnectionMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl ConnectionMsg {
+    pub fn new(peer: impl Into<String>, state: LinkState, reason: Option<String>) -> Self {
+        Self {
+            peer: peer.into(),
+            state,
+            reason,
+        }
+    }
+}
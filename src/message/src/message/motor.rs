@@ -5,18 +5,40 @@ use super::Message;
 
 
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
 pub enum MoveDirection {
     #[default]
     Up,
+    Down,
 }
 
+/// 电机指令，旧消息没有该字段时按 Move 处理
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MotorCommand {
+    #[default]
+    Move,
+    Stop,
+    Enable,
+    Disable,
+    /// Run the homing sequence to find the reference position.
+    Home,
+    /// Move to the absolute position in `target`.
+    MoveTo,
+    /// Move by the distance in `target`; negative distances move down.
+    MoveBy,
+}
 
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct MotorMsg {
-    id: i32,
-    direction:MoveDirection,
+    pub id: i32,
+    pub direction:MoveDirection,
+    #[serde(default)]
+    pub command: MotorCommand,
+    /// 仅 MoveTo 和 MoveBy 使用，单位由设备配置决定
+    #[serde(default)]
+    pub target: f64,
 }
 
 impl Message for MotorMsg {
@@ -33,8 +55,20 @@ impl Message for MotorMsg {
 impl MotorMsg {
     pub fn new(id:i32, direction:MoveDirection) -> Self {
         Self {
-            id: 0,
-            direction: MoveDirection::Up,
+            id,
+            direction,
+            command: MotorCommand::Move,
+            target: 0.0,
         }
     }
+
+    pub fn command(mut self, command: MotorCommand) -> Self {
+        self.command = command;
+        self
+    }
+
+    pub fn target(mut self, target: f64) -> Self {
+        self.target = target;
+        self
+    }
 }
\ No newline at end of file
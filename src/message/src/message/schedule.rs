@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+use crate::Msg;
+
+/// When a scheduled message is published.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum ScheduleKind {
+    /// Once, after a delay.
+    Once { delay_ms: u64 },
+    /// Repeatedly, the first time one period from now.
+    Every { period_ms: u64 },
+    /// Cron expression with seconds, e.g. `0 */5 * * * *` (UTC).
+    Cron { expr: String },
+}
+
+/// Sent with `MessageType::Schedule` to have the scheduler publish `msg` later.
+/// Scheduling under a name that is already in use replaces that schedule.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScheduleMsg {
+    pub name: String,
+    pub kind: ScheduleKind,
+    pub msg: Msg,
+}
+
+impl Message for ScheduleMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl ScheduleMsg {
+    pub fn new(name: impl Into<String>, kind: ScheduleKind, msg: Msg) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            msg,
+        }
+    }
+}
+
+/// Sent with `MessageType::Unschedule` to cancel a schedule by name.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UnscheduleMsg {
+    pub name: String,
+}
+
+impl Message for UnscheduleMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl UnscheduleMsg {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// Sent with `MessageType::Setpoint`: asks for a new target value on a
+/// device, in engineering units like telemetry.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct SetpointMsg {
+    pub device: String,
+    pub name: String,
+    pub value: f64,
+}
+
+impl Message for SetpointMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl SetpointMsg {
+    pub fn new(device: impl Into<String>, name: impl Into<String>, value: f64) -> Self {
+        Self {
+            device: device.into(),
+            name: name.into(),
+            value,
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// Sent with `MessageType::Subscribe` / `MessageType::Unsubscribe` by a remote client
+/// to choose which topics it receives, e.g. `motor/3/#`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SubscribeMsg {
+    pub filters: Vec<String>,
+}
+
+impl Message for SubscribeMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl SubscribeMsg {
+    pub fn new(filters: Vec<String>) -> Self {
+        Self { filters }
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// Sent with `MessageType::Telemetry`: named readings from one device taken
+/// at the same time, already scaled to engineering units.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct TelemetryMsg {
+    pub device: String,
+    /// 同一次采样的一组数据，如一个寄存器块
+    pub group: String,
+    pub values: BTreeMap<String, f64>,
+}
+
+impl Message for TelemetryMsg {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+impl TelemetryMsg {
+    pub fn new(device: impl Into<String>, group: impl Into<String>) -> Self {
+        Self {
+            device: device.into(),
+            group: group.into(),
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub fn value(mut self, name: impl Into<String>, value: f64) -> Self {
+        self.values.insert(name.into(), value);
+        self
+    }
+}
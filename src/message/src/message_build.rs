@@ -2,12 +2,14 @@
 use serde::Serialize;
 use uuid::Uuid;
 
-use crate::{msg::Msg, Message, MessageType, MsgInfo};
+use crate::{msg::Msg, Message, MessageType, MsgInfo, Priority};
 
 pub struct MsgBuilder {
     info: Option<MsgInfo>,
     data: Option<Box<dyn Message>>,
     row_data: Option<Vec<u8>>,
+    priority: Option<Priority>,
+    topic: Option<String>,
 }
 
 impl MsgBuilder {
@@ -16,6 +18,8 @@ impl MsgBuilder {
             info: None,
             data: None,
             row_data: None,
+            priority: None,
+            topic: None,
         }
     }
 
@@ -24,13 +28,30 @@ impl MsgBuilder {
         self
     }
 
+    /// Overrides the default priority of the message type.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
     pub fn data(mut self, data: Box<dyn Message>) -> Self {
         self.data = Some(data);
         self
     }
 
     pub fn build(self) -> Result<Msg, &'static str> {
-        let info = self.info.ok_or("info is required")?;
+        let mut info = self.info.ok_or("info is required")?;
+        if let Some(priority) = self.priority {
+            info.priority = priority;
+        }
+        if let Some(topic) = self.topic {
+            info.topic = Some(topic);
+        }
         let data = self.data.ok_or("data is required")?;
 
         Ok(Msg {
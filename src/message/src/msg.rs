@@ -1,4 +1,5 @@
-﻿use std::fmt::{self, Debug};
+﻿use std::borrow::Cow;
+use std::fmt::{self, Debug};
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::de::{self, MapAccess};
@@ -17,17 +18,148 @@ pub enum MessageType {
     Quit,
     Move,
     Join,
+    Stop,
+    Subscribe,
+    Unsubscribe,
+    Alarm,
+    Schedule,
+    Unschedule,
+    Heartbeat,
+    Connection,
+    Telemetry,
+    CanFrame,
+    Setpoint,
+}
+
+impl MessageType {
+    /// 未指定 topic 时使用的默认 topic
+    pub fn topic(&self) -> &'static str {
+        match self {
+            MessageType::None => "none",
+            MessageType::Quit => "quit",
+            MessageType::Move => "move",
+            MessageType::Join => "join",
+            MessageType::Stop => "stop",
+            MessageType::Subscribe => "subscribe",
+            MessageType::Unsubscribe => "unsubscribe",
+            MessageType::Alarm => "alarm",
+            MessageType::Schedule => "schedule",
+            MessageType::Unschedule => "unschedule",
+            MessageType::Heartbeat => "heartbeat",
+            MessageType::Connection => "connection",
+            MessageType::Telemetry => "telemetry",
+            MessageType::CanFrame => "can",
+            MessageType::Setpoint => "setpoint",
+        }
+    }
+
+    /// 停止类消息默认走最高优先级
+    pub fn default_priority(&self) -> Priority {
+        match self {
+            MessageType::Quit | MessageType::Stop => Priority::Critical,
+            MessageType::Alarm => Priority::High,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// 按 topic 名称解析，如 `move`
+impl std::str::FromStr for MessageType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut value = 0u64;
+        while let Ok(msg_type) = MessageType::try_from(value) {
+            if msg_type.topic().eq_ignore_ascii_case(s) {
+                return Ok(msg_type);
+            }
+            value += 1;
+        }
+        Err(format!("unknown message type `{}`", s))
+    }
+}
+
+/// Higher priorities are always serviced before lower ones.
+#[derive(Debug, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Critical,
+}
+
+impl Priority {
+    pub const COUNT: usize = 4;
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(from = "WireInfo")]
 pub struct MsgInfo {
     pub msg_type: MessageType,
     pub uid: Uuid,
+    pub priority: Priority,
+    /// 发出该消息的子系统
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// 只投递给该子系统，None 表示按类型分发
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// 回复所对应的请求 uid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Uuid>,
+    /// 分级 topic，如 `motor/3/status`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+/// [`MsgInfo`] as decoded. Frames from clients that predate priorities
+/// carry none and get the default of their type, so their stops are still
+/// expedited.
+#[derive(Deserialize)]
+struct WireInfo {
+    msg_type: MessageType,
+    uid: Uuid,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    reply_to: Option<Uuid>,
+    #[serde(default)]
+    topic: Option<String>,
+}
+
+impl From<WireInfo> for MsgInfo {
+    fn from(wire: WireInfo) -> Self {
+        let priority = wire.priority.unwrap_or_else(|| wire.msg_type.default_priority());
+        Self {
+            msg_type: wire.msg_type,
+            uid: wire.uid,
+            priority,
+            source: wire.source,
+            target: wire.target,
+            reply_to: wire.reply_to,
+            topic: wire.topic,
+        }
+    }
 }
 
 impl MsgInfo {
     pub fn new(msg_type:MessageType) -> Self {
-        Self { msg_type, uid: Uuid::new_v4() }
+        let priority = msg_type.default_priority();
+        Self {
+            msg_type,
+            uid: Uuid::new_v4(),
+            priority,
+            source: None,
+            target: None,
+            reply_to: None,
+            topic: None,
+        }
     }
 }
 
@@ -47,6 +179,21 @@ impl Debug for Msg {
     }
 }
 
+// data 无法直接克隆，克隆时编码进 row_data，与序列化后的结果一致
+impl Clone for Msg {
+    fn clone(&self) -> Self {
+        let row_data = match &self.data {
+            Some(data) => Some(data.encode()),
+            None => self.row_data.clone(),
+        };
+        Msg {
+            info: self.info.clone(),
+            data: None,
+            row_data,
+        }
+    }
+}
+
 impl Serialize for Msg {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -143,7 +290,8 @@ impl<'de> Deserialize<'de> for Msg {
                             if row_data.is_some() {
                                 return Err(de::Error::duplicate_field("row_data"));
                             }
-                            row_data = Some(map.next_value()?);
+                            // 不带数据的消息序列化为 null
+                            row_data = Some(map.next_value::<Option<Vec<u8>>>()?);
                         }
                     }
                 }
@@ -154,7 +302,7 @@ impl<'de> Deserialize<'de> for Msg {
                 Ok(Msg {
                     info,
                     data,
-                    row_data: Some(row_data),
+                    row_data,
                 })
             }
         }
@@ -173,7 +321,27 @@ impl Msg {
     }
 
     pub fn set_msg_type(&mut self, msg_type: MessageType) {
-        self.info = MsgInfo::default();
+        self.info = MsgInfo::new(msg_type);
+    }
+
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.info.priority = priority;
+    }
+
+    pub fn get_priority(&self) -> Priority {
+        self.info.priority
+    }
+
+    pub fn set_topic(&mut self, topic: impl Into<String>) {
+        self.info.topic = Some(topic.into());
+    }
+
+    /// The explicit topic, or the message type's default topic.
+    pub fn topic(&self) -> Cow<'_, str> {
+        match &self.info.topic {
+            Some(topic) => Cow::Borrowed(topic),
+            None => Cow::Borrowed(self.info.msg_type.topic()),
+        }
     }
 
     pub fn set_data<T: Message + 'static>(&mut self, data: T) {
@@ -194,7 +362,10 @@ impl Msg {
     }
 
     pub fn get_data<T: Message + for<'de> Deserialize<'de>>(&self) -> Option<T> {
-        if let Some(data) = &self.row_data {
+        // 进程内通过总线传递的消息还没有序列化，row_data 为空
+        if let Some(data) = &self.data {
+            serde_json::from_slice(&data.encode()).ok()
+        } else if let Some(data) = &self.row_data {
             serde_json::from_slice(data).ok()
         } else {
             None
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::{Msg, Priority};
+
+/// Anything that can be ordered by a [`MsgQueue`].
+pub trait Prioritized {
+    fn priority(&self) -> Priority;
+}
+
+impl Prioritized for Msg {
+    fn priority(&self) -> Priority {
+        self.get_priority()
+    }
+}
+
+impl<T: Prioritized> Prioritized for Arc<T> {
+    fn priority(&self) -> Priority {
+        (**self).priority()
+    }
+}
+
+/// A queue that always pops the highest priority first, FIFO within a priority.
+#[derive(Debug)]
+pub struct MsgQueue<T = Msg> {
+    levels: [VecDeque<T>; Priority::COUNT],
+    len: usize,
+}
+
+impl<T: Prioritized> MsgQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            levels: Default::default(),
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        let level = u8::from(item.priority()) as usize;
+        self.levels[level].push_back(item);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self
+            .levels
+            .iter_mut()
+            .rev()
+            .find_map(|level| level.pop_front())?;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Removes the oldest item of the lowest priority queued.
+    pub fn pop_oldest_lowest(&mut self) -> Option<T> {
+        let item = self.levels.iter_mut().find_map(|level| level.pop_front())?;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Removes the newest item of the lowest priority queued.
+    pub fn pop_newest_lowest(&mut self) -> Option<T> {
+        let item = self.levels.iter_mut().find_map(|level| level.pop_back())?;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Lowest priority currently queued.
+    pub fn lowest_priority(&self) -> Option<Priority> {
+        self.levels
+            .iter()
+            .find_map(|level| level.front().map(|item| item.priority()))
+    }
+
+    /// Keeps only the items for which `f` returns true, preserving order.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        for level in self.levels.iter_mut() {
+            level.retain(&mut f);
+        }
+        self.len = self.levels.iter().map(VecDeque::len).sum();
+    }
+
+    /// Returns the priority of the item that would be popped next.
+    pub fn peek_priority(&self) -> Option<Priority> {
+        self.levels
+            .iter()
+            .rev()
+            .find_map(|level| level.front().map(|item| item.priority()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.levels.iter_mut().for_each(VecDeque::clear);
+        self.len = 0;
+    }
+}
+
+impl<T: Prioritized> Default for MsgQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Prioritized> Extend<T> for MsgQueue<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_frame, MessageType};
+
+    fn msg(msg_type: MessageType, topic: &str) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_type(msg_type);
+        msg.set_topic(topic);
+        msg
+    }
+
+    fn topics(queue: &mut MsgQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop()).map(|msg| msg.topic().into_owned()).collect()
+    }
+
+    #[test]
+    fn stops_outrank_queued_traffic() {
+        let mut queue = MsgQueue::new();
+        queue.push(msg(MessageType::Move, "move/1"));
+        queue.push(msg(MessageType::Telemetry, "telemetry/1"));
+        queue.push(msg(MessageType::Alarm, "alarm/1"));
+        queue.push(msg(MessageType::Stop, "stop/1"));
+        queue.push(msg(MessageType::Quit, "quit"));
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.peek_priority(), Some(Priority::Critical));
+        assert_eq!(topics(&mut queue), ["stop/1", "quit", "alarm/1", "move/1", "telemetry/1"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn fifo_within_a_priority() {
+        let mut queue = MsgQueue::new();
+        for n in 0..3 {
+            queue.push(msg(MessageType::Move, &format!("move/{}", n)));
+            queue.push(msg(MessageType::Stop, &format!("stop/{}", n)));
+        }
+        let mut low = msg(MessageType::Move, "move/low");
+        low.set_priority(Priority::Low);
+        queue.push(low);
+        assert_eq!(queue.lowest_priority(), Some(Priority::Low));
+        assert_eq!(queue.pop_oldest_lowest().unwrap().topic(), "move/low");
+        assert_eq!(queue.pop_newest_lowest().unwrap().topic(), "move/2");
+        assert_eq!(topics(&mut queue), ["stop/0", "stop/1", "stop/2", "move/0", "move/1"]);
+    }
+
+    #[test]
+    fn frames_without_priority_get_the_type_default() {
+        let stop = br#"{"info":{"msg_type":"Stop","uid":"6f1c2a4e-9b1d-4c55-8a53-0f4d2f0c9e11"},"row_data":null}"#;
+        let stop = decode_frame(stop).unwrap();
+        assert_eq!(stop.get_priority(), Priority::Critical);
+        let moved = br#"{"info":{"msg_type":"Move","uid":"6f1c2a4e-9b1d-4c55-8a53-0f4d2f0c9e12"},"row_data":null}"#;
+        assert_eq!(decode_frame(moved).unwrap().get_priority(), Priority::Normal);
+
+        // 显式给出的优先级保持不变
+        let low = br#"{"info":{"msg_type":"Stop","uid":"6f1c2a4e-9b1d-4c55-8a53-0f4d2f0c9e13","priority":"Low"},"row_data":null}"#;
+        assert_eq!(decode_frame(low).unwrap().get_priority(), Priority::Low);
+
+        let mut queue = MsgQueue::new();
+        queue.push(msg(MessageType::Move, "move/1"));
+        queue.push(stop);
+        assert_eq!(queue.pop().unwrap().get_msg_type(), MessageType::Stop);
+    }
+}
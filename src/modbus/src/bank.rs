@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+
+use tokio_modbus::{ExceptionCode, Request, Response};
+
+use crate::config::Table;
+
+/// The four tables of one slave. Only addresses that have been set exist;
+/// anything else is answered with `IllegalDataAddress`, as a real device would.
+/// Bits are kept as 0 and 1.
+#[derive(Debug, Clone, Default)]
+pub struct RegisterBank {
+    tables: HashMap<Table, BTreeMap<u16, u16>>,
+}
+
+impl RegisterBank {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates or overwrites `values.len()` entries starting at `address`.
+    pub fn set(&mut self, table: Table, address: u16, values: &[u16]) {
+        let entries = self.tables.entry(table).or_default();
+        for (address, value) in (address..).zip(values) {
+            let value = if table.is_bits() { (*value != 0) as u16 } else { *value };
+            entries.insert(address, value);
+        }
+    }
+
+    pub fn get(&self, table: Table, address: u16) -> Option<u16> {
+        self.tables.get(&table)?.get(&address).copied()
+    }
+
+    pub fn read(&self, table: Table, address: u16, count: u16) -> Result<Vec<u16>, ExceptionCode> {
+        (0..count)
+            .map(|i| {
+                address
+                    .checked_add(i)
+                    .and_then(|address| self.get(table, address))
+                    .ok_or(ExceptionCode::IllegalDataAddress)
+            })
+            .collect()
+    }
+
+    /// Writes only to addresses that exist, all or nothing.
+    pub fn write(&mut self, table: Table, address: u16, values: &[u16]) -> Result<(), ExceptionCode> {
+        self.read(table, address, values.len() as u16)?;
+        self.set(table, address, values);
+        Ok(())
+    }
+
+    /// Answers a request the way a plain slave does. Writes to input tables
+    /// and other functions are `IllegalFunction`.
+    pub fn handle(&mut self, request: Request<'_>) -> Result<Response, ExceptionCode> {
+        match request {
+            Request::ReadCoils(address, count) => Ok(Response::ReadCoils(bits(self.read(Table::Coil, address, count)?))),
+            Request::ReadDiscreteInputs(address, count) => {
+                Ok(Response::ReadDiscreteInputs(bits(self.read(Table::Discrete, address, count)?)))
+            }
+            Request::ReadInputRegisters(address, count) => {
+                Ok(Response::ReadInputRegisters(self.read(Table::Input, address, count)?))
+            }
+            Request::ReadHoldingRegisters(address, count) => {
+                Ok(Response::ReadHoldingRegisters(self.read(Table::Holding, address, count)?))
+            }
+            Request::WriteSingleCoil(address, value) => {
+                self.write(Table::Coil, address, &[value as u16])?;
+                Ok(Response::WriteSingleCoil(address, value))
+            }
+            Request::WriteMultipleCoils(address, values) => {
+                let words: Vec<u16> = values.iter().map(|value| *value as u16).collect();
+                self.write(Table::Coil, address, &words)?;
+                Ok(Response::WriteMultipleCoils(address, words.len() as u16))
+            }
+            Request::WriteSingleRegister(address, value) => {
+                self.write(Table::Holding, address, &[value])?;
+                Ok(Response::WriteSingleRegister(address, value))
+            }
+            Request::WriteMultipleRegisters(address, values) => {
+                self.write(Table::Holding, address, &values)?;
+                Ok(Response::WriteMultipleRegisters(address, values.len() as u16))
+            }
+            _ => Err(ExceptionCode::IllegalFunction),
+        }
+    }
+}
+
+/// Table, first address and count a request touches.
+pub fn target(request: &Request<'_>) -> Option<(Table, u16, u16)> {
+    match request {
+        Request::ReadCoils(address, count) => Some((Table::Coil, *address, *count)),
+        Request::ReadDiscreteInputs(address, count) => Some((Table::Discrete, *address, *count)),
+        Request::ReadInputRegisters(address, count) => Some((Table::Input, *address, *count)),
+        Request::ReadHoldingRegisters(address, count) => Some((Table::Holding, *address, *count)),
+        Request::WriteSingleCoil(address, _) => Some((Table::Coil, *address, 1)),
+        Request::WriteMultipleCoils(address, values) => Some((Table::Coil, *address, values.len() as u16)),
+        Request::WriteSingleRegister(address, _) => Some((Table::Holding, *address, 1)),
+        Request::WriteMultipleRegisters(address, values) => Some((Table::Holding, *address, values.len() as u16)),
+        _ => None,
+    }
+}
+
+fn bits(words: Vec<u16>) -> Vec<bool> {
+    words.into_iter().map(|word| word != 0).collect()
+}
@@ -0,0 +1,33 @@
+//! Runs a Modbus TCP slave simulator for trying masters without hardware.
+//!
+//! `modbus-sim sim.json --listen 127.0.0.1:5020`
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+use modbus::{Simulator, SimulatorConfig};
+use tokio::net::TcpListener;
+
+#[derive(Parser)]
+#[command(about = "Simulate Modbus TCP slaves")]
+struct Cli {
+    /// Slaves, registers, script and exceptions, see `SimulatorConfig`.
+    config: PathBuf,
+    /// Address to accept masters on.
+    #[arg(long, default_value = "127.0.0.1:5020")]
+    listen: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let config = SimulatorConfig::load(&cli.config).map_err(|e| e.to_string())?;
+    let listener = TcpListener::bind(cli.listen).await?;
+    println!("Simulating {} slaves on {}", config.slaves.len(), cli.listen);
+    tokio::select! {
+        result = Simulator::new(config).serve(listener) => result?,
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
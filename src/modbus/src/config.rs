@@ -0,0 +1,226 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use message::{MotorCommand, MoveDirection};
+use serde::{Deserialize, Serialize};
+use subsystem::{BoxError, SerialConfig};
+
+/// What a Modbus master polls and how it maps registers, read from a JSON file:
+///
+/// ```json
+/// {
+///   "transport": { "rtu": { "path": "/dev/ttyUSB0", "baud_rate": 19200, "parity": "Even" } },
+///   "timeout_ms": 500,
+///   "slaves": [{
+///     "unit": 1,
+///     "name": "drive1",
+///     "blocks": [{
+///       "name": "status", "table": "holding", "address": 0, "count": 4, "interval_ms": 1000,
+///       "points": [
+///         { "name": "speed", "offset": 0, "kind": "i32", "word_order": "low_first", "scale": 0.1 },
+///         { "name": "current", "offset": 2, "kind": "u16", "scale": 0.01 }
+///       ]
+///     }],
+///     "motors": [{
+///       "motor": 1,
+///       "commands": [
+///         { "command": "Enable", "writes": [{ "coil": { "address": 0, "value": true } }] },
+///         { "command": "Move", "direction": "Down", "writes": [{ "holding": { "address": 10, "values": [2] } }] }
+///       ]
+///     }]
+///   }]
+/// }
+/// ```
+///
+/// For Modbus TCP the transport is `{ "tcp": "192.168.0.10:502" }`; `unit`
+/// then addresses devices behind a gateway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModbusConfig {
+    pub transport: Transport,
+    /// 单次请求等待应答的时间
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub slaves: Vec<SlaveConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Rtu(SerialConfig),
+    Tcp(SocketAddr),
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Rtu(port) => write!(f, "{}", port.path),
+            Transport::Tcp(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaveConfig {
+    /// Modbus unit id on the line.
+    pub unit: u8,
+    /// Used as the telemetry device and in topics.
+    pub name: String,
+    #[serde(default)]
+    pub blocks: Vec<BlockConfig>,
+    #[serde(default)]
+    pub motors: Vec<MotorMapping>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Table {
+    Coil,
+    Discrete,
+    Input,
+    Holding,
+}
+
+impl Table {
+    pub fn is_bits(self) -> bool {
+        matches!(self, Table::Coil | Table::Discrete)
+    }
+}
+
+/// A run of registers or bits read in one request and published as one
+/// telemetry message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockConfig {
+    pub name: String,
+    pub table: Table,
+    pub address: u16,
+    pub count: u16,
+    pub interval_ms: u64,
+    pub points: Vec<PointConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueKind {
+    Bool,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl ValueKind {
+    /// Registers or bits the value takes up.
+    pub fn width(self) -> u16 {
+        match self {
+            ValueKind::Bool | ValueKind::U16 | ValueKind::I16 => 1,
+            ValueKind::U32 | ValueKind::I32 | ValueKind::F32 => 2,
+        }
+    }
+}
+
+/// Order of the two registers of a 32-bit value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WordOrder {
+    #[default]
+    HighFirst,
+    LowFirst,
+}
+
+/// One named value inside a block: `raw * scale + bias`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointConfig {
+    pub name: String,
+    /// Registers or bits from the start of the block.
+    pub offset: u16,
+    pub kind: ValueKind,
+    #[serde(default)]
+    pub word_order: WordOrder,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub bias: f64,
+}
+
+/// Register writes that carry out motor commands for one motor id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotorMapping {
+    pub motor: i32,
+    pub commands: Vec<CommandMapping>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandMapping {
+    pub command: MotorCommand,
+    /// Only for moves in this direction; any direction if not given.
+    #[serde(default)]
+    pub direction: Option<MoveDirection>,
+    /// Done in order, stopping at the first failure.
+    pub writes: Vec<RegisterWrite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterWrite {
+    Coil { address: u16, value: bool },
+    Holding { address: u16, values: Vec<u16> },
+}
+
+fn default_timeout_ms() -> u64 {
+    500
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl ModbusConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that every point fits its block and every block is polled.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        for slave in &self.slaves {
+            for block in &slave.blocks {
+                let at = format!("{}/{}", slave.name, block.name);
+                if block.interval_ms == 0 {
+                    return Err(format!("{}: interval must be greater than zero", at).into());
+                }
+                if block.count == 0 {
+                    return Err(format!("{}: count must be greater than zero", at).into());
+                }
+                for point in &block.points {
+                    if block.table.is_bits() != (point.kind == ValueKind::Bool) {
+                        return Err(format!("{}: {} does not match a {:?} table", at, point.name, block.table).into());
+                    }
+                    if point.offset as u32 + point.kind.width() as u32 > block.count as u32 {
+                        return Err(format!("{}: {} is outside the block", at, point.name).into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SlaveConfig {
+    /// Writes for `command` on `motor`, if it is mapped on this slave.
+    pub fn writes_for(&self, motor: i32, command: MotorCommand, direction: MoveDirection) -> Option<&[RegisterWrite]> {
+        self.motors
+            .iter()
+            .filter(|mapping| mapping.motor == motor)
+            .flat_map(|mapping| &mapping.commands)
+            .find(|mapping| {
+                mapping.command == command
+                    && (command != MotorCommand::Move || mapping.direction.is_none_or(|d| d == direction))
+            })
+            .map(|mapping| mapping.writes.as_slice())
+    }
+}
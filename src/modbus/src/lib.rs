@@ -0,0 +1,17 @@
+//! Modbus for the CSC: a master subsystem that maps registers to telemetry
+//! and motor commands, a slave that serves CSC state to PLCs, both over RTU
+//! or TCP, and a slave simulator to test masters with.
+
+mod bank;
+mod config;
+mod master;
+mod simulator;
+mod slave;
+mod value;
+
+pub use bank::*;
+pub use config::*;
+pub use master::*;
+pub use simulator::*;
+pub use slave::*;
+pub use value::*;
@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use message::{AlarmMsg, AlarmSeverity, LinkState, MessageType, MotorCommand, MotorMsg, Msg, MsgBuilder, TelemetryMsg};
+use subsystem::{set_link_state, Backoff, BoxError, Clock, Context, Health, Publisher, SubSystem, SystemClock};
+use tokio::sync::{
+    mpsc::{channel, error::TrySendError, Receiver, Sender},
+    oneshot,
+};
+use tokio::task::JoinHandle;
+use tokio::time::{error::Elapsed, timeout};
+use tokio_modbus::client::{rtu, tcp, Context as ModbusContext};
+use tokio_modbus::prelude::{Reader, Slave, SlaveContext, Writer};
+use tokio_modbus::ExceptionCode;
+
+use crate::config::{BlockConfig, ModbusConfig, RegisterWrite, SlaveConfig, Table, Transport};
+use crate::value::BlockData;
+
+/// Why a request got no usable answer.
+enum Fault {
+    Timeout(Duration),
+    Exception(ExceptionCode),
+    /// 校验失败或应答格式不对，链路本身还在
+    Corrupt(String),
+    /// The port or connection itself failed and has to be reopened.
+    Link(io::Error),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Timeout(after) => write!(f, "no response within {} ms", after.as_millis()),
+            Fault::Exception(code) => write!(f, "exception response {:?}", code),
+            Fault::Corrupt(reason) => write!(f, "corrupt response (CRC or framing): {}", reason),
+            Fault::Link(e) => write!(f, "link failed: {}", e),
+        }
+    }
+}
+
+fn classify<T>(result: Result<tokio_modbus::Result<T>, Elapsed>, after: Duration) -> Result<T, Fault> {
+    match result {
+        Err(_) => Err(Fault::Timeout(after)),
+        Ok(Ok(Ok(value))) => Ok(value),
+        Ok(Ok(Err(code))) => Err(Fault::Exception(code)),
+        Ok(Err(tokio_modbus::Error::Protocol(e))) => Err(Fault::Corrupt(e.to_string())),
+        Ok(Err(tokio_modbus::Error::Transport(e))) if e.kind() == io::ErrorKind::InvalidData => {
+            Err(Fault::Corrupt(e.to_string()))
+        }
+        Ok(Err(tokio_modbus::Error::Transport(e))) => Err(Fault::Link(e)),
+    }
+}
+
+async fn read_block(
+    ctx: &mut ModbusContext,
+    unit: u8,
+    block: &BlockConfig,
+    after: Duration,
+) -> Result<BlockData, Fault> {
+    ctx.set_slave(Slave(unit));
+    let (address, count) = (block.address, block.count);
+    match block.table {
+        Table::Coil => classify(timeout(after, ctx.read_coils(address, count)).await, after).map(BlockData::Bits),
+        Table::Discrete => {
+            classify(timeout(after, ctx.read_discrete_inputs(address, count)).await, after).map(BlockData::Bits)
+        }
+        Table::Input => {
+            classify(timeout(after, ctx.read_input_registers(address, count)).await, after).map(BlockData::Words)
+        }
+        Table::Holding => {
+            classify(timeout(after, ctx.read_holding_registers(address, count)).await, after).map(BlockData::Words)
+        }
+    }
+}
+
+async fn write_one(ctx: &mut ModbusContext, unit: u8, write: &RegisterWrite, after: Duration) -> Result<(), Fault> {
+    ctx.set_slave(Slave(unit));
+    match write {
+        RegisterWrite::Coil { address, value } => {
+            classify(timeout(after, ctx.write_single_coil(*address, *value)).await, after)
+        }
+        RegisterWrite::Holding { address, values } if values.len() == 1 => {
+            classify(timeout(after, ctx.write_single_register(*address, values[0])).await, after)
+        }
+        RegisterWrite::Holding { address, values } => {
+            classify(timeout(after, ctx.write_multiple_registers(*address, values)).await, after)
+        }
+    }
+}
+
+/// Opens a client link over `transport`.
+pub async fn connect(transport: &Transport) -> io::Result<ModbusContext> {
+    match transport {
+        Transport::Rtu(port) => Ok(rtu::attach(port.open()?)),
+        Transport::Tcp(addr) => tcp::connect(*addr).await,
+    }
+}
+
+/// Commands waiting for the poll task. Past this new commands are dropped with an alarm.
+const COMMAND_QUEUE_SIZE: usize = 64;
+
+/// Stops the poll task, which hands the command queue back.
+type PollTask = (oneshot::Sender<()>, JoinHandle<Receiver<MotorMsg>>);
+
+/// A Modbus master on one serial line (RTU) or one TCP connection.
+///
+/// Polls every configured register block on its own interval and publishes
+/// the mapped values as a [`TelemetryMsg`] on `telemetry/{slave}/{block}`.
+/// [`MotorMsg`] commands routed to it are carried out as the register writes
+/// mapped for that motor. Timeouts, CRC errors and exception responses are
+/// published as alarms on `alarm/modbus/{slave}`; a failing block alarms once
+/// and again when it recovers. If the link fails it is reopened with backoff;
+/// commands still queued when it failed are dropped with an alarm.
+pub struct ModbusMaster {
+    config: Arc<ModbusConfig>,
+    backoff: Backoff,
+    clock: Arc<dyn Clock>,
+    commands: Sender<MotorMsg>,
+    pending: Option<Receiver<MotorMsg>>,
+    state: Arc<Mutex<LinkState>>,
+    poller: Option<PollTask>,
+}
+
+impl ModbusMaster {
+    pub fn new(config: ModbusConfig) -> Self {
+        let (commands, pending) = channel(COMMAND_QUEUE_SIZE);
+        Self {
+            config: Arc::new(config),
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(10)),
+            clock: Arc::new(SystemClock),
+            commands,
+            pending: Some(pending),
+            state: Arc::new(Mutex::new(LinkState::Connecting)),
+            poller: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Delay between attempts to reopen the link. Defaults to 1 s doubling up to 10 s.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+/// The poll task.
+struct Poller {
+    config: Arc<ModbusConfig>,
+    backoff: Backoff,
+    clock: Arc<dyn Clock>,
+    state: Arc<Mutex<LinkState>>,
+    publisher: Publisher,
+    timeout: Duration,
+    /// 每个块下次采集的时间，按 (从站, 块) 下标
+    due: Vec<((usize, usize), DateTime<Utc>)>,
+    /// 正在告警的块及原因
+    faults: HashMap<String, String>,
+}
+
+impl Poller {
+    async fn run(mut self, mut commands: Receiver<MotorMsg>, mut stop: oneshot::Receiver<()>) -> Receiver<MotorMsg> {
+        let mut attempt = 0;
+        loop {
+            let connected = tokio::select! {
+                _ = &mut stop => break,
+                connected = connect(&self.config.transport) => connected,
+            };
+            let reason = match connected {
+                Ok(mut ctx) => {
+                    attempt = 0;
+                    // 断线前一刻入队的指令也不能在新连接上补发
+                    self.discard(&mut commands, "link was reopened").await;
+                    self.set_state(LinkState::Connected, None).await;
+                    let result = tokio::select! {
+                        _ = &mut stop => break,
+                        result = self.session(&mut ctx, &mut commands) => result,
+                    };
+                    let reason = match result {
+                        Ok(()) => "stopped".to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    eprintln!("Modbus link {} lost: {}", self.config.transport, reason);
+                    self.set_state(LinkState::Disconnected, Some(reason)).await;
+                    self.discard(&mut commands, "link was lost").await;
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            };
+
+            eprintln!("Failed to open Modbus link {}: {}", self.config.transport, reason);
+            self.set_state(LinkState::Disconnected, Some(reason)).await;
+            let delay = self.backoff.delay(attempt);
+            attempt = attempt.saturating_add(1);
+            tokio::select! {
+                _ = &mut stop => break,
+                _ = self.clock.sleep(delay) => {}
+            }
+        }
+        *self.state.lock().unwrap() = LinkState::Disconnected;
+        self.discard(&mut commands, "master stopped").await;
+        commands
+    }
+
+    async fn set_state(&self, state: LinkState, reason: Option<String>) {
+        set_link_state(&self.state, &self.publisher, self.config.transport.to_string(), state, reason).await;
+    }
+
+    /// Drops every queued command with an alarm, so none is carried out late.
+    async fn discard(&self, commands: &mut Receiver<MotorMsg>, reason: &str) {
+        while let Ok(motor) = commands.try_recv() {
+            let text = format!("motor {} {:?} dropped: {}", motor.id, motor.command, reason);
+            eprintln!("Modbus alarm: {}", text);
+            let source = self.publisher.source().as_str().to_string();
+            let alarm = AlarmMsg::new(&source, command_severity(motor.command), text);
+            self.publisher.publish(alarm_msg(&source, alarm)).await;
+        }
+    }
+
+    /// Polls and writes until the link fails. Every block is read right away.
+    async fn session(
+        &mut self,
+        ctx: &mut ModbusContext,
+        commands: &mut Receiver<MotorMsg>,
+    ) -> io::Result<()> {
+        let now = self.clock.now();
+        for (_, deadline) in &mut self.due {
+            *deadline = now;
+        }
+        loop {
+            let next = self.due.iter().map(|(_, deadline)| *deadline).min();
+            tokio::select! {
+                // 指令优先于轮询
+                biased;
+                command = commands.recv() => match command {
+                    Some(command) => self.command(ctx, command).await?,
+                    None => return Ok(()),
+                },
+                _ = sleep_until(&*self.clock, next) => self.poll_due(ctx).await?,
+            }
+        }
+    }
+
+    async fn poll_due(&mut self, ctx: &mut ModbusContext) -> io::Result<()> {
+        let config = self.config.clone();
+        let now = self.clock.now();
+        for index in 0..self.due.len() {
+            let ((slave, block), deadline) = self.due[index];
+            if deadline > now {
+                continue;
+            }
+            let slave = &config.slaves[slave];
+            let block = &slave.blocks[block];
+            let interval = Duration::from_millis(block.interval_ms);
+            // 落后时不补采，从现在重新计
+            let next = deadline + interval;
+            self.due[index].1 = if next > now { next } else { now + interval };
+
+            let key = format!("{}/{}", slave.name, block.name);
+            match read_block(ctx, slave.unit, block, self.timeout).await {
+                Ok(data) => {
+                    self.publish_telemetry(block.telemetry(&slave.name, &data)).await;
+                    if self.faults.remove(&key).is_some() {
+                        let text = format!("{} recovered", key);
+                        self.alarm(slave, AlarmSeverity::Info, text).await;
+                    }
+                }
+                Err(Fault::Link(e)) => return Err(e),
+                Err(fault) => {
+                    let reason = fault.to_string();
+                    if self.faults.get(&key) != Some(&reason) {
+                        let text = format!("reading {} failed: {}", key, reason);
+                        self.faults.insert(key, reason);
+                        self.alarm(slave, AlarmSeverity::Warning, text).await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Does the writes mapped for `motor` on every slave that maps it.
+    async fn command(&mut self, ctx: &mut ModbusContext, motor: MotorMsg) -> io::Result<()> {
+        let config = self.config.clone();
+        let mut mapped = false;
+        for slave in &config.slaves {
+            let Some(writes) = slave.writes_for(motor.id, motor.command, motor.direction) else {
+                continue;
+            };
+            mapped = true;
+            for write in writes {
+                let Err(fault) = write_one(ctx, slave.unit, write, self.timeout).await else {
+                    continue;
+                };
+                let text = format!("motor {} {:?} failed: {}", motor.id, motor.command, fault);
+                self.alarm(slave, command_severity(motor.command), text).await;
+                if let Fault::Link(e) = fault {
+                    return Err(e);
+                }
+                break;
+            }
+        }
+        if !mapped {
+            eprintln!("No register mapping for motor {} {:?}", motor.id, motor.command);
+        }
+        Ok(())
+    }
+
+    async fn publish_telemetry(&self, telemetry: TelemetryMsg) {
+        let msg = MsgBuilder::new()
+            .msg_type(MessageType::Telemetry)
+            .topic(format!("telemetry/{}/{}", telemetry.device, telemetry.group))
+            .data(Box::new(telemetry))
+            .build()
+            .unwrap();
+        self.publisher.publish(msg).await;
+    }
+
+    async fn alarm(&self, slave: &SlaveConfig, severity: AlarmSeverity, text: String) {
+        eprintln!("Modbus alarm on {}: {}", slave.name, text);
+        let source = format!("{}/{}", self.publisher.source(), slave.name);
+        let msg = alarm_msg(&slave.name, AlarmMsg::new(source, severity, text));
+        self.publisher.publish(msg).await;
+    }
+}
+
+/// 丢掉或没执行的停止比移动严重
+fn command_severity(command: MotorCommand) -> AlarmSeverity {
+    match command {
+        MotorCommand::Stop => AlarmSeverity::Critical,
+        _ => AlarmSeverity::Warning,
+    }
+}
+
+fn alarm_msg(slave: &str, alarm: AlarmMsg) -> Msg {
+    MsgBuilder::new()
+        .msg_type(MessageType::Alarm)
+        .topic(format!("alarm/modbus/{}", slave))
+        .data(Box::new(alarm))
+        .build()
+        .unwrap()
+}
+
+async fn sleep_until(clock: &dyn Clock, deadline: Option<DateTime<Utc>>) {
+    match deadline {
+        Some(deadline) => clock.sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[async_trait]
+impl SubSystem for ModbusMaster {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let commands = self.pending.take().ok_or("command queue was lost when the poll task failed")?;
+        let due = self
+            .config
+            .slaves
+            .iter()
+            .enumerate()
+            .flat_map(|(slave, config)| (0..config.blocks.len()).map(move |block| (slave, block)))
+            .map(|index| (index, self.clock.now()))
+            .collect();
+        let poller = Poller {
+            config: self.config.clone(),
+            backoff: self.backoff,
+            clock: self.clock.clone(),
+            state: self.state.clone(),
+            publisher: ctx.publisher(),
+            timeout: Duration::from_millis(self.config.timeout_ms),
+            due,
+            faults: HashMap::new(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.poller = Some((stop, tokio::spawn(poller.run(commands, stopped))));
+        Ok(())
+    }
+
+    /// Queues the [`MotorMsg`] carried by a move or stop for the poll task.
+    /// Commands that arrive while the link is down or the queue is full are
+    /// dropped with an alarm rather than carried out late.
+    async fn exec(&mut self, msg: &Msg, ctx: &mut Context) -> Result<(), BoxError> {
+        if !matches!(msg.get_msg_type(), MessageType::Move | MessageType::Stop) {
+            return Ok(());
+        }
+        let Some(mut motor) = msg.get_data::<MotorMsg>() else {
+            return Ok(());
+        };
+        if msg.get_msg_type() == MessageType::Stop {
+            motor.command = MotorCommand::Stop;
+        }
+        let (id, command) = (motor.id, motor.command);
+        let reason = if *self.state.lock().unwrap() != LinkState::Connected {
+            format!("{} is not open", self.config.transport)
+        } else {
+            match self.commands.try_send(motor) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(_)) => "command queue is full".to_string(),
+                Err(TrySendError::Closed(_)) => "poll task has stopped".to_string(),
+            }
+        };
+        let text = format!("motor {} {:?} dropped: {}", id, command, reason);
+        let source = ctx.source().as_str().to_string();
+        let alarm = alarm_msg(&source, AlarmMsg::new(&source, command_severity(command), text));
+        ctx.publish(alarm);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.poller.take() {
+            let _ = stop.send(());
+            self.pending = Some(task.await?);
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &*self.state.lock().unwrap() {
+            LinkState::Connected => Health::Healthy,
+            _ => Health::Degraded(format!("Modbus link {} not open", self.config.transport)),
+        }
+    }
+
+    fn rollup(&mut self) {}
+}
@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use subsystem::{BoxError, Clock, SystemClock};
+use tokio::net::TcpListener;
+use tokio_modbus::server::tcp::{accept_tcp_connection, Server};
+use tokio_modbus::server::Service;
+use tokio_modbus::{ExceptionCode, Request, Response, SlaveRequest};
+
+use crate::bank::{target, RegisterBank};
+use crate::config::Table;
+
+/// Slaves a [`Simulator`] answers for, read from a JSON file:
+///
+/// ```json
+/// {
+///   "slaves": [{
+///     "unit": 1,
+///     "holding": { "0": [0, 0, 150], "10": [0] },
+///     "coils": { "0": [0] },
+///     "script": [
+///       { "after_ms": 0, "every_ms": 1000, "table": "holding", "address": 2, "change": { "add": 10 } }
+///     ],
+///     "exceptions": [
+///       { "table": "holding", "address": 2, "exception": "server_device_busy", "times": 3 }
+///     ]
+///   }]
+/// }
+/// ```
+///
+/// Table entries map a first address to consecutive values.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimulatorConfig {
+    pub slaves: Vec<SimSlaveConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimSlaveConfig {
+    pub unit: u8,
+    #[serde(default)]
+    pub coils: BTreeMap<u16, Vec<u16>>,
+    #[serde(default)]
+    pub discrete: BTreeMap<u16, Vec<u16>>,
+    #[serde(default)]
+    pub input: BTreeMap<u16, Vec<u16>>,
+    #[serde(default)]
+    pub holding: BTreeMap<u16, Vec<u16>>,
+    #[serde(default)]
+    pub script: Vec<ScriptStep>,
+    #[serde(default)]
+    pub exceptions: Vec<Injection>,
+}
+
+/// A scripted change to one register, once after `after_ms` and then every
+/// `every_ms` if given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptStep {
+    #[serde(default)]
+    pub after_ms: u64,
+    #[serde(default)]
+    pub every_ms: Option<u64>,
+    pub table: Table,
+    pub address: u16,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    /// Sets consecutive registers from `address`.
+    Set(Vec<u16>),
+    /// Adds to the register, wrapping around.
+    Add(i16),
+}
+
+/// Answers requests touching `count` entries from `address` with an exception,
+/// `times` times or until cleared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Injection {
+    pub table: Table,
+    pub address: u16,
+    #[serde(default = "one")]
+    pub count: u16,
+    pub exception: Exception,
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+impl Injection {
+    pub fn new(table: Table, address: u16, exception: Exception) -> Self {
+        Self {
+            table,
+            address,
+            count: 1,
+            exception,
+            times: None,
+        }
+    }
+
+    pub fn times(mut self, times: u32) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn hits(&self, table: Table, address: u16, count: u16) -> bool {
+        let (start, end) = (address as u32, address as u32 + count as u32);
+        let (from, to) = (self.address as u32, self.address as u32 + self.count as u32);
+        self.table == table && start < to && from < end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exception {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Acknowledge,
+    ServerDeviceBusy,
+    GatewayPathUnavailable,
+    GatewayTargetDevice,
+}
+
+impl From<Exception> for ExceptionCode {
+    fn from(exception: Exception) -> Self {
+        match exception {
+            Exception::IllegalFunction => ExceptionCode::IllegalFunction,
+            Exception::IllegalDataAddress => ExceptionCode::IllegalDataAddress,
+            Exception::IllegalDataValue => ExceptionCode::IllegalDataValue,
+            Exception::ServerDeviceFailure => ExceptionCode::ServerDeviceFailure,
+            Exception::Acknowledge => ExceptionCode::Acknowledge,
+            Exception::ServerDeviceBusy => ExceptionCode::ServerDeviceBusy,
+            Exception::GatewayPathUnavailable => ExceptionCode::GatewayPathUnavailable,
+            Exception::GatewayTargetDevice => ExceptionCode::GatewayTargetDevice,
+        }
+    }
+}
+
+fn one() -> u16 {
+    1
+}
+
+impl SimulatorConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimSlave {
+    bank: RegisterBank,
+    injections: Vec<Injection>,
+}
+
+impl SimSlave {
+    fn handle(&mut self, request: Request<'_>) -> Result<Response, ExceptionCode> {
+        if let Some((table, address, count)) = target(&request) {
+            if let Some(index) = self.injections.iter().position(|i| i.hits(table, address, count)) {
+                let injection = &mut self.injections[index];
+                let exception = injection.exception;
+                if let Some(times) = &mut injection.times {
+                    *times -= 1;
+                    if *times == 0 {
+                        self.injections.remove(index);
+                    }
+                }
+                return Err(exception.into());
+            }
+        }
+        self.bank.handle(request)
+    }
+}
+
+type Slaves = Arc<Mutex<HashMap<u8, SimSlave>>>;
+
+/// Looks at and changes a running [`Simulator`] from tests.
+#[derive(Debug, Clone)]
+pub struct SimulatorHandle {
+    slaves: Slaves,
+}
+
+impl SimulatorHandle {
+    pub fn get(&self, unit: u8, table: Table, address: u16) -> Option<u16> {
+        self.slaves.lock().unwrap().get(&unit)?.bank.get(table, address)
+    }
+
+    /// Sets registers, adding the slave if it isn't simulated yet.
+    pub fn set(&self, unit: u8, table: Table, address: u16, values: &[u16]) {
+        let mut slaves = self.slaves.lock().unwrap();
+        slaves.entry(unit).or_default().bank.set(table, address, values);
+    }
+
+    pub fn inject(&self, unit: u8, injection: Injection) {
+        let mut slaves = self.slaves.lock().unwrap();
+        slaves.entry(unit).or_default().injections.push(injection);
+    }
+
+    pub fn clear_injections(&self, unit: u8) {
+        if let Some(slave) = self.slaves.lock().unwrap().get_mut(&unit) {
+            slave.injections.clear();
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SimService {
+    slaves: Slaves,
+}
+
+impl Service for SimService {
+    type Request = SlaveRequest<'static>;
+    type Response = Response;
+    type Exception = ExceptionCode;
+    type Future = std::future::Ready<Result<Response, ExceptionCode>>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        let mut slaves = self.slaves.lock().unwrap();
+        // 网关后面没有这个设备
+        let response = match slaves.get_mut(&request.slave) {
+            Some(slave) => slave.handle(request.request),
+            None => Err(ExceptionCode::GatewayTargetDevice),
+        };
+        std::future::ready(response)
+    }
+}
+
+/// An in-process Modbus TCP slave for testing masters without hardware.
+///
+/// Answers for every configured unit from its own [`RegisterBank`], changes
+/// registers as scripted and fails requests with injected exceptions. Units
+/// that aren't simulated get `GatewayTargetDevice`.
+pub struct Simulator {
+    slaves: Slaves,
+    script: Vec<(u8, ScriptStep)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        let mut slaves = HashMap::new();
+        let mut script = Vec::new();
+        for config in config.slaves {
+            let mut slave = SimSlave::default();
+            for (table, entries) in [
+                (Table::Coil, &config.coils),
+                (Table::Discrete, &config.discrete),
+                (Table::Input, &config.input),
+                (Table::Holding, &config.holding),
+            ] {
+                for (address, values) in entries {
+                    slave.bank.set(table, *address, values);
+                }
+            }
+            slave.injections = config.exceptions;
+            script.extend(config.script.into_iter().map(|step| (config.unit, step)));
+            slaves.insert(config.unit, slave);
+        }
+        Self {
+            slaves: Arc::new(Mutex::new(slaves)),
+            script,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Clock the script runs on.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn handle(&self) -> SimulatorHandle {
+        SimulatorHandle {
+            slaves: self.slaves.clone(),
+        }
+    }
+
+    /// Serves connections on `listener` and runs the script until dropped.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let script = tokio::spawn(run_script(self.slaves.clone(), self.clock, self.script));
+        let _script = AbortOnDrop(script);
+        let slaves = self.slaves;
+        let on_connected = |stream, addr| {
+            let slaves = slaves.clone();
+            async move {
+                accept_tcp_connection(stream, addr, move |_| {
+                    Ok(Some(SimService {
+                        slaves: slaves.clone(),
+                    }))
+                })
+            }
+        };
+        let on_process_error = |e| eprintln!("Simulator failed to process request: {}", e);
+        Server::new(listener).serve(&on_connected, on_process_error).await
+    }
+}
+
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn run_script(slaves: Slaves, clock: Arc<dyn Clock>, script: Vec<(u8, ScriptStep)>) {
+    let start = clock.now();
+    let mut due: Vec<Option<DateTime<Utc>>> = script
+        .iter()
+        .map(|(_, step)| Some(start + Duration::from_millis(step.after_ms)))
+        .collect();
+    loop {
+        let Some(next) = due.iter().flatten().min().copied() else {
+            return;
+        };
+        clock.sleep_until(next).await;
+        let now = clock.now();
+        let mut slaves = slaves.lock().unwrap();
+        for ((unit, step), deadline) in script.iter().zip(&mut due) {
+            if !deadline.is_some_and(|deadline| deadline <= now) {
+                continue;
+            }
+            let bank = &mut slaves.entry(*unit).or_default().bank;
+            match &step.change {
+                Change::Set(values) => bank.set(step.table, step.address, values),
+                Change::Add(delta) => {
+                    let value = bank.get(step.table, step.address).unwrap_or(0);
+                    bank.set(step.table, step.address, &[value.wrapping_add_signed(*delta)]);
+                }
+            }
+            *deadline = step.every_ms.map(|every| now + Duration::from_millis(every.max(1)));
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use message::{MessageType, MotorCommand, MotorMsg, MoveDirection, Msg, MsgBuilder, TelemetryMsg};
+use serde::{Deserialize, Serialize};
+use subsystem::{BoxError, Context, Health, Publisher, SerialConfig, SubSystem};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_modbus::server::{rtu, tcp, Service};
+use tokio_modbus::{ExceptionCode, Request, Response, SlaveRequest};
+use tokio_serial::SerialStream;
+
+use crate::bank::{target, RegisterBank};
+use crate::config::{Table, ValueKind, WordOrder};
+use crate::value::encode_value;
+
+/// The register map a [`ModbusSlave`] serves, read from a JSON file:
+///
+/// ```json
+/// {
+///   "listen": { "tcp": "0.0.0.0:502" },
+///   "unit": 1,
+///   "inputs": [
+///     { "device": "drive1", "group": "status", "point": "position", "address": 0, "kind": "i32", "scale": 0.01 }
+///   ],
+///   "commands": [{
+///     "address": 100, "motor": 1,
+///     "values": [
+///       { "value": 0, "command": "Stop" },
+///       { "value": 1, "command": "Move", "direction": "Up" },
+///       { "value": 2, "command": "Move", "direction": "Down" }
+///     ]
+///   }],
+///   "access": [
+///     { "table": "holding", "address": 100, "count": 1, "access": "read_write", "clients": ["10.0.0.5"] },
+///     { "table": "holding", "address": 100, "count": 1, "access": "read" }
+///   ]
+/// }
+/// ```
+///
+/// Over RTU `listen` is `{ "rtu": { "path": ..., "baud_rate": ... } }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaveServerConfig {
+    pub listen: Listen,
+    /// 只应答这个站号，TCP 下忽略
+    #[serde(default = "default_unit")]
+    pub unit: u8,
+    #[serde(default)]
+    pub inputs: Vec<InputMapping>,
+    #[serde(default)]
+    pub commands: Vec<CommandRegister>,
+    #[serde(default)]
+    pub access: Vec<AccessRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Listen {
+    Rtu(SerialConfig),
+    Tcp(SocketAddr),
+}
+
+/// A telemetry value mirrored into input registers, or a discrete input for
+/// `bool`. The register holds `(value - bias) / scale`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputMapping {
+    pub device: String,
+    pub group: String,
+    pub point: String,
+    pub address: u16,
+    pub kind: ValueKind,
+    #[serde(default)]
+    pub word_order: WordOrder,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub bias: f64,
+}
+
+impl InputMapping {
+    fn table(&self) -> Table {
+        match self.kind {
+            ValueKind::Bool => Table::Discrete,
+            _ => Table::Input,
+        }
+    }
+}
+
+/// A holding register that turns values written to it into motor commands.
+/// Values not listed are refused with `IllegalDataValue`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRegister {
+    pub address: u16,
+    pub motor: i32,
+    pub values: Vec<CommandValue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandValue {
+    pub value: u16,
+    pub command: MotorCommand,
+    #[serde(default)]
+    pub direction: MoveDirection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    None,
+    Read,
+    ReadWrite,
+}
+
+/// Who may read or write `count` entries from `address`. The first rule that
+/// covers an address and the client decides; without one, mapped inputs and
+/// command registers are only readable, so writing commands needs a
+/// `read_write` rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRule {
+    pub table: Table,
+    pub address: u16,
+    #[serde(default = "default_count")]
+    pub count: u16,
+    pub access: Access,
+    /// Client addresses the rule applies to, every client if empty. Rules with
+    /// clients never apply over RTU.
+    #[serde(default)]
+    pub clients: Vec<IpAddr>,
+}
+
+impl AccessRule {
+    fn covers(&self, table: Table, address: u16, client: Option<IpAddr>) -> bool {
+        let end = self.address as u32 + self.count as u32;
+        self.table == table
+            && (self.address as u32..end).contains(&(address as u32))
+            && (self.clients.is_empty() || client.is_some_and(|client| self.clients.contains(&client)))
+    }
+}
+
+fn default_unit() -> u8 {
+    1
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_count() -> u16 {
+    1
+}
+
+impl SlaveServerConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// Every mapped address set to zero.
+    fn bank(&self) -> RegisterBank {
+        let mut bank = RegisterBank::new();
+        for input in &self.inputs {
+            bank.set(input.table(), input.address, &vec![0; input.kind.width() as usize]);
+        }
+        for command in &self.commands {
+            bank.set(Table::Holding, command.address, &[0]);
+        }
+        bank
+    }
+
+    fn allows(&self, table: Table, address: u16, write: bool, client: Option<IpAddr>) -> bool {
+        let access = match self.access.iter().find(|rule| rule.covers(table, address, client)) {
+            Some(rule) => rule.access,
+            None => Access::Read,
+        };
+        match access {
+            Access::None => false,
+            Access::Read => !write,
+            Access::ReadWrite => true,
+        }
+    }
+
+    fn command(&self, address: u16, value: u16) -> Option<Result<MotorMsg, ExceptionCode>> {
+        let register = self.commands.iter().find(|command| command.address == address)?;
+        let motor = register
+            .values
+            .iter()
+            .find(|mapping| mapping.value == value)
+            .map(|mapping| MotorMsg::new(register.motor, mapping.direction).command(mapping.command))
+            .ok_or(ExceptionCode::IllegalDataValue);
+        Some(motor)
+    }
+}
+
+struct Shared {
+    config: SlaveServerConfig,
+    bank: Mutex<RegisterBank>,
+    publisher: Publisher,
+}
+
+impl Shared {
+    fn handle(&self, request: Request<'_>, client: Option<IpAddr>) -> Result<Response, ExceptionCode> {
+        let (table, address, count) = target(&request).ok_or(ExceptionCode::IllegalFunction)?;
+        let written: Vec<(u16, u16)> = match &request {
+            Request::WriteSingleRegister(address, value) => vec![(*address, *value)],
+            Request::WriteMultipleRegisters(address, values) => (*address..).zip(values.iter().copied()).collect(),
+            Request::WriteSingleCoil(..) | Request::WriteMultipleCoils(..) => Vec::new(),
+            _ => {
+                let readable = (0..count).all(|i| {
+                    address
+                        .checked_add(i)
+                        .is_some_and(|address| self.config.allows(table, address, false, client))
+                });
+                if !readable {
+                    return Err(ExceptionCode::IllegalDataAddress);
+                }
+                return self.bank.lock().unwrap().handle(request);
+            }
+        };
+        if !(0..count).all(|i| {
+            address
+                .checked_add(i)
+                .is_some_and(|address| self.config.allows(table, address, true, client))
+        }) {
+            return Err(ExceptionCode::IllegalDataAddress);
+        }
+        // 先检查所有写入的值，全部有效才生效
+        let mut motors = Vec::new();
+        for (address, value) in written {
+            if let Some(motor) = self.config.command(address, value) {
+                motors.push(motor?);
+            }
+        }
+        let response = self.bank.lock().unwrap().handle(request)?;
+        let mut busy = false;
+        for motor in motors {
+            let msg_type = match motor.command {
+                MotorCommand::Stop => MessageType::Stop,
+                _ => MessageType::Move,
+            };
+            let msg = MsgBuilder::new().msg_type(msg_type).data(Box::new(motor)).build().unwrap();
+            // 指令没人接收时要让主站知道并重试
+            if self.publisher.try_publish(msg) == 0 {
+                busy = true;
+            }
+        }
+        if busy {
+            return Err(ExceptionCode::ServerDeviceBusy);
+        }
+        Ok(response)
+    }
+}
+
+#[derive(Clone)]
+struct SlaveService {
+    shared: Arc<Shared>,
+    client: Option<IpAddr>,
+}
+
+impl Service for SlaveService {
+    type Request = SlaveRequest<'static>;
+    type Response = Option<Response>;
+    type Exception = ExceptionCode;
+    type Future = std::future::Ready<Result<Option<Response>, ExceptionCode>>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        // RTU 总线上其他站号的请求不应答，广播只执行不应答
+        let rtu = self.client.is_none();
+        let unit = self.shared.config.unit;
+        if rtu && request.slave != unit && request.slave != 0 {
+            return std::future::ready(Ok(None));
+        }
+        let response = self.shared.handle(request.request, self.client);
+        if rtu && request.slave == 0 {
+            return std::future::ready(Ok(None));
+        }
+        std::future::ready(response.map(Some))
+    }
+}
+
+/// Serves CSC state to PLCs and HMIs as a Modbus slave, over TCP or RTU.
+///
+/// Register it for `MessageType::Telemetry`: mapped telemetry values are kept
+/// in input registers and discrete inputs. Writes to command registers are
+/// published as [`MotorMsg`] moves or stops; a write whose command nobody
+/// took is answered with `ServerDeviceBusy` so the master retries it. Requests
+/// outside the map, or refused by an [`AccessRule`], are answered with
+/// `IllegalDataAddress`.
+pub struct ModbusSlave {
+    shared: Option<Arc<Shared>>,
+    config: SlaveServerConfig,
+    listener: Option<TcpListener>,
+    server: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl ModbusSlave {
+    pub fn new(config: SlaveServerConfig) -> Self {
+        Self {
+            shared: None,
+            config,
+            listener: None,
+            server: None,
+        }
+    }
+
+    /// Serves on an already bound listener instead of `listen`, e.g. port 0 in
+    /// tests. Only for the first start; a restart binds `listen`.
+    pub fn with_listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    async fn serve(shared: Arc<Shared>, bound: Bound) -> std::io::Result<()> {
+        let listener = match bound {
+            Bound::Rtu(port) => {
+                let service = SlaveService { shared, client: None };
+                return rtu::Server::new(port).serve_forever(service).await;
+            }
+            Bound::Tcp(listener) => listener,
+        };
+        let on_connected = |stream, addr: SocketAddr| {
+            let shared = shared.clone();
+            async move {
+                tcp::accept_tcp_connection(stream, addr, move |addr: SocketAddr| {
+                    Ok(Some(SlaveService {
+                        shared: shared.clone(),
+                        client: Some(addr.ip()),
+                    }))
+                })
+            }
+        };
+        let on_process_error = |e| eprintln!("Modbus slave failed to process request: {}", e);
+        tcp::Server::new(listener).serve(&on_connected, on_process_error).await
+    }
+}
+
+/// The port or listener, opened in `start` so a busy port fails the start.
+enum Bound {
+    Rtu(SerialStream),
+    Tcp(TcpListener),
+}
+
+#[async_trait]
+impl SubSystem for ModbusSlave {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        // 重启后保留已有的寄存器值
+        let shared = match self.shared.take() {
+            Some(shared) => shared,
+            None => Arc::new(Shared {
+                config: self.config.clone(),
+                bank: Mutex::new(self.config.bank()),
+                publisher: ctx.publisher(),
+            }),
+        };
+        self.shared = Some(shared.clone());
+        let bound = match (self.listener.take(), &self.config.listen) {
+            (Some(listener), _) => Bound::Tcp(listener),
+            (None, Listen::Tcp(addr)) => Bound::Tcp(TcpListener::bind(addr).await?),
+            (None, Listen::Rtu(port)) => Bound::Rtu(port.open()?),
+        };
+        let server = tokio::spawn(Self::serve(shared, bound));
+        self.server = Some(server);
+        Ok(())
+    }
+
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        if msg.get_msg_type() != MessageType::Telemetry {
+            return Ok(());
+        }
+        let (Some(telemetry), Some(shared)) = (msg.get_data::<TelemetryMsg>(), &self.shared) else {
+            return Ok(());
+        };
+        let mut bank = shared.bank.lock().unwrap();
+        for input in &self.config.inputs {
+            if input.device != telemetry.device || input.group != telemetry.group {
+                continue;
+            }
+            if let Some(value) = telemetry.values.get(&input.point) {
+                let words = encode_value(*value, input.kind, input.word_order, input.scale, input.bias);
+                bank.set(input.table(), input.address, &words);
+            }
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &self.server {
+            Some(server) if server.is_finished() => Health::Unhealthy("Modbus slave server stopped".to_string()),
+            _ => Health::Healthy,
+        }
+    }
+
+    fn rollup(&mut self) {}
+}
@@ -0,0 +1,80 @@
+use message::TelemetryMsg;
+
+use crate::config::{BlockConfig, PointConfig, ValueKind, WordOrder};
+
+/// What one read of a block returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockData {
+    Bits(Vec<bool>),
+    Words(Vec<u16>),
+}
+
+impl PointConfig {
+    /// The scaled value, or `None` if the data is too short or of the wrong kind.
+    pub fn read(&self, data: &BlockData) -> Option<f64> {
+        let at = self.offset as usize;
+        let raw = match (data, self.kind) {
+            (BlockData::Bits(bits), ValueKind::Bool) => {
+                if *bits.get(at)? {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            (BlockData::Words(words), ValueKind::U16) => *words.get(at)? as f64,
+            (BlockData::Words(words), ValueKind::I16) => *words.get(at)? as i16 as f64,
+            (BlockData::Words(words), kind) => {
+                let bits = join(words.get(at..at + 2)?, self.word_order);
+                match kind {
+                    ValueKind::U32 => bits as f64,
+                    ValueKind::I32 => bits as i32 as f64,
+                    ValueKind::F32 => f32::from_bits(bits) as f64,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(raw * self.scale + self.bias)
+    }
+}
+
+impl BlockConfig {
+    pub fn telemetry(&self, device: &str, data: &BlockData) -> TelemetryMsg {
+        let mut telemetry = TelemetryMsg::new(device, &self.name);
+        for point in &self.points {
+            if let Some(value) = point.read(data) {
+                telemetry = telemetry.value(&point.name, value);
+            }
+        }
+        telemetry
+    }
+}
+
+/// Registers holding `value` as a point with this encoding would read it back,
+/// saturating at the limits of `kind`.
+pub fn encode_value(value: f64, kind: ValueKind, word_order: WordOrder, scale: f64, bias: f64) -> Vec<u16> {
+    let raw = (value - bias) / scale;
+    match kind {
+        ValueKind::Bool => vec![(raw != 0.0) as u16],
+        ValueKind::U16 => vec![raw.round() as u16],
+        ValueKind::I16 => vec![raw.round() as i16 as u16],
+        ValueKind::U32 => split(raw.round() as u32, word_order),
+        ValueKind::I32 => split(raw.round() as i32 as u32, word_order),
+        ValueKind::F32 => split((raw as f32).to_bits(), word_order),
+    }
+}
+
+fn join(pair: &[u16], word_order: WordOrder) -> u32 {
+    match word_order {
+        WordOrder::HighFirst => (pair[0] as u32) << 16 | pair[1] as u32,
+        WordOrder::LowFirst => (pair[1] as u32) << 16 | pair[0] as u32,
+    }
+}
+
+fn split(bits: u32, word_order: WordOrder) -> Vec<u16> {
+    let (high, low) = ((bits >> 16) as u16, bits as u16);
+    match word_order {
+        WordOrder::HighFirst => vec![high, low],
+        WordOrder::LowFirst => vec![low, high],
+    }
+}
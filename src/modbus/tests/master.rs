@@ -0,0 +1,213 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use message::{AlarmSeverity, ConnectionMsg, LinkState, MessageType, MotorCommand, MotorMsg, MoveDirection};
+use modbus::{Exception, Injection, ModbusConfig, ModbusMaster, Simulator, SimulatorConfig, SimulatorHandle, Table};
+use testkit::{expect, Harness};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+
+/// A drive on unit 1: speed as a low-first i32 in 0.1 rpm, current in 0.01 A,
+/// an enable coil and a mode register.
+const MASTER: &str = r#"{
+    "transport": { "tcp": "127.0.0.1:0" },
+    "timeout_ms": 500,
+    "slaves": [{
+        "unit": 1,
+        "name": "drive1",
+        "blocks": [{
+            "name": "status", "table": "holding", "address": 0, "count": 3, "interval_ms": 1000,
+            "points": [
+                { "name": "speed", "offset": 0, "kind": "i32", "word_order": "low_first", "scale": 0.1 },
+                { "name": "current", "offset": 2, "kind": "u16", "scale": 0.01 }
+            ]
+        }],
+        "motors": [{
+            "motor": 1,
+            "commands": [
+                { "command": "Enable", "writes": [{ "coil": { "address": 0, "value": true } }] },
+                { "command": "Move", "direction": "Down", "writes": [{ "holding": { "address": 10, "values": [2] } }] },
+                { "command": "Stop", "writes": [{ "holding": { "address": 10, "values": [0] } }] }
+            ]
+        }]
+    }]
+}"#;
+
+const SIMULATOR: &str = r#"{
+    "slaves": [{
+        "unit": 1,
+        "holding": { "0": [65526, 65535, 150], "10": [0] },
+        "coils": { "0": [0] }
+    }]
+}"#;
+
+async fn simulator() -> (SocketAddr, SimulatorHandle) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let simulator = Simulator::new(serde_json::from_str::<SimulatorConfig>(SIMULATOR).unwrap());
+    let handle = simulator.handle();
+    tokio::spawn(simulator.serve(listener));
+    (addr, handle)
+}
+
+async fn start(addr: SocketAddr) -> Harness {
+    let config: ModbusConfig = serde_json::from_str(&MASTER.replace("127.0.0.1:0", &addr.to_string())).unwrap();
+    config.validate().unwrap();
+    let mut harness = Harness::new();
+    let master = ModbusMaster::new(config).with_clock(harness.clock());
+    harness.register("modbus", master, vec![MessageType::Move, MessageType::Stop]);
+    harness.start().await;
+    harness
+        .wait_until(|h| {
+            h.emitted_of("modbus", MessageType::Connection)
+                .iter()
+                .filter_map(|msg| msg.get_data::<ConnectionMsg>())
+                .any(|event| event.state == LinkState::Connected)
+        })
+        .await;
+    harness
+}
+
+#[tokio::test]
+async fn polls_registers_into_telemetry() {
+    let (addr, sim) = simulator().await;
+    let mut harness = start(addr).await;
+
+    harness.wait_until(|h| h.telemetry("modbus").len() == 1).await;
+    let first = &harness.telemetry("modbus")[0];
+    assert_eq!((first.device.as_str(), first.group.as_str()), ("drive1", "status"));
+    assert_eq!(first.values["speed"], -1.0);
+    assert_eq!(first.values["current"], 1.5);
+
+    sim.set(1, Table::Holding, 0, &[25, 0]);
+    harness.wait_for_sleepers(1).await;
+    harness.advance(Duration::from_secs(1)).await;
+    harness.wait_until(|h| h.telemetry("modbus").len() == 2).await;
+    assert_eq!(harness.telemetry("modbus")[1].values["speed"], 2.5);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn motor_commands_write_mapped_registers() {
+    let (addr, sim) = simulator().await;
+    let mut harness = start(addr).await;
+
+    let enable = MotorMsg::new(1, MoveDirection::Up).command(MotorCommand::Enable);
+    harness.inject_data(MessageType::Move, enable).await;
+    harness.wait_until(|_| sim.get(1, Table::Coil, 0) == Some(1)).await;
+
+    harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.wait_until(|_| sim.get(1, Table::Holding, 10) == Some(2)).await;
+
+    // Stop 类型的消息按停止处理
+    harness.inject_data(MessageType::Stop, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.wait_until(|_| sim.get(1, Table::Holding, 10) == Some(0)).await;
+    assert!(harness.alarms("modbus").is_empty());
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn exception_response_alarms_once_until_recovered() {
+    let (addr, sim) = simulator().await;
+    sim.inject(1, Injection::new(Table::Holding, 2, Exception::ServerDeviceBusy));
+    let mut harness = start(addr).await;
+
+    harness.wait_until(|h| h.alarms("modbus").len() == 1).await;
+    assert_eq!(harness.alarms("modbus")[0].severity, AlarmSeverity::Warning);
+    assert!(harness.alarms("modbus")[0].text.contains("ServerDeviceBusy"));
+
+    for _ in 0..2 {
+        harness.wait_for_sleepers(1).await;
+        harness.advance(Duration::from_secs(1)).await;
+    }
+    assert_eq!(harness.alarms("modbus").len(), 1);
+    assert!(harness.telemetry("modbus").is_empty());
+
+    sim.clear_injections(1);
+    harness.wait_for_sleepers(1).await;
+    harness.advance(Duration::from_secs(1)).await;
+    harness.wait_until(|h| h.alarms("modbus").len() == 2).await;
+    assert_eq!(harness.alarms("modbus")[1].severity, AlarmSeverity::Info);
+    harness.wait_until(|h| h.telemetry("modbus").len() == 1).await;
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn failed_command_write_raises_alarm() {
+    let (addr, sim) = simulator().await;
+    let mut harness = start(addr).await;
+    sim.inject(1, Injection::new(Table::Holding, 10, Exception::IllegalDataValue).times(1));
+
+    harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.wait_until(|h| !h.alarms("modbus").is_empty()).await;
+    assert!(harness.alarms("modbus")[0].text.contains("motor 1 Move failed"));
+    assert_eq!(sim.get(1, Table::Holding, 10), Some(0));
+
+    harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.wait_until(|_| sim.get(1, Table::Holding, 10) == Some(2)).await;
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn commands_while_disconnected_are_dropped_with_an_alarm() {
+    // 端口上没人监听，链路一直连不上
+    let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let config: ModbusConfig = serde_json::from_str(&MASTER.replace("127.0.0.1:0", &addr.to_string())).unwrap();
+    let mut harness = Harness::new();
+    let master = ModbusMaster::new(config).with_clock(harness.clock());
+    harness.register("modbus", master, vec![MessageType::Move, MessageType::Stop]);
+    harness.start().await;
+
+    harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.inject_data(MessageType::Stop, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.wait_until(|h| h.alarms("modbus").len() == 2).await;
+    let alarms = harness.alarms("modbus");
+    assert_eq!(alarms[0].severity, AlarmSeverity::Warning);
+    assert_eq!(alarms[0].text, format!("motor 1 Move dropped: {} is not open", addr));
+    // 丢掉的停止比丢掉的移动严重
+    assert_eq!(alarms[1].severity, AlarmSeverity::Critical);
+    assert_eq!(alarms[1].text, format!("motor 1 Stop dropped: {} is not open", addr));
+    harness.stop().await;
+}
+
+/// Reads one Modbus TCP request and returns its function code.
+async fn function_code(stream: &mut TcpStream) -> u8 {
+    let mut header = [0; 7];
+    expect(stream.read_exact(&mut header)).await.unwrap();
+    let mut pdu = vec![0; u16::from_be_bytes([header[4], header[5]]) as usize - 1];
+    expect(stream.read_exact(&mut pdu)).await.unwrap();
+    pdu[0]
+}
+
+#[tokio::test]
+async fn commands_queued_when_the_link_fails_are_not_carried_out_after_reconnecting() {
+    // 一个只收不答的从站，让采集卡住，指令留在队列里
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config: ModbusConfig = serde_json::from_str(&MASTER.replace("127.0.0.1:0", &addr.to_string())).unwrap();
+    let mut harness = Harness::new();
+    let master = ModbusMaster::new(config).with_clock(harness.clock());
+    harness.register("modbus", master, vec![MessageType::Move, MessageType::Stop]);
+    harness.start().await;
+
+    let (mut stuck, _) = expect(listener.accept()).await.unwrap();
+    assert_eq!(function_code(&mut stuck).await, READ_HOLDING_REGISTERS);
+    harness.inject_data(MessageType::Move, MotorMsg::new(1, MoveDirection::Down)).await;
+    harness.inject_data(MessageType::Stop, MotorMsg::new(1, MoveDirection::Down)).await;
+    assert!(harness.alarms("modbus").is_empty());
+
+    drop(stuck);
+    harness.wait_until(|h| h.alarms("modbus").len() == 2).await;
+    let alarms = harness.alarms("modbus");
+    assert_eq!(alarms[0].severity, AlarmSeverity::Warning);
+    assert_eq!(alarms[0].text, "motor 1 Move dropped: link was lost");
+    assert_eq!(alarms[1].severity, AlarmSeverity::Critical);
+    assert_eq!(alarms[1].text, "motor 1 Stop dropped: link was lost");
+
+    // 重连后先发的是采集，不是旧指令
+    let (mut reopened, _) = expect(listener.accept()).await.unwrap();
+    assert_eq!(function_code(&mut reopened).await, READ_HOLDING_REGISTERS);
+    harness.stop().await;
+}
@@ -0,0 +1,168 @@
+use std::net::SocketAddr;
+
+use message::{MessageType, MotorCommand, MotorMsg, MoveDirection, MsgBuilder, TelemetryMsg};
+use modbus::{ModbusSlave, SlaveServerConfig};
+use subsystem::Subscription;
+use testkit::Harness;
+use tokio::net::TcpListener;
+use tokio_modbus::client::{tcp, Context, Reader, Writer};
+use tokio_modbus::ExceptionCode;
+
+/// Drive position as a high-first i32 in 0.01 mm and a ready flag, a command
+/// register for motor 1 that anyone may write, one only 10.0.0.5 may write and
+/// one nobody may.
+const SLAVE: &str = r#"{
+    "listen": { "tcp": "127.0.0.1:0" },
+    "inputs": [
+        { "device": "drive1", "group": "status", "point": "position", "address": 0, "kind": "i32", "scale": 0.01 },
+        { "device": "drive1", "group": "status", "point": "ready", "address": 0, "kind": "bool" }
+    ],
+    "commands": [
+        {
+            "address": 100, "motor": 1,
+            "values": [
+                { "value": 0, "command": "Stop" },
+                { "value": 1, "command": "Move", "direction": "Up" },
+                { "value": 2, "command": "Move", "direction": "Down" }
+            ]
+        },
+        { "address": 101, "motor": 2, "values": [{ "value": 0, "command": "Stop" }] },
+        { "address": 102, "motor": 3, "values": [{ "value": 0, "command": "Stop" }] }
+    ],
+    "access": [
+        { "table": "holding", "address": 100, "access": "read_write" },
+        { "table": "holding", "address": 101, "access": "read_write", "clients": ["10.0.0.5"] },
+        { "table": "holding", "address": 101, "access": "read" }
+    ]
+}"#;
+
+/// The slave under test, a client connected to it, and a subscriber standing
+/// in for the drives that carry out its commands.
+async fn start() -> (Harness, Context, Subscription) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let config: SlaveServerConfig = serde_json::from_str(SLAVE).unwrap();
+    let mut harness = Harness::new();
+    let slave = ModbusSlave::new(config).with_listener(listener);
+    harness.register("modbus-slave", slave, vec![MessageType::Telemetry]);
+    harness.start().await;
+    let drives = harness.center().bus().subscribe("drives", [MessageType::Move, MessageType::Stop]);
+    let client = tcp::connect(addr).await.unwrap();
+    (harness, client, drives)
+}
+
+fn motors(harness: &Harness) -> Vec<(MessageType, MotorMsg)> {
+    harness
+        .emitted("modbus-slave")
+        .iter()
+        .filter_map(|msg| Some((msg.get_msg_type(), msg.get_data::<MotorMsg>()?)))
+        .collect()
+}
+
+#[tokio::test]
+async fn telemetry_is_served_as_input_registers() {
+    let (mut harness, mut client, _drives) = start().await;
+    assert_eq!(client.read_input_registers(0, 2).await.unwrap(), Ok(vec![0, 0]));
+
+    let telemetry = TelemetryMsg::new("drive1", "status").value("position", -1.5).value("ready", 1.0);
+    let msg = MsgBuilder::new()
+        .msg_type(MessageType::Telemetry)
+        .data(Box::new(telemetry))
+        .build()
+        .unwrap();
+    harness.inject(msg).await;
+
+    assert_eq!(client.read_input_registers(0, 2).await.unwrap(), Ok(vec![65535, 65386]));
+    assert_eq!(client.read_discrete_inputs(0, 1).await.unwrap(), Ok(vec![true]));
+    // 未映射的地址
+    assert_eq!(
+        client.read_input_registers(2, 1).await.unwrap(),
+        Err(ExceptionCode::IllegalDataAddress)
+    );
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn command_register_writes_publish_motor_commands() {
+    let (mut harness, mut client, _drives) = start().await;
+
+    client.write_single_register(100, 2).await.unwrap().unwrap();
+    harness.wait_until(|h| motors(h).len() == 1).await;
+    client.write_single_register(100, 0).await.unwrap().unwrap();
+    harness.wait_until(|h| motors(h).len() == 2).await;
+
+    let motors = motors(&harness);
+    assert_eq!(motors[0].0, MessageType::Move);
+    assert_eq!((motors[0].1.id, motors[0].1.direction), (1, MoveDirection::Down));
+    assert_eq!(motors[0].1.command, MotorCommand::Move);
+    assert_eq!(motors[1].0, MessageType::Stop);
+    assert_eq!(motors[1].1.command, MotorCommand::Stop);
+    assert_eq!(client.read_holding_registers(100, 1).await.unwrap(), Ok(vec![0]));
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn unmapped_command_value_is_refused() {
+    let (mut harness, mut client, _drives) = start().await;
+
+    assert_eq!(
+        client.write_single_register(100, 7).await.unwrap(),
+        Err(ExceptionCode::IllegalDataValue)
+    );
+    harness.settle().await;
+    assert!(motors(&harness).is_empty());
+    assert_eq!(client.read_holding_registers(100, 1).await.unwrap(), Ok(vec![0]));
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn access_rules_refuse_writes_from_other_clients() {
+    let (mut harness, mut client, _drives) = start().await;
+
+    assert_eq!(client.read_holding_registers(101, 1).await.unwrap(), Ok(vec![0]));
+    assert_eq!(
+        client.write_single_register(101, 0).await.unwrap(),
+        Err(ExceptionCode::IllegalDataAddress)
+    );
+    // 写多个寄存器时任何一个被拒绝都不生效
+    assert_eq!(
+        client.write_multiple_registers(100, &[1, 0]).await.unwrap(),
+        Err(ExceptionCode::IllegalDataAddress)
+    );
+    harness.settle().await;
+    assert!(motors(&harness).is_empty());
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn command_registers_without_a_rule_are_read_only() {
+    let (mut harness, mut client, _drives) = start().await;
+
+    assert_eq!(client.read_holding_registers(102, 1).await.unwrap(), Ok(vec![0]));
+    assert_eq!(
+        client.write_single_register(102, 0).await.unwrap(),
+        Err(ExceptionCode::IllegalDataAddress)
+    );
+    harness.settle().await;
+    assert!(motors(&harness).is_empty());
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn a_command_nobody_takes_is_answered_busy() {
+    let (mut harness, mut client, drives) = start().await;
+    drop(drives);
+
+    // 停止和移动一样，丢了都要让主站重试
+    for value in [0, 1] {
+        assert_eq!(
+            client.write_single_register(100, value).await.unwrap(),
+            Err(ExceptionCode::ServerDeviceBusy)
+        );
+    }
+    assert_eq!(
+        client.write_multiple_registers(100, &[2]).await.unwrap(),
+        Err(ExceptionCode::ServerDeviceBusy)
+    );
+    harness.stop().await;
+}
@@ -0,0 +1,298 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use message::{AlarmMsg, AlarmSeverity, LinkState, MessageType, MotorCommand, MotorMsg, MoveDirection, Msg, MsgBuilder};
+use rumqttc::{AsyncClient, ConnectionError, Event, EventLoop, MqttOptions, Outgoing, Packet, Publish};
+use serde::Deserialize;
+use serde_json::Value;
+use subsystem::{set_link_state, Backoff, BoxError, Clock, Context, Health, Publisher, SubSystem, SystemClock, TopicFilter};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::config::{CommandConfig, MqttConfig, PublishRule};
+use crate::template::TopicTemplate;
+
+/// 发往 broker 的请求队列长度，满了就丢弃
+const QUEUE: usize = 256;
+
+/// How long stopping waits for the disconnect to go out.
+const DISCONNECT: Duration = Duration::from_millis(500);
+
+/// A publish rule with its filter and template parsed.
+struct Rule {
+    filter: TopicFilter,
+    topic: TopicTemplate,
+    config: PublishRule,
+}
+
+/// A motor command received from MQTT, e.g.
+/// `{ "motor": 1, "command": "Move", "direction": "Down" }`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandPayload {
+    motor: i32,
+    command: MotorCommand,
+    #[serde(default)]
+    direction: MoveDirection,
+}
+
+/// Stops the link task.
+type LinkTask = (oneshot::Sender<()>, JoinHandle<()>);
+
+/// Bridges the bus to an MQTT broker.
+///
+/// Every bus message routed to it that matches a publish rule is published
+/// to the broker as its JSON payload, on the topic rendered from the rule's
+/// template and with the rule's QoS and retain flag. If commands are
+/// configured, their topic is subscribed to and each valid [`MotorMsg`]
+/// payload for an allowed motor is published on the bus as a `Move`, or a
+/// `Stop` for the stop command; anything else raises a warning on
+/// `alarm/mqtt/{client_id}`. The link state is published like other links
+/// and a lost connection is retried with backoff.
+pub struct MqttBridge {
+    config: Arc<MqttConfig>,
+    rules: Vec<Rule>,
+    backoff: Backoff,
+    clock: Arc<dyn Clock>,
+    client: Option<AsyncClient>,
+    state: Arc<Mutex<LinkState>>,
+    task: Option<LinkTask>,
+}
+
+impl MqttBridge {
+    /// Takes a config that passed [`MqttConfig::validate`]; rules that
+    /// don't parse are left out.
+    pub fn new(config: MqttConfig) -> Self {
+        let rules = config
+            .publish
+            .iter()
+            .filter_map(|rule| {
+                Some(Rule {
+                    filter: TopicFilter::new(&rule.filter).ok()?,
+                    topic: TopicTemplate::parse(&rule.topic).ok()?,
+                    config: rule.clone(),
+                })
+            })
+            .collect();
+        Self {
+            config: Arc::new(config),
+            rules,
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(30)),
+            clock: Arc::new(SystemClock),
+            client: None,
+            state: Arc::new(Mutex::new(LinkState::Connecting)),
+            task: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Delay between attempts to reconnect. Defaults to 1 s doubling up to 30 s.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    fn options(&self) -> MqttOptions {
+        let config = &self.config;
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(config.keep_alive_secs));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        options
+    }
+}
+
+/// The link task, which drives the MQTT event loop.
+struct Link {
+    config: Arc<MqttConfig>,
+    client: AsyncClient,
+    backoff: Backoff,
+    clock: Arc<dyn Clock>,
+    state: Arc<Mutex<LinkState>>,
+    publisher: Publisher,
+}
+
+impl Link {
+    async fn run(self, mut events: EventLoop, mut stop: oneshot::Receiver<()>) {
+        let mut attempt = 0;
+        loop {
+            let event = tokio::select! {
+                _ = &mut stop => break,
+                event = events.poll() => event,
+            };
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    attempt = 0;
+                    self.set_state(LinkState::Connected, None).await;
+                    // 每次连上都要重新订阅
+                    if let Some(commands) = &self.config.commands {
+                        if let Err(e) = self.client.try_subscribe(&commands.topic, commands.qos.into()) {
+                            eprintln!("Failed to subscribe to {}: {}", commands.topic, e);
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => self.command(publish).await,
+                Ok(_) => {}
+                Err(e) => {
+                    let reason = describe(&e);
+                    eprintln!("MQTT link {} lost: {}", self.peer(), reason);
+                    self.set_state(LinkState::Disconnected, Some(reason)).await;
+                    let delay = self.backoff.delay(attempt);
+                    attempt = attempt.saturating_add(1);
+                    tokio::select! {
+                        _ = &mut stop => break,
+                        _ = self.clock.sleep(delay) => {}
+                    }
+                }
+            }
+        }
+        // 连着时尽量正常断开，让 broker 知道不是掉线
+        let connected = *self.state.lock().unwrap() == LinkState::Connected;
+        if connected && self.client.try_disconnect().is_ok() {
+            let _ = tokio::time::timeout(DISCONNECT, async {
+                while !matches!(events.poll().await, Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_)) {}
+            })
+            .await;
+        }
+        *self.state.lock().unwrap() = LinkState::Disconnected;
+    }
+
+    fn peer(&self) -> String {
+        format!("{}:{}", self.config.host, self.config.port)
+    }
+
+    async fn set_state(&self, state: LinkState, reason: Option<String>) {
+        set_link_state(&self.state, &self.publisher, self.peer(), state, reason).await;
+    }
+
+    /// Checks a command received on the command topic and puts it on the bus.
+    async fn command(&self, publish: Publish) {
+        let Some(commands) = &self.config.commands else {
+            return;
+        };
+        let motor = match accept(commands, &publish.payload) {
+            Ok(motor) => motor,
+            Err(reason) => {
+                let text = format!("command on {} rejected: {}", publish.topic, reason);
+                self.alarm(AlarmSeverity::Warning, text).await;
+                return;
+            }
+        };
+        let msg_type = match motor.command {
+            MotorCommand::Stop => MessageType::Stop,
+            _ => MessageType::Move,
+        };
+        let msg = MsgBuilder::new().msg_type(msg_type).data(Box::new(motor)).build().unwrap();
+        self.publisher.publish(msg).await;
+    }
+
+    async fn alarm(&self, severity: AlarmSeverity, text: String) {
+        eprintln!("MQTT alarm on {}: {}", self.config.client_id, text);
+        let source = format!("{}/{}", self.publisher.source(), self.config.client_id);
+        let msg = MsgBuilder::new()
+            .msg_type(MessageType::Alarm)
+            .topic(format!("alarm/mqtt/{}", self.config.client_id))
+            .data(Box::new(AlarmMsg::new(source, severity, text)))
+            .build()
+            .unwrap();
+        self.publisher.publish(msg).await;
+    }
+}
+
+fn accept(commands: &CommandConfig, payload: &[u8]) -> Result<MotorMsg, String> {
+    let command: CommandPayload = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+    if !commands.motors.is_empty() && !commands.motors.contains(&command.motor) {
+        return Err(format!("motor {} is not allowed", command.motor));
+    }
+    Ok(MotorMsg::new(command.motor, command.direction).command(command.command))
+}
+
+fn describe(e: &ConnectionError) -> String {
+    match e {
+        ConnectionError::ConnectionRefused(code) => format!("refused by broker: {:?}", code),
+        e => e.to_string(),
+    }
+}
+
+/// The JSON payload of a bus message.
+fn payload(msg: &Msg) -> Option<Vec<u8>> {
+    match &msg.data {
+        Some(data) => Some(data.encode()),
+        None => msg.row_data.clone(),
+    }
+}
+
+#[async_trait]
+impl SubSystem for MqttBridge {
+    /// Starts the link task; the broker is connected to in the background.
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let (client, events) = AsyncClient::new(self.options(), QUEUE);
+        *self.state.lock().unwrap() = LinkState::Connecting;
+        let link = Link {
+            config: self.config.clone(),
+            client: client.clone(),
+            backoff: self.backoff,
+            clock: self.clock.clone(),
+            state: self.state.clone(),
+            publisher: ctx.publisher(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.task = Some((stop, tokio::spawn(link.run(events, stopped))));
+        self.client = Some(client);
+        Ok(())
+    }
+
+    /// Publishes the message to the broker for every rule it matches.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        let Some(client) = &self.client else {
+            return Ok(());
+        };
+        let topic = msg.topic();
+        let mut matching = self.rules.iter().filter(|rule| rule.filter.matches(&topic)).peekable();
+        if matching.peek().is_none() {
+            return Ok(());
+        }
+        let Some(bytes) = payload(msg) else {
+            return Ok(());
+        };
+        let value: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        for rule in matching {
+            let target = match rule.topic.render(msg, &value) {
+                Ok(target) => target,
+                Err(e) => {
+                    eprintln!("Not publishing {} to MQTT: {}", topic, e);
+                    continue;
+                }
+            };
+            // 队列满说明 broker 断开太久，丢弃而不阻塞总线
+            if let Err(e) = client.try_publish(target, rule.config.qos.into(), rule.config.retain, bytes.clone()) {
+                eprintln!("Dropped {} for MQTT: {}", topic, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        self.client = None;
+        if let Some((stop, task)) = self.task.take() {
+            let _ = stop.send(());
+            task.await?;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &*self.state.lock().unwrap() {
+            LinkState::Connected => Health::Healthy,
+            _ => Health::Degraded(format!("MQTT broker {}:{} not connected", self.config.host, self.config.port)),
+        }
+    }
+
+    fn rollup(&mut self) {}
+}
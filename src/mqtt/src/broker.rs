@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use rumqttc::mqttbytes::{self, v4};
+use rumqttc::{
+    ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, PubComp, PubRec, PubRel, Publish, QoS, SubAck,
+    SubscribeReasonCode, UnsubAck,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// 单个报文的上限
+const MAX_PACKET: usize = 1024 * 1024;
+
+#[derive(Default)]
+struct State {
+    next: u64,
+    sessions: HashMap<u64, Session>,
+    retained: BTreeMap<String, Publish>,
+}
+
+struct Session {
+    client_id: String,
+    subscriptions: Vec<(String, QoS)>,
+    outbox: UnboundedSender<Packet>,
+    /// 下一个发出的报文标识
+    pkid: u16,
+}
+
+impl Session {
+    fn send(&mut self, mut publish: Publish, qos: QoS) {
+        publish.qos = qos;
+        publish.dup = false;
+        publish.pkid = 0;
+        if qos != QoS::AtMostOnce {
+            self.pkid = self.pkid.checked_add(1).unwrap_or(1);
+            publish.pkid = self.pkid;
+        }
+        let _ = self.outbox.send(Packet::Publish(publish));
+    }
+}
+
+/// A small MQTT 3.1.1 broker for tests and single-machine setups: QoS 0-2,
+/// wildcard subscriptions, retained messages and an optional login. There
+/// are no persistent sessions or last wills, and QoS 1 and 2 deliveries to
+/// subscribers are not retried.
+pub struct Broker {
+    listener: TcpListener,
+    login: Option<(String, String)>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Broker {
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            login: None,
+            state: Arc::new(Mutex::new(State::default())),
+        })
+    }
+
+    /// Only accepts clients connecting with this username and password.
+    pub fn login(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.login = Some((username.into(), password.into()));
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn handle(&self) -> BrokerHandle {
+        BrokerHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Serves clients until accepting fails.
+    pub async fn run(self) -> io::Result<()> {
+        let login = Arc::new(self.login);
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            let connection = Connection {
+                state: self.state.clone(),
+                login: login.clone(),
+            };
+            tokio::spawn(connection.serve(stream));
+        }
+    }
+}
+
+/// Looks into and disturbs a running [`Broker`].
+#[derive(Clone)]
+pub struct BrokerHandle {
+    state: Arc<Mutex<State>>,
+}
+
+impl BrokerHandle {
+    /// Ids of the connected clients.
+    pub fn clients(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut clients: Vec<String> = state.sessions.values().map(|session| session.client_id.clone()).collect();
+        clients.sort();
+        clients
+    }
+
+    /// Subscriptions of a connected client.
+    pub fn subscriptions(&self, client_id: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .sessions
+            .values()
+            .filter(|session| session.client_id == client_id)
+            .flat_map(|session| session.subscriptions.iter().map(|(filter, _)| filter.clone()))
+            .collect()
+    }
+
+    pub fn retained(&self, topic: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().retained.get(topic).map(|publish| publish.payload.to_vec())
+    }
+
+    /// Closes every client connection, as if the broker restarted.
+    pub fn drop_clients(&self) {
+        self.state.lock().unwrap().sessions.clear();
+    }
+}
+
+struct Connection {
+    state: Arc<Mutex<State>>,
+    login: Arc<Option<(String, String)>>,
+}
+
+impl Connection {
+    async fn serve(self, stream: TcpStream) {
+        let (mut reader, mut writer) = stream.into_split();
+        let mut buffer = BytesMut::new();
+        let (outbox, mut outgoing) = unbounded_channel::<Packet>();
+        // 写任务在会话被移除、发送端全部释放后关闭连接
+        tokio::spawn(async move {
+            let mut bytes = BytesMut::new();
+            while let Some(packet) = outgoing.recv().await {
+                bytes.clear();
+                if write(&packet, &mut bytes).is_err() || writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+
+        let connect = match next_packet(&mut reader, &mut buffer).await {
+            Ok(Packet::Connect(connect)) => connect,
+            _ => return,
+        };
+        let accepted = match self.login.as_ref() {
+            None => true,
+            Some((username, password)) => connect.login.as_ref().is_some_and(|login| login.validate(username, password)),
+        };
+        if !accepted {
+            let _ = outbox.send(Packet::ConnAck(ConnAck::new(ConnectReturnCode::BadUserNamePassword, false)));
+            return;
+        }
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.next += 1;
+            let id = state.next;
+            // 同一个 client id 再次连接时踢掉旧连接
+            state.sessions.retain(|_, session| session.client_id != connect.client_id);
+            let session = Session {
+                client_id: connect.client_id.clone(),
+                subscriptions: Vec::new(),
+                outbox: outbox.clone(),
+                pkid: 0,
+            };
+            state.sessions.insert(id, session);
+            id
+        };
+        let _ = outbox.send(Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)));
+        drop(outbox);
+
+        while let Ok(packet) = next_packet(&mut reader, &mut buffer).await {
+            if !self.packet(id, packet) {
+                break;
+            }
+        }
+        self.state.lock().unwrap().sessions.remove(&id);
+    }
+
+    /// Handles one packet from client `id`; false once the client is gone.
+    fn packet(&self, id: u64, packet: Packet) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(session) = state.sessions.get_mut(&id) else {
+            return false;
+        };
+        let outbox = session.outbox.clone();
+        let reply = match packet {
+            Packet::Publish(publish) => {
+                let reply = match publish.qos {
+                    QoS::AtMostOnce => None,
+                    QoS::AtLeastOnce => Some(Packet::PubAck(PubAck::new(publish.pkid))),
+                    QoS::ExactlyOnce => Some(Packet::PubRec(PubRec::new(publish.pkid))),
+                };
+                route(&mut state, publish);
+                reply
+            }
+            Packet::PubRel(pubrel) => Some(Packet::PubComp(PubComp::new(pubrel.pkid))),
+            Packet::PubRec(pubrec) => Some(Packet::PubRel(PubRel::new(pubrec.pkid))),
+            Packet::Subscribe(subscribe) => {
+                let mut codes = Vec::new();
+                let mut retained = Vec::new();
+                for filter in subscribe.filters {
+                    if !mqttbytes::valid_filter(&filter.path) {
+                        codes.push(SubscribeReasonCode::Failure);
+                        continue;
+                    }
+                    codes.push(SubscribeReasonCode::Success(filter.qos));
+                    for publish in state.retained.values() {
+                        if mqttbytes::matches(&publish.topic, &filter.path) {
+                            retained.push((publish.clone(), lower(publish.qos, filter.qos)));
+                        }
+                    }
+                    let session = state.sessions.get_mut(&id).unwrap();
+                    session.subscriptions.retain(|(path, _)| *path != filter.path);
+                    session.subscriptions.push((filter.path, filter.qos));
+                }
+                let session = state.sessions.get_mut(&id).unwrap();
+                let _ = outbox.send(Packet::SubAck(SubAck::new(subscribe.pkid, codes)));
+                for (publish, qos) in retained {
+                    session.send(publish, qos);
+                }
+                None
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                session.subscriptions.retain(|(path, _)| !unsubscribe.topics.contains(path));
+                Some(Packet::UnsubAck(UnsubAck::new(unsubscribe.pkid)))
+            }
+            Packet::PingReq => Some(Packet::PingResp),
+            Packet::Disconnect => return false,
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            let _ = outbox.send(reply);
+        }
+        true
+    }
+}
+
+/// Delivers a publish to every matching subscription and keeps it if retained.
+fn route(state: &mut State, publish: Publish) {
+    if publish.retain {
+        if publish.payload.is_empty() {
+            state.retained.remove(&publish.topic);
+        } else {
+            state.retained.insert(publish.topic.clone(), publish.clone());
+        }
+    }
+    let mut delivered = publish;
+    // 转发给订阅者时不带 retain 标志
+    delivered.retain = false;
+    for session in state.sessions.values_mut() {
+        let qos = session
+            .subscriptions
+            .iter()
+            .filter(|(filter, _)| mqttbytes::matches(&delivered.topic, filter))
+            .map(|(_, qos)| *qos)
+            .reduce(|a, b| if b > a { b } else { a });
+        if let Some(qos) = qos {
+            session.send(delivered.clone(), lower(delivered.qos, qos));
+        }
+    }
+}
+
+fn lower(a: QoS, b: QoS) -> QoS {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+async fn next_packet(reader: &mut OwnedReadHalf, buffer: &mut BytesMut) -> io::Result<Packet> {
+    loop {
+        match v4::read(buffer, MAX_PACKET) {
+            Ok(packet) => return Ok(packet),
+            Err(mqttbytes::Error::InsufficientBytes(_)) => {}
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+        if reader.read_buf(buffer).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+}
+
+fn write(packet: &Packet, bytes: &mut BytesMut) -> Result<usize, mqttbytes::Error> {
+    match packet {
+        Packet::ConnAck(connack) => connack.write(bytes),
+        Packet::Publish(publish) => publish.write(bytes),
+        Packet::PubAck(puback) => puback.write(bytes),
+        Packet::PubRec(pubrec) => pubrec.write(bytes),
+        Packet::PubRel(pubrel) => pubrel.write(bytes),
+        Packet::PubComp(pubcomp) => pubcomp.write(bytes),
+        Packet::SubAck(suback) => suback.write(bytes),
+        Packet::UnsubAck(unsuback) => unsuback.write(bytes),
+        Packet::PingResp => PingResp.write(bytes),
+        _ => Err(mqttbytes::Error::IncorrectPacketFormat),
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use subsystem::{BoxError, Filter, TopicFilter};
+
+use crate::template::TopicTemplate;
+
+/// The broker a bridge connects to and what it forwards, read from a JSON file:
+///
+/// ```json
+/// {
+///   "host": "broker.plant.local",
+///   "port": 1883,
+///   "client_id": "csc-line1",
+///   "username": "csc",
+///   "password": "secret",
+///   "publish": [
+///     { "filter": "telemetry/#", "topic": "plant/line1/{device}/{group}", "qos": 0 },
+///     { "filter": "alarm/#", "topic": "plant/line1/alarms/{severity}", "qos": 1 },
+///     { "filter": "connection/#", "topic": "plant/line1/state/{topic}", "qos": 1, "retain": true }
+///   ],
+///   "commands": { "topic": "plant/line1/cmd", "qos": 1, "motors": [1, 2] }
+/// }
+/// ```
+///
+/// Every bus message matching a rule's `filter` is published as its JSON
+/// payload on the rule's topic template, see [`TopicTemplate`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_keep_alive_secs")]
+    pub keep_alive_secs: u64,
+    #[serde(default)]
+    pub publish: Vec<PublishRule>,
+    #[serde(default)]
+    pub commands: Option<CommandConfig>,
+}
+
+/// MQTT delivery guarantee, written as 0, 1 or 2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Qos {
+    #[default]
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+impl TryFrom<u8> for Qos {
+    type Error = String;
+
+    fn try_from(level: u8) -> Result<Self, Self::Error> {
+        match level {
+            0 => Ok(Qos::AtMostOnce),
+            1 => Ok(Qos::AtLeastOnce),
+            2 => Ok(Qos::ExactlyOnce),
+            _ => Err(format!("QoS {} is not 0, 1 or 2", level)),
+        }
+    }
+}
+
+impl From<Qos> for u8 {
+    fn from(qos: Qos) -> Self {
+        qos as u8
+    }
+}
+
+impl From<Qos> for QoS {
+    fn from(qos: Qos) -> Self {
+        match qos {
+            Qos::AtMostOnce => QoS::AtMostOnce,
+            Qos::AtLeastOnce => QoS::AtLeastOnce,
+            Qos::ExactlyOnce => QoS::ExactlyOnce,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishRule {
+    /// Bus topics forwarded by this rule, e.g. `alarm/#`.
+    pub filter: String,
+    /// MQTT topic template.
+    pub topic: String,
+    #[serde(default)]
+    pub qos: Qos,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+/// Motor commands accepted from MQTT, as JSON payloads such as
+/// `{ "motor": 1, "command": "Move", "direction": "Down" }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandConfig {
+    /// MQTT topic filter to subscribe to.
+    pub topic: String,
+    #[serde(default)]
+    pub qos: Qos,
+    /// Motors commands may address; any motor if empty.
+    #[serde(default)]
+    pub motors: Vec<i32>,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "csc".to_string()
+}
+
+fn default_keep_alive_secs() -> u64 {
+    30
+}
+
+impl MqttConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks every filter and template.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if self.keep_alive_secs == 0 {
+            return Err("keep alive must be at least a second".into());
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err("a password needs a username".into());
+        }
+        for rule in &self.publish {
+            TopicFilter::new(&rule.filter)?;
+            TopicTemplate::parse(&rule.topic)?;
+        }
+        if let Some(commands) = &self.commands {
+            if !rumqttc::valid_filter(&commands.topic) {
+                return Err(format!("{} is not a valid MQTT topic filter", commands.topic).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// What to register the bridge for: the filters of its publish rules.
+    pub fn filters(&self) -> Vec<Filter> {
+        self.publish
+            .iter()
+            .filter_map(|rule| TopicFilter::new(&rule.filter).ok())
+            .map(Filter::Topic)
+            .collect()
+    }
+}
@@ -0,0 +1,16 @@
+//! MQTT bridge for the CSC: forwards selected bus topics to a broker under
+//! configurable topic templates and QoS, and turns validated command
+//! payloads from the broker into motor messages. The `test-broker` feature
+//! adds a small embedded broker to test with.
+
+mod bridge;
+#[cfg(feature = "test-broker")]
+mod broker;
+mod config;
+mod template;
+
+pub use bridge::*;
+#[cfg(feature = "test-broker")]
+pub use broker::*;
+pub use config::*;
+pub use template::*;
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use message::Msg;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+/// An MQTT topic with `{name}` placeholders, e.g. `plant/csc/{device}/{group}`.
+///
+/// `{topic}`, `{type}` and `{source}` are the bus topic, message type and
+/// source of the message; any other name is a top-level field of its
+/// payload, such as `device` of a telemetry message or `severity` of an alarm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicTemplate {
+    raw: String,
+    parts: Vec<Part>,
+}
+
+impl TopicTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` in {}", template))?;
+            let name = &rest[start + 1..start + end];
+            if name.is_empty() || name.contains('{') {
+                return Err(format!("bad placeholder in {}", template));
+            }
+            parts.push(Part::Field(name.to_string()));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        let text_ok = parts.iter().all(|part| match part {
+            Part::Text(text) => !text.contains(['+', '#', '}']),
+            Part::Field(_) => true,
+        });
+        if parts.is_empty() || !text_ok {
+            return Err(format!("{} is not a valid MQTT topic", template));
+        }
+        Ok(Self {
+            raw: template.to_string(),
+            parts,
+        })
+    }
+
+    /// The topic for `msg`, whose JSON payload is `payload`.
+    pub fn render(&self, msg: &Msg, payload: &Value) -> Result<String, String> {
+        let mut topic = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => topic.push_str(text),
+                Part::Field(name) => {
+                    let value = field(msg, payload, name)
+                        .ok_or_else(|| format!("no `{}` in {:?} message for {}", name, msg.get_msg_type(), self.raw))?;
+                    // 值里的通配符会让 topic 失效
+                    if value.is_empty() || value.contains(['+', '#']) {
+                        return Err(format!("`{}` = {:?} can't be used in topic {}", name, value, self.raw));
+                    }
+                    topic.push_str(&value);
+                }
+            }
+        }
+        Ok(topic)
+    }
+}
+
+fn field(msg: &Msg, payload: &Value, name: &str) -> Option<String> {
+    match name {
+        "topic" => return Some(msg.topic().into_owned()),
+        "type" => return Some(msg.get_msg_type().topic().to_string()),
+        "source" => return msg.info.source.clone(),
+        _ => {}
+    }
+    match payload.get(name)? {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+impl fmt::Display for TopicTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use message::{
+    AlarmMsg, AlarmSeverity, ConnectionMsg, LinkState, MessageType, MotorCommand, MotorMsg, MoveDirection, MsgBuilder,
+    TelemetryMsg,
+};
+use mqtt::{Broker, BrokerHandle, MqttBridge, MqttConfig};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, Publish, QoS};
+use subsystem::{Backoff, Health, SystemId};
+use testkit::{expect, Harness};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+
+/// Telemetry, alarms and link state under `plant/line1`, motor 1 and 2
+/// commands from `plant/line1/cmd`.
+const BRIDGE: &str = r#"{
+    "host": "127.0.0.1",
+    "client_id": "csc",
+    "publish": [
+        { "filter": "telemetry/#", "topic": "plant/line1/{device}/{group}", "qos": 1 },
+        { "filter": "alarm/#", "topic": "plant/line1/alarms/{severity}", "qos": 2 },
+        { "filter": "connection/#", "topic": "plant/line1/state/{source}", "qos": 1, "retain": true }
+    ],
+    "commands": { "topic": "plant/line1/cmd", "qos": 1, "motors": [1, 2] }
+}"#;
+
+struct Bench {
+    harness: Harness,
+    broker: BrokerHandle,
+    addr: SocketAddr,
+}
+
+async fn broker(login: Option<(&str, &str)>) -> (BrokerHandle, SocketAddr) {
+    let mut broker = Broker::bind("127.0.0.1:0").await.unwrap();
+    if let Some((username, password)) = login {
+        broker = broker.login(username, password);
+    }
+    let addr = broker.local_addr().unwrap();
+    let handle = broker.handle();
+    tokio::spawn(broker.run());
+    (handle, addr)
+}
+
+fn config(addr: SocketAddr, extra: &str) -> MqttConfig {
+    let json = BRIDGE.replace(r#""host": "127.0.0.1","#, &format!(r#""host": "127.0.0.1", "port": {}, {}"#, addr.port(), extra));
+    let config: MqttConfig = serde_json::from_str(&json).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+/// Starts a bridge to a fresh broker and waits until it has subscribed.
+async fn start() -> Bench {
+    let (broker, addr) = broker(None).await;
+    let config = config(addr, "");
+    let mut harness = Harness::new();
+    let filters = config.filters();
+    let bridge = MqttBridge::new(config)
+        .with_clock(harness.clock())
+        .backoff(Backoff::new(Duration::from_secs(1), Duration::from_secs(30)).jitter(0.0));
+    harness.register("mqtt", bridge, filters);
+    harness.start().await;
+    let handle = broker.clone();
+    harness.wait_until(|_| handle.subscriptions("csc") == ["plant/line1/cmd"]).await;
+    Bench { harness, broker, addr }
+}
+
+/// An MQTT client on the side of the plant, collecting what it receives.
+struct Plant {
+    client: AsyncClient,
+    received: UnboundedReceiver<Publish>,
+}
+
+async fn plant(bench: &Bench, subscribe: &str) -> Plant {
+    let options = MqttOptions::new("plant", "127.0.0.1", bench.addr.port());
+    let (client, mut events) = AsyncClient::new(options, 16);
+    let (sender, received) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(event) = events.poll().await {
+            if let Event::Incoming(Packet::Publish(publish)) = event {
+                let _ = sender.send(publish);
+            }
+        }
+    });
+    client.subscribe(subscribe, QoS::ExactlyOnce).await.unwrap();
+    let broker = bench.broker.clone();
+    bench.harness.wait_until(|_| !broker.subscriptions("plant").is_empty()).await;
+    Plant { client, received }
+}
+
+async fn next(plant: &mut Plant) -> Publish {
+    expect(plant.received.recv()).await.unwrap()
+}
+
+async fn command(plant: &Plant, payload: &str) {
+    plant.client.publish("plant/line1/cmd", QoS::AtLeastOnce, false, payload.as_bytes().to_vec()).await.unwrap();
+}
+
+#[tokio::test]
+async fn telemetry_and_alarms_follow_templates_and_qos() {
+    let mut bench = start().await;
+    let mut plant = plant(&bench, "plant/line1/#").await;
+
+    let values = BTreeMap::from([("flow".to_string(), 12.5)]);
+    let telemetry = TelemetryMsg {
+        device: "pump1".to_string(),
+        group: "status".to_string(),
+        values,
+    };
+    let msg = MsgBuilder::new()
+        .msg_type(MessageType::Telemetry)
+        .topic("telemetry/pump1/status")
+        .data(Box::new(telemetry.clone()))
+        .build()
+        .unwrap();
+    bench.harness.inject(msg).await;
+    let published = next(&mut plant).await;
+    assert_eq!(published.topic, "plant/line1/pump1/status");
+    assert_eq!(published.qos, QoS::AtLeastOnce);
+    assert_eq!(serde_json::from_slice::<TelemetryMsg>(&published.payload).unwrap(), telemetry);
+
+    let alarm = AlarmMsg::new("plc", AlarmSeverity::Critical, "overpressure");
+    let msg = MsgBuilder::new()
+        .msg_type(MessageType::Alarm)
+        .topic("alarm/plc")
+        .data(Box::new(alarm))
+        .build()
+        .unwrap();
+    bench.harness.inject(msg).await;
+    let published = next(&mut plant).await;
+    assert_eq!(published.topic, "plant/line1/alarms/Critical");
+    assert_eq!(published.qos, QoS::ExactlyOnce);
+    assert_eq!(serde_json::from_slice::<AlarmMsg>(&published.payload).unwrap().text, "overpressure");
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn link_state_is_retained() {
+    let mut bench = start().await;
+
+    let link = ConnectionMsg::new("10.0.0.7:502".to_string(), LinkState::Connected, None);
+    let mut msg = MsgBuilder::new()
+        .msg_type(MessageType::Connection)
+        .topic("connection/plc")
+        .data(Box::new(link.clone()))
+        .build()
+        .unwrap();
+    msg.info.source = Some("plc".to_string());
+    bench.harness.inject(msg).await;
+    let broker = bench.broker.clone();
+    bench.harness.wait_until(|_| broker.retained("plant/line1/state/plc").is_some()).await;
+
+    // 后来的订阅者也能拿到最新状态
+    let mut plant = plant(&bench, "plant/line1/state/+").await;
+    let published = next(&mut plant).await;
+    assert!(published.retain);
+    let state: ConnectionMsg = serde_json::from_slice(&published.payload).unwrap();
+    assert_eq!((state.peer, state.state), (link.peer, link.state));
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn valid_commands_become_motor_messages() {
+    let mut bench = start().await;
+    let plant = plant(&bench, "unused").await;
+
+    command(&plant, r#"{ "motor": 1, "command": "Move", "direction": "Down" }"#).await;
+    command(&plant, r#"{ "motor": 2, "command": "Stop" }"#).await;
+    bench.harness.wait_until(|h| h.emitted("mqtt").len() >= 3).await;
+
+    let moves = bench.harness.emitted_of("mqtt", MessageType::Move);
+    let motor = moves[0].get_data::<MotorMsg>().unwrap();
+    assert_eq!((motor.id, motor.direction, motor.command), (1, MoveDirection::Down, MotorCommand::Move));
+    let stops = bench.harness.emitted_of("mqtt", MessageType::Stop);
+    let motor = stops[0].get_data::<MotorMsg>().unwrap();
+    assert_eq!((motor.id, motor.command), (2, MotorCommand::Stop));
+    assert!(bench.harness.alarms("mqtt").is_empty());
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn invalid_commands_raise_alarms() {
+    let mut bench = start().await;
+    let plant = plant(&bench, "unused").await;
+
+    command(&plant, r#"{ "motor": 9, "command": "Move" }"#).await;
+    command(&plant, r#"{ "motor": 1, "command": "Spin" }"#).await;
+    command(&plant, r#"{ "motor": 1, "command": "Move", "speed": 3 }"#).await;
+    command(&plant, "not json").await;
+    bench.harness.wait_until(|h| h.alarms("mqtt").len() == 4).await;
+
+    let alarms = bench.harness.alarms("mqtt");
+    assert!(alarms.iter().all(|alarm| alarm.severity == AlarmSeverity::Warning));
+    assert!(alarms[0].text.contains("motor 9 is not allowed"), "{}", alarms[0].text);
+    assert!(bench.harness.emitted_of("mqtt", MessageType::Move).is_empty());
+    let raised = bench.harness.emitted_of("mqtt", MessageType::Alarm);
+    assert_eq!(raised[0].topic(), "alarm/mqtt/csc");
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn reconnects_and_subscribes_again() {
+    let mut bench = start().await;
+    assert_eq!(bench.harness.link_states("mqtt")[0], LinkState::Connected);
+
+    bench.broker.drop_clients();
+    bench.harness.wait_until(|h| h.link_states("mqtt").len() == 2).await;
+    assert_eq!(bench.harness.link_states("mqtt")[1], LinkState::Disconnected);
+    let health = bench.harness.center().health().await;
+    assert!(matches!(health[&SystemId::from("mqtt")], Health::Degraded(_)));
+
+    bench.harness.wait_for_sleepers(1).await;
+    bench.harness.advance(Duration::from_secs(1)).await;
+    bench.harness.wait_until(|h| h.link_states("mqtt").len() == 3).await;
+    assert_eq!(bench.harness.link_states("mqtt")[2], LinkState::Connected);
+    let broker = bench.broker.clone();
+    bench.harness.wait_until(|_| broker.subscriptions("csc") == ["plant/line1/cmd"]).await;
+    let health = bench.harness.center().health().await;
+    assert_eq!(health[&SystemId::from("mqtt")], Health::Healthy);
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn username_and_password_are_checked() {
+    let (broker, addr) = broker(Some(("csc", "secret"))).await;
+
+    let mut harness = Harness::new();
+    let wrong = config(addr, r#""username": "csc", "password": "guess","#);
+    harness.register("mqtt", MqttBridge::new(wrong).with_clock(harness.clock()), Vec::<MessageType>::new());
+    harness.start().await;
+    harness.wait_until(|h| !h.link_states("mqtt").is_empty()).await;
+    let link = &harness.emitted_data::<ConnectionMsg>("mqtt", MessageType::Connection)[0];
+    assert_eq!(link.state, LinkState::Disconnected);
+    assert!(link.reason.as_deref().unwrap().contains("BadUserNamePassword"), "{:?}", link.reason);
+    assert!(broker.clients().is_empty());
+    harness.stop().await;
+
+    let mut harness = Harness::new();
+    let right = config(addr, r#""username": "csc", "password": "secret","#);
+    harness.register("mqtt", MqttBridge::new(right).with_clock(harness.clock()), Vec::<MessageType>::new());
+    harness.start().await;
+    harness.wait_until(|h| !h.link_states("mqtt").is_empty()).await;
+    assert_eq!(harness.link_states("mqtt")[0], LinkState::Connected);
+    assert_eq!(broker.clients(), ["csc"]);
+    harness.stop().await;
+}
+
+#[test]
+fn bad_configs_are_rejected() {
+    let bad = [
+        r#"{ "host": "h", "publish": [{ "filter": "telemetry/#", "topic": "plant/#" }] }"#,
+        r#"{ "host": "h", "publish": [{ "filter": "telemetry/#", "topic": "plant/{device" }] }"#,
+        r#"{ "host": "h", "commands": { "topic": "plant/#/cmd" } }"#,
+        r#"{ "host": "h", "password": "secret" }"#,
+    ];
+    for json in bad {
+        let config: MqttConfig = serde_json::from_str(json).unwrap();
+        assert!(config.validate().is_err(), "{}", json);
+    }
+    assert!(serde_json::from_str::<MqttConfig>(r#"{ "host": "h", "publish": [{ "filter": "a", "topic": "b", "qos": 3 }] }"#).is_err());
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::codec::Decoder;
+use crate::ids;
+use crate::services::*;
+use crate::transport::{self, Assembler, Limits, Secured};
+use crate::types::{DataValue, NodeId, StatusCode, Variant};
+
+/// A minimal OPC UA client over an unsecured channel: one session, the
+/// services the server offers, requests answered one at a time except for
+/// Publish. Errors are the status of a ServiceFault, the ERR message that
+/// closed the connection, or `BadCommunicationError`.
+pub struct Client {
+    reader: OwnedReadHalf,
+    writer: OwnedWriteHalf,
+    endpoint_url: String,
+    channel_id: u32,
+    token_id: u32,
+    sequence: u32,
+    request_id: u32,
+    send_buffer: u32,
+    authentication_token: NodeId,
+    assembler: Assembler,
+    /// Responses read while waiting for another, by request id.
+    responses: HashMap<u32, Vec<u8>>,
+}
+
+fn io(e: std::io::Error) -> StatusCode {
+    eprintln!("OPC UA client connection failed: {}", e);
+    StatusCode::BAD_COMMUNICATION_ERROR
+}
+
+impl Client {
+    /// Connects to `host:port` and opens a secure channel with policy None.
+    pub async fn connect(addr: &str) -> Result<Self, StatusCode> {
+        let stream = TcpStream::connect(addr).await.map_err(io)?;
+        let (reader, writer) = stream.into_split();
+        let mut client = Self {
+            reader,
+            writer,
+            endpoint_url: format!("opc.tcp://{}", addr),
+            channel_id: 0,
+            token_id: 0,
+            sequence: 0,
+            request_id: 0,
+            send_buffer: transport::BUFFER_SIZE,
+            authentication_token: NodeId::NULL,
+            assembler: Assembler::default(),
+            responses: HashMap::new(),
+        };
+        client.writer.write_all(&transport::hello(&Limits::ours(), &client.endpoint_url)).await.map_err(io)?;
+        let chunk = transport::read_chunk(&mut client.reader, transport::MAX_MESSAGE).await.map_err(io)?;
+        match &chunk.kind {
+            b"ACK" => client.send_buffer = transport::decode_acknowledge(&chunk.body)?.receive_buffer,
+            b"ERR" => return Err(transport::decode_error(&chunk.body).0),
+            _ => return Err(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID),
+        }
+        client.open(0).await?;
+        Ok(client)
+    }
+
+    /// Connects, then creates and activates a session with `identity`.
+    pub async fn login(addr: &str, identity: Identity) -> Result<Self, StatusCode> {
+        let mut client = Self::connect(addr).await?;
+        client.create_session("csc test").await?;
+        client.activate_session(identity).await?;
+        Ok(client)
+    }
+
+    fn header(&self) -> RequestHeader {
+        RequestHeader {
+            authentication_token: self.authentication_token.clone(),
+            timestamp: Utc::now(),
+            request_handle: self.request_id,
+            timeout_hint: 10_000,
+        }
+    }
+
+    /// Issues (0) or renews (1) the channel's security token.
+    pub async fn open(&mut self, request_type: u32) -> Result<u32, StatusCode> {
+        self.request_id += 1;
+        let request = OpenSecureChannelRequest {
+            request_type,
+            security_mode: SECURITY_MODE_NONE,
+            requested_lifetime: 600_000,
+        };
+        let body = encode_message(ids::OPEN_SECURE_CHANNEL_REQUEST, &self.header(), &request);
+        self.sequence += 1;
+        let bytes = transport::open(self.channel_id, self.sequence, self.request_id, &body);
+        self.writer.write_all(&bytes).await.map_err(io)?;
+        let request_id = self.request_id;
+        let body = self.response(request_id).await?;
+        let mut decoder = Decoder::new(&body);
+        let response: OpenSecureChannelResponse = decode_response(&mut decoder, ids::OPEN_SECURE_CHANNEL_RESPONSE)?;
+        self.channel_id = response.channel_id;
+        self.token_id = response.token_id;
+        Ok(response.token_id)
+    }
+
+    /// Sends a request and returns its request id without waiting.
+    pub async fn send<B: Binary>(&mut self, encoding: u32, body: &B) -> Result<u32, StatusCode> {
+        self.request_id += 1;
+        let body = encode_message(encoding, &self.header(), body);
+        let bytes = transport::message(
+            b"MSG",
+            self.channel_id,
+            self.token_id,
+            &mut self.sequence,
+            self.request_id,
+            &body,
+            self.send_buffer,
+        );
+        self.writer.write_all(&bytes).await.map_err(io)?;
+        Ok(self.request_id)
+    }
+
+    /// Waits for the response to a request sent with [`send`](Client::send).
+    pub async fn receive<B: Binary>(&mut self, request_id: u32, encoding: u32) -> Result<B, StatusCode> {
+        let body = self.response(request_id).await?;
+        decode_response(&mut Decoder::new(&body), encoding)
+    }
+
+    pub async fn request<Q: Binary, B: Binary>(&mut self, encoding: u32, body: &Q, response: u32) -> Result<B, StatusCode> {
+        let request_id = self.send(encoding, body).await?;
+        self.receive(request_id, response).await
+    }
+
+    async fn response(&mut self, request_id: u32) -> Result<Vec<u8>, StatusCode> {
+        loop {
+            if let Some(body) = self.responses.remove(&request_id) {
+                return Ok(body);
+            }
+            let chunk = transport::read_chunk(&mut self.reader, transport::MAX_MESSAGE).await.map_err(io)?;
+            match &chunk.kind {
+                b"OPN" => {
+                    let secured = Secured::decode(&chunk)?;
+                    self.responses.insert(secured.request_id, secured.body);
+                }
+                b"MSG" => {
+                    let secured = Secured::decode(&chunk)?;
+                    if let Some(message) = self.assembler.push(&chunk, secured)? {
+                        self.responses.insert(message.request_id, message.body);
+                    }
+                }
+                b"ERR" => return Err(transport::decode_error(&chunk.body).0),
+                _ => return Err(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID),
+            }
+        }
+    }
+
+    pub async fn get_endpoints(&mut self) -> Result<Vec<EndpointDescription>, StatusCode> {
+        let request = DiscoveryRequest {
+            endpoint_url: self.endpoint_url.clone(),
+        };
+        let endpoints: List<EndpointDescription> =
+            self.request(ids::GET_ENDPOINTS_REQUEST, &request, ids::GET_ENDPOINTS_RESPONSE).await?;
+        Ok(endpoints.0)
+    }
+
+    pub async fn create_session(&mut self, name: &str) -> Result<CreateSessionResponse, StatusCode> {
+        let request = CreateSessionRequest {
+            client: ApplicationDescription {
+                application_uri: "urn:csc:client".to_string(),
+                application_type: 1,
+                ..Default::default()
+            },
+            endpoint_url: self.endpoint_url.clone(),
+            session_name: name.to_string(),
+            requested_timeout: 60_000.0,
+        };
+        let response: CreateSessionResponse =
+            self.request(ids::CREATE_SESSION_REQUEST, &request, ids::CREATE_SESSION_RESPONSE).await?;
+        self.authentication_token = response.authentication_token.clone();
+        Ok(response)
+    }
+
+    pub async fn activate_session(&mut self, identity: Identity) -> Result<(), StatusCode> {
+        let request = ActivateSessionRequest {
+            identity: identity.to_extension_object(),
+        };
+        let _: ActivateSessionResponse =
+            self.request(ids::ACTIVATE_SESSION_REQUEST, &request, ids::ACTIVATE_SESSION_RESPONSE).await?;
+        Ok(())
+    }
+
+    pub async fn close_session(&mut self) -> Result<(), StatusCode> {
+        let request = CloseSessionRequest {
+            delete_subscriptions: true,
+        };
+        let _: Empty = self.request(ids::CLOSE_SESSION_REQUEST, &request, ids::CLOSE_SESSION_RESPONSE).await?;
+        self.authentication_token = NodeId::NULL;
+        Ok(())
+    }
+
+    pub async fn browse(&mut self, nodes: Vec<BrowseDescription>, max_references: u32) -> Result<Vec<BrowseResult>, StatusCode> {
+        let request = BrowseRequest { max_references, nodes };
+        let response: BrowseResponse = self.request(ids::BROWSE_REQUEST, &request, ids::BROWSE_RESPONSE).await?;
+        Ok(response.results)
+    }
+
+    pub async fn browse_next(&mut self, continuation_points: Vec<Vec<u8>>) -> Result<Vec<BrowseResult>, StatusCode> {
+        let request = BrowseNextRequest {
+            release: false,
+            continuation_points,
+        };
+        let response: BrowseResponse = self.request(ids::BROWSE_NEXT_REQUEST, &request, ids::BROWSE_NEXT_RESPONSE).await?;
+        Ok(response.results)
+    }
+
+    pub async fn read(&mut self, nodes: Vec<ReadValueId>) -> Result<Vec<DataValue>, StatusCode> {
+        let request = ReadRequest {
+            max_age: 0.0,
+            timestamps: TIMESTAMPS_BOTH,
+            nodes,
+        };
+        let response: ReadResponse = self.request(ids::READ_REQUEST, &request, ids::READ_RESPONSE).await?;
+        Ok(response.results)
+    }
+
+    pub async fn read_value(&mut self, node: &NodeId) -> Result<DataValue, StatusCode> {
+        let mut values = self.read(vec![ReadValueId::value(node.clone())]).await?;
+        values.pop().ok_or(StatusCode::BAD_UNEXPECTED_ERROR)
+    }
+
+    /// Writes the Value attribute of one node; the operation's status.
+    pub async fn write_value(&mut self, node: &NodeId, value: Variant) -> Result<StatusCode, StatusCode> {
+        let request = WriteRequest {
+            nodes: vec![WriteValue {
+                node_id: node.clone(),
+                attribute_id: ids::ATTR_VALUE,
+                index_range: None,
+                value: DataValue::plain(value),
+            }],
+        };
+        let response: StatusResponse = self.request(ids::WRITE_REQUEST, &request, ids::WRITE_RESPONSE).await?;
+        response.results.first().copied().ok_or(StatusCode::BAD_UNEXPECTED_ERROR)
+    }
+
+    pub async fn call(&mut self, object_id: &NodeId, method_id: &NodeId, input_arguments: Vec<Variant>) -> Result<CallMethodResult, StatusCode> {
+        let request = CallRequest {
+            methods: vec![CallMethodRequest {
+                object_id: object_id.clone(),
+                method_id: method_id.clone(),
+                input_arguments,
+            }],
+        };
+        let mut response: CallResponse = self.request(ids::CALL_REQUEST, &request, ids::CALL_RESPONSE).await?;
+        response.results.pop().ok_or(StatusCode::BAD_UNEXPECTED_ERROR)
+    }
+
+    pub async fn create_subscription(&mut self, interval_ms: f64, max_keep_alive_count: u32) -> Result<CreateSubscriptionResponse, StatusCode> {
+        let request = CreateSubscriptionRequest {
+            publishing_interval: interval_ms,
+            lifetime_count: max_keep_alive_count * 3,
+            max_keep_alive_count,
+            max_notifications: 0,
+            publishing_enabled: true,
+            priority: 0,
+        };
+        self.request(ids::CREATE_SUBSCRIPTION_REQUEST, &request, ids::CREATE_SUBSCRIPTION_RESPONSE).await
+    }
+
+    pub async fn create_monitored_items(
+        &mut self,
+        subscription_id: u32,
+        items: Vec<MonitoredItemCreateRequest>,
+    ) -> Result<Vec<MonitoredItemCreateResult>, StatusCode> {
+        let request = CreateMonitoredItemsRequest {
+            subscription_id,
+            timestamps: TIMESTAMPS_BOTH,
+            items,
+        };
+        let response: CreateMonitoredItemsResponse =
+            self.request(ids::CREATE_MONITORED_ITEMS_REQUEST, &request, ids::CREATE_MONITORED_ITEMS_RESPONSE).await?;
+        Ok(response.results)
+    }
+
+    pub async fn delete_subscriptions(&mut self, subscription_ids: Vec<u32>) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = DeleteSubscriptionsRequest { subscription_ids };
+        let response: StatusResponse =
+            self.request(ids::DELETE_SUBSCRIPTIONS_REQUEST, &request, ids::DELETE_SUBSCRIPTIONS_RESPONSE).await?;
+        Ok(response.results)
+    }
+
+    /// Sends a Publish request; the server answers it once it has
+    /// something to report, see [`publish_response`](Client::publish_response).
+    pub async fn publish(&mut self, acknowledgements: Vec<Acknowledgement>) -> Result<u32, StatusCode> {
+        self.send(ids::PUBLISH_REQUEST, &PublishRequest { acknowledgements }).await
+    }
+
+    pub async fn publish_response(&mut self, request_id: u32) -> Result<PublishResponse, StatusCode> {
+        self.receive(request_id, ids::PUBLISH_RESPONSE).await
+    }
+
+    /// Closes the secure channel and the connection.
+    pub async fn disconnect(mut self) -> Result<(), StatusCode> {
+        self.request_id += 1;
+        let body = encode_message(ids::CLOSE_SECURE_CHANNEL_REQUEST, &self.header(), &Empty);
+        let bytes = transport::message(
+            b"CLO",
+            self.channel_id,
+            self.token_id,
+            &mut self.sequence,
+            self.request_id,
+            &body,
+            self.send_buffer,
+        );
+        self.writer.write_all(&bytes).await.map_err(io)?;
+        self.writer.shutdown().await.map_err(io)
+    }
+
+    /// The channel and token a chunk was sent with, for tests of the
+    /// channel checks.
+    pub fn channel(&self) -> (u32, u32) {
+        (self.channel_id, self.token_id)
+    }
+}
+
+/// A response body, or the ServiceFault's status.
+fn decode_response<B: Binary>(decoder: &mut Decoder, encoding: u32) -> Result<B, StatusCode> {
+    let found = message_type(decoder)?;
+    let header = ResponseHeader::decode(decoder)?;
+    if found == ids::SERVICE_FAULT {
+        return Err(header.service_result);
+    }
+    if found != encoding {
+        return Err(StatusCode::BAD_UNEXPECTED_ERROR);
+    }
+    if header.service_result.is_bad() {
+        return Err(header.service_result);
+    }
+    B::decode(decoder)
+}
+
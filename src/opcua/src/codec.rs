@@ -0,0 +1,515 @@
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::types::{
+    DataValue, ExtensionObject, Identifier, LocalizedText, NodeId, QualifiedName, StatusCode, Variant,
+};
+
+/// 1601-01-01 到 1970-01-01 之间的 100 ns 数
+const EPOCH_OFFSET: i64 = 116_444_736_000_000_000;
+
+/// Longest string, byte string or array accepted from a peer.
+const MAX_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Nesting allowed for variants inside variants.
+const MAX_DEPTH: usize = 8;
+
+pub type DecodeResult<T> = Result<T, StatusCode>;
+
+/// Reads the OPC UA binary encoding, little endian throughout.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0, depth: 0 }
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    fn take(&mut self, len: usize) -> DecodeResult<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+        let end = end.ok_or(StatusCode::BAD_DECODING_ERROR)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> DecodeResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> DecodeResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> DecodeResult<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> DecodeResult<u16> {
+        Ok(u16::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn i16(&mut self) -> DecodeResult<i16> {
+        Ok(i16::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn u32(&mut self) -> DecodeResult<u32> {
+        Ok(u32::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn i32(&mut self) -> DecodeResult<i32> {
+        Ok(i32::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn u64(&mut self) -> DecodeResult<u64> {
+        Ok(u64::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn i64(&mut self) -> DecodeResult<i64> {
+        Ok(i64::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn f32(&mut self) -> DecodeResult<f32> {
+        Ok(f32::from_le_bytes(self.fixed()?))
+    }
+
+    pub fn f64(&mut self) -> DecodeResult<f64> {
+        Ok(f64::from_le_bytes(self.fixed()?))
+    }
+
+    /// A length prefix; `None` for -1, the encoding of null.
+    fn length(&mut self) -> DecodeResult<Option<usize>> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let len = len as usize;
+        if len > MAX_LENGTH {
+            return Err(StatusCode::BAD_ENCODING_LIMITS_EXCEEDED);
+        }
+        Ok(Some(len))
+    }
+
+    pub fn byte_string(&mut self) -> DecodeResult<Option<Vec<u8>>> {
+        match self.length()? {
+            Some(len) => Ok(Some(self.take(len)?.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn string(&mut self) -> DecodeResult<Option<String>> {
+        match self.byte_string()? {
+            Some(bytes) => String::from_utf8(bytes).map(Some).map_err(|_| StatusCode::BAD_DECODING_ERROR),
+            None => Ok(None),
+        }
+    }
+
+    pub fn date_time(&mut self) -> DecodeResult<DateTime<Utc>> {
+        Ok(from_ticks(self.i64()?))
+    }
+
+    /// An array, empty when null.
+    pub fn array<T>(&mut self, mut item: impl FnMut(&mut Self) -> DecodeResult<T>) -> DecodeResult<Vec<T>> {
+        let len = self.length()?.unwrap_or(0);
+        // 每个元素至少一个字节，先挡住伪造的长度
+        if len > self.data.len() - self.pos {
+            return Err(StatusCode::BAD_DECODING_ERROR);
+        }
+        (0..len).map(|_| item(self)).collect()
+    }
+
+    pub fn node_id(&mut self) -> DecodeResult<NodeId> {
+        let encoding = self.u8()?;
+        self.node_id_body(encoding & 0x3F)
+    }
+
+    fn node_id_body(&mut self, encoding: u8) -> DecodeResult<NodeId> {
+        let node = match encoding {
+            0x00 => NodeId::numeric(0, self.u8()? as u32),
+            0x01 => {
+                let namespace = self.u8()? as u16;
+                NodeId::numeric(namespace, self.u16()? as u32)
+            }
+            0x02 => {
+                let namespace = self.u16()?;
+                NodeId::numeric(namespace, self.u32()?)
+            }
+            0x03 => {
+                let namespace = self.u16()?;
+                let id = self.string()?.ok_or(StatusCode::BAD_DECODING_ERROR)?;
+                NodeId::string(namespace, id)
+            }
+            0x04 => NodeId {
+                namespace: self.u16()?,
+                identifier: Identifier::Guid(self.fixed()?),
+            },
+            0x05 => NodeId {
+                namespace: self.u16()?,
+                identifier: Identifier::Opaque(self.byte_string()?.unwrap_or_default()),
+            },
+            _ => return Err(StatusCode::BAD_DECODING_ERROR),
+        };
+        Ok(node)
+    }
+
+    /// An expanded node id; the namespace uri and server index are dropped.
+    pub fn expanded_node_id(&mut self) -> DecodeResult<NodeId> {
+        let encoding = self.u8()?;
+        let node = self.node_id_body(encoding & 0x3F)?;
+        if encoding & 0x80 != 0 {
+            self.string()?;
+        }
+        if encoding & 0x40 != 0 {
+            self.u32()?;
+        }
+        Ok(node)
+    }
+
+    pub fn status_code(&mut self) -> DecodeResult<StatusCode> {
+        Ok(StatusCode(self.u32()?))
+    }
+
+    pub fn qualified_name(&mut self) -> DecodeResult<QualifiedName> {
+        Ok(QualifiedName {
+            namespace: self.u16()?,
+            name: self.string()?,
+        })
+    }
+
+    pub fn localized_text(&mut self) -> DecodeResult<LocalizedText> {
+        let mask = self.u8()?;
+        let locale = if mask & 0x01 != 0 { self.string()? } else { None };
+        let text = if mask & 0x02 != 0 { self.string()? } else { None };
+        Ok(LocalizedText { locale, text })
+    }
+
+    pub fn extension_object(&mut self) -> DecodeResult<ExtensionObject> {
+        let type_id = self.node_id()?;
+        let body = match self.u8()? {
+            0 => None,
+            1 | 2 => self.byte_string()?,
+            _ => return Err(StatusCode::BAD_DECODING_ERROR),
+        };
+        Ok(ExtensionObject { type_id, body })
+    }
+
+    /// Skips a diagnostic info; the server never asks for them.
+    pub fn diagnostic_info(&mut self) -> DecodeResult<()> {
+        let mask = self.u8()?;
+        for bit in [0x01, 0x02, 0x04, 0x08] {
+            if mask & bit != 0 {
+                self.i32()?;
+            }
+        }
+        if mask & 0x10 != 0 {
+            self.string()?;
+        }
+        if mask & 0x20 != 0 {
+            self.u32()?;
+        }
+        if mask & 0x40 != 0 {
+            self.enter()?;
+            self.diagnostic_info()?;
+            self.depth -= 1;
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self) -> DecodeResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(StatusCode::BAD_ENCODING_LIMITS_EXCEEDED);
+        }
+        Ok(())
+    }
+
+    pub fn variant(&mut self) -> DecodeResult<Variant> {
+        let mask = self.u8()?;
+        let type_id = mask & 0x3F;
+        if mask & 0x80 == 0 {
+            return self.scalar(type_id);
+        }
+        self.enter()?;
+        let items = self.array(|decoder| decoder.scalar(type_id));
+        self.depth -= 1;
+        let items = items?;
+        if mask & 0x40 != 0 {
+            // 多维数组按一维处理
+            self.array(|decoder| decoder.i32())?;
+        }
+        Ok(Variant::Array(type_id, items))
+    }
+
+    fn scalar(&mut self, type_id: u8) -> DecodeResult<Variant> {
+        let value = match type_id {
+            0 => Variant::Empty,
+            1 => Variant::Boolean(self.bool()?),
+            2 => Variant::SByte(self.u8()? as i8),
+            3 => Variant::Byte(self.u8()?),
+            4 => Variant::Int16(self.i16()?),
+            5 => Variant::UInt16(self.u16()?),
+            6 => Variant::Int32(self.i32()?),
+            7 => Variant::UInt32(self.u32()?),
+            8 => Variant::Int64(self.i64()?),
+            9 => Variant::UInt64(self.u64()?),
+            10 => Variant::Float(self.f32()?),
+            11 => Variant::Double(self.f64()?),
+            12 => Variant::String(self.string()?),
+            13 => Variant::DateTime(self.date_time()?),
+            15 => Variant::ByteString(self.byte_string()?),
+            17 => Variant::NodeId(self.node_id()?),
+            19 => Variant::StatusCode(self.status_code()?),
+            20 => Variant::QualifiedName(self.qualified_name()?),
+            21 => Variant::LocalizedText(self.localized_text()?),
+            22 => Variant::ExtensionObject(self.extension_object()?),
+            _ => return Err(StatusCode::BAD_DECODING_ERROR),
+        };
+        Ok(value)
+    }
+
+    pub fn data_value(&mut self) -> DecodeResult<DataValue> {
+        let mask = self.u8()?;
+        let value = if mask & 0x01 != 0 { Some(self.variant()?) } else { None };
+        let status = if mask & 0x02 != 0 { Some(self.status_code()?) } else { None };
+        let source_timestamp = if mask & 0x04 != 0 { Some(self.date_time()?) } else { None };
+        if mask & 0x10 != 0 {
+            self.u16()?;
+        }
+        let server_timestamp = if mask & 0x08 != 0 { Some(self.date_time()?) } else { None };
+        if mask & 0x20 != 0 {
+            self.u16()?;
+        }
+        Ok(DataValue {
+            value,
+            status,
+            source_timestamp,
+            server_timestamp,
+        })
+    }
+}
+
+/// Writes the OPC UA binary encoding.
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn i16(&mut self, value: i16) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn i64(&mut self, value: i64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn f32(&mut self, value: f32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn f64(&mut self, value: f64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn byte_string(&mut self, value: Option<&[u8]>) -> &mut Self {
+        match value {
+            Some(bytes) => self.i32(bytes.len() as i32).bytes(bytes),
+            None => self.i32(-1),
+        }
+    }
+
+    pub fn string(&mut self, value: Option<&str>) -> &mut Self {
+        self.byte_string(value.map(str::as_bytes))
+    }
+
+    pub fn str(&mut self, value: &str) -> &mut Self {
+        self.string(Some(value))
+    }
+
+    pub fn date_time(&mut self, value: DateTime<Utc>) -> &mut Self {
+        self.i64(to_ticks(value))
+    }
+
+    pub fn array<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T)) -> &mut Self {
+        self.i32(items.len() as i32);
+        for value in items {
+            item(self, value);
+        }
+        self
+    }
+
+    pub fn node_id(&mut self, node: &NodeId) -> &mut Self {
+        self.node_id_with(node, 0)
+    }
+
+    fn node_id_with(&mut self, node: &NodeId, flags: u8) -> &mut Self {
+        match &node.identifier {
+            Identifier::Numeric(id) if node.namespace == 0 && *id <= 0xFF => self.u8(flags).u8(*id as u8),
+            Identifier::Numeric(id) if node.namespace <= 0xFF && *id <= 0xFFFF => {
+                self.u8(flags | 0x01).u8(node.namespace as u8).u16(*id as u16)
+            }
+            Identifier::Numeric(id) => self.u8(flags | 0x02).u16(node.namespace).u32(*id),
+            Identifier::String(id) => self.u8(flags | 0x03).u16(node.namespace).str(id),
+            Identifier::Guid(id) => self.u8(flags | 0x04).u16(node.namespace).bytes(id),
+            Identifier::Opaque(id) => self.u8(flags | 0x05).u16(node.namespace).byte_string(Some(id)),
+        }
+    }
+
+    pub fn expanded_node_id(&mut self, node: &NodeId) -> &mut Self {
+        self.node_id(node)
+    }
+
+    pub fn status_code(&mut self, status: StatusCode) -> &mut Self {
+        self.u32(status.0)
+    }
+
+    pub fn qualified_name(&mut self, name: &QualifiedName) -> &mut Self {
+        self.u16(name.namespace).string(name.name.as_deref())
+    }
+
+    pub fn localized_text(&mut self, text: &LocalizedText) -> &mut Self {
+        let mask = text.locale.is_some() as u8 | (text.text.is_some() as u8) << 1;
+        self.u8(mask);
+        if let Some(locale) = &text.locale {
+            self.str(locale);
+        }
+        if let Some(text) = &text.text {
+            self.str(text);
+        }
+        self
+    }
+
+    pub fn extension_object(&mut self, object: &ExtensionObject) -> &mut Self {
+        self.node_id(&object.type_id);
+        match &object.body {
+            Some(body) => self.u8(1).byte_string(Some(body)),
+            None => self.u8(0),
+        }
+    }
+
+    /// An empty diagnostic info.
+    pub fn diagnostic_info(&mut self) -> &mut Self {
+        self.u8(0)
+    }
+
+    pub fn variant(&mut self, value: &Variant) -> &mut Self {
+        match value {
+            Variant::Array(type_id, items) => {
+                self.u8(type_id | 0x80);
+                self.array(items, |encoder, item| {
+                    encoder.scalar(item);
+                })
+            }
+            Variant::Empty => self.u8(0),
+            value => self.u8(value.type_id()).scalar(value),
+        }
+    }
+
+    fn scalar(&mut self, value: &Variant) -> &mut Self {
+        match value {
+            Variant::Empty | Variant::Array(..) => self,
+            Variant::Boolean(v) => self.bool(*v),
+            Variant::SByte(v) => self.u8(*v as u8),
+            Variant::Byte(v) => self.u8(*v),
+            Variant::Int16(v) => self.i16(*v),
+            Variant::UInt16(v) => self.u16(*v),
+            Variant::Int32(v) => self.i32(*v),
+            Variant::UInt32(v) => self.u32(*v),
+            Variant::Int64(v) => self.i64(*v),
+            Variant::UInt64(v) => self.u64(*v),
+            Variant::Float(v) => self.f32(*v),
+            Variant::Double(v) => self.f64(*v),
+            Variant::String(v) => self.string(v.as_deref()),
+            Variant::DateTime(v) => self.date_time(*v),
+            Variant::ByteString(v) => self.byte_string(v.as_deref()),
+            Variant::NodeId(v) => self.node_id(v),
+            Variant::StatusCode(v) => self.status_code(*v),
+            Variant::QualifiedName(v) => self.qualified_name(v),
+            Variant::LocalizedText(v) => self.localized_text(v),
+            Variant::ExtensionObject(v) => self.extension_object(v),
+        }
+    }
+
+    pub fn data_value(&mut self, value: &DataValue) -> &mut Self {
+        let mask = value.value.is_some() as u8
+            | (value.status.is_some() as u8) << 1
+            | (value.source_timestamp.is_some() as u8) << 2
+            | (value.server_timestamp.is_some() as u8) << 3;
+        self.u8(mask);
+        if let Some(variant) = &value.value {
+            self.variant(variant);
+        }
+        if let Some(status) = value.status {
+            self.status_code(status);
+        }
+        if let Some(at) = value.source_timestamp {
+            self.date_time(at);
+        }
+        if let Some(at) = value.server_timestamp {
+            self.date_time(at);
+        }
+        self
+    }
+}
+
+pub fn to_ticks(at: DateTime<Utc>) -> i64 {
+    let micros = at.timestamp_micros();
+    micros.saturating_mul(10).saturating_add(EPOCH_OFFSET).max(0)
+}
+
+pub fn from_ticks(ticks: i64) -> DateTime<Utc> {
+    let micros = (ticks.max(0) - EPOCH_OFFSET) / 10;
+    Utc.timestamp_micros(micros).single().unwrap_or_default()
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use subsystem::BoxError;
+
+/// What the OPC UA server listens on, who may log in and which devices it
+/// exposes, read from a JSON file:
+///
+/// ```json
+/// {
+///   "listen": "0.0.0.0:4840",
+///   "allow_remote": true,
+///   "application_name": "CSC line 1",
+///   "anonymous": "read_only",
+///   "allow_plaintext_passwords": true,
+///   "users": [{ "username": "operator", "password": "secret", "access": "operate" }],
+///   "devices": [
+///     {
+///       "name": "pump1",
+///       "telemetry": [{ "group": "status", "values": ["flow", "pressure"] }],
+///       "setpoints": ["speed"],
+///       "motor": 1
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `listen` defaults to `127.0.0.1:4840`. The server has only been tested
+/// against its own [`Client`](crate::Client), so it is meant for loopback
+/// and development use: `listen` must be a loopback address unless
+/// `allow_remote` is set. Leaving out `anonymous` refuses
+/// anonymous sessions. The server only speaks security policy None, so user
+/// passwords cross the network in the clear; `users` are refused unless
+/// `allow_plaintext_passwords` says that is acceptable. Telemetry variables
+/// are filled from the bus; a device with a `motor` gets Move, Stop and
+/// Home methods for that motor id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpcuaConfig {
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    /// 监听非回环地址要显式允许
+    #[serde(default)]
+    pub allow_remote: bool,
+    /// URL announced to clients; derived from `listen` if not set.
+    #[serde(default)]
+    pub endpoint_url: Option<String>,
+    #[serde(default = "default_application_name")]
+    pub application_name: String,
+    #[serde(default)]
+    pub anonymous: Option<Access>,
+    /// 明文密码要显式允许
+    #[serde(default)]
+    pub allow_plaintext_passwords: bool,
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// What a session may do beyond browsing and reading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    ReadOnly,
+    /// Write setpoints and call methods as well.
+    #[default]
+    Operate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub access: Access,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub telemetry: Vec<TelemetryGroup>,
+    #[serde(default)]
+    pub setpoints: Vec<String>,
+    #[serde(default)]
+    pub motor: Option<i32>,
+}
+
+/// The values of one telemetry group, as in [`message::TelemetryMsg`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TelemetryGroup {
+    pub group: String,
+    pub values: Vec<String>,
+}
+
+/// Names of the setpoint folder and the methods under a device node.
+const RESERVED: [&str; 4] = ["setpoints", "Move", "Stop", "Home"];
+
+fn default_listen() -> String {
+    "127.0.0.1:4840".to_string()
+}
+
+fn default_application_name() -> String {
+    "CSC".to_string()
+}
+
+impl OpcuaConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that `listen` is a loopback address unless remote clients are
+    /// allowed, that names are unique and usable in node ids, that somebody
+    /// can log in, and that users are only configured if plaintext passwords
+    /// are allowed.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if !self.allow_remote && !is_loopback(&self.listen) {
+            let reason = format!("listen {} is not a loopback address; set allow_remote to serve other hosts", self.listen);
+            return Err(reason.into());
+        }
+        if self.anonymous.is_none() && self.users.is_empty() {
+            return Err("neither anonymous sessions nor any user allowed".into());
+        }
+        if !self.users.is_empty() && !self.allow_plaintext_passwords {
+            let reason = "users would send their passwords in plaintext; set allow_plaintext_passwords to accept that";
+            return Err(reason.into());
+        }
+        let mut users = HashSet::new();
+        for user in &self.users {
+            if user.username.is_empty() || !users.insert(&user.username) {
+                return Err(format!("user name `{}` empty or repeated", user.username).into());
+            }
+        }
+        let mut devices = HashSet::new();
+        for device in &self.devices {
+            check_name("device", &device.name)?;
+            if !devices.insert(&device.name) {
+                return Err(format!("device {} configured twice", device.name).into());
+            }
+            let mut names = HashSet::new();
+            for group in &device.telemetry {
+                check_name("telemetry group", &group.group)?;
+                if RESERVED.contains(&group.group.as_str()) {
+                    return Err(format!("{}: group name {} is reserved", device.name, group.group).into());
+                }
+                for value in &group.values {
+                    check_name("telemetry value", value)?;
+                    if !names.insert(format!("{}/{}", group.group, value)) {
+                        return Err(format!("{}: {}/{} configured twice", device.name, group.group, value).into());
+                    }
+                }
+            }
+            for setpoint in &device.setpoints {
+                check_name("setpoint", setpoint)?;
+                if !names.insert(format!("setpoints/{}", setpoint)) {
+                    return Err(format!("{}: setpoint {} configured twice", device.name, setpoint).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `listen` is a loopback address or `localhost`.
+fn is_loopback(listen: &str) -> bool {
+    match listen.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().is_loopback(),
+        Err(_) => listen.rsplit_once(':').is_some_and(|(host, _)| host.eq_ignore_ascii_case("localhost")),
+    }
+}
+
+fn check_name(what: &str, name: &str) -> Result<(), BoxError> {
+    if name.is_empty() || name.contains('/') {
+        return Err(format!("{} name `{}` is empty or contains `/`", what, name).into());
+    }
+    Ok(())
+}
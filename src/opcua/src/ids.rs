@@ -0,0 +1,125 @@
+//! Numeric ids of namespace 0 nodes used by the server.
+
+// 标准对象
+pub const ROOT_FOLDER: u32 = 84;
+pub const OBJECTS_FOLDER: u32 = 85;
+pub const TYPES_FOLDER: u32 = 86;
+pub const VIEWS_FOLDER: u32 = 87;
+pub const SERVER: u32 = 2253;
+pub const SERVER_ARRAY: u32 = 2254;
+pub const NAMESPACE_ARRAY: u32 = 2255;
+pub const SERVER_STATUS: u32 = 2256;
+pub const SERVER_STATUS_START_TIME: u32 = 2257;
+pub const SERVER_STATUS_CURRENT_TIME: u32 = 2258;
+pub const SERVER_STATUS_STATE: u32 = 2259;
+
+// 引用类型
+pub const REFERENCES: u32 = 31;
+pub const NON_HIERARCHICAL_REFERENCES: u32 = 32;
+pub const HIERARCHICAL_REFERENCES: u32 = 33;
+pub const HAS_CHILD: u32 = 34;
+pub const ORGANIZES: u32 = 35;
+pub const HAS_TYPE_DEFINITION: u32 = 40;
+pub const AGGREGATES: u32 = 44;
+pub const HAS_PROPERTY: u32 = 46;
+pub const HAS_COMPONENT: u32 = 47;
+
+// 类型定义
+pub const BASE_OBJECT_TYPE: u32 = 58;
+pub const FOLDER_TYPE: u32 = 61;
+pub const BASE_DATA_VARIABLE_TYPE: u32 = 63;
+pub const PROPERTY_TYPE: u32 = 68;
+pub const SERVER_TYPE: u32 = 2004;
+pub const SERVER_STATUS_TYPE: u32 = 2138;
+
+// 数据类型
+pub const BOOLEAN: u32 = 1;
+pub const INT32: u32 = 6;
+pub const DOUBLE: u32 = 11;
+pub const STRING: u32 = 12;
+pub const DATE_TIME: u32 = 13;
+pub const ARGUMENT: u32 = 296;
+pub const SERVER_STATE: u32 = 852;
+pub const SERVER_STATUS_DATA_TYPE: u32 = 862;
+
+// 二进制编码
+pub const ARGUMENT_ENCODING: u32 = 298;
+pub const ANONYMOUS_IDENTITY_TOKEN: u32 = 321;
+pub const USER_NAME_IDENTITY_TOKEN: u32 = 324;
+pub const SERVER_STATUS_ENCODING: u32 = 864;
+pub const DATA_CHANGE_NOTIFICATION: u32 = 811;
+pub const SERVICE_FAULT: u32 = 397;
+
+pub const FIND_SERVERS_REQUEST: u32 = 422;
+pub const FIND_SERVERS_RESPONSE: u32 = 425;
+pub const GET_ENDPOINTS_REQUEST: u32 = 428;
+pub const GET_ENDPOINTS_RESPONSE: u32 = 431;
+pub const OPEN_SECURE_CHANNEL_REQUEST: u32 = 446;
+pub const OPEN_SECURE_CHANNEL_RESPONSE: u32 = 449;
+pub const CLOSE_SECURE_CHANNEL_REQUEST: u32 = 452;
+pub const CREATE_SESSION_REQUEST: u32 = 461;
+pub const CREATE_SESSION_RESPONSE: u32 = 464;
+pub const ACTIVATE_SESSION_REQUEST: u32 = 467;
+pub const ACTIVATE_SESSION_RESPONSE: u32 = 470;
+pub const CLOSE_SESSION_REQUEST: u32 = 473;
+pub const CLOSE_SESSION_RESPONSE: u32 = 476;
+pub const BROWSE_REQUEST: u32 = 527;
+pub const BROWSE_RESPONSE: u32 = 530;
+pub const BROWSE_NEXT_REQUEST: u32 = 533;
+pub const BROWSE_NEXT_RESPONSE: u32 = 536;
+pub const READ_REQUEST: u32 = 631;
+pub const READ_RESPONSE: u32 = 634;
+pub const WRITE_REQUEST: u32 = 673;
+pub const WRITE_RESPONSE: u32 = 676;
+pub const CALL_REQUEST: u32 = 712;
+pub const CALL_RESPONSE: u32 = 715;
+pub const CREATE_MONITORED_ITEMS_REQUEST: u32 = 751;
+pub const CREATE_MONITORED_ITEMS_RESPONSE: u32 = 754;
+pub const DELETE_MONITORED_ITEMS_REQUEST: u32 = 781;
+pub const DELETE_MONITORED_ITEMS_RESPONSE: u32 = 784;
+pub const CREATE_SUBSCRIPTION_REQUEST: u32 = 787;
+pub const CREATE_SUBSCRIPTION_RESPONSE: u32 = 790;
+pub const SET_PUBLISHING_MODE_REQUEST: u32 = 799;
+pub const SET_PUBLISHING_MODE_RESPONSE: u32 = 802;
+pub const PUBLISH_REQUEST: u32 = 826;
+pub const PUBLISH_RESPONSE: u32 = 829;
+pub const DELETE_SUBSCRIPTIONS_REQUEST: u32 = 847;
+pub const DELETE_SUBSCRIPTIONS_RESPONSE: u32 = 850;
+
+// 属性
+pub const ATTR_NODE_ID: u32 = 1;
+pub const ATTR_NODE_CLASS: u32 = 2;
+pub const ATTR_BROWSE_NAME: u32 = 3;
+pub const ATTR_DISPLAY_NAME: u32 = 4;
+pub const ATTR_DESCRIPTION: u32 = 5;
+pub const ATTR_WRITE_MASK: u32 = 6;
+pub const ATTR_USER_WRITE_MASK: u32 = 7;
+pub const ATTR_EVENT_NOTIFIER: u32 = 12;
+pub const ATTR_VALUE: u32 = 13;
+pub const ATTR_DATA_TYPE: u32 = 14;
+pub const ATTR_VALUE_RANK: u32 = 15;
+pub const ATTR_ARRAY_DIMENSIONS: u32 = 16;
+pub const ATTR_ACCESS_LEVEL: u32 = 17;
+pub const ATTR_USER_ACCESS_LEVEL: u32 = 18;
+pub const ATTR_MINIMUM_SAMPLING_INTERVAL: u32 = 19;
+pub const ATTR_HISTORIZING: u32 = 20;
+pub const ATTR_EXECUTABLE: u32 = 21;
+pub const ATTR_USER_EXECUTABLE: u32 = 22;
+
+/// Whether reference type `child` is `parent` or one of its subtypes.
+pub fn is_subtype(child: u32, parent: u32) -> bool {
+    let mut current = child;
+    loop {
+        if current == parent {
+            return true;
+        }
+        current = match current {
+            NON_HIERARCHICAL_REFERENCES | HIERARCHICAL_REFERENCES => REFERENCES,
+            HAS_CHILD | ORGANIZES => HIERARCHICAL_REFERENCES,
+            AGGREGATES => HAS_CHILD,
+            HAS_PROPERTY | HAS_COMPONENT => AGGREGATES,
+            HAS_TYPE_DEFINITION => NON_HIERARCHICAL_REFERENCES,
+            _ => return false,
+        };
+    }
+}
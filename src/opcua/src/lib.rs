@@ -0,0 +1,25 @@
+//! OPC UA server for the CSC: exposes the configured devices with their
+//! telemetry, writable setpoints and motor methods over UA TCP with the
+//! binary encoding and security policy None, plus a small client to test
+//! with. Only the subset of the protocol the server needs is implemented,
+//! and it has not been tested against another OPC UA stack, so it serves
+//! loopback clients for development unless remote access is enabled.
+
+mod client;
+mod codec;
+mod config;
+pub mod ids;
+mod server;
+mod services;
+mod space;
+mod transport;
+mod types;
+
+pub use client::*;
+pub use codec::*;
+pub use config::*;
+pub use server::*;
+pub use services::*;
+pub use space::*;
+pub use transport::*;
+pub use types::*;
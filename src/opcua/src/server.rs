@@ -0,0 +1,1056 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use message::{MessageType, MotorCommand, MotorMsg, MoveDirection, Msg, MsgBuilder, SetpointMsg, TelemetryMsg};
+use subsystem::{BoxError, Clock, Context, Health, Publisher, SubSystem, SystemClock};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::codec::Decoder;
+use crate::config::{Access, OpcuaConfig};
+use crate::ids;
+use crate::services::*;
+use crate::space::{AddressSpace, Binding, MethodKind, NAMESPACE};
+use crate::transport::{self, Assembler, Chunk, Secured, Security, SECURITY_POLICY_NONE, TRANSPORT_PROFILE};
+use crate::types::{DataValue, NodeId, StatusCode, Variant};
+
+/// Publish requests a connection may hold back for later notifications.
+const MAX_PUBLISH_REQUESTS: usize = 10;
+/// Subscriptions per connection.
+const MAX_SUBSCRIPTIONS: usize = 16;
+/// Monitored items per subscription.
+const MAX_ITEMS: usize = 1000;
+/// Operations in one Read, Write, Browse or Call.
+const MAX_OPERATIONS: usize = 1000;
+/// 会话超时与安全通道生命周期的取值范围，毫秒
+const MIN_LIFETIME: u32 = 10_000;
+const MAX_LIFETIME: u32 = 3_600_000;
+const MIN_PUBLISHING_INTERVAL: f64 = 50.0;
+const DEFAULT_PUBLISHING_INTERVAL: f64 = 1000.0;
+
+const POLICY_ANONYMOUS: &str = "anonymous";
+const POLICY_USER_NAME: &str = "username";
+
+/// State shared by the subsystem and its connections.
+struct Shared {
+    config: OpcuaConfig,
+    endpoint_url: String,
+    space: Mutex<AddressSpace>,
+    clock: Arc<dyn Clock>,
+    publisher: Publisher,
+    /// 通道、会话 id 共用的计数器
+    next_id: AtomicU32,
+}
+
+impl Shared {
+    fn next_id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn endpoints(&self) -> Vec<EndpointDescription> {
+        let mut tokens = Vec::new();
+        if self.config.anonymous.is_some() {
+            tokens.push(UserTokenPolicy {
+                policy_id: POLICY_ANONYMOUS.to_string(),
+                token_type: TOKEN_ANONYMOUS,
+            });
+        }
+        if self.config.allow_plaintext_passwords && !self.config.users.is_empty() {
+            tokens.push(UserTokenPolicy {
+                policy_id: POLICY_USER_NAME.to_string(),
+                token_type: TOKEN_USER_NAME,
+            });
+        }
+        vec![EndpointDescription {
+            endpoint_url: self.endpoint_url.clone(),
+            server: self.application(),
+            security_mode: SECURITY_MODE_NONE,
+            security_policy_uri: SECURITY_POLICY_NONE.to_string(),
+            user_identity_tokens: tokens,
+            transport_profile_uri: TRANSPORT_PROFILE.to_string(),
+        }]
+    }
+
+    fn application(&self) -> ApplicationDescription {
+        ApplicationDescription {
+            application_uri: PRODUCT_URI.to_string(),
+            product_uri: PRODUCT_URI.to_string(),
+            application_name: crate::types::LocalizedText::new(self.config.application_name.as_str()),
+            application_type: 0,
+            discovery_urls: vec![self.endpoint_url.clone()],
+        }
+    }
+
+    /// The access an identity token gets, if any.
+    fn authenticate(&self, identity: &Identity) -> Result<Access, StatusCode> {
+        match identity {
+            Identity::Anonymous { .. } => self.config.anonymous.ok_or(StatusCode::BAD_IDENTITY_TOKEN_REJECTED),
+            Identity::UserName {
+                username,
+                password,
+                encryption,
+                ..
+            } => {
+                if !self.config.allow_plaintext_passwords {
+                    return Err(StatusCode::BAD_IDENTITY_TOKEN_REJECTED);
+                }
+                // 通道没有加密，只收明文密码
+                if encryption.as_deref().is_some_and(|algorithm| !algorithm.is_empty()) {
+                    return Err(StatusCode::BAD_IDENTITY_TOKEN_INVALID);
+                }
+                let user = self.config.users.iter().find(|user| &user.username == username);
+                match user {
+                    Some(user) if user.password.as_bytes() == password.as_slice() => Ok(user.access),
+                    _ => Err(StatusCode::BAD_USER_ACCESS_DENIED),
+                }
+            }
+        }
+    }
+}
+
+/// Serves the configured devices over OPC UA (UA TCP, binary encoding,
+/// security policy None).
+///
+/// Register it for `MessageType::Telemetry` and `MessageType::Setpoint`:
+/// telemetry values and setpoints from the bus update the variables in the
+/// [`AddressSpace`], which clients read, browse and subscribe to. A write to
+/// a setpoint variable is published as a [`SetpointMsg`]; the device's Move,
+/// Stop and Home methods publish [`MotorMsg`]s like the other front ends.
+/// Sessions are anonymous or, where the config allows plaintext passwords,
+/// log in with a configured user name and password; read-only sessions get `BadUserAccessDenied` for writes and
+/// calls. Sessions and subscriptions live as long as the connection.
+///
+/// Refuses to start on a non-loopback address unless the config sets
+/// `allow_remote`; see [`OpcuaConfig`].
+pub struct OpcuaServer {
+    config: OpcuaConfig,
+    listener: Option<TcpListener>,
+    clock: Arc<dyn Clock>,
+    shared: Option<Arc<Shared>>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl OpcuaServer {
+    pub fn new(config: OpcuaConfig) -> Self {
+        Self {
+            config,
+            listener: None,
+            clock: Arc::new(SystemClock),
+            shared: None,
+            server: None,
+        }
+    }
+
+    /// Serves on an already bound listener instead of `listen`, e.g. port 0 in
+    /// tests. Only for the first start; a restart binds `listen`.
+    pub fn with_listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn endpoint_url(&self, local: SocketAddr) -> String {
+        if let Some(url) = &self.config.endpoint_url {
+            return url.clone();
+        }
+        let host = if local.ip().is_unspecified() { "localhost".to_string() } else { local.ip().to_string() };
+        format!("opc.tcp://{}:{}", host, local.port())
+    }
+
+    async fn serve(shared: Arc<Shared>, listener: TcpListener) {
+        // 停止时丢弃 JoinSet 会一并断开所有连接
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let _ = stream.set_nodelay(true);
+                        connections.spawn(Connection::run(shared.clone(), stream, peer));
+                    }
+                    Err(e) => {
+                        eprintln!("OPC UA server failed to accept: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                },
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SubSystem for OpcuaServer {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => TcpListener::bind(&self.config.listen).await?,
+        };
+        let local = listener.local_addr()?;
+        // 回环检查也覆盖解析成远程地址的主机名和外部传入的监听器
+        if !self.config.allow_remote && !local.ip().is_loopback() {
+            return Err(format!("refusing to serve OPC UA on {} without allow_remote", local).into());
+        }
+        let endpoint_url = self.endpoint_url(local);
+        // 重启后保留已有的变量值
+        let space = match self.shared.take() {
+            Some(shared) => Arc::try_unwrap(shared)
+                .map(|shared| shared.space.into_inner().unwrap())
+                .unwrap_or_else(|_| AddressSpace::new(&self.config, self.clock.now())),
+            None => AddressSpace::new(&self.config, self.clock.now()),
+        };
+        let shared = Arc::new(Shared {
+            config: self.config.clone(),
+            endpoint_url,
+            space: Mutex::new(space),
+            clock: self.clock.clone(),
+            publisher: ctx.publisher(),
+            next_id: AtomicU32::new(1),
+        });
+        self.shared = Some(shared.clone());
+        self.server = Some(tokio::spawn(Self::serve(shared, listener)));
+        Ok(())
+    }
+
+    /// Updates the variables of the telemetry values or setpoint.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        let Some(shared) = &self.shared else {
+            return Ok(());
+        };
+        let now = shared.clock.now();
+        match msg.get_msg_type() {
+            MessageType::Telemetry => {
+                let Some(telemetry) = msg.get_data::<TelemetryMsg>() else {
+                    return Ok(());
+                };
+                let mut space = shared.space.lock().unwrap();
+                for (name, value) in &telemetry.values {
+                    let id = NodeId::string(NAMESPACE, format!("{}/{}/{}", telemetry.device, telemetry.group, name));
+                    space.set_value(&id, *value, now);
+                }
+            }
+            MessageType::Setpoint => {
+                let Some(setpoint) = msg.get_data::<SetpointMsg>() else {
+                    return Ok(());
+                };
+                let id = NodeId::string(NAMESPACE, format!("{}/setpoints/{}", setpoint.device, setpoint.name));
+                shared.space.lock().unwrap().set_value(&id, setpoint.value, now);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &self.server {
+            Some(server) if server.is_finished() => Health::Unhealthy("OPC UA server stopped".to_string()),
+            _ => Health::Healthy,
+        }
+    }
+
+    fn rollup(&mut self) {}
+}
+
+struct Session {
+    /// None until activated.
+    access: Option<Access>,
+    /// 浏览结果剩下的部分
+    continuation: HashMap<Vec<u8>, Vec<ReferenceDescription>>,
+}
+
+struct Item {
+    node: NodeId,
+    attribute: u32,
+    handle: u32,
+    mode: u32,
+    timestamps: u32,
+    /// Last value reported, to find changes.
+    reported: Option<DataValue>,
+}
+
+struct Subscription {
+    /// Authentication token of the owning session.
+    session: NodeId,
+    interval: Duration,
+    max_keep_alive: u32,
+    /// Publishing intervals since the last message.
+    idle: u32,
+    enabled: bool,
+    due: DateTime<Utc>,
+    sequence: u32,
+    items: BTreeMap<u32, Item>,
+}
+
+impl Subscription {
+    /// Items whose value changed since last reported.
+    fn changes(&self, space: &AddressSpace) -> Vec<(u32, DataValue)> {
+        self.items
+            .iter()
+            .filter(|(_, item)| item.mode == MONITORING_REPORTING)
+            .filter_map(|(id, item)| {
+                let value = filter_timestamps(space.read(&item.node, item.attribute, false), item.timestamps);
+                let changed = match &item.reported {
+                    Some(reported) => reported.value != value.value || reported.status != value.status,
+                    None => true,
+                };
+                changed.then_some((*id, value))
+            })
+            .collect()
+    }
+}
+
+/// A Publish request waiting for something to report.
+struct Pending {
+    request_id: u32,
+    header: RequestHeader,
+}
+
+/// One client connection with its secure channel, sessions and subscriptions.
+struct Connection {
+    shared: Arc<Shared>,
+    peer: SocketAddr,
+    writer: OwnedWriteHalf,
+    channel_id: u32,
+    token_id: u32,
+    sequence: u32,
+    /// Largest chunk the client takes.
+    send_buffer: u32,
+    sessions: HashMap<NodeId, Session>,
+    subscriptions: BTreeMap<u32, Subscription>,
+    pending: VecDeque<Pending>,
+}
+
+/// Why a connection ends: the client went away, or a protocol error to
+/// report with an ERR message.
+enum Closed {
+    Gone,
+    Error(StatusCode, String),
+}
+
+impl From<std::io::Error> for Closed {
+    fn from(_: std::io::Error) -> Self {
+        Closed::Gone
+    }
+}
+
+impl Connection {
+    async fn run(shared: Arc<Shared>, stream: TcpStream, peer: SocketAddr) {
+        let (mut reader, writer) = stream.into_split();
+        let (sender, mut chunks) = mpsc::channel(16);
+        // 单独的读任务，select 里取消也不会丢半个分块
+        let reading = tokio::spawn(async move {
+            loop {
+                let chunk = transport::read_chunk(&mut reader, transport::BUFFER_SIZE).await;
+                let failed = chunk.is_err();
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut connection = Connection {
+            shared,
+            peer,
+            writer,
+            channel_id: 0,
+            token_id: 0,
+            sequence: 0,
+            send_buffer: transport::BUFFER_SIZE,
+            sessions: HashMap::new(),
+            subscriptions: BTreeMap::new(),
+            pending: VecDeque::new(),
+        };
+        if let Err(Closed::Error(status, reason)) = connection.serve(&mut chunks).await {
+            eprintln!("OPC UA client {} dropped: {} {}", peer, status, reason);
+            let _ = connection.writer.write_all(&transport::error(status, &reason)).await;
+        }
+        reading.abort();
+    }
+
+    async fn serve(&mut self, chunks: &mut mpsc::Receiver<std::io::Result<Chunk>>) -> Result<(), Closed> {
+        let hello = chunks.recv().await.ok_or(Closed::Gone)??;
+        if &hello.kind != b"HEL" {
+            return Err(Closed::Error(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID, "expected Hello".to_string()));
+        }
+        let (limits, _url) = transport::decode_hello(&hello.body).map_err(|status| Closed::Error(status, "bad Hello".to_string()))?;
+        let answer = limits.answer().map_err(|status| Closed::Error(status, "buffers too small".to_string()))?;
+        self.send_buffer = answer.send_buffer;
+        self.writer.write_all(&transport::acknowledge(&answer)).await?;
+
+        let mut assembler = Assembler::default();
+        loop {
+            let due = self.subscriptions.values().map(|subscription| subscription.due).min();
+            let clock = self.shared.clock.clone();
+            let chunk = tokio::select! {
+                chunk = chunks.recv() => chunk.ok_or(Closed::Gone)??,
+                _ = wait(&*clock, due) => {
+                    self.cycle().await?;
+                    continue;
+                }
+            };
+            let bad = |status: StatusCode| Closed::Error(status, "malformed chunk".to_string());
+            match &chunk.kind {
+                b"OPN" => {
+                    let secured = Secured::decode(&chunk).map_err(bad)?;
+                    self.open(secured).await?;
+                }
+                b"MSG" => {
+                    let secured = Secured::decode(&chunk).map_err(bad)?;
+                    self.check_channel(&secured)?;
+                    if let Some(message) = assembler.push(&chunk, secured).map_err(bad)? {
+                        self.message(message).await?;
+                    }
+                }
+                b"CLO" => return Ok(()),
+                _ => return Err(Closed::Error(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID, "unexpected message".to_string())),
+            }
+        }
+    }
+
+    fn check_channel(&self, secured: &Secured) -> Result<(), Closed> {
+        let token_ok = matches!(secured.security, Security::Token(token) if token == self.token_id);
+        if self.channel_id == 0 || secured.channel_id != self.channel_id || !token_ok {
+            return Err(Closed::Error(StatusCode::BAD_SECURE_CHANNEL_ID_INVALID, "unknown channel or token".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn open(&mut self, secured: Secured) -> Result<(), Closed> {
+        let Security::Policy(policy) = &secured.security else {
+            return Err(Closed::Error(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID, "OPN without policy".to_string()));
+        };
+        if policy.as_deref() != Some(SECURITY_POLICY_NONE) {
+            return Err(Closed::Error(StatusCode::BAD_SECURITY_POLICY_REJECTED, format!("policy {:?}", policy)));
+        }
+        let bad = |status: StatusCode| Closed::Error(status, "malformed OpenSecureChannel".to_string());
+        let mut decoder = Decoder::new(&secured.body);
+        if message_type(&mut decoder).map_err(bad)? != ids::OPEN_SECURE_CHANNEL_REQUEST {
+            return Err(bad(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID));
+        }
+        let header = RequestHeader::decode(&mut decoder).map_err(bad)?;
+        let request = OpenSecureChannelRequest::decode(&mut decoder).map_err(bad)?;
+        if request.security_mode != SECURITY_MODE_NONE {
+            return Err(Closed::Error(StatusCode::BAD_SECURITY_POLICY_REJECTED, "security mode".to_string()));
+        }
+        if request.request_type == 1 {
+            if self.channel_id == 0 || secured.channel_id != self.channel_id {
+                return Err(Closed::Error(StatusCode::BAD_SECURE_CHANNEL_ID_INVALID, "renewing unknown channel".to_string()));
+            }
+        } else {
+            self.channel_id = self.shared.next_id();
+        }
+        self.token_id += 1;
+        let now = self.shared.clock.now();
+        let response = OpenSecureChannelResponse {
+            channel_id: self.channel_id,
+            token_id: self.token_id,
+            created_at: now,
+            revised_lifetime: request.requested_lifetime.clamp(MIN_LIFETIME, MAX_LIFETIME),
+        };
+        let header = ResponseHeader::new(&header, now, StatusCode::GOOD);
+        let body = encode_message(ids::OPEN_SECURE_CHANNEL_RESPONSE, &header, &response);
+        self.sequence = self.sequence.wrapping_add(1).max(1);
+        let bytes = transport::open(self.channel_id, self.sequence, secured.request_id, &body);
+        self.writer.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    async fn send<B: Binary>(&mut self, request_id: u32, encoding: u32, header: &ResponseHeader, body: &B) -> Result<(), Closed> {
+        let body = encode_message(encoding, header, body);
+        let bytes = transport::message(b"MSG", self.channel_id, self.token_id, &mut self.sequence, request_id, &body, self.send_buffer);
+        self.writer.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    async fn fault(&mut self, request_id: u32, header: &RequestHeader, status: StatusCode) -> Result<(), Closed> {
+        let header = ResponseHeader::new(header, self.shared.clock.now(), status);
+        self.send(request_id, ids::SERVICE_FAULT, &header, &Empty).await
+    }
+
+    async fn message(&mut self, message: Secured) -> Result<(), Closed> {
+        let request_id = message.request_id;
+        let mut decoder = Decoder::new(&message.body);
+        let bad = |status: StatusCode| Closed::Error(status, "malformed request".to_string());
+        let encoding = message_type(&mut decoder).map_err(bad)?;
+        let header = RequestHeader::decode(&mut decoder).map_err(bad)?;
+        if encoding == ids::CLOSE_SECURE_CHANNEL_REQUEST {
+            return Err(Closed::Gone);
+        }
+        match self.service(encoding, request_id, &header, &mut decoder).await {
+            Ok(()) => Ok(()),
+            Err(Fault::Status(status)) => self.fault(request_id, &header, status).await,
+            Err(Fault::Closed(closed)) => Err(closed),
+        }
+    }
+
+    /// The session of a request's authentication token, if activated.
+    fn session(&self, header: &RequestHeader) -> Result<&Session, StatusCode> {
+        let session = self.sessions.get(&header.authentication_token).ok_or(StatusCode::BAD_SESSION_ID_INVALID)?;
+        if session.access.is_none() {
+            return Err(StatusCode::BAD_SESSION_NOT_ACTIVATED);
+        }
+        Ok(session)
+    }
+
+    fn may_operate(&self, header: &RequestHeader) -> Result<bool, StatusCode> {
+        Ok(self.session(header)?.access == Some(Access::Operate))
+    }
+
+    async fn service(&mut self, encoding: u32, request_id: u32, header: &RequestHeader, decoder: &mut Decoder<'_>) -> Result<(), Fault> {
+        let now = self.shared.clock.now();
+        let ok = ResponseHeader::new(header, now, StatusCode::GOOD);
+        match encoding {
+            ids::GET_ENDPOINTS_REQUEST => {
+                DiscoveryRequest::decode(decoder)?;
+                let endpoints = List(self.shared.endpoints());
+                self.send(request_id, ids::GET_ENDPOINTS_RESPONSE, &ok, &endpoints).await?;
+            }
+            ids::FIND_SERVERS_REQUEST => {
+                DiscoveryRequest::decode(decoder)?;
+                let servers = List(vec![self.shared.application()]);
+                self.send(request_id, ids::FIND_SERVERS_RESPONSE, &ok, &servers).await?;
+            }
+            ids::CREATE_SESSION_REQUEST => {
+                let request = CreateSessionRequest::decode(decoder)?;
+                let id = NodeId::numeric(NAMESPACE, self.shared.next_id());
+                // 令牌用随机 GUID，不能被猜到
+                let token = NodeId {
+                    namespace: NAMESPACE,
+                    identifier: crate::types::Identifier::Guid(rand::random()),
+                };
+                self.sessions.insert(
+                    token.clone(),
+                    Session {
+                        access: None,
+                        continuation: HashMap::new(),
+                    },
+                );
+                let response = CreateSessionResponse {
+                    session_id: id,
+                    authentication_token: token,
+                    revised_timeout: request.requested_timeout.clamp(MIN_LIFETIME as f64, MAX_LIFETIME as f64),
+                    endpoints: self.shared.endpoints(),
+                };
+                self.send(request_id, ids::CREATE_SESSION_RESPONSE, &ok, &response).await?;
+            }
+            ids::ACTIVATE_SESSION_REQUEST => {
+                let request = ActivateSessionRequest::decode(decoder)?;
+                if !self.sessions.contains_key(&header.authentication_token) {
+                    return Err(StatusCode::BAD_SESSION_ID_INVALID.into());
+                }
+                let identity = Identity::from_extension_object(&request.identity)?;
+                let access = self.shared.authenticate(&identity).inspect_err(|status| {
+                    eprintln!("OPC UA client {} not activated: {}", self.peer, status);
+                })?;
+                self.sessions.get_mut(&header.authentication_token).unwrap().access = Some(access);
+                self.send(request_id, ids::ACTIVATE_SESSION_RESPONSE, &ok, &ActivateSessionResponse).await?;
+            }
+            ids::CLOSE_SESSION_REQUEST => {
+                CloseSessionRequest::decode(decoder)?;
+                if self.sessions.remove(&header.authentication_token).is_none() {
+                    return Err(StatusCode::BAD_SESSION_ID_INVALID.into());
+                }
+                // 连接内的订阅随会话一起删除
+                self.subscriptions.retain(|_, subscription| subscription.session != header.authentication_token);
+                self.send(request_id, ids::CLOSE_SESSION_RESPONSE, &ok, &Empty).await?;
+            }
+            ids::BROWSE_REQUEST => {
+                let request = BrowseRequest::decode(decoder)?;
+                self.session(header)?;
+                check_count(request.nodes.len())?;
+                let results = {
+                    let space = self.shared.space.lock().unwrap();
+                    request.nodes.iter().map(|node| space.browse(node)).collect::<Vec<_>>()
+                };
+                let results = results.into_iter().map(|result| self.page(header, result, request.max_references)).collect();
+                self.send(request_id, ids::BROWSE_RESPONSE, &ok, &BrowseResponse { results }).await?;
+            }
+            ids::BROWSE_NEXT_REQUEST => {
+                let request = BrowseNextRequest::decode(decoder)?;
+                self.session(header)?;
+                check_count(request.continuation_points.len())?;
+                let session = self.sessions.get_mut(&header.authentication_token).unwrap();
+                let taken: Vec<_> = request.continuation_points.iter().map(|point| session.continuation.remove(point)).collect();
+                let mut results = Vec::new();
+                for (point, references) in request.continuation_points.iter().zip(taken) {
+                    match references {
+                        None => results.push(BrowseResult::bad(StatusCode::BAD_CONTINUATION_POINT_INVALID)),
+                        Some(_) if request.release => results.push(BrowseResult::bad(StatusCode::GOOD)),
+                        Some(references) => {
+                            let result = BrowseResult {
+                                status: StatusCode::GOOD,
+                                continuation_point: None,
+                                references,
+                            };
+                            // 续页沿用第一次请求的每页数量
+                            let max = point.get(4..8).map(|max| u32::from_le_bytes(max.try_into().unwrap())).unwrap_or(0);
+                            results.push(self.page(header, result, max));
+                        }
+                    }
+                }
+                self.send(request_id, ids::BROWSE_NEXT_RESPONSE, &ok, &BrowseResponse { results }).await?;
+            }
+            ids::READ_REQUEST => {
+                let request = ReadRequest::decode(decoder)?;
+                let writable = self.may_operate(header)?;
+                check_count(request.nodes.len())?;
+                if request.timestamps > TIMESTAMPS_NEITHER {
+                    return Err(StatusCode::BAD_INVALID_ARGUMENT.into());
+                }
+                let results = {
+                    let mut space = self.shared.space.lock().unwrap();
+                    space.tick(now, &self.shared.config.application_name);
+                    request
+                        .nodes
+                        .iter()
+                        .map(|node| {
+                            if node.index_range.as_deref().is_some_and(|range| !range.is_empty()) {
+                                return DataValue::bad(StatusCode::BAD_INDEX_RANGE_INVALID);
+                            }
+                            let value = space.read(&node.node_id, node.attribute_id, writable);
+                            if node.attribute_id == ids::ATTR_VALUE {
+                                filter_timestamps(value, request.timestamps)
+                            } else {
+                                value
+                            }
+                        })
+                        .collect()
+                };
+                self.send(request_id, ids::READ_RESPONSE, &ok, &ReadResponse { results }).await?;
+            }
+            ids::WRITE_REQUEST => {
+                let request = WriteRequest::decode(decoder)?;
+                let writable = self.may_operate(header)?;
+                check_count(request.nodes.len())?;
+                let mut results = Vec::with_capacity(request.nodes.len());
+                for node in &request.nodes {
+                    results.push(self.write(node, writable, now).await);
+                }
+                self.send(request_id, ids::WRITE_RESPONSE, &ok, &StatusResponse { results }).await?;
+            }
+            ids::CALL_REQUEST => {
+                let request = CallRequest::decode(decoder)?;
+                let writable = self.may_operate(header)?;
+                check_count(request.methods.len())?;
+                let mut results = Vec::with_capacity(request.methods.len());
+                for method in &request.methods {
+                    results.push(self.call(method, writable).await);
+                }
+                self.send(request_id, ids::CALL_RESPONSE, &ok, &CallResponse { results }).await?;
+            }
+            ids::CREATE_SUBSCRIPTION_REQUEST => {
+                let request = CreateSubscriptionRequest::decode(decoder)?;
+                self.session(header)?;
+                if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                    return Err(StatusCode::BAD_TOO_MANY_SUBSCRIPTIONS.into());
+                }
+                let interval = if request.publishing_interval.is_nan() || request.publishing_interval <= 0.0 {
+                    DEFAULT_PUBLISHING_INTERVAL
+                } else {
+                    request.publishing_interval.clamp(MIN_PUBLISHING_INTERVAL, MAX_LIFETIME as f64)
+                };
+                let max_keep_alive = request.max_keep_alive_count.clamp(1, 1000);
+                let id = self.shared.next_id();
+                let duration = Duration::from_micros((interval * 1000.0) as u64);
+                self.subscriptions.insert(
+                    id,
+                    Subscription {
+                        session: header.authentication_token.clone(),
+                        interval: duration,
+                        max_keep_alive,
+                        idle: 0,
+                        enabled: request.publishing_enabled,
+                        due: now + duration,
+                        sequence: 0,
+                        items: BTreeMap::new(),
+                    },
+                );
+                let response = CreateSubscriptionResponse {
+                    subscription_id: id,
+                    publishing_interval: interval,
+                    lifetime_count: request.lifetime_count.max(max_keep_alive * 3),
+                    max_keep_alive_count: max_keep_alive,
+                };
+                self.send(request_id, ids::CREATE_SUBSCRIPTION_RESPONSE, &ok, &response).await?;
+            }
+            ids::CREATE_MONITORED_ITEMS_REQUEST => {
+                let request = CreateMonitoredItemsRequest::decode(decoder)?;
+                self.session(header)?;
+                check_count(request.items.len())?;
+                if request.timestamps > TIMESTAMPS_NEITHER {
+                    return Err(StatusCode::BAD_INVALID_ARGUMENT.into());
+                }
+                let shared = self.shared.clone();
+                let subscription = self.subscription(header, request.subscription_id)?;
+                let results = {
+                    let space = shared.space.lock().unwrap();
+                    let mut results = Vec::new();
+                    for item in request.items {
+                        let node = &item.item.node_id;
+                        let status = if !space.contains(node) {
+                            StatusCode::BAD_NODE_ID_UNKNOWN
+                        } else if space.read(node, item.item.attribute_id, false).status() == StatusCode::BAD_ATTRIBUTE_ID_INVALID {
+                            StatusCode::BAD_ATTRIBUTE_ID_INVALID
+                        } else if item.monitoring_mode > MONITORING_REPORTING {
+                            StatusCode::BAD_MONITORING_MODE_INVALID
+                        } else if subscription.items.len() >= MAX_ITEMS {
+                            StatusCode::BAD_TOO_MANY_OPERATIONS
+                        } else {
+                            StatusCode::GOOD
+                        };
+                        let mut result = MonitoredItemCreateResult {
+                            status,
+                            monitored_item_id: 0,
+                            sampling_interval: subscription.interval.as_secs_f64() * 1000.0,
+                            queue_size: 1,
+                        };
+                        if status.is_good() {
+                            result.monitored_item_id = shared.next_id();
+                            subscription.items.insert(
+                                result.monitored_item_id,
+                                Item {
+                                    node: item.item.node_id,
+                                    attribute: item.item.attribute_id,
+                                    handle: item.client_handle,
+                                    mode: item.monitoring_mode,
+                                    timestamps: request.timestamps,
+                                    reported: None,
+                                },
+                            );
+                        }
+                        results.push(result);
+                    }
+                    results
+                };
+                self.send(request_id, ids::CREATE_MONITORED_ITEMS_RESPONSE, &ok, &CreateMonitoredItemsResponse { results })
+                    .await?;
+            }
+            ids::DELETE_MONITORED_ITEMS_REQUEST => {
+                let request = DeleteMonitoredItemsRequest::decode(decoder)?;
+                self.session(header)?;
+                check_count(request.monitored_item_ids.len())?;
+                let subscription = self.subscription(header, request.subscription_id)?;
+                let results = request
+                    .monitored_item_ids
+                    .iter()
+                    .map(|id| match subscription.items.remove(id) {
+                        Some(_) => StatusCode::GOOD,
+                        None => StatusCode::BAD_MONITORED_ITEM_ID_INVALID,
+                    })
+                    .collect();
+                self.send(request_id, ids::DELETE_MONITORED_ITEMS_RESPONSE, &ok, &StatusResponse { results }).await?;
+            }
+            ids::SET_PUBLISHING_MODE_REQUEST => {
+                let request = SetPublishingModeRequest::decode(decoder)?;
+                self.session(header)?;
+                check_count(request.subscription_ids.len())?;
+                let results = request
+                    .subscription_ids
+                    .iter()
+                    .map(|id| match self.subscription(header, *id) {
+                        Ok(subscription) => {
+                            subscription.enabled = request.publishing_enabled;
+                            StatusCode::GOOD
+                        }
+                        Err(status) => status,
+                    })
+                    .collect();
+                self.send(request_id, ids::SET_PUBLISHING_MODE_RESPONSE, &ok, &StatusResponse { results }).await?;
+            }
+            ids::DELETE_SUBSCRIPTIONS_REQUEST => {
+                let request = DeleteSubscriptionsRequest::decode(decoder)?;
+                self.session(header)?;
+                check_count(request.subscription_ids.len())?;
+                let results = request
+                    .subscription_ids
+                    .iter()
+                    .map(|id| match self.subscription(header, *id) {
+                        Ok(_) => {
+                            self.subscriptions.remove(id);
+                            StatusCode::GOOD
+                        }
+                        Err(status) => status,
+                    })
+                    .collect();
+                self.send(request_id, ids::DELETE_SUBSCRIPTIONS_RESPONSE, &ok, &StatusResponse { results }).await?;
+                self.answer_without_subscriptions().await?;
+            }
+            ids::PUBLISH_REQUEST => {
+                PublishRequest::decode(decoder)?;
+                self.session(header)?;
+                if !self.subscriptions.values().any(|subscription| subscription.session == header.authentication_token) {
+                    return Err(StatusCode::BAD_NO_SUBSCRIPTION.into());
+                }
+                if self.pending.len() >= MAX_PUBLISH_REQUESTS {
+                    let oldest = self.pending.pop_front().unwrap();
+                    self.fault(oldest.request_id, &oldest.header, StatusCode::BAD_TOO_MANY_PUBLISH_REQUESTS).await?;
+                }
+                self.pending.push_back(Pending {
+                    request_id,
+                    header: header.clone(),
+                });
+                // 已有待报的变化就不等下个周期
+                self.report(false).await?;
+            }
+            _ => return Err(StatusCode::BAD_SERVICE_UNSUPPORTED.into()),
+        }
+        Ok(())
+    }
+
+    fn subscription(&mut self, header: &RequestHeader, id: u32) -> Result<&mut Subscription, StatusCode> {
+        match self.subscriptions.get_mut(&id) {
+            Some(subscription) if subscription.session == header.authentication_token => Ok(subscription),
+            _ => Err(StatusCode::BAD_SUBSCRIPTION_ID_INVALID),
+        }
+    }
+
+    /// Cuts a browse result down to `max` references, keeping the rest for
+    /// BrowseNext.
+    fn page(&mut self, header: &RequestHeader, mut result: BrowseResult, max: u32) -> BrowseResult {
+        if max == 0 || result.references.len() <= max as usize {
+            return result;
+        }
+        let rest = result.references.split_off(max as usize);
+        let mut point = self.shared.next_id().to_le_bytes().to_vec();
+        point.extend_from_slice(&max.to_le_bytes());
+        if let Some(session) = self.sessions.get_mut(&header.authentication_token) {
+            session.continuation.insert(point.clone(), rest);
+        }
+        result.continuation_point = Some(point);
+        result
+    }
+
+    async fn write(&self, node: &WriteValue, writable: bool, now: DateTime<Utc>) -> StatusCode {
+        let binding = {
+            let space = self.shared.space.lock().unwrap();
+            if let Err(status) = space.check_write(&node.node_id, node.attribute_id) {
+                return status;
+            }
+            space.binding(&node.node_id).cloned()
+        };
+        if !writable {
+            return StatusCode::BAD_USER_ACCESS_DENIED;
+        }
+        if node.index_range.as_deref().is_some_and(|range| !range.is_empty()) {
+            return StatusCode::BAD_INDEX_RANGE_INVALID;
+        }
+        if node.value.source_timestamp.is_some() || node.value.server_timestamp.is_some() || node.value.status.is_some() {
+            return StatusCode::BAD_WRITE_NOT_SUPPORTED;
+        }
+        let Some(value) = node.value.value.as_ref().and_then(Variant::as_f64) else {
+            return StatusCode::BAD_TYPE_MISMATCH;
+        };
+        let Some(Binding::Setpoint { device, name }) = binding else {
+            return StatusCode::BAD_NOT_WRITABLE;
+        };
+        self.shared.space.lock().unwrap().set_value(&node.node_id, value, now);
+        let msg = MsgBuilder::new()
+            .msg_type(MessageType::Setpoint)
+            .topic(format!("setpoint/{}", device))
+            .data(Box::new(SetpointMsg::new(device, name, value)))
+            .build()
+            .unwrap();
+        self.shared.publisher.publish(msg).await;
+        StatusCode::GOOD
+    }
+
+    async fn call(&self, method: &CallMethodRequest, writable: bool) -> CallMethodResult {
+        let binding = {
+            let space = self.shared.space.lock().unwrap();
+            if !space.contains(&method.object_id) {
+                return CallMethodResult::status(StatusCode::BAD_NODE_ID_UNKNOWN);
+            }
+            if !space.has_method(&method.object_id, &method.method_id) {
+                return CallMethodResult::status(StatusCode::BAD_METHOD_INVALID);
+            }
+            space.binding(&method.method_id).cloned()
+        };
+        let Some(Binding::Method { motor, kind, .. }) = binding else {
+            return CallMethodResult::status(StatusCode::BAD_METHOD_INVALID);
+        };
+        if !writable {
+            return CallMethodResult::status(StatusCode::BAD_USER_ACCESS_DENIED);
+        }
+        let arguments = &method.input_arguments;
+        let (msg_type, motor) = match kind {
+            MethodKind::Move => {
+                let Some(argument) = arguments.first() else {
+                    return CallMethodResult::status(StatusCode::BAD_ARGUMENTS_MISSING);
+                };
+                if arguments.len() > 1 {
+                    return CallMethodResult::status(StatusCode::BAD_INVALID_ARGUMENT);
+                }
+                let direction = match argument.as_str() {
+                    Some("Up") => MoveDirection::Up,
+                    Some("Down") => MoveDirection::Down,
+                    other => {
+                        let status = if other.is_some() { StatusCode::BAD_INVALID_ARGUMENT } else { StatusCode::BAD_TYPE_MISMATCH };
+                        return CallMethodResult {
+                            status: StatusCode::BAD_INVALID_ARGUMENT,
+                            input_argument_results: vec![status],
+                            output_arguments: Vec::new(),
+                        };
+                    }
+                };
+                (MessageType::Move, MotorMsg::new(motor, direction))
+            }
+            _ if !arguments.is_empty() => return CallMethodResult::status(StatusCode::BAD_INVALID_ARGUMENT),
+            MethodKind::Stop => (MessageType::Stop, MotorMsg::new(motor, MoveDirection::Up).command(MotorCommand::Stop)),
+            MethodKind::Home => (MessageType::Move, MotorMsg::new(motor, MoveDirection::Up).command(MotorCommand::Home)),
+        };
+        let msg = MsgBuilder::new().msg_type(msg_type).data(Box::new(motor)).build().unwrap();
+        self.shared.publisher.publish(msg).await;
+        CallMethodResult::status(StatusCode::GOOD)
+    }
+
+    /// One publishing cycle for every subscription that is due.
+    async fn cycle(&mut self) -> Result<(), Closed> {
+        let now = self.shared.clock.now();
+        for subscription in self.subscriptions.values_mut() {
+            if subscription.due > now {
+                continue;
+            }
+            subscription.due += subscription.interval;
+            if subscription.due <= now {
+                subscription.due = now + subscription.interval;
+            }
+            subscription.idle += 1;
+        }
+        self.report(true).await
+    }
+
+    /// Answers waiting Publish requests with the changes of subscriptions;
+    /// at the end of a cycle also with keep-alives for those that have
+    /// been quiet for their keep-alive count.
+    async fn report(&mut self, cycle_end: bool) -> Result<(), Closed> {
+        let now = self.shared.clock.now();
+        let ids: Vec<u32> = self.subscriptions.keys().copied().collect();
+        for id in ids {
+            let Some(session) = self.pending_for(id) else {
+                continue;
+            };
+            let changes = {
+                let mut space = self.shared.space.lock().unwrap();
+                space.tick(now, &self.shared.config.application_name);
+                let subscription = &self.subscriptions[&id];
+                if subscription.enabled { subscription.changes(&space) } else { Vec::new() }
+            };
+            let subscription = self.subscriptions.get_mut(&id).unwrap();
+            let keep_alive = changes.is_empty() && cycle_end && subscription.idle >= subscription.max_keep_alive;
+            if changes.is_empty() && !keep_alive {
+                continue;
+            }
+            let index = self.pending.iter().position(|pending| pending.header.authentication_token == session).unwrap();
+            let pending = self.pending.remove(index).unwrap();
+            subscription.idle = 0;
+            let mut notifications = Vec::new();
+            for (item_id, value) in changes {
+                let item = subscription.items.get_mut(&item_id).unwrap();
+                item.reported = Some(value.clone());
+                notifications.push(MonitoredItemNotification {
+                    client_handle: item.handle,
+                    value,
+                });
+            }
+            // 保活消息带下一个序号但不占用它
+            let sequence_number = subscription.sequence.wrapping_add(1).max(1);
+            if !notifications.is_empty() {
+                subscription.sequence = sequence_number;
+            }
+            let response = PublishResponse {
+                subscription_id: id,
+                available_sequence_numbers: Vec::new(),
+                more_notifications: false,
+                sequence_number,
+                publish_time: now,
+                notifications,
+                results: Vec::new(),
+            };
+            let header = ResponseHeader::new(&pending.header, now, StatusCode::GOOD);
+            self.send(pending.request_id, ids::PUBLISH_RESPONSE, &header, &response).await?;
+        }
+        Ok(())
+    }
+
+    /// The session of a subscription if it has a Publish request waiting.
+    fn pending_for(&self, id: u32) -> Option<NodeId> {
+        let session = &self.subscriptions.get(&id)?.session;
+        self.pending.iter().any(|pending| &pending.header.authentication_token == session).then(|| session.clone())
+    }
+
+    /// Publish requests of sessions left without subscriptions get
+    /// `BadNoSubscription`.
+    async fn answer_without_subscriptions(&mut self) -> Result<(), Closed> {
+        let pending = std::mem::take(&mut self.pending);
+        for request in pending {
+            let token = &request.header.authentication_token;
+            if self.subscriptions.values().any(|subscription| &subscription.session == token) {
+                self.pending.push_back(request);
+            } else {
+                self.fault(request.request_id, &request.header, StatusCode::BAD_NO_SUBSCRIPTION).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What ends a service call: a fault for the request, or the connection.
+enum Fault {
+    Status(StatusCode),
+    Closed(Closed),
+}
+
+impl From<StatusCode> for Fault {
+    fn from(status: StatusCode) -> Self {
+        Fault::Status(status)
+    }
+}
+
+impl From<Closed> for Fault {
+    fn from(closed: Closed) -> Self {
+        Fault::Closed(closed)
+    }
+}
+
+fn check_count(count: usize) -> Result<(), StatusCode> {
+    match count {
+        0 => Err(StatusCode::BAD_NOTHING_TO_DO),
+        count if count > MAX_OPERATIONS => Err(StatusCode::BAD_TOO_MANY_OPERATIONS),
+        _ => Ok(()),
+    }
+}
+
+fn filter_timestamps(mut value: DataValue, timestamps: u32) -> DataValue {
+    if matches!(timestamps, TIMESTAMPS_SERVER | TIMESTAMPS_NEITHER) {
+        value.source_timestamp = None;
+    }
+    if matches!(timestamps, TIMESTAMPS_SOURCE | TIMESTAMPS_NEITHER) {
+        value.server_timestamp = None;
+    }
+    value
+}
+
+async fn wait(clock: &dyn Clock, due: Option<DateTime<Utc>>) {
+    match due {
+        Some(due) => clock.sleep_until(due).await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,1417 @@
+//! Request and response structures of the services the server offers, in
+//! the binary encoding. Fields the server has no use for are read and
+//! dropped, and written with their null value.
+
+use chrono::{DateTime, Utc};
+
+use crate::codec::{DecodeResult, Decoder, Encoder};
+use crate::ids;
+use crate::types::{DataValue, ExtensionObject, LocalizedText, NodeId, QualifiedName, StatusCode, Variant};
+
+/// A structure in the binary encoding.
+pub trait Binary: Sized {
+    fn encode(&self, encoder: &mut Encoder);
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self>;
+}
+
+fn encode_all<T: Binary>(encoder: &mut Encoder, items: &[T]) {
+    encoder.array(items, |encoder, item| item.encode(encoder));
+}
+
+fn decode_all<T: Binary>(decoder: &mut Decoder) -> DecodeResult<Vec<T>> {
+    decoder.array(T::decode)
+}
+
+fn strings(decoder: &mut Decoder) -> DecodeResult<Vec<String>> {
+    decoder.array(|decoder| Ok(decoder.string()?.unwrap_or_default()))
+}
+
+fn encode_strings(encoder: &mut Encoder, items: &[String]) {
+    encoder.array(items, |encoder, item| {
+        encoder.str(item);
+    });
+}
+
+fn statuses(decoder: &mut Decoder) -> DecodeResult<Vec<StatusCode>> {
+    decoder.array(|decoder| decoder.status_code())
+}
+
+fn encode_statuses(encoder: &mut Encoder, items: &[StatusCode]) {
+    encoder.array(items, |encoder, status| {
+        encoder.status_code(*status);
+    });
+}
+
+fn skip_diagnostics(decoder: &mut Decoder) -> DecodeResult<()> {
+    decoder.array(|decoder| decoder.diagnostic_info())?;
+    Ok(())
+}
+
+fn no_diagnostics(encoder: &mut Encoder) {
+    encoder.i32(-1);
+}
+
+/// An empty SignatureData.
+fn no_signature(encoder: &mut Encoder) {
+    encoder.string(None).byte_string(None);
+}
+
+fn skip_signature(decoder: &mut Decoder) -> DecodeResult<()> {
+    decoder.string()?;
+    decoder.byte_string()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestHeader {
+    pub authentication_token: NodeId,
+    pub timestamp: DateTime<Utc>,
+    pub request_handle: u32,
+    pub timeout_hint: u32,
+}
+
+impl Binary for RequestHeader {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .node_id(&self.authentication_token)
+            .date_time(self.timestamp)
+            .u32(self.request_handle)
+            .u32(0)
+            .string(None)
+            .u32(self.timeout_hint)
+            .extension_object(&ExtensionObject::null());
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let authentication_token = decoder.node_id()?;
+        let timestamp = decoder.date_time()?;
+        let request_handle = decoder.u32()?;
+        // 诊断掩码与审计 id
+        decoder.u32()?;
+        decoder.string()?;
+        let timeout_hint = decoder.u32()?;
+        decoder.extension_object()?;
+        Ok(Self {
+            authentication_token,
+            timestamp,
+            request_handle,
+            timeout_hint,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseHeader {
+    pub timestamp: DateTime<Utc>,
+    pub request_handle: u32,
+    pub service_result: StatusCode,
+}
+
+impl ResponseHeader {
+    pub fn new(request: &RequestHeader, at: DateTime<Utc>, service_result: StatusCode) -> Self {
+        Self {
+            timestamp: at,
+            request_handle: request.request_handle,
+            service_result,
+        }
+    }
+}
+
+impl Binary for ResponseHeader {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.date_time(self.timestamp).u32(self.request_handle).status_code(self.service_result).diagnostic_info();
+        encoder.i32(-1).extension_object(&ExtensionObject::null());
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let timestamp = decoder.date_time()?;
+        let request_handle = decoder.u32()?;
+        let service_result = decoder.status_code()?;
+        decoder.diagnostic_info()?;
+        strings(decoder)?;
+        decoder.extension_object()?;
+        Ok(Self {
+            timestamp,
+            request_handle,
+            service_result,
+        })
+    }
+}
+
+/// Body of a message without anything past the header, such as
+/// CloseSecureChannel or a ServiceFault.
+#[derive(Debug, Clone, Default)]
+pub struct Empty;
+
+impl Binary for Empty {
+    fn encode(&self, _encoder: &mut Encoder) {}
+
+    fn decode(_decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Empty)
+    }
+}
+
+/// A whole service message: encoding id, header and body.
+pub fn encode_message<H: Binary, B: Binary>(encoding: u32, header: &H, body: &B) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder.node_id(&NodeId::ns0(encoding));
+    header.encode(&mut encoder);
+    body.encode(&mut encoder);
+    encoder.finish()
+}
+
+/// The encoding id in front of a service message.
+pub fn message_type(decoder: &mut Decoder) -> DecodeResult<u32> {
+    decoder.node_id()?.as_ns0().ok_or(StatusCode::BAD_DECODING_ERROR)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApplicationDescription {
+    pub application_uri: String,
+    pub product_uri: String,
+    pub application_name: LocalizedText,
+    /// 0 server, 1 client.
+    pub application_type: u32,
+    pub discovery_urls: Vec<String>,
+}
+
+impl Binary for ApplicationDescription {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .str(&self.application_uri)
+            .str(&self.product_uri)
+            .localized_text(&self.application_name)
+            .u32(self.application_type)
+            .string(None)
+            .string(None);
+        encode_strings(encoder, &self.discovery_urls);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let application_uri = decoder.string()?.unwrap_or_default();
+        let product_uri = decoder.string()?.unwrap_or_default();
+        let application_name = decoder.localized_text()?;
+        let application_type = decoder.u32()?;
+        decoder.string()?;
+        decoder.string()?;
+        Ok(Self {
+            application_uri,
+            product_uri,
+            application_name,
+            application_type,
+            discovery_urls: strings(decoder)?,
+        })
+    }
+}
+
+pub const TOKEN_ANONYMOUS: u32 = 0;
+pub const TOKEN_USER_NAME: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserTokenPolicy {
+    pub policy_id: String,
+    /// [`TOKEN_ANONYMOUS`] or [`TOKEN_USER_NAME`].
+    pub token_type: u32,
+}
+
+impl Binary for UserTokenPolicy {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.str(&self.policy_id).u32(self.token_type).string(None).string(None).string(None);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let policy_id = decoder.string()?.unwrap_or_default();
+        let token_type = decoder.u32()?;
+        decoder.string()?;
+        decoder.string()?;
+        decoder.string()?;
+        Ok(Self { policy_id, token_type })
+    }
+}
+
+/// MessageSecurityMode None.
+pub const SECURITY_MODE_NONE: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointDescription {
+    pub endpoint_url: String,
+    pub server: ApplicationDescription,
+    pub security_mode: u32,
+    pub security_policy_uri: String,
+    pub user_identity_tokens: Vec<UserTokenPolicy>,
+    pub transport_profile_uri: String,
+}
+
+impl Binary for EndpointDescription {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.str(&self.endpoint_url);
+        self.server.encode(encoder);
+        encoder.byte_string(None).u32(self.security_mode).str(&self.security_policy_uri);
+        encode_all(encoder, &self.user_identity_tokens);
+        encoder.str(&self.transport_profile_uri).u8(0);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let endpoint_url = decoder.string()?.unwrap_or_default();
+        let server = ApplicationDescription::decode(decoder)?;
+        decoder.byte_string()?;
+        let security_mode = decoder.u32()?;
+        let security_policy_uri = decoder.string()?.unwrap_or_default();
+        let user_identity_tokens = decode_all(decoder)?;
+        let transport_profile_uri = decoder.string()?.unwrap_or_default();
+        decoder.u8()?;
+        Ok(Self {
+            endpoint_url,
+            server,
+            security_mode,
+            security_policy_uri,
+            user_identity_tokens,
+            transport_profile_uri,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OpenSecureChannelRequest {
+    /// 0 to issue a token, 1 to renew it.
+    pub request_type: u32,
+    pub security_mode: u32,
+    pub requested_lifetime: u32,
+}
+
+impl Binary for OpenSecureChannelRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .u32(0)
+            .u32(self.request_type)
+            .u32(self.security_mode)
+            .byte_string(None)
+            .u32(self.requested_lifetime);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        decoder.u32()?;
+        let request_type = decoder.u32()?;
+        let security_mode = decoder.u32()?;
+        decoder.byte_string()?;
+        Ok(Self {
+            request_type,
+            security_mode,
+            requested_lifetime: decoder.u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenSecureChannelResponse {
+    pub channel_id: u32,
+    pub token_id: u32,
+    pub created_at: DateTime<Utc>,
+    pub revised_lifetime: u32,
+}
+
+impl Binary for OpenSecureChannelResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .u32(0)
+            .u32(self.channel_id)
+            .u32(self.token_id)
+            .date_time(self.created_at)
+            .u32(self.revised_lifetime)
+            .byte_string(None);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        decoder.u32()?;
+        let response = Self {
+            channel_id: decoder.u32()?,
+            token_id: decoder.u32()?,
+            created_at: decoder.date_time()?,
+            revised_lifetime: decoder.u32()?,
+        };
+        decoder.byte_string()?;
+        Ok(response)
+    }
+}
+
+/// GetEndpoints or FindServers, which ask the same.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryRequest {
+    pub endpoint_url: String,
+}
+
+impl Binary for DiscoveryRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.str(&self.endpoint_url).i32(-1).i32(-1);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let endpoint_url = decoder.string()?.unwrap_or_default();
+        strings(decoder)?;
+        strings(decoder)?;
+        Ok(Self { endpoint_url })
+    }
+}
+
+/// The response to GetEndpoints, or FindServers with the application
+/// descriptions.
+#[derive(Debug, Clone)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T: Binary> Binary for List<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.0);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(List(decode_all(decoder)?))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CreateSessionRequest {
+    pub client: ApplicationDescription,
+    pub endpoint_url: String,
+    pub session_name: String,
+    /// Milliseconds.
+    pub requested_timeout: f64,
+}
+
+impl Binary for CreateSessionRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.client.encode(encoder);
+        encoder
+            .string(None)
+            .str(&self.endpoint_url)
+            .str(&self.session_name)
+            .byte_string(None)
+            .byte_string(None)
+            .f64(self.requested_timeout)
+            .u32(0);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let client = ApplicationDescription::decode(decoder)?;
+        decoder.string()?;
+        let endpoint_url = decoder.string()?.unwrap_or_default();
+        let session_name = decoder.string()?.unwrap_or_default();
+        decoder.byte_string()?;
+        decoder.byte_string()?;
+        let requested_timeout = decoder.f64()?;
+        decoder.u32()?;
+        Ok(Self {
+            client,
+            endpoint_url,
+            session_name,
+            requested_timeout,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateSessionResponse {
+    pub session_id: NodeId,
+    pub authentication_token: NodeId,
+    pub revised_timeout: f64,
+    pub endpoints: Vec<EndpointDescription>,
+}
+
+impl Binary for CreateSessionResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .node_id(&self.session_id)
+            .node_id(&self.authentication_token)
+            .f64(self.revised_timeout)
+            .byte_string(None)
+            .byte_string(None);
+        encode_all(encoder, &self.endpoints);
+        encoder.i32(-1);
+        no_signature(encoder);
+        encoder.u32(0);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let session_id = decoder.node_id()?;
+        let authentication_token = decoder.node_id()?;
+        let revised_timeout = decoder.f64()?;
+        decoder.byte_string()?;
+        decoder.byte_string()?;
+        let endpoints = decode_all(decoder)?;
+        decoder.array(|decoder| {
+            decoder.byte_string()?;
+            decoder.byte_string()
+        })?;
+        skip_signature(decoder)?;
+        decoder.u32()?;
+        Ok(Self {
+            session_id,
+            authentication_token,
+            revised_timeout,
+            endpoints,
+        })
+    }
+}
+
+/// The user identity token of an ActivateSession request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identity {
+    Anonymous { policy_id: String },
+    /// Only unencrypted passwords; the channel has no security to encrypt with.
+    UserName {
+        policy_id: String,
+        username: String,
+        password: Vec<u8>,
+        encryption: Option<String>,
+    },
+}
+
+impl Identity {
+    pub fn to_extension_object(&self) -> ExtensionObject {
+        let mut encoder = Encoder::new();
+        match self {
+            Identity::Anonymous { policy_id } => {
+                encoder.str(policy_id);
+                ExtensionObject::binary(ids::ANONYMOUS_IDENTITY_TOKEN, encoder.finish())
+            }
+            Identity::UserName {
+                policy_id,
+                username,
+                password,
+                encryption,
+            } => {
+                encoder.str(policy_id).str(username).byte_string(Some(password)).string(encryption.as_deref());
+                ExtensionObject::binary(ids::USER_NAME_IDENTITY_TOKEN, encoder.finish())
+            }
+        }
+    }
+
+    /// The token in an extension object; a missing token is anonymous.
+    pub fn from_extension_object(object: &ExtensionObject) -> DecodeResult<Self> {
+        if object.type_id.is_null() {
+            return Ok(Identity::Anonymous { policy_id: String::new() });
+        }
+        let body = object.body.as_deref().unwrap_or_default();
+        let mut decoder = Decoder::new(body);
+        match object.type_id.as_ns0() {
+            Some(ids::ANONYMOUS_IDENTITY_TOKEN) => Ok(Identity::Anonymous {
+                policy_id: decoder.string()?.unwrap_or_default(),
+            }),
+            Some(ids::USER_NAME_IDENTITY_TOKEN) => Ok(Identity::UserName {
+                policy_id: decoder.string()?.unwrap_or_default(),
+                username: decoder.string()?.unwrap_or_default(),
+                password: decoder.byte_string()?.unwrap_or_default(),
+                encryption: decoder.string()?,
+            }),
+            _ => Err(StatusCode::BAD_IDENTITY_TOKEN_INVALID),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ActivateSessionRequest {
+    pub identity: ExtensionObject,
+}
+
+impl Binary for ActivateSessionRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        no_signature(encoder);
+        encoder.i32(-1).i32(-1).extension_object(&self.identity);
+        no_signature(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        skip_signature(decoder)?;
+        decoder.array(|decoder| {
+            decoder.byte_string()?;
+            decoder.byte_string()
+        })?;
+        strings(decoder)?;
+        let identity = decoder.extension_object()?;
+        skip_signature(decoder)?;
+        Ok(Self { identity })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ActivateSessionResponse;
+
+impl Binary for ActivateSessionResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.byte_string(None).i32(-1);
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        decoder.byte_string()?;
+        statuses(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CloseSessionRequest {
+    pub delete_subscriptions: bool,
+}
+
+impl Binary for CloseSessionRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bool(self.delete_subscriptions);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            delete_subscriptions: decoder.bool()?,
+        })
+    }
+}
+
+pub const BROWSE_FORWARD: u32 = 0;
+pub const BROWSE_INVERSE: u32 = 1;
+pub const BROWSE_BOTH: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct BrowseDescription {
+    pub node_id: NodeId,
+    pub direction: u32,
+    /// Null for any reference.
+    pub reference_type: NodeId,
+    pub include_subtypes: bool,
+    /// Node classes to return, 0 for all.
+    pub node_class_mask: u32,
+    pub result_mask: u32,
+}
+
+impl BrowseDescription {
+    /// Hierarchical references going down from `node_id`.
+    pub fn children(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            direction: BROWSE_FORWARD,
+            reference_type: NodeId::ns0(ids::HIERARCHICAL_REFERENCES),
+            include_subtypes: true,
+            node_class_mask: 0,
+            result_mask: 0x3F,
+        }
+    }
+}
+
+impl Binary for BrowseDescription {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .node_id(&self.node_id)
+            .u32(self.direction)
+            .node_id(&self.reference_type)
+            .bool(self.include_subtypes)
+            .u32(self.node_class_mask)
+            .u32(self.result_mask);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            node_id: decoder.node_id()?,
+            direction: decoder.u32()?,
+            reference_type: decoder.node_id()?,
+            include_subtypes: decoder.bool()?,
+            node_class_mask: decoder.u32()?,
+            result_mask: decoder.u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowseRequest {
+    pub max_references: u32,
+    pub nodes: Vec<BrowseDescription>,
+}
+
+impl Binary for BrowseRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        // 视图：空节点、时间 0、版本 0
+        encoder.node_id(&NodeId::NULL).i64(0).u32(0).u32(self.max_references);
+        encode_all(encoder, &self.nodes);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        decoder.node_id()?;
+        decoder.i64()?;
+        decoder.u32()?;
+        Ok(Self {
+            max_references: decoder.u32()?,
+            nodes: decode_all(decoder)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceDescription {
+    pub reference_type: NodeId,
+    pub is_forward: bool,
+    pub node_id: NodeId,
+    pub browse_name: QualifiedName,
+    pub display_name: LocalizedText,
+    pub node_class: u32,
+    pub type_definition: NodeId,
+}
+
+impl Binary for ReferenceDescription {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .node_id(&self.reference_type)
+            .bool(self.is_forward)
+            .expanded_node_id(&self.node_id)
+            .qualified_name(&self.browse_name)
+            .localized_text(&self.display_name)
+            .u32(self.node_class)
+            .expanded_node_id(&self.type_definition);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            reference_type: decoder.node_id()?,
+            is_forward: decoder.bool()?,
+            node_id: decoder.expanded_node_id()?,
+            browse_name: decoder.qualified_name()?,
+            display_name: decoder.localized_text()?,
+            node_class: decoder.u32()?,
+            type_definition: decoder.expanded_node_id()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowseResult {
+    pub status: StatusCode,
+    pub continuation_point: Option<Vec<u8>>,
+    pub references: Vec<ReferenceDescription>,
+}
+
+impl BrowseResult {
+    pub fn bad(status: StatusCode) -> Self {
+        Self {
+            status,
+            continuation_point: None,
+            references: Vec::new(),
+        }
+    }
+}
+
+impl Binary for BrowseResult {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.status_code(self.status).byte_string(self.continuation_point.as_deref());
+        encode_all(encoder, &self.references);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            status: decoder.status_code()?,
+            continuation_point: decoder.byte_string()?,
+            references: decode_all(decoder)?,
+        })
+    }
+}
+
+/// The response of Browse and BrowseNext.
+#[derive(Debug, Clone)]
+pub struct BrowseResponse {
+    pub results: Vec<BrowseResult>,
+}
+
+impl Binary for BrowseResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.results);
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let results = decode_all(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self { results })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowseNextRequest {
+    pub release: bool,
+    pub continuation_points: Vec<Vec<u8>>,
+}
+
+impl Binary for BrowseNextRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bool(self.release).array(&self.continuation_points, |encoder, point| {
+            encoder.byte_string(Some(point));
+        });
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            release: decoder.bool()?,
+            continuation_points: decoder.array(|decoder| Ok(decoder.byte_string()?.unwrap_or_default()))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadValueId {
+    pub node_id: NodeId,
+    pub attribute_id: u32,
+    pub index_range: Option<String>,
+}
+
+impl ReadValueId {
+    pub fn value(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            attribute_id: ids::ATTR_VALUE,
+            index_range: None,
+        }
+    }
+}
+
+impl Binary for ReadValueId {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .node_id(&self.node_id)
+            .u32(self.attribute_id)
+            .string(self.index_range.as_deref())
+            .qualified_name(&QualifiedName::default());
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let value = Self {
+            node_id: decoder.node_id()?,
+            attribute_id: decoder.u32()?,
+            index_range: decoder.string()?,
+        };
+        decoder.qualified_name()?;
+        Ok(value)
+    }
+}
+
+pub const TIMESTAMPS_SOURCE: u32 = 0;
+pub const TIMESTAMPS_SERVER: u32 = 1;
+pub const TIMESTAMPS_BOTH: u32 = 2;
+pub const TIMESTAMPS_NEITHER: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct ReadRequest {
+    pub max_age: f64,
+    pub timestamps: u32,
+    pub nodes: Vec<ReadValueId>,
+}
+
+impl Binary for ReadRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.f64(self.max_age).u32(self.timestamps);
+        encode_all(encoder, &self.nodes);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            max_age: decoder.f64()?,
+            timestamps: decoder.u32()?,
+            nodes: decode_all(decoder)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadResponse {
+    pub results: Vec<DataValue>,
+}
+
+impl Binary for ReadResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.array(&self.results, |encoder, value| {
+            encoder.data_value(value);
+        });
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let results = decoder.array(|decoder| decoder.data_value())?;
+        skip_diagnostics(decoder)?;
+        Ok(Self { results })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteValue {
+    pub node_id: NodeId,
+    pub attribute_id: u32,
+    pub index_range: Option<String>,
+    pub value: DataValue,
+}
+
+impl Binary for WriteValue {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .node_id(&self.node_id)
+            .u32(self.attribute_id)
+            .string(self.index_range.as_deref())
+            .data_value(&self.value);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            node_id: decoder.node_id()?,
+            attribute_id: decoder.u32()?,
+            index_range: decoder.string()?,
+            value: decoder.data_value()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteRequest {
+    pub nodes: Vec<WriteValue>,
+}
+
+impl Binary for WriteRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.nodes);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            nodes: decode_all(decoder)?,
+        })
+    }
+}
+
+/// A response that is one status per operation: Write, SetPublishingMode,
+/// DeleteMonitoredItems and DeleteSubscriptions.
+#[derive(Debug, Clone)]
+pub struct StatusResponse {
+    pub results: Vec<StatusCode>,
+}
+
+impl Binary for StatusResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_statuses(encoder, &self.results);
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let results = statuses(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self { results })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallMethodRequest {
+    pub object_id: NodeId,
+    pub method_id: NodeId,
+    pub input_arguments: Vec<Variant>,
+}
+
+impl Binary for CallMethodRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.node_id(&self.object_id).node_id(&self.method_id).array(&self.input_arguments, |encoder, value| {
+            encoder.variant(value);
+        });
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            object_id: decoder.node_id()?,
+            method_id: decoder.node_id()?,
+            input_arguments: decoder.array(|decoder| decoder.variant())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallMethodResult {
+    pub status: StatusCode,
+    pub input_argument_results: Vec<StatusCode>,
+    pub output_arguments: Vec<Variant>,
+}
+
+impl CallMethodResult {
+    pub fn status(status: StatusCode) -> Self {
+        Self {
+            status,
+            input_argument_results: Vec::new(),
+            output_arguments: Vec::new(),
+        }
+    }
+}
+
+impl Binary for CallMethodResult {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.status_code(self.status);
+        encode_statuses(encoder, &self.input_argument_results);
+        no_diagnostics(encoder);
+        encoder.array(&self.output_arguments, |encoder, value| {
+            encoder.variant(value);
+        });
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let status = decoder.status_code()?;
+        let input_argument_results = statuses(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self {
+            status,
+            input_argument_results,
+            output_arguments: decoder.array(|decoder| decoder.variant())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallRequest {
+    pub methods: Vec<CallMethodRequest>,
+}
+
+impl Binary for CallRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.methods);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            methods: decode_all(decoder)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallResponse {
+    pub results: Vec<CallMethodResult>,
+}
+
+impl Binary for CallResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.results);
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let results = decode_all(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self { results })
+    }
+}
+
+/// Describes a method argument in the InputArguments property.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argument {
+    pub name: String,
+    pub data_type: NodeId,
+    /// -1 for a scalar.
+    pub value_rank: i32,
+    pub description: LocalizedText,
+}
+
+impl Binary for Argument {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .str(&self.name)
+            .node_id(&self.data_type)
+            .i32(self.value_rank)
+            .i32(-1)
+            .localized_text(&self.description);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let name = decoder.string()?.unwrap_or_default();
+        let data_type = decoder.node_id()?;
+        let value_rank = decoder.i32()?;
+        decoder.array(|decoder| decoder.u32())?;
+        Ok(Self {
+            name,
+            data_type,
+            value_rank,
+            description: decoder.localized_text()?,
+        })
+    }
+}
+
+impl Argument {
+    pub fn to_extension_object(&self) -> ExtensionObject {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        ExtensionObject::binary(ids::ARGUMENT_ENCODING, encoder.finish())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateSubscriptionRequest {
+    /// Milliseconds.
+    pub publishing_interval: f64,
+    pub lifetime_count: u32,
+    pub max_keep_alive_count: u32,
+    pub max_notifications: u32,
+    pub publishing_enabled: bool,
+    pub priority: u8,
+}
+
+impl Binary for CreateSubscriptionRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .f64(self.publishing_interval)
+            .u32(self.lifetime_count)
+            .u32(self.max_keep_alive_count)
+            .u32(self.max_notifications)
+            .bool(self.publishing_enabled)
+            .u8(self.priority);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            publishing_interval: decoder.f64()?,
+            lifetime_count: decoder.u32()?,
+            max_keep_alive_count: decoder.u32()?,
+            max_notifications: decoder.u32()?,
+            publishing_enabled: decoder.bool()?,
+            priority: decoder.u8()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateSubscriptionResponse {
+    pub subscription_id: u32,
+    pub publishing_interval: f64,
+    pub lifetime_count: u32,
+    pub max_keep_alive_count: u32,
+}
+
+impl Binary for CreateSubscriptionResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .u32(self.subscription_id)
+            .f64(self.publishing_interval)
+            .u32(self.lifetime_count)
+            .u32(self.max_keep_alive_count);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            subscription_id: decoder.u32()?,
+            publishing_interval: decoder.f64()?,
+            lifetime_count: decoder.u32()?,
+            max_keep_alive_count: decoder.u32()?,
+        })
+    }
+}
+
+pub const MONITORING_DISABLED: u32 = 0;
+pub const MONITORING_SAMPLING: u32 = 1;
+pub const MONITORING_REPORTING: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct MonitoredItemCreateRequest {
+    pub item: ReadValueId,
+    pub monitoring_mode: u32,
+    pub client_handle: u32,
+    pub sampling_interval: f64,
+    pub queue_size: u32,
+    pub discard_oldest: bool,
+}
+
+impl MonitoredItemCreateRequest {
+    /// Reports changes of a node's value under `client_handle`.
+    pub fn value(node_id: NodeId, client_handle: u32) -> Self {
+        Self {
+            item: ReadValueId::value(node_id),
+            monitoring_mode: MONITORING_REPORTING,
+            client_handle,
+            sampling_interval: -1.0,
+            queue_size: 1,
+            discard_oldest: true,
+        }
+    }
+}
+
+impl Binary for MonitoredItemCreateRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.item.encode(encoder);
+        encoder
+            .u32(self.monitoring_mode)
+            .u32(self.client_handle)
+            .f64(self.sampling_interval)
+            .extension_object(&ExtensionObject::null())
+            .u32(self.queue_size)
+            .bool(self.discard_oldest);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let item = ReadValueId::decode(decoder)?;
+        let monitoring_mode = decoder.u32()?;
+        let client_handle = decoder.u32()?;
+        let sampling_interval = decoder.f64()?;
+        // 过滤器不支持，只报值变化
+        decoder.extension_object()?;
+        Ok(Self {
+            item,
+            monitoring_mode,
+            client_handle,
+            sampling_interval,
+            queue_size: decoder.u32()?,
+            discard_oldest: decoder.bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MonitoredItemCreateResult {
+    pub status: StatusCode,
+    pub monitored_item_id: u32,
+    pub sampling_interval: f64,
+    pub queue_size: u32,
+}
+
+impl Binary for MonitoredItemCreateResult {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .status_code(self.status)
+            .u32(self.monitored_item_id)
+            .f64(self.sampling_interval)
+            .u32(self.queue_size)
+            .extension_object(&ExtensionObject::null());
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let result = Self {
+            status: decoder.status_code()?,
+            monitored_item_id: decoder.u32()?,
+            sampling_interval: decoder.f64()?,
+            queue_size: decoder.u32()?,
+        };
+        decoder.extension_object()?;
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateMonitoredItemsRequest {
+    pub subscription_id: u32,
+    pub timestamps: u32,
+    pub items: Vec<MonitoredItemCreateRequest>,
+}
+
+impl Binary for CreateMonitoredItemsRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(self.subscription_id).u32(self.timestamps);
+        encode_all(encoder, &self.items);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            subscription_id: decoder.u32()?,
+            timestamps: decoder.u32()?,
+            items: decode_all(decoder)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateMonitoredItemsResponse {
+    pub results: Vec<MonitoredItemCreateResult>,
+}
+
+impl Binary for CreateMonitoredItemsResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.results);
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let results = decode_all(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self { results })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteMonitoredItemsRequest {
+    pub subscription_id: u32,
+    pub monitored_item_ids: Vec<u32>,
+}
+
+impl Binary for DeleteMonitoredItemsRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(self.subscription_id).array(&self.monitored_item_ids, |encoder, id| {
+            encoder.u32(*id);
+        });
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            subscription_id: decoder.u32()?,
+            monitored_item_ids: decoder.array(|decoder| decoder.u32())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SetPublishingModeRequest {
+    pub publishing_enabled: bool,
+    pub subscription_ids: Vec<u32>,
+}
+
+impl Binary for SetPublishingModeRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bool(self.publishing_enabled).array(&self.subscription_ids, |encoder, id| {
+            encoder.u32(*id);
+        });
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            publishing_enabled: decoder.bool()?,
+            subscription_ids: decoder.array(|decoder| decoder.u32())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteSubscriptionsRequest {
+    pub subscription_ids: Vec<u32>,
+}
+
+impl Binary for DeleteSubscriptionsRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.array(&self.subscription_ids, |encoder, id| {
+            encoder.u32(*id);
+        });
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            subscription_ids: decoder.array(|decoder| decoder.u32())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Acknowledgement {
+    pub subscription_id: u32,
+    pub sequence_number: u32,
+}
+
+impl Binary for Acknowledgement {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(self.subscription_id).u32(self.sequence_number);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            subscription_id: decoder.u32()?,
+            sequence_number: decoder.u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PublishRequest {
+    pub acknowledgements: Vec<Acknowledgement>,
+}
+
+impl Binary for PublishRequest {
+    fn encode(&self, encoder: &mut Encoder) {
+        encode_all(encoder, &self.acknowledgements);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            acknowledgements: decode_all(decoder)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitoredItemNotification {
+    pub client_handle: u32,
+    pub value: DataValue,
+}
+
+/// A Publish response; no notifications at all is a keep-alive.
+#[derive(Debug, Clone)]
+pub struct PublishResponse {
+    pub subscription_id: u32,
+    pub available_sequence_numbers: Vec<u32>,
+    pub more_notifications: bool,
+    pub sequence_number: u32,
+    pub publish_time: DateTime<Utc>,
+    /// The data changes, sent as one DataChangeNotification.
+    pub notifications: Vec<MonitoredItemNotification>,
+    pub results: Vec<StatusCode>,
+}
+
+impl Binary for PublishResponse {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(self.subscription_id).array(&self.available_sequence_numbers, |encoder, number| {
+            encoder.u32(*number);
+        });
+        encoder.bool(self.more_notifications).u32(self.sequence_number).date_time(self.publish_time);
+        if self.notifications.is_empty() {
+            encoder.i32(0);
+        } else {
+            let mut data = Encoder::new();
+            data.array(&self.notifications, |encoder, item| {
+                encoder.u32(item.client_handle).data_value(&item.value);
+            });
+            data.i32(-1);
+            let change = ExtensionObject::binary(ids::DATA_CHANGE_NOTIFICATION, data.finish());
+            encoder.i32(1).extension_object(&change);
+        }
+        encode_statuses(encoder, &self.results);
+        no_diagnostics(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        let subscription_id = decoder.u32()?;
+        let available_sequence_numbers = decoder.array(|decoder| decoder.u32())?;
+        let more_notifications = decoder.bool()?;
+        let sequence_number = decoder.u32()?;
+        let publish_time = decoder.date_time()?;
+        let mut notifications = Vec::new();
+        for data in decoder.array(|decoder| decoder.extension_object())? {
+            if data.type_id.as_ns0() != Some(ids::DATA_CHANGE_NOTIFICATION) {
+                continue;
+            }
+            let body = data.body.unwrap_or_default();
+            let mut data = Decoder::new(&body);
+            notifications.extend(data.array(|decoder| {
+                Ok(MonitoredItemNotification {
+                    client_handle: decoder.u32()?,
+                    value: decoder.data_value()?,
+                })
+            })?);
+        }
+        let results = statuses(decoder)?;
+        skip_diagnostics(decoder)?;
+        Ok(Self {
+            subscription_id,
+            available_sequence_numbers,
+            more_notifications,
+            sequence_number,
+            publish_time,
+            notifications,
+            results,
+        })
+    }
+}
+
+/// The ServerStatus variable's value.
+pub fn server_status(start_time: DateTime<Utc>, now: DateTime<Utc>, product_name: &str) -> ExtensionObject {
+    let mut encoder = Encoder::new();
+    encoder.date_time(start_time).date_time(now).u32(0);
+    // BuildInfo
+    encoder
+        .str(PRODUCT_URI)
+        .str("CSC")
+        .str(product_name)
+        .str(env!("CARGO_PKG_VERSION"))
+        .string(None)
+        .date_time(start_time);
+    encoder.u32(0).localized_text(&LocalizedText::default());
+    ExtensionObject::binary(ids::SERVER_STATUS_ENCODING, encoder.finish())
+}
+
+pub const PRODUCT_URI: &str = "urn:csc:server";
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{DeviceConfig, OpcuaConfig};
+use crate::ids;
+use crate::services::{Argument, BrowseDescription, BrowseResult, ReferenceDescription, BROWSE_BOTH, BROWSE_FORWARD, BROWSE_INVERSE};
+use crate::types::{DataValue, LocalizedText, NodeId, QualifiedName, StatusCode, Variant};
+
+/// Namespace of the device nodes.
+pub const NAMESPACE_URI: &str = "urn:csc:devices";
+pub const NAMESPACE: u16 = 1;
+
+/// The folder holding one object per configured device.
+pub const DEVICES_FOLDER: NodeId = NodeId::numeric(NAMESPACE, 1);
+
+/// Bits of the NodeClass attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeClass {
+    Object = 1,
+    Variable = 2,
+    Method = 4,
+    ObjectType = 8,
+    VariableType = 16,
+    ReferenceType = 32,
+    DataType = 64,
+    View = 128,
+}
+
+const ACCESS_READ: u8 = 0x01;
+const ACCESS_WRITE: u8 = 0x02;
+
+/// What a node stands for on the bus.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    None,
+    Telemetry,
+    Setpoint { device: String, name: String },
+    Method { device: String, motor: i32, kind: MethodKind },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    Move,
+    Stop,
+    Home,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reference {
+    type_id: u32,
+    forward: bool,
+    target: usize,
+}
+
+struct Node {
+    id: NodeId,
+    class: NodeClass,
+    browse_name: QualifiedName,
+    display_name: LocalizedText,
+    type_definition: Option<NodeId>,
+    value: DataValue,
+    data_type: u32,
+    value_rank: i32,
+    access: u8,
+    binding: Binding,
+    references: Vec<Reference>,
+}
+
+impl Node {
+    fn new(id: NodeId, class: NodeClass, name: &str) -> Self {
+        Self {
+            browse_name: QualifiedName::new(id.namespace, name),
+            display_name: LocalizedText::new(name),
+            id,
+            class,
+            type_definition: None,
+            value: DataValue::bad(StatusCode::BAD_WAITING_FOR_INITIAL_DATA),
+            data_type: 0,
+            value_rank: -1,
+            access: ACCESS_READ,
+            binding: Binding::None,
+            references: Vec::new(),
+        }
+    }
+}
+
+/// The nodes a client sees: the Server object with its status, and under
+/// Objects/Devices one object per configured device holding its telemetry
+/// groups, a `setpoints` folder and the motor methods.
+///
+/// Device nodes have string ids in namespace 1 built from their path, e.g.
+/// `ns=1;s=pump1/status/flow` or `ns=1;s=pump1/setpoints/speed`.
+pub struct AddressSpace {
+    nodes: Vec<Node>,
+    index: HashMap<NodeId, usize>,
+    start_time: DateTime<Utc>,
+}
+
+impl AddressSpace {
+    pub fn new(config: &OpcuaConfig, start_time: DateTime<Utc>) -> Self {
+        let mut space = Self {
+            nodes: Vec::new(),
+            index: HashMap::new(),
+            start_time,
+        };
+        space.standard_nodes(&config.application_name);
+        let devices = space.add(Node::new(DEVICES_FOLDER, NodeClass::Object, "Devices"), ids::OBJECTS_FOLDER, ids::ORGANIZES);
+        space.typed(devices, ids::FOLDER_TYPE);
+        for device in &config.devices {
+            space.device(devices, device);
+        }
+        space
+    }
+
+    fn standard_nodes(&mut self, application_name: &str) {
+        let root = self.push(Node::new(NodeId::ns0(ids::ROOT_FOLDER), NodeClass::Object, "Root"));
+        self.typed(root, ids::FOLDER_TYPE);
+        for (id, name) in [(ids::OBJECTS_FOLDER, "Objects"), (ids::TYPES_FOLDER, "Types"), (ids::VIEWS_FOLDER, "Views")] {
+            let folder = self.add(Node::new(NodeId::ns0(id), NodeClass::Object, name), ids::ROOT_FOLDER, ids::ORGANIZES);
+            self.typed(folder, ids::FOLDER_TYPE);
+        }
+        let server = self.add(Node::new(NodeId::ns0(ids::SERVER), NodeClass::Object, "Server"), ids::OBJECTS_FOLDER, ids::ORGANIZES);
+        self.typed(server, ids::SERVER_TYPE);
+
+        let namespaces = Variant::strings(["http://opcfoundation.org/UA/", NAMESPACE_URI]);
+        self.variable(ids::NAMESPACE_ARRAY, "NamespaceArray", ids::SERVER, ids::HAS_PROPERTY, ids::STRING, namespaces);
+        let servers = Variant::strings([crate::services::PRODUCT_URI]);
+        self.variable(ids::SERVER_ARRAY, "ServerArray", ids::SERVER, ids::HAS_PROPERTY, ids::STRING, servers);
+        let status = Variant::ExtensionObject(crate::services::server_status(self.start_time, self.start_time, application_name));
+        let node = self.variable(ids::SERVER_STATUS, "ServerStatus", ids::SERVER, ids::HAS_COMPONENT, ids::SERVER_STATUS_DATA_TYPE, status);
+        self.typed(node, ids::SERVER_STATUS_TYPE);
+        let start = Variant::DateTime(self.start_time);
+        self.variable(ids::SERVER_STATUS_START_TIME, "StartTime", ids::SERVER_STATUS, ids::HAS_COMPONENT, ids::DATE_TIME, start.clone());
+        self.variable(ids::SERVER_STATUS_CURRENT_TIME, "CurrentTime", ids::SERVER_STATUS, ids::HAS_COMPONENT, ids::DATE_TIME, start);
+        // 0 = Running
+        self.variable(ids::SERVER_STATUS_STATE, "State", ids::SERVER_STATUS, ids::HAS_COMPONENT, ids::SERVER_STATE, Variant::Int32(0));
+    }
+
+    /// A namespace 0 variable with a fixed value.
+    fn variable(&mut self, id: u32, name: &str, parent: u32, reference: u32, data_type: u32, value: Variant) -> usize {
+        let mut node = Node::new(NodeId::ns0(id), NodeClass::Variable, name);
+        node.value_rank = if matches!(value, Variant::Array(..)) { 1 } else { -1 };
+        node.value = DataValue::new(value, self.start_time);
+        node.data_type = data_type;
+        let index = self.add(node, parent, reference);
+        let type_definition = if reference == ids::HAS_PROPERTY { ids::PROPERTY_TYPE } else { ids::BASE_DATA_VARIABLE_TYPE };
+        self.typed(index, type_definition);
+        index
+    }
+
+    fn device(&mut self, folder: usize, device: &DeviceConfig) {
+        let name = &device.name;
+        let object = self.child(folder, NodeId::string(NAMESPACE, name.as_str()), NodeClass::Object, name, ids::ORGANIZES);
+        self.typed(object, ids::BASE_OBJECT_TYPE);
+        for group in &device.telemetry {
+            let id = NodeId::string(NAMESPACE, format!("{}/{}", name, group.group));
+            let folder = self.child(object, id, NodeClass::Object, &group.group, ids::HAS_COMPONENT);
+            self.typed(folder, ids::FOLDER_TYPE);
+            for value in &group.values {
+                let id = NodeId::string(NAMESPACE, format!("{}/{}/{}", name, group.group, value));
+                self.measurement(folder, id, value, Binding::Telemetry);
+            }
+        }
+        if !device.setpoints.is_empty() {
+            let id = NodeId::string(NAMESPACE, format!("{}/setpoints", name));
+            let folder = self.child(object, id, NodeClass::Object, "setpoints", ids::HAS_COMPONENT);
+            self.typed(folder, ids::FOLDER_TYPE);
+            for setpoint in &device.setpoints {
+                let id = NodeId::string(NAMESPACE, format!("{}/setpoints/{}", name, setpoint));
+                let binding = Binding::Setpoint {
+                    device: name.clone(),
+                    name: setpoint.clone(),
+                };
+                let node = self.measurement(folder, id, setpoint, binding);
+                self.nodes[node].access = ACCESS_READ | ACCESS_WRITE;
+            }
+        }
+        if let Some(motor) = device.motor {
+            for kind in [MethodKind::Move, MethodKind::Stop, MethodKind::Home] {
+                let method_name = format!("{:?}", kind);
+                let id = NodeId::string(NAMESPACE, format!("{}/{}", name, method_name));
+                let method = self.child(object, id.clone(), NodeClass::Method, &method_name, ids::HAS_COMPONENT);
+                self.nodes[method].binding = Binding::Method {
+                    device: name.clone(),
+                    motor,
+                    kind,
+                };
+                if kind == MethodKind::Move {
+                    let argument = Argument {
+                        name: "direction".to_string(),
+                        data_type: NodeId::ns0(ids::STRING),
+                        value_rank: -1,
+                        description: LocalizedText::new("Up or Down"),
+                    };
+                    let value = Variant::Array(22, vec![Variant::ExtensionObject(argument.to_extension_object())]);
+                    let id = NodeId::string(NAMESPACE, format!("{}/{}/InputArguments", name, method_name));
+                    let property = self.child(method, id, NodeClass::Variable, "InputArguments", ids::HAS_PROPERTY);
+                    let node = &mut self.nodes[property];
+                    node.browse_name = QualifiedName::new(0, "InputArguments");
+                    node.value = DataValue::new(value, self.start_time);
+                    node.data_type = ids::ARGUMENT;
+                    node.value_rank = 1;
+                    self.typed(property, ids::PROPERTY_TYPE);
+                }
+            }
+        }
+    }
+
+    /// A Double variable filled from the bus.
+    fn measurement(&mut self, parent: usize, id: NodeId, name: &str, binding: Binding) -> usize {
+        let node = self.child(parent, id, NodeClass::Variable, name, ids::HAS_COMPONENT);
+        self.nodes[node].data_type = ids::DOUBLE;
+        self.nodes[node].binding = binding;
+        self.typed(node, ids::BASE_DATA_VARIABLE_TYPE);
+        node
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        let index = self.nodes.len();
+        self.index.insert(node.id.clone(), index);
+        self.nodes.push(node);
+        index
+    }
+
+    fn add(&mut self, node: Node, parent: u32, reference: u32) -> usize {
+        let parent = self.index[&NodeId::ns0(parent)];
+        let index = self.push(node);
+        self.link(parent, index, reference);
+        index
+    }
+
+    fn child(&mut self, parent: usize, id: NodeId, class: NodeClass, name: &str, reference: u32) -> usize {
+        let index = self.push(Node::new(id, class, name));
+        self.link(parent, index, reference);
+        index
+    }
+
+    fn link(&mut self, source: usize, target: usize, type_id: u32) {
+        self.nodes[source].references.push(Reference { type_id, forward: true, target });
+        self.nodes[target].references.push(Reference {
+            type_id,
+            forward: false,
+            target: source,
+        });
+    }
+
+    fn typed(&mut self, index: usize, type_definition: u32) {
+        self.nodes[index].type_definition = Some(NodeId::ns0(type_definition));
+    }
+
+    fn node(&self, id: &NodeId) -> Option<&Node> {
+        self.index.get(id).map(|index| &self.nodes[*index])
+    }
+
+    pub fn contains(&self, id: &NodeId) -> bool {
+        self.index.contains_key(id)
+    }
+
+    pub fn binding(&self, id: &NodeId) -> Option<&Binding> {
+        self.node(id).map(|node| &node.binding)
+    }
+
+    /// Whether `method` is a method of `object`.
+    pub fn has_method(&self, object: &NodeId, method: &NodeId) -> bool {
+        let (Some(object), Some(&method)) = (self.node(object), self.index.get(method)) else {
+            return false;
+        };
+        object.references.iter().any(|reference| reference.forward && reference.target == method)
+    }
+
+    /// Keeps CurrentTime and ServerStatus up to date.
+    pub fn tick(&mut self, now: DateTime<Utc>, application_name: &str) {
+        if let Some(&index) = self.index.get(&NodeId::ns0(ids::SERVER_STATUS_CURRENT_TIME)) {
+            self.nodes[index].value = DataValue::new(Variant::DateTime(now), now);
+        }
+        if let Some(&index) = self.index.get(&NodeId::ns0(ids::SERVER_STATUS)) {
+            let status = crate::services::server_status(self.start_time, now, application_name);
+            self.nodes[index].value = DataValue::new(Variant::ExtensionObject(status), now);
+        }
+    }
+
+    /// Sets the value of a variable bound to the bus; false if there is none.
+    pub fn set_value(&mut self, id: &NodeId, value: f64, at: DateTime<Utc>) -> bool {
+        let Some(&index) = self.index.get(id) else {
+            return false;
+        };
+        let node = &mut self.nodes[index];
+        if node.binding == Binding::None || node.class != NodeClass::Variable {
+            return false;
+        }
+        node.value = DataValue::new(Variant::Double(value), at);
+        true
+    }
+
+    pub fn value(&self, id: &NodeId) -> Option<&DataValue> {
+        self.node(id).filter(|node| node.class == NodeClass::Variable).map(|node| &node.value)
+    }
+
+    /// Whether a write to the Value attribute of `id` would be taken.
+    pub fn check_write(&self, id: &NodeId, attribute_id: u32) -> Result<(), StatusCode> {
+        let node = self.node(id).ok_or(StatusCode::BAD_NODE_ID_UNKNOWN)?;
+        if attribute_id != ids::ATTR_VALUE {
+            return Err(if attribute_id == 0 || attribute_id > ids::ATTR_USER_EXECUTABLE {
+                StatusCode::BAD_ATTRIBUTE_ID_INVALID
+            } else {
+                StatusCode::BAD_NOT_WRITABLE
+            });
+        }
+        if node.access & ACCESS_WRITE == 0 {
+            return Err(StatusCode::BAD_NOT_WRITABLE);
+        }
+        Ok(())
+    }
+
+    /// An attribute of a node; `writable` is what the session may do.
+    pub fn read(&self, id: &NodeId, attribute_id: u32, writable: bool) -> DataValue {
+        let Some(node) = self.node(id) else {
+            return DataValue::bad(StatusCode::BAD_NODE_ID_UNKNOWN);
+        };
+        let variable = node.class == NodeClass::Variable;
+        let value = match attribute_id {
+            ids::ATTR_NODE_ID => Variant::NodeId(node.id.clone()),
+            ids::ATTR_NODE_CLASS => Variant::Int32(node.class as i32),
+            ids::ATTR_BROWSE_NAME => Variant::QualifiedName(node.browse_name.clone()),
+            ids::ATTR_DISPLAY_NAME => Variant::LocalizedText(node.display_name.clone()),
+            ids::ATTR_DESCRIPTION => Variant::LocalizedText(LocalizedText::default()),
+            ids::ATTR_WRITE_MASK | ids::ATTR_USER_WRITE_MASK => Variant::UInt32(0),
+            ids::ATTR_EVENT_NOTIFIER if node.class == NodeClass::Object => Variant::Byte(0),
+            ids::ATTR_VALUE if variable => return node.value.clone(),
+            ids::ATTR_DATA_TYPE if variable => Variant::NodeId(NodeId::ns0(node.data_type)),
+            ids::ATTR_VALUE_RANK if variable => Variant::Int32(node.value_rank),
+            ids::ATTR_ARRAY_DIMENSIONS if variable => {
+                if node.value_rank == 1 {
+                    Variant::Array(7, vec![Variant::UInt32(0)])
+                } else {
+                    Variant::Array(7, Vec::new())
+                }
+            }
+            ids::ATTR_ACCESS_LEVEL if variable => Variant::Byte(node.access),
+            ids::ATTR_USER_ACCESS_LEVEL if variable => {
+                Variant::Byte(if writable { node.access } else { node.access & !ACCESS_WRITE })
+            }
+            ids::ATTR_MINIMUM_SAMPLING_INTERVAL if variable => Variant::Double(0.0),
+            ids::ATTR_HISTORIZING if variable => Variant::Boolean(false),
+            ids::ATTR_EXECUTABLE if node.class == NodeClass::Method => Variant::Boolean(true),
+            ids::ATTR_USER_EXECUTABLE if node.class == NodeClass::Method => Variant::Boolean(writable),
+            _ => return DataValue::bad(StatusCode::BAD_ATTRIBUTE_ID_INVALID),
+        };
+        DataValue::plain(value)
+    }
+
+    /// The references of a node that pass the description's filters.
+    pub fn browse(&self, description: &BrowseDescription) -> BrowseResult {
+        let Some(node) = self.node(&description.node_id) else {
+            return BrowseResult::bad(StatusCode::BAD_NODE_ID_UNKNOWN);
+        };
+        if description.direction > BROWSE_BOTH {
+            return BrowseResult::bad(StatusCode::BAD_BROWSE_DIRECTION_INVALID);
+        }
+        let wanted_type = if description.reference_type.is_null() {
+            None
+        } else {
+            match description.reference_type.as_ns0() {
+                Some(id) => Some(id),
+                None => return BrowseResult::bad(StatusCode::BAD_INVALID_ARGUMENT),
+            }
+        };
+        let mut references: Vec<ReferenceDescription> = node
+            .references
+            .iter()
+            .filter(|reference| match description.direction {
+                BROWSE_FORWARD => reference.forward,
+                BROWSE_BOTH => true,
+                _ => !reference.forward,
+            })
+            .filter(|reference| match wanted_type {
+                None => true,
+                Some(wanted) if description.include_subtypes => ids::is_subtype(reference.type_id, wanted),
+                Some(wanted) => reference.type_id == wanted,
+            })
+            .map(|reference| (reference, &self.nodes[reference.target]))
+            .filter(|(_, target)| description.node_class_mask == 0 || description.node_class_mask & target.class as u32 != 0)
+            .map(|(reference, target)| ReferenceDescription {
+                reference_type: NodeId::ns0(reference.type_id),
+                is_forward: reference.forward,
+                node_id: target.id.clone(),
+                browse_name: target.browse_name.clone(),
+                display_name: target.display_name.clone(),
+                node_class: target.class as u32,
+                type_definition: target.type_definition.clone().unwrap_or(NodeId::NULL),
+            })
+            .collect();
+        // 类型定义引用指向未建模的类型节点，单独给出
+        if description.direction != BROWSE_INVERSE {
+            if let Some(type_definition) = &node.type_definition {
+                let wanted = match wanted_type {
+                    None => true,
+                    Some(wanted) if description.include_subtypes => ids::is_subtype(ids::HAS_TYPE_DEFINITION, wanted),
+                    Some(wanted) => wanted == ids::HAS_TYPE_DEFINITION,
+                };
+                let class = if node.class == NodeClass::Variable { NodeClass::VariableType } else { NodeClass::ObjectType };
+                if wanted && (description.node_class_mask == 0 || description.node_class_mask & class as u32 != 0) {
+                    references.push(ReferenceDescription {
+                        reference_type: NodeId::ns0(ids::HAS_TYPE_DEFINITION),
+                        is_forward: true,
+                        node_id: type_definition.clone(),
+                        browse_name: QualifiedName::default(),
+                        display_name: LocalizedText::default(),
+                        node_class: class as u32,
+                        type_definition: NodeId::NULL,
+                    });
+                }
+            }
+        }
+        BrowseResult {
+            status: StatusCode::GOOD,
+            continuation_point: None,
+            references,
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::codec::{DecodeResult, Decoder, Encoder};
+use crate::types::StatusCode;
+
+pub const SECURITY_POLICY_NONE: &str = "http://opcfoundation.org/UA/SecurityPolicy#None";
+pub const TRANSPORT_PROFILE: &str = "http://opcfoundation.org/UA-Profile/Transport/uatcp-uasc-uabinary";
+
+/// Buffer size offered for both directions.
+pub const BUFFER_SIZE: u32 = 65_536;
+/// Largest message put together from chunks.
+pub const MAX_MESSAGE: u32 = 16 * 1024 * 1024;
+/// Smallest buffer the protocol allows.
+const MIN_BUFFER: u32 = 8192;
+
+/// 消息头：类型 3 字节、分块标志 1 字节、总长度 4 字节
+const HEADER: usize = 8;
+/// Channel id, token id, sequence number and request id in front of every MSG chunk body.
+const SYMMETRIC_HEADER: usize = 16;
+
+/// One chunk as read from the wire, without its 8 byte header.
+pub struct Chunk {
+    pub kind: [u8; 3],
+    /// `F` for the final chunk, `C` for more to come, `A` for an aborted message.
+    pub flag: u8,
+    pub body: Vec<u8>,
+}
+
+pub async fn read_chunk(reader: &mut (impl AsyncRead + Unpin), max: u32) -> io::Result<Chunk> {
+    let mut header = [0u8; HEADER];
+    reader.read_exact(&mut header).await?;
+    let size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if (size as usize) < HEADER || size > max {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("chunk of {} bytes", size)));
+    }
+    let mut body = vec![0u8; size as usize - HEADER];
+    reader.read_exact(&mut body).await?;
+    Ok(Chunk {
+        kind: header[..3].try_into().unwrap(),
+        flag: header[3],
+        body,
+    })
+}
+
+fn frame(kind: &[u8; 3], flag: u8, body: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(HEADER + body.len());
+    chunk.extend_from_slice(kind);
+    chunk.push(flag);
+    chunk.extend_from_slice(&((HEADER + body.len()) as u32).to_le_bytes());
+    chunk.extend_from_slice(body);
+    chunk
+}
+
+/// Buffer sizes of a Hello or Acknowledge message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub protocol_version: u32,
+    pub receive_buffer: u32,
+    pub send_buffer: u32,
+    /// 0 means no limit.
+    pub max_message: u32,
+    pub max_chunks: u32,
+}
+
+impl Limits {
+    pub fn ours() -> Self {
+        Self {
+            protocol_version: 0,
+            receive_buffer: BUFFER_SIZE,
+            send_buffer: BUFFER_SIZE,
+            max_message: MAX_MESSAGE,
+            max_chunks: 0,
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> DecodeResult<Self> {
+        Ok(Self {
+            protocol_version: decoder.u32()?,
+            receive_buffer: decoder.u32()?,
+            send_buffer: decoder.u32()?,
+            max_message: decoder.u32()?,
+            max_chunks: decoder.u32()?,
+        })
+    }
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .u32(self.protocol_version)
+            .u32(self.receive_buffer)
+            .u32(self.send_buffer)
+            .u32(self.max_message)
+            .u32(self.max_chunks);
+    }
+
+    /// What the server answers a client's Hello with: no buffer larger than
+    /// either side offers.
+    pub fn answer(&self) -> Result<Self, StatusCode> {
+        if self.receive_buffer < MIN_BUFFER || self.send_buffer < MIN_BUFFER {
+            return Err(StatusCode::BAD_TCP_MESSAGE_TOO_LARGE);
+        }
+        Ok(Self {
+            protocol_version: 0,
+            receive_buffer: self.send_buffer.min(BUFFER_SIZE),
+            send_buffer: self.receive_buffer.min(BUFFER_SIZE),
+            max_message: MAX_MESSAGE,
+            max_chunks: 0,
+        })
+    }
+}
+
+pub fn decode_hello(body: &[u8]) -> DecodeResult<(Limits, String)> {
+    let mut decoder = Decoder::new(body);
+    let limits = Limits::decode(&mut decoder)?;
+    let url = decoder.string()?.unwrap_or_default();
+    Ok((limits, url))
+}
+
+pub fn hello(limits: &Limits, endpoint_url: &str) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    limits.encode(&mut encoder);
+    encoder.str(endpoint_url);
+    frame(b"HEL", b'F', &encoder.finish())
+}
+
+pub fn decode_acknowledge(body: &[u8]) -> DecodeResult<Limits> {
+    Limits::decode(&mut Decoder::new(body))
+}
+
+pub fn acknowledge(limits: &Limits) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    limits.encode(&mut encoder);
+    frame(b"ACK", b'F', &encoder.finish())
+}
+
+pub fn error(status: StatusCode, reason: &str) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder.status_code(status).str(reason);
+    frame(b"ERR", b'F', &encoder.finish())
+}
+
+pub fn decode_error(body: &[u8]) -> (StatusCode, String) {
+    let mut decoder = Decoder::new(body);
+    let status = decoder.status_code().unwrap_or(StatusCode::BAD_COMMUNICATION_ERROR);
+    (status, decoder.string().ok().flatten().unwrap_or_default())
+}
+
+/// A secure conversation chunk with its headers taken apart.
+pub struct Secured {
+    pub channel_id: u32,
+    /// Security policy of an OPN chunk, token id of the others.
+    pub security: Security,
+    pub sequence: u32,
+    pub request_id: u32,
+    pub body: Vec<u8>,
+}
+
+pub enum Security {
+    Policy(Option<String>),
+    Token(u32),
+}
+
+impl Secured {
+    pub fn decode(chunk: &Chunk) -> DecodeResult<Self> {
+        let mut decoder = Decoder::new(&chunk.body);
+        let channel_id = decoder.u32()?;
+        let security = if &chunk.kind == b"OPN" {
+            let policy = decoder.string()?;
+            // 证书与指纹，None 策略下为空
+            decoder.byte_string()?;
+            decoder.byte_string()?;
+            Security::Policy(policy)
+        } else {
+            Security::Token(decoder.u32()?)
+        };
+        Ok(Self {
+            channel_id,
+            security,
+            sequence: decoder.u32()?,
+            request_id: decoder.u32()?,
+            body: decoder.remaining().to_vec(),
+        })
+    }
+}
+
+/// The one-chunk OPN message carrying an OpenSecureChannel request or response.
+pub fn open(channel_id: u32, sequence: u32, request_id: u32, body: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder
+        .u32(channel_id)
+        .str(SECURITY_POLICY_NONE)
+        .byte_string(None)
+        .byte_string(None)
+        .u32(sequence)
+        .u32(request_id)
+        .bytes(body);
+    frame(b"OPN", b'F', &encoder.finish())
+}
+
+/// A MSG or CLO message split into chunks no larger than `max_chunk`,
+/// numbered on from `sequence`.
+pub fn message(
+    kind: &[u8; 3],
+    channel_id: u32,
+    token_id: u32,
+    sequence: &mut u32,
+    request_id: u32,
+    body: &[u8],
+    max_chunk: u32,
+) -> Vec<u8> {
+    let room = (max_chunk as usize).saturating_sub(HEADER + SYMMETRIC_HEADER).max(1);
+    let parts: Vec<&[u8]> = if body.is_empty() { vec![body] } else { body.chunks(room).collect() };
+    let mut bytes = Vec::with_capacity(body.len() + parts.len() * (HEADER + SYMMETRIC_HEADER));
+    for (index, part) in parts.iter().enumerate() {
+        let flag = if index + 1 == parts.len() { b'F' } else { b'C' };
+        *sequence = sequence.wrapping_add(1).max(1);
+        let mut encoder = Encoder::new();
+        encoder.u32(channel_id).u32(token_id).u32(*sequence).u32(request_id).bytes(part);
+        bytes.extend(frame(kind, flag, &encoder.finish()));
+    }
+    bytes
+}
+
+/// Puts MSG chunks back together.
+#[derive(Default)]
+pub struct Assembler {
+    body: Vec<u8>,
+}
+
+impl Assembler {
+    /// Adds a chunk; the whole message once its final chunk is in.
+    pub fn push(&mut self, chunk: &Chunk, secured: Secured) -> Result<Option<Secured>, StatusCode> {
+        match chunk.flag {
+            b'A' => {
+                self.body.clear();
+                Ok(None)
+            }
+            b'C' | b'F' => {
+                if self.body.len() + secured.body.len() > MAX_MESSAGE as usize {
+                    self.body.clear();
+                    return Err(StatusCode::BAD_TCP_MESSAGE_TOO_LARGE);
+                }
+                self.body.extend_from_slice(&secured.body);
+                if chunk.flag == b'C' {
+                    return Ok(None);
+                }
+                Ok(Some(Secured {
+                    body: std::mem::take(&mut self.body),
+                    ..secured
+                }))
+            }
+            _ => Err(StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID),
+        }
+    }
+}
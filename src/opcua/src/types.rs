@@ -0,0 +1,389 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+/// An OPC UA status code. The top two bits give the severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusCode(pub u32);
+
+impl StatusCode {
+    pub const GOOD: StatusCode = StatusCode(0);
+    pub const BAD_UNEXPECTED_ERROR: StatusCode = StatusCode(0x8001_0000);
+    pub const BAD_INTERNAL_ERROR: StatusCode = StatusCode(0x8002_0000);
+    pub const BAD_COMMUNICATION_ERROR: StatusCode = StatusCode(0x8005_0000);
+    pub const BAD_ENCODING_ERROR: StatusCode = StatusCode(0x8006_0000);
+    pub const BAD_DECODING_ERROR: StatusCode = StatusCode(0x8007_0000);
+    pub const BAD_ENCODING_LIMITS_EXCEEDED: StatusCode = StatusCode(0x8008_0000);
+    pub const BAD_TIMEOUT: StatusCode = StatusCode(0x800A_0000);
+    pub const BAD_SERVICE_UNSUPPORTED: StatusCode = StatusCode(0x800B_0000);
+    pub const BAD_NOTHING_TO_DO: StatusCode = StatusCode(0x800F_0000);
+    pub const BAD_TOO_MANY_OPERATIONS: StatusCode = StatusCode(0x8010_0000);
+    pub const BAD_USER_ACCESS_DENIED: StatusCode = StatusCode(0x801F_0000);
+    pub const BAD_IDENTITY_TOKEN_INVALID: StatusCode = StatusCode(0x8020_0000);
+    pub const BAD_IDENTITY_TOKEN_REJECTED: StatusCode = StatusCode(0x8021_0000);
+    pub const BAD_SECURE_CHANNEL_ID_INVALID: StatusCode = StatusCode(0x8022_0000);
+    pub const BAD_SESSION_ID_INVALID: StatusCode = StatusCode(0x8025_0000);
+    pub const BAD_SESSION_CLOSED: StatusCode = StatusCode(0x8026_0000);
+    pub const BAD_SESSION_NOT_ACTIVATED: StatusCode = StatusCode(0x8027_0000);
+    pub const BAD_SUBSCRIPTION_ID_INVALID: StatusCode = StatusCode(0x8028_0000);
+    pub const BAD_WAITING_FOR_INITIAL_DATA: StatusCode = StatusCode(0x8032_0000);
+    pub const BAD_NODE_ID_UNKNOWN: StatusCode = StatusCode(0x8034_0000);
+    pub const BAD_ATTRIBUTE_ID_INVALID: StatusCode = StatusCode(0x8035_0000);
+    pub const BAD_INDEX_RANGE_INVALID: StatusCode = StatusCode(0x8036_0000);
+    pub const BAD_NOT_WRITABLE: StatusCode = StatusCode(0x803B_0000);
+    pub const BAD_MONITORED_ITEM_ID_INVALID: StatusCode = StatusCode(0x8042_0000);
+    pub const BAD_MONITORING_MODE_INVALID: StatusCode = StatusCode(0x8041_0000);
+    pub const BAD_CONTINUATION_POINT_INVALID: StatusCode = StatusCode(0x804A_0000);
+    pub const BAD_BROWSE_DIRECTION_INVALID: StatusCode = StatusCode(0x804D_0000);
+    pub const BAD_SECURITY_POLICY_REJECTED: StatusCode = StatusCode(0x8055_0000);
+    pub const BAD_WRITE_NOT_SUPPORTED: StatusCode = StatusCode(0x8073_0000);
+    pub const BAD_TYPE_MISMATCH: StatusCode = StatusCode(0x8074_0000);
+    pub const BAD_METHOD_INVALID: StatusCode = StatusCode(0x8075_0000);
+    pub const BAD_ARGUMENTS_MISSING: StatusCode = StatusCode(0x8076_0000);
+    pub const BAD_TOO_MANY_SUBSCRIPTIONS: StatusCode = StatusCode(0x8077_0000);
+    pub const BAD_TOO_MANY_PUBLISH_REQUESTS: StatusCode = StatusCode(0x8078_0000);
+    pub const BAD_NO_SUBSCRIPTION: StatusCode = StatusCode(0x8079_0000);
+    pub const BAD_MESSAGE_NOT_AVAILABLE: StatusCode = StatusCode(0x807B_0000);
+    pub const BAD_TCP_MESSAGE_TYPE_INVALID: StatusCode = StatusCode(0x807E_0000);
+    pub const BAD_TCP_MESSAGE_TOO_LARGE: StatusCode = StatusCode(0x8080_0000);
+    pub const BAD_TCP_ENDPOINT_URL_INVALID: StatusCode = StatusCode(0x8083_0000);
+    pub const BAD_SECURE_CHANNEL_CLOSED: StatusCode = StatusCode(0x8086_0000);
+    pub const BAD_INVALID_ARGUMENT: StatusCode = StatusCode(0x80AB_0000);
+
+    pub fn is_good(self) -> bool {
+        self.0 & 0xC000_0000 == 0
+    }
+
+    pub fn is_bad(self) -> bool {
+        self.0 & 0x8000_0000 != 0
+    }
+
+    fn name(self) -> Option<&'static str> {
+        let name = match self {
+            StatusCode::GOOD => "Good",
+            StatusCode::BAD_UNEXPECTED_ERROR => "BadUnexpectedError",
+            StatusCode::BAD_INTERNAL_ERROR => "BadInternalError",
+            StatusCode::BAD_COMMUNICATION_ERROR => "BadCommunicationError",
+            StatusCode::BAD_ENCODING_ERROR => "BadEncodingError",
+            StatusCode::BAD_DECODING_ERROR => "BadDecodingError",
+            StatusCode::BAD_ENCODING_LIMITS_EXCEEDED => "BadEncodingLimitsExceeded",
+            StatusCode::BAD_TIMEOUT => "BadTimeout",
+            StatusCode::BAD_SERVICE_UNSUPPORTED => "BadServiceUnsupported",
+            StatusCode::BAD_NOTHING_TO_DO => "BadNothingToDo",
+            StatusCode::BAD_TOO_MANY_OPERATIONS => "BadTooManyOperations",
+            StatusCode::BAD_USER_ACCESS_DENIED => "BadUserAccessDenied",
+            StatusCode::BAD_IDENTITY_TOKEN_INVALID => "BadIdentityTokenInvalid",
+            StatusCode::BAD_IDENTITY_TOKEN_REJECTED => "BadIdentityTokenRejected",
+            StatusCode::BAD_SECURE_CHANNEL_ID_INVALID => "BadSecureChannelIdInvalid",
+            StatusCode::BAD_SESSION_ID_INVALID => "BadSessionIdInvalid",
+            StatusCode::BAD_SESSION_CLOSED => "BadSessionClosed",
+            StatusCode::BAD_SESSION_NOT_ACTIVATED => "BadSessionNotActivated",
+            StatusCode::BAD_SUBSCRIPTION_ID_INVALID => "BadSubscriptionIdInvalid",
+            StatusCode::BAD_WAITING_FOR_INITIAL_DATA => "BadWaitingForInitialData",
+            StatusCode::BAD_NODE_ID_UNKNOWN => "BadNodeIdUnknown",
+            StatusCode::BAD_ATTRIBUTE_ID_INVALID => "BadAttributeIdInvalid",
+            StatusCode::BAD_INDEX_RANGE_INVALID => "BadIndexRangeInvalid",
+            StatusCode::BAD_NOT_WRITABLE => "BadNotWritable",
+            StatusCode::BAD_MONITORED_ITEM_ID_INVALID => "BadMonitoredItemIdInvalid",
+            StatusCode::BAD_MONITORING_MODE_INVALID => "BadMonitoringModeInvalid",
+            StatusCode::BAD_CONTINUATION_POINT_INVALID => "BadContinuationPointInvalid",
+            StatusCode::BAD_BROWSE_DIRECTION_INVALID => "BadBrowseDirectionInvalid",
+            StatusCode::BAD_SECURITY_POLICY_REJECTED => "BadSecurityPolicyRejected",
+            StatusCode::BAD_WRITE_NOT_SUPPORTED => "BadWriteNotSupported",
+            StatusCode::BAD_TYPE_MISMATCH => "BadTypeMismatch",
+            StatusCode::BAD_METHOD_INVALID => "BadMethodInvalid",
+            StatusCode::BAD_ARGUMENTS_MISSING => "BadArgumentsMissing",
+            StatusCode::BAD_TOO_MANY_SUBSCRIPTIONS => "BadTooManySubscriptions",
+            StatusCode::BAD_TOO_MANY_PUBLISH_REQUESTS => "BadTooManyPublishRequests",
+            StatusCode::BAD_NO_SUBSCRIPTION => "BadNoSubscription",
+            StatusCode::BAD_MESSAGE_NOT_AVAILABLE => "BadMessageNotAvailable",
+            StatusCode::BAD_TCP_MESSAGE_TYPE_INVALID => "BadTcpMessageTypeInvalid",
+            StatusCode::BAD_TCP_MESSAGE_TOO_LARGE => "BadTcpMessageTooLarge",
+            StatusCode::BAD_TCP_ENDPOINT_URL_INVALID => "BadTcpEndpointUrlInvalid",
+            StatusCode::BAD_SECURE_CHANNEL_CLOSED => "BadSecureChannelClosed",
+            StatusCode::BAD_INVALID_ARGUMENT => "BadInvalidArgument",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:08X}", self.0),
+        }
+    }
+}
+
+impl std::error::Error for StatusCode {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Identifier {
+    Numeric(u32),
+    String(String),
+    Guid([u8; 16]),
+    Opaque(Vec<u8>),
+}
+
+/// A node id, written like `ns=1;s=pump1/status/flow` or `i=85`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId {
+    pub namespace: u16,
+    pub identifier: Identifier,
+}
+
+impl NodeId {
+    pub const NULL: NodeId = NodeId::numeric(0, 0);
+
+    pub const fn numeric(namespace: u16, id: u32) -> Self {
+        Self {
+            namespace,
+            identifier: Identifier::Numeric(id),
+        }
+    }
+
+    pub fn string(namespace: u16, id: impl Into<String>) -> Self {
+        Self {
+            namespace,
+            identifier: Identifier::String(id.into()),
+        }
+    }
+
+    /// A node of the standard namespace 0.
+    pub const fn ns0(id: u32) -> Self {
+        Self::numeric(0, id)
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == NodeId::NULL
+    }
+
+    pub fn as_ns0(&self) -> Option<u32> {
+        match self.identifier {
+            Identifier::Numeric(id) if self.namespace == 0 => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.namespace != 0 {
+            write!(f, "ns={};", self.namespace)?;
+        }
+        match &self.identifier {
+            Identifier::Numeric(id) => write!(f, "i={}", id),
+            Identifier::String(id) => write!(f, "s={}", id),
+            Identifier::Guid(id) => {
+                write!(f, "g=")?;
+                id.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            Identifier::Opaque(id) => write!(f, "b={} bytes", id.len()),
+        }
+    }
+}
+
+impl std::str::FromStr for NodeId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (namespace, rest) = match s.strip_prefix("ns=").and_then(|rest| rest.split_once(';')) {
+            Some((namespace, rest)) => (namespace.parse().map_err(|_| format!("bad namespace in {}", s))?, rest),
+            None => (0, s),
+        };
+        if let Some(id) = rest.strip_prefix("i=") {
+            return Ok(NodeId::numeric(namespace, id.parse().map_err(|_| format!("bad numeric id in {}", s))?));
+        }
+        if let Some(id) = rest.strip_prefix("s=") {
+            return Ok(NodeId::string(namespace, id));
+        }
+        Err(format!("{} is not a node id like `ns=1;s=name` or `i=85`", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QualifiedName {
+    pub namespace: u16,
+    pub name: Option<String>,
+}
+
+impl QualifiedName {
+    pub fn new(namespace: u16, name: impl Into<String>) -> Self {
+        Self {
+            namespace,
+            name: Some(name.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LocalizedText {
+    pub locale: Option<String>,
+    pub text: Option<String>,
+}
+
+impl LocalizedText {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            locale: None,
+            text: Some(text.into()),
+        }
+    }
+}
+
+/// A structure in its binary encoding, tagged with the id of that encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionObject {
+    pub type_id: NodeId,
+    pub body: Option<Vec<u8>>,
+}
+
+impl ExtensionObject {
+    pub fn null() -> Self {
+        Self {
+            type_id: NodeId::NULL,
+            body: None,
+        }
+    }
+
+    pub fn binary(encoding: u32, body: Vec<u8>) -> Self {
+        Self {
+            type_id: NodeId::ns0(encoding),
+            body: Some(body),
+        }
+    }
+}
+
+/// Values of the built-in types the server deals in; arrays hold elements
+/// of one type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variant {
+    Empty,
+    Boolean(bool),
+    SByte(i8),
+    Byte(u8),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Float(f32),
+    Double(f64),
+    String(Option<String>),
+    DateTime(DateTime<Utc>),
+    ByteString(Option<Vec<u8>>),
+    NodeId(NodeId),
+    StatusCode(StatusCode),
+    QualifiedName(QualifiedName),
+    LocalizedText(LocalizedText),
+    ExtensionObject(ExtensionObject),
+    Array(u8, Vec<Variant>),
+}
+
+impl Variant {
+    /// The built-in type id used in the binary encoding.
+    pub fn type_id(&self) -> u8 {
+        match self {
+            Variant::Empty => 0,
+            Variant::Boolean(_) => 1,
+            Variant::SByte(_) => 2,
+            Variant::Byte(_) => 3,
+            Variant::Int16(_) => 4,
+            Variant::UInt16(_) => 5,
+            Variant::Int32(_) => 6,
+            Variant::UInt32(_) => 7,
+            Variant::Int64(_) => 8,
+            Variant::UInt64(_) => 9,
+            Variant::Float(_) => 10,
+            Variant::Double(_) => 11,
+            Variant::String(_) => 12,
+            Variant::DateTime(_) => 13,
+            Variant::ByteString(_) => 15,
+            Variant::NodeId(_) => 17,
+            Variant::StatusCode(_) => 19,
+            Variant::QualifiedName(_) => 20,
+            Variant::LocalizedText(_) => 21,
+            Variant::ExtensionObject(_) => 22,
+            Variant::Array(type_id, _) => *type_id,
+        }
+    }
+
+    pub fn string(text: impl Into<String>) -> Self {
+        Variant::String(Some(text.into()))
+    }
+
+    pub fn strings<S: Into<String>>(items: impl IntoIterator<Item = S>) -> Self {
+        Variant::Array(12, items.into_iter().map(Variant::string).collect())
+    }
+
+    /// Any numeric value as a double, as clients write setpoints in
+    /// whatever type they like.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Variant::SByte(v) => Some(v as f64),
+            Variant::Byte(v) => Some(v as f64),
+            Variant::Int16(v) => Some(v as f64),
+            Variant::UInt16(v) => Some(v as f64),
+            Variant::Int32(v) => Some(v as f64),
+            Variant::UInt32(v) => Some(v as f64),
+            Variant::Int64(v) => Some(v as f64),
+            Variant::UInt64(v) => Some(v as f64),
+            Variant::Float(v) => Some(v as f64),
+            Variant::Double(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Variant::String(Some(text)) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// A value with its quality and timestamps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataValue {
+    pub value: Option<Variant>,
+    pub status: Option<StatusCode>,
+    pub source_timestamp: Option<DateTime<Utc>>,
+    pub server_timestamp: Option<DateTime<Utc>>,
+}
+
+impl DataValue {
+    pub fn new(value: Variant, at: DateTime<Utc>) -> Self {
+        Self {
+            value: Some(value),
+            status: None,
+            source_timestamp: Some(at),
+            server_timestamp: Some(at),
+        }
+    }
+
+    /// A value that is just the variant, e.g. for attributes other than Value.
+    pub fn plain(value: Variant) -> Self {
+        Self {
+            value: Some(value),
+            status: None,
+            source_timestamp: None,
+            server_timestamp: None,
+        }
+    }
+
+    pub fn bad(status: StatusCode) -> Self {
+        Self {
+            value: None,
+            status: Some(status),
+            source_timestamp: None,
+            server_timestamp: None,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status.unwrap_or(StatusCode::GOOD)
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use message::{MessageType, MotorCommand, MotorMsg, MoveDirection, MsgBuilder, SetpointMsg, TelemetryMsg};
+use opcua::{
+    ids, Acknowledgement, BrowseDescription, Client, Identity, MonitoredItemCreateRequest, NodeId, OpcuaConfig, OpcuaServer,
+    StatusCode, Variant, DEVICES_FOLDER, TOKEN_ANONYMOUS, TOKEN_USER_NAME,
+};
+use subsystem::Clock;
+use testkit::Harness;
+use tokio::net::TcpListener;
+
+/// Anonymous sessions may only read; `operator` may write and call methods.
+const SERVER: &str = r#"{
+    "application_name": "CSC test",
+    "anonymous": "read_only",
+    "allow_plaintext_passwords": true,
+    "users": [
+        { "username": "operator", "password": "secret" },
+        { "username": "viewer", "password": "view", "access": "read_only" }
+    ],
+    "devices": [
+        {
+            "name": "pump1",
+            "telemetry": [{ "group": "status", "values": ["flow", "pressure"] }],
+            "setpoints": ["speed"],
+            "motor": 1
+        },
+        { "name": "valve2", "telemetry": [{ "group": "state", "values": ["open"] }] }
+    ]
+}"#;
+
+struct Bench {
+    harness: Harness,
+    addr: String,
+}
+
+async fn start(json: &str) -> Bench {
+    let config: OpcuaConfig = serde_json::from_str(json).unwrap();
+    config.validate().unwrap();
+    serve(config).await
+}
+
+/// Serves `config` without validating it first.
+async fn serve(config: OpcuaConfig) -> Bench {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut harness = Harness::new();
+    let server = OpcuaServer::new(config).with_listener(listener).with_clock(harness.clock());
+    harness.register("opcua", server, vec![MessageType::Telemetry, MessageType::Setpoint]);
+    harness.start().await;
+    Bench { harness, addr }
+}
+
+fn anonymous() -> Identity {
+    Identity::Anonymous {
+        policy_id: "anonymous".to_string(),
+    }
+}
+
+fn user(username: &str, password: &str) -> Identity {
+    Identity::UserName {
+        policy_id: "username".to_string(),
+        username: username.to_string(),
+        password: password.as_bytes().to_vec(),
+        encryption: None,
+    }
+}
+
+fn node(path: &str) -> NodeId {
+    NodeId::string(1, path)
+}
+
+fn names(results: &[opcua::ReferenceDescription]) -> Vec<String> {
+    results.iter().filter_map(|reference| reference.browse_name.name.clone()).collect()
+}
+
+async fn telemetry(harness: &mut Harness, device: &str, group: &str, values: &[(&str, f64)]) {
+    let values: BTreeMap<String, f64> = values.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+    let msg = MsgBuilder::new()
+        .msg_type(MessageType::Telemetry)
+        .topic(format!("telemetry/{}/{}", device, group))
+        .data(Box::new(TelemetryMsg {
+            device: device.to_string(),
+            group: group.to_string(),
+            values,
+        }))
+        .build()
+        .unwrap();
+    harness.inject(msg).await;
+}
+
+#[tokio::test]
+async fn browsing_finds_devices_and_server_status() {
+    let mut bench = start(SERVER).await;
+    let mut client = Client::connect(&bench.addr).await.unwrap();
+    let endpoints = client.get_endpoints().await.unwrap();
+    let tokens: Vec<u32> = endpoints[0].user_identity_tokens.iter().map(|token| token.token_type).collect();
+    assert_eq!(tokens, [TOKEN_ANONYMOUS, TOKEN_USER_NAME]);
+    client.create_session("browse").await.unwrap();
+    client.activate_session(anonymous()).await.unwrap();
+
+    let objects = client.browse(vec![BrowseDescription::children(NodeId::ns0(ids::OBJECTS_FOLDER))], 0).await.unwrap();
+    assert_eq!(names(&objects[0].references), ["Server", "Devices"]);
+    let devices = client.browse(vec![BrowseDescription::children(DEVICES_FOLDER)], 0).await.unwrap();
+    assert_eq!(names(&devices[0].references), ["pump1", "valve2"]);
+    let pump = client.browse(vec![BrowseDescription::children(node("pump1"))], 0).await.unwrap();
+    assert_eq!(names(&pump[0].references), ["status", "setpoints", "Move", "Stop", "Home"]);
+    assert_eq!(pump[0].references[2].node_class, 4);
+
+    // 每页两个引用，剩下的用 BrowseNext 取
+    let first = client.browse(vec![BrowseDescription::children(node("pump1"))], 2).await.unwrap();
+    assert_eq!(names(&first[0].references), ["status", "setpoints"]);
+    let point = first[0].continuation_point.clone().unwrap();
+    let second = client.browse_next(vec![point.clone()]).await.unwrap();
+    assert_eq!(names(&second[0].references), ["Move", "Stop"]);
+    let third = client.browse_next(vec![second[0].continuation_point.clone().unwrap()]).await.unwrap();
+    assert_eq!(names(&third[0].references), ["Home"]);
+    assert!(third[0].continuation_point.is_none());
+    let reused = client.browse_next(vec![point]).await.unwrap();
+    assert_eq!(reused[0].status, StatusCode::BAD_CONTINUATION_POINT_INVALID);
+
+    let namespaces = client.read_value(&NodeId::ns0(ids::NAMESPACE_ARRAY)).await.unwrap();
+    assert_eq!(namespaces.value, Some(Variant::strings(["http://opcfoundation.org/UA/", "urn:csc:devices"])));
+    let state = client.read_value(&NodeId::ns0(ids::SERVER_STATUS_STATE)).await.unwrap();
+    assert_eq!(state.value, Some(Variant::Int32(0)));
+    let unknown = client.read_value(&node("pump9")).await.unwrap();
+    assert_eq!(unknown.status(), StatusCode::BAD_NODE_ID_UNKNOWN);
+    client.disconnect().await.unwrap();
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn telemetry_and_setpoints_from_the_bus_update_variables() {
+    let mut bench = start(SERVER).await;
+    let mut client = Client::login(&bench.addr, anonymous()).await.unwrap();
+    let flow = node("pump1/status/flow");
+    assert_eq!(client.read_value(&flow).await.unwrap().status(), StatusCode::BAD_WAITING_FOR_INITIAL_DATA);
+
+    telemetry(&mut bench.harness, "pump1", "status", &[("flow", 12.5), ("unmapped", 1.0)]).await;
+    let value = client.read_value(&flow).await.unwrap();
+    assert_eq!(value.value, Some(Variant::Double(12.5)));
+    assert_eq!(value.status(), StatusCode::GOOD);
+    assert_eq!(value.source_timestamp, Some(bench.harness.clock().now()));
+
+    let msg = MsgBuilder::new()
+        .msg_type(MessageType::Setpoint)
+        .topic("setpoint/pump1")
+        .data(Box::new(SetpointMsg::new("pump1", "speed", 900.0)))
+        .build()
+        .unwrap();
+    bench.harness.inject(msg).await;
+    let speed = client.read_value(&node("pump1/setpoints/speed")).await.unwrap();
+    assert_eq!(speed.value, Some(Variant::Double(900.0)));
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn writing_a_setpoint_publishes_it() {
+    let mut bench = start(SERVER).await;
+    let mut client = Client::login(&bench.addr, user("operator", "secret")).await.unwrap();
+    let speed = node("pump1/setpoints/speed");
+
+    assert_eq!(client.write_value(&speed, Variant::Int32(1500)).await.unwrap(), StatusCode::GOOD);
+    let published = bench.harness.emitted_of("opcua", MessageType::Setpoint);
+    assert_eq!(published[0].topic(), "setpoint/pump1");
+    assert_eq!(published[0].get_data::<SetpointMsg>().unwrap(), SetpointMsg::new("pump1", "speed", 1500.0));
+    assert_eq!(client.read_value(&speed).await.unwrap().value, Some(Variant::Double(1500.0)));
+
+    let flow = node("pump1/status/flow");
+    assert_eq!(client.write_value(&flow, Variant::Double(1.0)).await.unwrap(), StatusCode::BAD_NOT_WRITABLE);
+    assert_eq!(client.write_value(&speed, Variant::string("fast")).await.unwrap(), StatusCode::BAD_TYPE_MISMATCH);
+    assert_eq!(client.write_value(&node("pump9"), Variant::Double(1.0)).await.unwrap(), StatusCode::BAD_NODE_ID_UNKNOWN);
+    assert_eq!(bench.harness.emitted_of("opcua", MessageType::Setpoint).len(), 1);
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn methods_publish_motor_commands() {
+    let mut bench = start(SERVER).await;
+    let mut client = Client::login(&bench.addr, user("operator", "secret")).await.unwrap();
+    let pump = node("pump1");
+
+    let result = client.call(&pump, &node("pump1/Move"), vec![Variant::string("Down")]).await.unwrap();
+    assert_eq!(result.status, StatusCode::GOOD);
+    let result = client.call(&pump, &node("pump1/Stop"), vec![]).await.unwrap();
+    assert_eq!(result.status, StatusCode::GOOD);
+    let result = client.call(&pump, &node("pump1/Home"), vec![]).await.unwrap();
+    assert_eq!(result.status, StatusCode::GOOD);
+
+    let moves = bench.harness.emitted_data::<MotorMsg>("opcua", MessageType::Move);
+    assert_eq!((moves[0].id, moves[0].direction, moves[0].command), (1, MoveDirection::Down, MotorCommand::Move));
+    assert_eq!((moves[1].id, moves[1].command), (1, MotorCommand::Home));
+    let stops = bench.harness.emitted_data::<MotorMsg>("opcua", MessageType::Stop);
+    assert_eq!((stops[0].id, stops[0].command), (1, MotorCommand::Stop));
+
+    let missing = client.call(&pump, &node("pump1/Move"), vec![]).await.unwrap();
+    assert_eq!(missing.status, StatusCode::BAD_ARGUMENTS_MISSING);
+    let sideways = client.call(&pump, &node("pump1/Move"), vec![Variant::string("Left")]).await.unwrap();
+    assert_eq!(sideways.status, StatusCode::BAD_INVALID_ARGUMENT);
+    assert_eq!(sideways.input_argument_results, [StatusCode::BAD_INVALID_ARGUMENT]);
+    let elsewhere = client.call(&node("valve2"), &node("pump1/Stop"), vec![]).await.unwrap();
+    assert_eq!(elsewhere.status, StatusCode::BAD_METHOD_INVALID);
+    assert_eq!(bench.harness.emitted("opcua").len(), 3);
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn identities_are_checked() {
+    let mut bench = start(SERVER).await;
+    let speed = node("pump1/setpoints/speed");
+
+    for identity in [anonymous(), user("viewer", "view")] {
+        let mut client = Client::login(&bench.addr, identity).await.unwrap();
+        assert_eq!(client.write_value(&speed, Variant::Double(1.0)).await.unwrap(), StatusCode::BAD_USER_ACCESS_DENIED);
+        let stop = client.call(&node("pump1"), &node("pump1/Stop"), vec![]).await.unwrap();
+        assert_eq!(stop.status, StatusCode::BAD_USER_ACCESS_DENIED);
+    }
+    assert!(bench.harness.emitted("opcua").is_empty());
+
+    let refused = Client::login(&bench.addr, user("operator", "guess")).await.err();
+    assert_eq!(refused, Some(StatusCode::BAD_USER_ACCESS_DENIED));
+    let refused = Client::login(&bench.addr, user("nobody", "secret")).await.err();
+    assert_eq!(refused, Some(StatusCode::BAD_USER_ACCESS_DENIED));
+
+    // 没有激活的会话什么都不能做
+    let mut client = Client::connect(&bench.addr).await.unwrap();
+    assert_eq!(client.read_value(&speed).await.err(), Some(StatusCode::BAD_SESSION_ID_INVALID));
+    client.create_session("early").await.unwrap();
+    assert_eq!(client.read_value(&speed).await.err(), Some(StatusCode::BAD_SESSION_NOT_ACTIVATED));
+    bench.harness.stop().await;
+
+    let mut bench = start(&SERVER.replace(r#""anonymous": "read_only","#, "")).await;
+    let refused = Client::login(&bench.addr, anonymous()).await.err();
+    assert_eq!(refused, Some(StatusCode::BAD_IDENTITY_TOKEN_REJECTED));
+    let mut client = Client::connect(&bench.addr).await.unwrap();
+    let tokens: Vec<u32> = client.get_endpoints().await.unwrap()[0].user_identity_tokens.iter().map(|token| token.token_type).collect();
+    assert_eq!(tokens, [TOKEN_USER_NAME]);
+    bench.harness.stop().await;
+
+    // 没有显式允许明文密码时，即使配置了用户也不收用户名令牌
+    let mut config: OpcuaConfig = serde_json::from_str(SERVER).unwrap();
+    config.allow_plaintext_passwords = false;
+    let mut bench = serve(config).await;
+    let mut client = Client::connect(&bench.addr).await.unwrap();
+    let tokens: Vec<u32> = client.get_endpoints().await.unwrap()[0].user_identity_tokens.iter().map(|token| token.token_type).collect();
+    assert_eq!(tokens, [TOKEN_ANONYMOUS]);
+    let refused = Client::login(&bench.addr, user("operator", "secret")).await.err();
+    assert_eq!(refused, Some(StatusCode::BAD_IDENTITY_TOKEN_REJECTED));
+    bench.harness.stop().await;
+}
+
+#[tokio::test]
+async fn subscriptions_report_changes_and_keep_alives() {
+    let mut bench = start(SERVER).await;
+    let mut client = Client::login(&bench.addr, anonymous()).await.unwrap();
+    let subscription = client.create_subscription(100.0, 3).await.unwrap();
+    let id = subscription.subscription_id;
+    let items = vec![
+        MonitoredItemCreateRequest::value(node("pump1/status/flow"), 7),
+        MonitoredItemCreateRequest::value(node("pump9/status/flow"), 8),
+    ];
+    let created = client.create_monitored_items(id, items).await.unwrap();
+    assert_eq!(created[0].status, StatusCode::GOOD);
+    assert_eq!(created[1].status, StatusCode::BAD_NODE_ID_UNKNOWN);
+    let interval = Duration::from_millis(100);
+
+    // 第一个周期报初始值
+    let request = client.publish(vec![]).await.unwrap();
+    bench.harness.wait_for_sleepers(1).await;
+    bench.harness.advance(interval).await;
+    let first = client.publish_response(request).await.unwrap();
+    assert_eq!(first.subscription_id, id);
+    assert_eq!(first.notifications.len(), 1);
+    assert_eq!(first.notifications[0].client_handle, 7);
+    assert_eq!(first.notifications[0].value.status(), StatusCode::BAD_WAITING_FOR_INITIAL_DATA);
+
+    telemetry(&mut bench.harness, "pump1", "status", &[("flow", 3.5)]).await;
+    let ack = Acknowledgement {
+        subscription_id: id,
+        sequence_number: first.sequence_number,
+    };
+    let request = client.publish(vec![ack]).await.unwrap();
+    bench.harness.wait_for_sleepers(1).await;
+    bench.harness.advance(interval).await;
+    let second = client.publish_response(request).await.unwrap();
+    assert_eq!(second.sequence_number, first.sequence_number + 1);
+    assert_eq!(second.notifications[0].value.value, Some(Variant::Double(3.5)));
+
+    // 值没变，三个周期后只收到保活
+    let request = client.publish(vec![]).await.unwrap();
+    for _ in 0..3 {
+        bench.harness.wait_for_sleepers(1).await;
+        bench.harness.advance(interval).await;
+    }
+    let keep_alive = client.publish_response(request).await.unwrap();
+    assert!(keep_alive.notifications.is_empty());
+    assert_eq!(keep_alive.sequence_number, second.sequence_number + 1);
+
+    assert_eq!(client.delete_subscriptions(vec![id]).await.unwrap(), [StatusCode::GOOD]);
+    let request = client.publish(vec![]).await.unwrap();
+    assert_eq!(client.publish_response(request).await.err(), Some(StatusCode::BAD_NO_SUBSCRIPTION));
+    bench.harness.stop().await;
+}
+
+#[test]
+fn bad_configs_are_rejected() {
+    let bad = [
+        r#"{ "devices": [] }"#,
+        r#"{ "anonymous": "operate", "devices": [{ "name": "a/b" }] }"#,
+        r#"{ "anonymous": "operate", "devices": [{ "name": "a" }, { "name": "a" }] }"#,
+        r#"{ "anonymous": "operate", "devices": [{ "name": "a", "telemetry": [{ "group": "Move", "values": ["x"] }] }] }"#,
+        r#"{ "anonymous": "operate", "devices": [{ "name": "a", "setpoints": ["x", "x"] }] }"#,
+        r#"{ "allow_plaintext_passwords": true, "users": [{ "username": "u", "password": "p" }, { "username": "u", "password": "q" }] }"#,
+        r#"{ "users": [{ "username": "u", "password": "p" }] }"#,
+        r#"{ "anonymous": "read_only", "listen": "0.0.0.0:4840" }"#,
+        r#"{ "anonymous": "read_only", "listen": "plc.example:4840" }"#,
+    ];
+    for json in bad {
+        let config: OpcuaConfig = serde_json::from_str(json).unwrap();
+        assert!(config.validate().is_err(), "{}", json);
+    }
+    assert!(serde_json::from_str::<OpcuaConfig>(r#"{ "anonymous": "admin" }"#).is_err());
+
+    let config: OpcuaConfig = serde_json::from_str(r#"{ "anonymous": "read_only" }"#).unwrap();
+    assert_eq!(config.listen, "127.0.0.1:4840");
+    for listen in ["[::1]:4840", "localhost:4840"] {
+        let json = format!(r#"{{ "anonymous": "read_only", "listen": "{}" }}"#, listen);
+        assert!(serde_json::from_str::<OpcuaConfig>(&json).unwrap().validate().is_ok(), "{}", listen);
+    }
+    let remote = r#"{ "anonymous": "read_only", "listen": "0.0.0.0:4840", "allow_remote": true }"#;
+    assert!(serde_json::from_str::<OpcuaConfig>(remote).unwrap().validate().is_ok());
+}
+
+#[tokio::test]
+async fn refuses_to_serve_remote_clients_unless_allowed() {
+    let config: OpcuaConfig = serde_json::from_str(r#"{ "anonymous": "read_only" }"#).unwrap();
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let mut harness = Harness::new();
+    harness.register("opcua", OpcuaServer::new(config.clone()).with_listener(listener), vec![MessageType::Telemetry]);
+    assert!(harness.center().start().await.is_err());
+
+    let config = OpcuaConfig { allow_remote: true, ..config };
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let mut harness = Harness::new();
+    harness.register("opcua", OpcuaServer::new(config).with_listener(listener), vec![MessageType::Telemetry]);
+    harness.start().await;
+    harness.stop().await;
+}
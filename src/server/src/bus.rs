@@ -1 +1,62 @@
-﻿
\ No newline at end of file
+use std::sync::Arc;
+
+use message::{MessageType, Msg, SubscribeMsg};
+use subsystem::{Bus, Filter, QueueConfig, QueuePolicy, Subscription, TopicFilter};
+
+const REMOTE_QUEUE_SIZE: usize = 1024;
+
+/// A remote client's view of the center bus. The client starts out subscribed to
+/// nothing and picks topics with `Subscribe` / `Unsubscribe` messages.
+/// A slow client loses its oldest messages rather than holding up the bus.
+pub struct RemoteSubscriber {
+    subscription: Subscription,
+}
+
+impl RemoteSubscriber {
+    pub fn new(bus: &Bus, label: impl Into<String>) -> Self {
+        Self {
+            subscription: bus.subscribe_with(
+                label,
+                Vec::<Filter>::new(),
+                QueueConfig::new(REMOTE_QUEUE_SIZE, QueuePolicy::DropOldest),
+            ),
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        self.subscription.label()
+    }
+
+    /// Applies a subscription request. Returns false if `msg` is not one, so the
+    /// caller should publish it as usual.
+    pub fn handle_request(&mut self, msg: &Msg) -> bool {
+        let subscribe = match msg.get_msg_type() {
+            MessageType::Subscribe => true,
+            MessageType::Unsubscribe => false,
+            _ => return false,
+        };
+        let Some(request) = msg.get_data::<SubscribeMsg>() else {
+            eprintln!("Malformed subscription request from {}", self.label());
+            return true;
+        };
+        for filter in request.filters {
+            let filter = match TopicFilter::new(&filter) {
+                Ok(filter) => Filter::Topic(filter),
+                Err(e) => {
+                    eprintln!("Ignoring filter from {}: {}", self.label(), e);
+                    continue;
+                }
+            };
+            if subscribe {
+                self.subscription.add_filter(filter);
+            } else {
+                self.subscription.remove_filter(&filter);
+            }
+        }
+        true
+    }
+
+    pub async fn recv(&self) -> Option<Arc<Msg>> {
+        self.subscription.recv().await
+    }
+}
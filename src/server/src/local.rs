@@ -0,0 +1,99 @@
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use subsystem::{CenterHandle, FrameLines};
+use tokio::net::unix::UCred;
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::session::run_session;
+
+/// Local users allowed to connect, by uid or by primary gid. Supplementary
+/// groups are not checked. With both lists empty anyone the socket file's
+/// permissions let through may connect.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAuth {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+}
+
+impl PeerAuth {
+    pub fn allows(&self, cred: &UCred) -> bool {
+        if self.uids.is_empty() && self.gids.is_empty() {
+            return true;
+        }
+        self.uids.contains(&cred.uid()) || self.gids.contains(&cred.gid())
+    }
+}
+
+/// Accepts processes on the same box over a Unix domain socket, with the same
+/// framing and sessions as the TCP listener.
+pub struct LocalServer {
+    listener: UnixListener,
+    path: PathBuf,
+    auth: PeerAuth,
+    center: CenterHandle,
+}
+
+impl LocalServer {
+    /// Binds `path`, replacing a socket left behind by a server that didn't
+    /// shut down cleanly. Any other file there is an error.
+    pub fn bind(path: &Path, auth: PeerAuth, center: CenterHandle) -> io::Result<Self> {
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                let reason = format!("{} is not a socket", path.display());
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, reason));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        Ok(LocalServer {
+            listener,
+            path: path.to_path_buf(),
+            auth,
+            center,
+        })
+    }
+
+    pub async fn run(&self) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(Self::handle_connection(stream, self.auth.clone(), self.center.clone()));
+                }
+                Err(e) => {
+                    eprintln!("Failed to accept local connection: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn handle_connection(stream: UnixStream, auth: PeerAuth, center: CenterHandle) {
+        let cred = match stream.peer_cred() {
+            Ok(cred) => cred,
+            Err(e) => {
+                eprintln!("Failed to read peer credentials: {}", e);
+                return;
+            }
+        };
+        if !auth.allows(&cred) {
+            eprintln!("Refused local connection from uid {} gid {}", cred.uid(), cred.gid());
+            return;
+        }
+        println!("New local connection from uid {}.", cred.uid());
+        let peer = match cred.pid() {
+            Some(pid) => format!("uid={},pid={}", cred.uid(), pid),
+            None => format!("uid={}", cred.uid()),
+        };
+        let (reader, writer) = stream.into_split();
+        run_session(FrameLines::new(reader), writer, format!("session/unix/{}", peer), center).await;
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -1,30 +1,131 @@
 use std::fmt::Debug;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, AsyncBufReadExt, AsyncBufRead};
+use std::net::SocketAddr;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::task;
 
-use message::Msg;
+use clap::Parser;
+use futures_util::StreamExt;
+use message::MessageType;
+use subsystem::{Advertised, CenterHandle, CenterSubsystem, DiscoveryResponder, FrameLines, ServerInfo, TransportKind};
+
+#[cfg(target_os = "linux")]
+use canopen::{CanopenConfig, CanopenMaster};
+use device::{DeviceConfig, DeviceSystem};
+use modbus::{ModbusConfig, ModbusMaster, ModbusSlave, SlaveServerConfig};
+use mqtt::{MqttBridge, MqttConfig};
+use opcua::{OpcuaConfig, OpcuaServer};
+use proto::ControlService;
+#[cfg(unix)]
+use local::{LocalServer, PeerAuth};
+use session::run_session;
 
 mod bus;
-mod subsystem;
+#[cfg(unix)]
+mod local;
+mod session;
+
+#[derive(Parser)]
+struct Args {
+    /// Address to accept TCP connections on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Poll Modbus slaves described by this JSON file
+    #[arg(long)]
+    modbus: Option<std::path::PathBuf>,
+    /// Serve CSC state as a Modbus slave with the register map in this JSON file
+    #[arg(long)]
+    modbus_slave: Option<std::path::PathBuf>,
+    /// Run the motion axes described by this JSON file through their drivers
+    #[arg(long)]
+    devices: Option<std::path::PathBuf>,
+    /// Run the CANopen nodes described by this JSON file
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    canopen: Option<std::path::PathBuf>,
+    /// Bridge the bus to the MQTT broker described by this JSON file
+    #[arg(long)]
+    mqtt: Option<std::path::PathBuf>,
+    /// Serve the devices described by this JSON file over OPC UA, on loopback unless it sets allow_remote
+    #[arg(long)]
+    opcua: Option<std::path::PathBuf>,
+    /// Address to accept WebSocket connections on
+    #[arg(long, default_value = "127.0.0.1:8081")]
+    ws: String,
+    /// Address to serve the gRPC control API on
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    grpc: SocketAddr,
+    #[cfg(unix)]
+    #[command(flatten)]
+    local: LocalArgs,
+    /// Address to answer discovery probes on
+    #[arg(long, default_value = "0.0.0.0:48080")]
+    discovery: SocketAddr,
+    /// Name reported to clients discovering servers
+    #[arg(long, default_value = "csc")]
+    name: String,
+}
+
+#[cfg(unix)]
+#[derive(clap::Args)]
+struct LocalArgs {
+    /// Also accept local processes on a Unix domain socket at this path
+    #[arg(long)]
+    uds: Option<std::path::PathBuf>,
+    /// Only let these uids connect over the Unix socket (repeatable)
+    #[arg(long)]
+    uds_uid: Vec<u32>,
+    /// Only let these primary gids connect over the Unix socket (repeatable)
+    #[arg(long)]
+    uds_gid: Vec<u32>,
+}
+
+#[cfg(unix)]
+impl LocalArgs {
+    fn bind(&self, center: CenterHandle) -> std::io::Result<Option<LocalServer>> {
+        let Some(path) = &self.uds else {
+            return Ok(None);
+        };
+        let auth = PeerAuth {
+            uids: self.uds_uid.clone(),
+            gids: self.uds_gid.clone(),
+        };
+        println!("Local server listening on {}", path.display());
+        LocalServer::bind(path, auth, center).map(Some)
+    }
+}
 
 struct Server {
     listener: TcpListener,
+    center: CenterHandle,
+    websocket: bool,
 }
 
 impl Server {
-    async fn new(addr: &str) -> Result<Self, std::io::Error> {
+    async fn new(addr: &str, center: CenterHandle) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Server { listener, center, websocket: false })
+    }
+
+    /// Accepts WebSocket connections carrying one frame per message.
+    async fn websocket(addr: &str, center: CenterHandle) -> Result<Self, std::io::Error> {
         let listener = TcpListener::bind(addr).await?;
-        Ok(Server { listener })
+        Ok(Server { listener, center, websocket: true })
+    }
+
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
     }
 
     async fn run(&self) {
         loop {
             match self.listener.accept().await {
-                Ok((stream, _)) => {
+                Ok((stream, peer)) => {
                     println!("New connection established.");
-                    tokio::spawn(Self::handle_connection(stream));
+                    if self.websocket {
+                        tokio::spawn(Self::handle_websocket(stream, peer, self.center.clone()));
+                    } else {
+                        tokio::spawn(Self::handle_connection(stream, peer, self.center.clone()));
+                    }
                 }
                 Err(e) => {
                     eprintln!("Failed to accept connection: {}", e);
@@ -33,45 +134,100 @@ impl Server {
         }
     }
 
-    async fn handle_connection(mut stream: TcpStream) {
-        let mut buffer = [0; 1024];
-        loop {
-            match stream.read(&mut buffer).await {
-                Ok(n) => {
-                    if n == 0 {
-                        println!("Connection closed by client.");
-                        break;
-                    }
-
-                    let data = &buffer[..n];
-                    let msg: Msg = match serde_json::from_slice(data) {
-                        Ok(msg) => msg,
-                        Err(e) => {
-                            eprintln!("Failed to deserialize message: {}", e);
-                            continue;
-                        }
-                    };
-                    println!("Received: {:?}", msg);
+    async fn handle_connection(stream: TcpStream, peer: SocketAddr, center: CenterHandle) {
+        let (reader, writer) = stream.into_split();
+        run_session(FrameLines::new(reader), writer, format!("session/{}", peer), center).await;
+    }
 
-                    // Echo the message back to the client
-                    if let Err(e) = stream.write_all(data).await {
-                        eprintln!("Failed to write to stream: {}", e);
-                        break;
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to read from stream: {}", e);
-                    break;
-                }
+    async fn handle_websocket(stream: TcpStream, peer: SocketAddr, center: CenterHandle) {
+        let stream = match tokio_tungstenite::accept_async(stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("WebSocket handshake with {} failed: {}", peer, e);
+                return;
             }
-        }
+        };
+        let (writer, reader) = stream.split();
+        run_session(reader, writer, format!("session/ws/{}", peer), center).await;
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let server = Server::new("127.0.0.1:8080").await?;
-    println!("Server listening on 127.0.0.1:8080");
-    server.run().await;
+    let args = Args::parse();
+    let mut center = CenterSubsystem::new();
+    if let Some(path) = &args.modbus {
+        let master = ModbusMaster::new(ModbusConfig::load(path).map_err(|e| e.to_string())?);
+        center.register("modbus", master, vec![MessageType::Move, MessageType::Stop]);
+    }
+    if let Some(path) = &args.modbus_slave {
+        let slave = ModbusSlave::new(SlaveServerConfig::load(path).map_err(|e| e.to_string())?);
+        center.register("modbus-slave", slave, vec![MessageType::Telemetry]);
+    }
+    if let Some(path) = &args.devices {
+        let devices = DeviceSystem::new(DeviceConfig::load(path).map_err(|e| e.to_string())?);
+        center.register("devices", devices, vec![MessageType::Move, MessageType::Stop]);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(path) = &args.canopen {
+        let master = CanopenMaster::new(CanopenConfig::load(path).map_err(|e| e.to_string())?);
+        center.register("canopen", master, vec![MessageType::Move, MessageType::Stop, MessageType::CanFrame]);
+    }
+    if let Some(path) = &args.mqtt {
+        let config = MqttConfig::load(path).map_err(|e| e.to_string())?;
+        let filters = config.filters();
+        center.register("mqtt", MqttBridge::new(config), filters);
+    }
+    if let Some(path) = &args.opcua {
+        let server = OpcuaServer::new(OpcuaConfig::load(path).map_err(|e| e.to_string())?);
+        center.register("opcua", server, vec![MessageType::Telemetry, MessageType::Setpoint]);
+    }
+    let handle = center.handle();
+    let center_task = tokio::spawn(async move { center.run().await });
+
+    let server = Server::new(&args.listen, handle.clone()).await?;
+    println!("Server listening on {}", args.listen);
+    let ws_server = Server::websocket(&args.ws, handle.clone()).await?;
+    println!("WebSocket server listening on {}", args.ws);
+    let grpc = tonic::transport::Server::builder()
+        .add_service(ControlService::new(handle.clone()).into_server())
+        .serve(args.grpc);
+    println!("gRPC server listening on {}", args.grpc);
+    let info = ServerInfo {
+        name: args.name.clone(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        endpoints: vec![
+            Advertised { transport: TransportKind::Tcp, addr: server.local_addr()? },
+            Advertised { transport: TransportKind::WebSocket, addr: ws_server.local_addr()? },
+            Advertised { transport: TransportKind::Grpc, addr: args.grpc },
+        ],
+    };
+    let discovery = DiscoveryResponder::bind(args.discovery, info).await?;
+    println!("Answering discovery probes on {}", args.discovery);
+    #[cfg(unix)]
+    let local = args.local.bind(handle.clone())?;
+    let local_run = async {
+        #[cfg(unix)]
+        if let Some(local) = &local {
+            return local.run().await;
+        }
+        std::future::pending().await
+    };
+    tokio::select! {
+        _ = server.run() => {}
+        _ = ws_server.run() => {}
+        _ = local_run => {}
+        _ = discovery.run() => {}
+        result = grpc => {
+            if let Err(e) = result {
+                eprintln!("gRPC server failed: {}", e);
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {
+            println!("Shutting down.");
+        }
+    }
+    handle.shutdown();
+    center_task.await??;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,172 @@
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use message::{decode_frame, encode_frame, MessageType, Msg, MsgQueue, MAX_FRAME_LEN};
+use subsystem::{CenterHandle, FrameLines};
+use tokio::io::{AsyncRead, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::unix;
+use tokio::net::{tcp, TcpStream};
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::bus::RemoteSubscriber;
+
+/// 每个连接待写出的消息上限，写不过来时阻塞该连接的读取
+const SESSION_QUEUE_SIZE: usize = 256;
+
+/// Where a session reads frames from: lines on TCP and Unix sockets, messages
+/// on WebSocket.
+#[async_trait]
+pub trait FrameReader: Send {
+    /// The next frame, or `None` once the client has closed the connection.
+    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+#[async_trait]
+pub trait FrameWriter: Send + 'static {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()>;
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> FrameReader for FrameLines<R> {
+    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.next_frame().await
+    }
+}
+
+#[async_trait]
+impl FrameWriter for tcp::OwnedWriteHalf {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        self.write_all(&frame).await
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl FrameWriter for unix::OwnedWriteHalf {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        self.write_all(&frame).await
+    }
+}
+
+#[async_trait]
+impl FrameReader for SplitStream<WebSocketStream<TcpStream>> {
+    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let frame = match self.next().await.transpose().map_err(io::Error::other)? {
+                None | Some(Message::Close(_)) => return Ok(None),
+                Some(Message::Text(text)) => text.into_bytes(),
+                Some(Message::Binary(data)) => data,
+                // ping 由 tungstenite 自动应答
+                Some(_) => continue,
+            };
+            if frame.len() > MAX_FRAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame longer than {} bytes", MAX_FRAME_LEN),
+                ));
+            }
+            return Ok(Some(frame));
+        }
+    }
+}
+
+#[async_trait]
+impl FrameWriter for SplitSink<WebSocketStream<TcpStream>, Message> {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        let text = String::from_utf8(frame).map_err(io::Error::other)?;
+        self.send(Message::Text(text)).await.map_err(io::Error::other)
+    }
+}
+
+/// Serves one client until it disconnects: publishes what it sends on the
+/// center bus as coming from `label` and forwards the topics it subscribes to.
+pub async fn run_session(mut reader: impl FrameReader, writer: impl FrameWriter, label: String, center: CenterHandle) {
+    let mut remote = RemoteSubscriber::new(center.bus(), label.clone());
+    let (sender, receiver) = channel::<Arc<Msg>>(SESSION_QUEUE_SIZE);
+    let write_task = tokio::spawn(write_loop(writer, receiver));
+
+    loop {
+        tokio::select! {
+            result = reader.read_frame() => match result {
+                Ok(None) => {
+                    println!("Connection closed by client.");
+                    break;
+                }
+                Ok(Some(frame)) => {
+                    let mut msg: Msg = match decode_frame(&frame) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            eprintln!("Failed to deserialize message: {}", e);
+                            continue;
+                        }
+                    };
+                    // 心跳只用于保持连接
+                    if msg.get_msg_type() == MessageType::Heartbeat {
+                        continue;
+                    }
+                    println!("Received: {:?}", msg);
+                    if remote.handle_request(&msg) {
+                        continue;
+                    }
+                    msg.info.source = Some(label.clone());
+
+                    // Echo the message back to the client
+                    if sender.send(Arc::new(msg.clone())).await.is_err() {
+                        break;
+                    }
+                    if center.publish(msg).await.is_err() {
+                        eprintln!("Center stopped, closing connection.");
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to read from stream: {}", e);
+                    break;
+                }
+            },
+            // 只转发客户端订阅了的 topic
+            Some(msg) = remote.recv() => {
+                if sender.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    drop(sender);
+    let _ = write_task.await;
+}
+
+/// Writes queued messages highest priority first.
+async fn write_loop(mut writer: impl FrameWriter, mut receiver: Receiver<Arc<Msg>>) {
+    let mut queue = MsgQueue::new();
+    while let Some(msg) = receiver.recv().await {
+        queue.push(msg);
+        loop {
+            // 每写一条之前都先收取新到的消息，让急停插队
+            while let Ok(msg) = receiver.try_recv() {
+                queue.push(msg);
+            }
+            let Some(msg) = queue.pop() else {
+                break;
+            };
+            let data = match encode_frame(&msg) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to serialize message: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = writer.write_frame(data).await {
+                eprintln!("Failed to write to stream: {}", e);
+                return;
+            }
+        }
+    }
+}
@@ -1,8 +0,0 @@
-﻿
-
-
-
-
-trait SubSystem {
-    fn register(&self,);
-}   
\ No newline at end of file
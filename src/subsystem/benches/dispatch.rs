@@ -0,0 +1,96 @@
+//! Messages per second delivered through `CenterSubsystem` with 1, 4 and 16 subsystems.
+//!
+//! Run with `cargo bench -p subsystem --bench dispatch`.
+
+use std::hint::black_box;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use message::{MessageType, Msg};
+use subsystem::{BoxError, CenterSubsystem, Context, SubSystem};
+use tokio::sync::Notify;
+
+const MESSAGES: usize = 20_000;
+
+/// 每条消息做一点计算，模拟真实子系统的开销
+struct Counter {
+    received: usize,
+    done: Arc<Notify>,
+    total: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl SubSystem for Counter {
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        let mut hash = msg.get_uid().as_u128();
+        for _ in 0..2_000 {
+            hash = black_box(hash.rotate_left(5) ^ 0x9e37_79b9_7f4a_7c15);
+        }
+        self.received += 1;
+        self.total.fetch_add(1, Ordering::Relaxed);
+        if self.received == MESSAGES {
+            self.done.notify_one();
+        }
+        Ok(())
+    }
+
+    fn rollup(&mut self) {}
+}
+
+async fn measure(subsystems: usize) -> (Duration, usize) {
+    let mut center = CenterSubsystem::new();
+    let total = Arc::new(AtomicUsize::new(0));
+    let mut done = Vec::new();
+    for i in 0..subsystems {
+        let notify = Arc::new(Notify::new());
+        done.push(notify.clone());
+        let counter = Counter {
+            received: 0,
+            done: notify,
+            total: total.clone(),
+        };
+        center.register(format!("counter/{}", i), counter, vec![MessageType::Move]);
+    }
+    let handle = center.handle();
+    center.start().await.unwrap();
+
+    let started = Instant::now();
+    for _ in 0..MESSAGES {
+        let mut msg = Msg::new();
+        msg.set_msg_type(MessageType::Move);
+        handle.publish(msg).await.unwrap();
+    }
+    for notify in done {
+        notify.notified().await;
+    }
+    let elapsed = started.elapsed();
+
+    center.stop().await;
+    (elapsed, total.load(Ordering::Relaxed))
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    println!(
+        "{} messages, {} worker threads",
+        MESSAGES,
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    );
+    for subsystems in [1, 4, 16] {
+        let (elapsed, delivered) = runtime.block_on(measure(subsystems));
+        println!(
+            "{:>2} subsystems: {:>10.0} msg/s delivered ({} deliveries in {:?})",
+            subsystems,
+            delivered as f64 / elapsed.as_secs_f64(),
+            delivered,
+            elapsed
+        );
+    }
+}
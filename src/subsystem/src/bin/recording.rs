@@ -0,0 +1,81 @@
+//! Inspects bus recordings made by `subsystem::Recorder`.
+//!
+//! `recording info bus.rec` summarizes a recording,
+//! `recording dump bus.rec --type move --from 10 --to 20` lists its messages.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use message::MessageType;
+use subsystem::{read_recording, Replayer};
+
+#[derive(Parser)]
+#[command(about = "Inspect bus recordings")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Message count, time span and count per message type.
+    Info { file: PathBuf },
+    /// One line per message.
+    Dump {
+        file: PathBuf,
+        /// Only messages of this type, e.g. `move`. May be repeated.
+        #[arg(long = "type")]
+        types: Vec<MessageType>,
+        /// Seconds after the first message.
+        #[arg(long)]
+        from: Option<f64>,
+        /// Seconds after the first message.
+        #[arg(long)]
+        to: Option<f64>,
+    },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Cli::parse().command {
+        Command::Info { file } => {
+            let records = read_recording(&file)?;
+            let (Some(first), Some(last)) = (records.first(), records.last()) else {
+                println!("{}: empty", file.display());
+                return Ok(());
+            };
+            let mut counts = BTreeMap::new();
+            for record in &records {
+                *counts.entry(record.msg.info.msg_type.topic()).or_insert(0) += 1;
+            }
+            println!("{}: {} messages", file.display(), records.len());
+            println!("start: {:.3}s since epoch", Duration::from_micros(first.ts).as_secs_f64());
+            println!("span:  {:.3}s", Duration::from_micros(last.ts.saturating_sub(first.ts)).as_secs_f64());
+            for (msg_type, count) in counts {
+                println!("  {:<12} {}", msg_type, count);
+            }
+        }
+        Command::Dump { file, types, from, to } => {
+            let records = read_recording(&file)?;
+            let start = records.first().map_or(0, |record| record.ts);
+            let replayer = Replayer::new(records)
+                .types(types)
+                .between(from.map(Duration::from_secs_f64), to.map(Duration::from_secs_f64));
+            for record in replayer.selected() {
+                let info = &record.msg.info;
+                println!(
+                    "{:>10.3} {:<12} {:<24} {:?} {} -> {} {}",
+                    Duration::from_micros(record.ts.saturating_sub(start)).as_secs_f64(),
+                    info.msg_type.topic(),
+                    record.msg.topic(),
+                    info.priority,
+                    info.source.as_deref().unwrap_or("-"),
+                    info.target.as_deref().unwrap_or("*"),
+                    info.uid,
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -1 +1,363 @@
-﻿
\ No newline at end of file
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock, Weak,
+};
+
+use message::Msg;
+
+mod intercept;
+mod queue;
+mod topic;
+
+pub use intercept::{Interceptor, InterceptorChain, Verdict};
+pub use queue::{QueueConfig, QueuePolicy, QueueStats};
+pub use topic::{Filter, TopicError, TopicFilter};
+
+pub(crate) use queue::{Push, SubscriberQueue};
+
+struct Subscriber {
+    id: u64,
+    filters: Vec<Filter>,
+    queue: Arc<SubscriberQueue>,
+}
+
+impl Subscriber {
+    fn wants(&self, msg: &Msg) -> bool {
+        match &msg.info.target {
+            Some(target) => *target == self.queue.label,
+            // 不把消息再投回发布者
+            None => {
+                msg.info.source.as_deref() != Some(self.queue.label.as_str())
+                    && self.filters.iter().any(|filter| filter.matches(msg))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct BusInner {
+    subscribers: RwLock<Vec<Subscriber>>,
+    interceptors: RwLock<InterceptorChain>,
+    next_id: AtomicU64,
+}
+
+impl Drop for BusInner {
+    fn drop(&mut self) {
+        for subscriber in self.subscribers.get_mut().unwrap().iter() {
+            subscriber.queue.close();
+        }
+    }
+}
+
+/// Fans published messages out to every subscriber whose filters match.
+///
+/// A message with `info.target` set only goes to the subscriber with that label,
+/// and a message is never delivered back to the subscriber named in `info.source`.
+/// Every published message first goes through the bus's [`InterceptorChain`].
+#[derive(Clone, Default)]
+pub struct Bus {
+    inner: Arc<BusInner>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a subscription with its own queue using [`QueueConfig::default`].
+    /// `label` is what `info.target` and `info.source` are compared against,
+    /// usually a subsystem id.
+    pub fn subscribe<F>(&self, label: impl Into<String>, filters: impl IntoIterator<Item = F>) -> Subscription
+    where
+        F: Into<Filter>,
+    {
+        self.subscribe_with(label, filters, QueueConfig::default())
+    }
+
+    pub fn subscribe_with<F>(&self, label: impl Into<String>, filters: impl IntoIterator<Item = F>, config: QueueConfig) -> Subscription
+    where
+        F: Into<Filter>,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(SubscriberQueue::new(label.into(), config));
+        self.inner.subscribers.write().unwrap().push(Subscriber {
+            id,
+            filters: filters.into_iter().map(Into::into).collect(),
+            queue: queue.clone(),
+        });
+        Subscription {
+            id,
+            bus: Arc::downgrade(&self.inner),
+            queue,
+        }
+    }
+
+    /// Appends an interceptor to the end of the chain.
+    pub fn add_interceptor(&self, interceptor: impl Interceptor + 'static) {
+        self.inner.interceptors.write().unwrap().push(interceptor);
+    }
+
+    /// Replaces the whole interceptor chain.
+    pub fn set_interceptors(&self, chain: InterceptorChain) {
+        *self.inner.interceptors.write().unwrap() = chain;
+    }
+
+    /// Queues the message for every matching subscriber, waiting on any full
+    /// [`QueuePolicy::Block`] queue. Returns how many subscribers it was queued for.
+    pub async fn publish(&self, msg: Msg) -> usize {
+        let Some(msgs) = self.intercept(msg) else {
+            return 0;
+        };
+        let mut delivered = 0;
+        for msg in msgs {
+            let msg = Arc::new(msg);
+            let count = self.dispatch(msg.clone()).await;
+            self.inner.interceptors.read().unwrap().after(&msg, count);
+            delivered += count;
+        }
+        delivered
+    }
+
+    /// Publishes a message that is already shared. It is only copied if
+    /// there are interceptors that need to own it.
+    pub async fn publish_shared(&self, msg: Arc<Msg>) -> usize {
+        if self.inner.interceptors.read().unwrap().is_empty() {
+            return self.dispatch(msg).await;
+        }
+        let msg = Arc::try_unwrap(msg).unwrap_or_else(|msg| (*msg).clone());
+        self.publish(msg).await
+    }
+
+    /// Like [`publish`](Bus::publish) but never waits: a full blocking queue
+    /// counts the message as dropped instead. Returns how many subscribers
+    /// actually queued it.
+    pub fn try_publish(&self, msg: Msg) -> usize {
+        let Some(msgs) = self.intercept(msg) else {
+            return 0;
+        };
+        let mut delivered = 0;
+        for msg in msgs {
+            let msg = Arc::new(msg);
+            let mut queued = 0;
+            for queue in self.matching(&msg) {
+                match queue.try_push(msg.clone()) {
+                    Push::Queued => queued += 1,
+                    Push::Full(_) => queue.drop_full(),
+                    Push::Dropped => {}
+                }
+            }
+            self.inner.interceptors.read().unwrap().after(&msg, queued);
+            delivered += queued;
+        }
+        delivered
+    }
+
+    /// Runs the interceptor chain. `None` if every message was rejected.
+    fn intercept(&self, msg: Msg) -> Option<Vec<Msg>> {
+        let chain = self.inner.interceptors.read().unwrap();
+        let msgs = if chain.is_empty() { vec![msg] } else { chain.before(msg) };
+        (!msgs.is_empty()).then_some(msgs)
+    }
+
+    async fn dispatch(&self, msg: Arc<Msg>) -> usize {
+        // 不能持有读锁等待，先取出匹配的队列
+        let queues = self.matching(&msg);
+        for queue in &queues {
+            queue.push(msg.clone()).await;
+        }
+        queues.len()
+    }
+
+    fn matching(&self, msg: &Msg) -> Vec<Arc<SubscriberQueue>> {
+        self.inner
+            .subscribers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|subscriber| subscriber.wants(msg))
+            .map(|subscriber| subscriber.queue.clone())
+            .collect()
+    }
+
+    /// Queue depth and drop counters for every subscriber.
+    pub fn stats(&self) -> Vec<QueueStats> {
+        self.inner
+            .subscribers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|subscriber| subscriber.queue.stats())
+            .collect()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.inner.subscribers.read().unwrap().len()
+    }
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bus")
+            .field("subscribers", &self.subscriber_count())
+            .finish()
+    }
+}
+
+/// One subscriber's end of the [`Bus`]. Dropping it unsubscribes.
+pub struct Subscription {
+    id: u64,
+    bus: Weak<BusInner>,
+    queue: Arc<SubscriberQueue>,
+}
+
+impl Subscription {
+    pub fn label(&self) -> &str {
+        &self.queue.label
+    }
+
+    /// Waits for the next message, highest priority first.
+    /// Returns `None` once the bus is gone and the queue is empty.
+    pub async fn recv(&self) -> Option<Arc<Msg>> {
+        loop {
+            if let Some(msg) = self.try_recv() {
+                return Some(msg);
+            }
+            if self.queue.is_closed() {
+                return None;
+            }
+            self.queue.ready().await;
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<Arc<Msg>> {
+        self.queue.pop()
+    }
+
+    /// Number of messages waiting in this subscriber's queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    pub fn add_filter(&self, filter: impl Into<Filter>) {
+        let filter = filter.into();
+        self.with_subscriber(|subscriber| {
+            if !subscriber.filters.contains(&filter) {
+                subscriber.filters.push(filter);
+            }
+        });
+    }
+
+    pub fn remove_filter(&self, filter: &Filter) {
+        self.with_subscriber(|subscriber| subscriber.filters.retain(|f| f != filter));
+    }
+
+    fn with_subscriber(&self, f: impl FnOnce(&mut Subscriber)) {
+        let Some(bus) = self.bus.upgrade() else {
+            return;
+        };
+        let mut subscribers = bus.subscribers.write().unwrap();
+        if let Some(subscriber) = subscribers.iter_mut().find(|subscriber| subscriber.id == self.id) {
+            f(subscriber);
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // 唤醒还在等空位的发布者
+        self.queue.close();
+        if let Some(bus) = self.bus.upgrade() {
+            bus.subscribers
+                .write()
+                .unwrap()
+                .retain(|subscriber| subscriber.id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use message::MessageType;
+
+    use super::*;
+
+    fn msg(msg_type: MessageType, topic: &str) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_type(msg_type);
+        msg.set_topic(topic);
+        msg
+    }
+
+    fn topics(subscription: &Subscription) -> Vec<String> {
+        std::iter::from_fn(|| subscription.try_recv()).map(|msg| msg.topic().into_owned()).collect()
+    }
+
+    #[tokio::test]
+    async fn delivers_to_every_matching_filter() {
+        let bus = Bus::new();
+        let moves = bus.subscribe("moves", [MessageType::Move]);
+        let status = bus.subscribe("status", [Filter::topic("motor/+/status").unwrap()]);
+        let stops = bus.subscribe("stops", [MessageType::Stop]);
+
+        assert_eq!(bus.publish(msg(MessageType::Move, "motor/3/status")).await, 2);
+        assert_eq!(bus.publish(msg(MessageType::Telemetry, "motor/4/status")).await, 1);
+        assert_eq!(bus.publish(msg(MessageType::Telemetry, "motor/4/speed")).await, 0);
+        assert_eq!(topics(&moves), ["motor/3/status"]);
+        assert_eq!(topics(&status), ["motor/3/status", "motor/4/status"]);
+        assert!(stops.is_empty());
+    }
+
+    #[tokio::test]
+    async fn targets_bypass_filters_and_sources_are_skipped() {
+        let bus = Bus::new();
+        let a = bus.subscribe("a", [MessageType::Move]);
+        let b = bus.subscribe("b", Vec::<Filter>::new());
+
+        let mut targeted = msg(MessageType::Move, "to/b");
+        targeted.info.target = Some("b".to_string());
+        assert_eq!(bus.publish(targeted).await, 1);
+        assert!(a.is_empty());
+        assert_eq!(topics(&b), ["to/b"]);
+
+        let mut own = msg(MessageType::Move, "from/a");
+        own.info.source = Some("a".to_string());
+        assert_eq!(bus.publish(own).await, 0);
+        assert!(a.is_empty());
+    }
+
+    #[tokio::test]
+    async fn filters_change_and_dropping_unsubscribes() {
+        let bus = Bus::new();
+        let sub = bus.subscribe("sub", [MessageType::Move]);
+        sub.add_filter(Filter::topic("alarm/#").unwrap());
+        sub.add_filter(MessageType::Move);
+        bus.try_publish(msg(MessageType::Alarm, "alarm/plc1"));
+        sub.remove_filter(&Filter::Type(MessageType::Move));
+        bus.try_publish(msg(MessageType::Move, "move"));
+        assert_eq!(topics(&sub), ["alarm/plc1"]);
+
+        assert_eq!(bus.subscriber_count(), 1);
+        drop(sub);
+        assert_eq!(bus.subscriber_count(), 0);
+        assert_eq!(bus.publish(msg(MessageType::Alarm, "alarm/plc1")).await, 0);
+    }
+
+    #[tokio::test]
+    async fn recv_ends_once_the_bus_is_gone() {
+        let bus = Bus::new();
+        let sub = bus.subscribe("sub", [MessageType::Move]);
+        bus.publish(msg(MessageType::Move, "move")).await;
+        drop(bus);
+        assert_eq!(sub.recv().await.unwrap().topic(), "move");
+        assert!(sub.recv().await.is_none());
+    }
+}
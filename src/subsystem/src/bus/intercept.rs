@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use message::Msg;
+
+/// What an [`Interceptor`] decides to do with a message before it is dispatched.
+#[derive(Debug)]
+pub enum Verdict {
+    /// Pass the (possibly modified) message on to the next interceptor.
+    Continue(Msg),
+    /// Drop the message. The reason is logged.
+    Reject(String),
+    /// Replace the message with these, each passed on to the next interceptor.
+    FanOut(Vec<Msg>),
+}
+
+/// Sees every message published on the bus, e.g. for logging, authorization,
+/// validation, metrics or rate limiting.
+///
+/// Interceptors run in the order they were added. `before` runs before the
+/// message is queued for subscribers, `after` runs in reverse order once it has been.
+pub trait Interceptor: Send + Sync {
+    /// Shown when the interceptor rejects a message.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    fn before(&self, msg: Msg) -> Verdict {
+        Verdict::Continue(msg)
+    }
+
+    /// `delivered` is the number of subscribers the message was queued for.
+    fn after(&self, _msg: &Msg, _delivered: usize) {}
+}
+
+/// An ordered list of interceptors. Usable on its own to test a chain without a bus.
+#[derive(Clone, Default)]
+pub struct InterceptorChain {
+    interceptors: Vec<Arc<dyn Interceptor>>,
+}
+
+impl InterceptorChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, interceptor: impl Interceptor + 'static) -> &mut Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.interceptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interceptors.is_empty()
+    }
+
+    /// Runs `msg` through every interceptor's `before`, returning what should be dispatched.
+    pub fn before(&self, msg: Msg) -> Vec<Msg> {
+        let mut msgs = vec![msg];
+        for interceptor in &self.interceptors {
+            let mut next = Vec::with_capacity(msgs.len());
+            for msg in msgs {
+                match interceptor.before(msg) {
+                    Verdict::Continue(msg) => next.push(msg),
+                    Verdict::FanOut(msgs) => next.extend(msgs),
+                    Verdict::Reject(reason) => {
+                        eprintln!("{} rejected message: {}", interceptor.name(), reason);
+                    }
+                }
+            }
+            msgs = next;
+            if msgs.is_empty() {
+                break;
+            }
+        }
+        msgs
+    }
+
+    pub fn after(&self, msg: &Msg, delivered: usize) {
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.after(msg, delivered);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use message::MessageType;
+
+    use super::*;
+    use crate::bus::Bus;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Logs every call and does what its name says to messages on `topic`.
+    struct Step {
+        name: &'static str,
+        log: Log,
+        topic: &'static str,
+    }
+
+    impl Interceptor for Step {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn before(&self, mut msg: Msg) -> Verdict {
+            self.log.lock().unwrap().push(format!("{} before {}", self.name, msg.topic()));
+            if msg.topic() != self.topic {
+                return Verdict::Continue(msg);
+            }
+            match self.name {
+                "reject" => Verdict::Reject("not allowed".to_string()),
+                "rewrite" => {
+                    msg.set_topic(format!("{}/checked", msg.topic()));
+                    Verdict::Continue(msg)
+                }
+                "split" => {
+                    let mut copy = msg.clone();
+                    copy.set_topic("copy");
+                    Verdict::FanOut(vec![msg, copy])
+                }
+                _ => Verdict::Continue(msg),
+            }
+        }
+
+        fn after(&self, msg: &Msg, delivered: usize) {
+            self.log.lock().unwrap().push(format!("{} after {} {}", self.name, msg.topic(), delivered));
+        }
+    }
+
+    fn chain(log: &Log, steps: &[(&'static str, &'static str)]) -> InterceptorChain {
+        let mut chain = InterceptorChain::new();
+        for (name, topic) in steps {
+            chain.push(Step {
+                name,
+                log: log.clone(),
+                topic,
+            });
+        }
+        chain
+    }
+
+    fn msg(topic: &str) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_type(MessageType::Move);
+        msg.set_topic(topic);
+        msg
+    }
+
+    fn topics(msgs: &[Msg]) -> Vec<String> {
+        msgs.iter().map(|msg| msg.topic().into_owned()).collect()
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        std::mem::take(&mut *log.lock().unwrap())
+    }
+
+    #[test]
+    fn before_runs_in_order_and_after_in_reverse() {
+        let log = Log::default();
+        let chain = chain(&log, &[("first", ""), ("second", "")]);
+        assert_eq!(chain.len(), 2);
+        let msgs = chain.before(msg("move"));
+        chain.after(&msgs[0], 3);
+        assert_eq!(
+            take(&log),
+            ["first before move", "second before move", "second after move 3", "first after move 3"]
+        );
+    }
+
+    #[test]
+    fn a_rejected_message_goes_no_further() {
+        let log = Log::default();
+        let chain = chain(&log, &[("reject", "secret"), ("last", "")]);
+        assert!(chain.before(msg("secret")).is_empty());
+        assert_eq!(take(&log), ["reject before secret"]);
+        assert_eq!(topics(&chain.before(msg("move"))), ["move"]);
+    }
+
+    #[test]
+    fn later_interceptors_see_rewrites_and_fan_outs() {
+        let log = Log::default();
+        let chain = chain(&log, &[("rewrite", "move"), ("split", "move/checked"), ("last", "")]);
+        let msgs = chain.before(msg("move"));
+        assert_eq!(topics(&msgs), ["move/checked", "copy"]);
+        assert_eq!(
+            take(&log),
+            [
+                "rewrite before move",
+                "split before move/checked",
+                "last before move/checked",
+                "last before copy"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_bus_dispatches_what_the_chain_lets_through() {
+        let log = Log::default();
+        let bus = Bus::new();
+        bus.set_interceptors(chain(&log, &[("reject", "secret"), ("rewrite", "move")]));
+        let sub = bus.subscribe("sub", [MessageType::Move]);
+
+        assert_eq!(bus.publish(msg("secret")).await, 0);
+        assert_eq!(bus.publish(msg("move")).await, 1);
+        assert_eq!(sub.try_recv().unwrap().topic(), "move/checked");
+        assert!(sub.try_recv().is_none());
+        let log = take(&log);
+        assert_eq!(&log[log.len() - 2..], ["rewrite after move/checked 1", "reject after move/checked 1"]);
+        assert!(!log.iter().any(|line| line.contains("after secret")));
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use message::{Msg, MsgQueue, Prioritized, Priority};
+use tokio::sync::Notify;
+
+/// What a full subscriber queue does with the next message.
+///
+/// Whatever the policy, `Priority::Critical` messages are always queued, and a
+/// dropping policy evicts lower priority messages before higher ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePolicy {
+    /// The publisher waits until the subscriber makes room.
+    #[default]
+    Block,
+    /// Make room by dropping the oldest queued message.
+    DropOldest,
+    /// Drop the message being published.
+    DropNewest,
+    /// A new message replaces any queued message with the same topic, e.g. `motor/3/status`.
+    /// If the queue is still full the oldest message is dropped.
+    LatestPerKey,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: QueuePolicy,
+}
+
+impl QueueConfig {
+    pub fn new(capacity: usize, policy: QueuePolicy) -> Self {
+        Self { capacity, policy }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self::new(1024, QueuePolicy::Block)
+    }
+}
+
+/// A snapshot of one subscriber's queue, for spotting who is falling behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueStats {
+    pub label: String,
+    pub depth: usize,
+    /// Deepest the queue has been.
+    pub peak: usize,
+    pub capacity: usize,
+    pub policy: QueuePolicy,
+    /// Messages dropped or replaced because the queue was full.
+    pub dropped: u64,
+}
+
+pub(crate) enum Push {
+    Queued,
+    Dropped,
+    /// Only returned under [`QueuePolicy::Block`].
+    Full(Arc<Msg>),
+}
+
+/// 每个订阅者独立的有界队列，按优先级出队
+pub(crate) struct SubscriberQueue {
+    pub(crate) label: String,
+    config: QueueConfig,
+    queue: Mutex<MsgQueue<Arc<Msg>>>,
+    /// 有新消息
+    ready: Notify,
+    /// 有空位
+    space: Notify,
+    closed: AtomicBool,
+    dropped: AtomicU64,
+    peak: AtomicUsize,
+}
+
+impl SubscriberQueue {
+    pub(crate) fn new(label: String, config: QueueConfig) -> Self {
+        Self {
+            label,
+            config,
+            queue: Mutex::new(MsgQueue::new()),
+            ready: Notify::new(),
+            space: Notify::new(),
+            closed: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queues `msg`, waiting for room under [`QueuePolicy::Block`].
+    pub(crate) async fn push(&self, mut msg: Arc<Msg>) {
+        loop {
+            let space = self.space.notified();
+            tokio::pin!(space);
+            // 先登记等待再尝试，避免错过出队时的通知
+            space.as_mut().enable();
+            match self.try_push(msg) {
+                Push::Full(returned) => msg = returned,
+                Push::Queued | Push::Dropped => return,
+            }
+            space.await;
+        }
+    }
+
+    pub(crate) fn try_push(&self, msg: Arc<Msg>) -> Push {
+        if self.is_closed() {
+            return Push::Dropped;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        if self.config.policy == QueuePolicy::LatestPerKey {
+            let topic = msg.topic();
+            let before = queue.len();
+            queue.retain(|queued| queued.topic() != topic);
+            self.count_dropped(before - queue.len());
+        }
+
+        let priority = msg.priority();
+        if queue.len() >= self.config.capacity && priority != Priority::Critical {
+            let lowest = queue.lowest_priority().unwrap_or(priority);
+            match self.config.policy {
+                QueuePolicy::Block => return Push::Full(msg),
+                QueuePolicy::DropOldest | QueuePolicy::LatestPerKey if lowest <= priority => {
+                    queue.pop_oldest_lowest();
+                }
+                QueuePolicy::DropNewest if lowest < priority => {
+                    queue.pop_newest_lowest();
+                }
+                _ => {
+                    self.count_dropped(1);
+                    return Push::Dropped;
+                }
+            }
+            self.count_dropped(1);
+        }
+
+        queue.push(msg);
+        self.peak.fetch_max(queue.len(), Ordering::Relaxed);
+        drop(queue);
+        self.ready.notify_one();
+        Push::Queued
+    }
+
+    pub(crate) fn pop(&self) -> Option<Arc<Msg>> {
+        let msg = self.queue.lock().unwrap().pop();
+        if msg.is_some() {
+            self.space.notify_waiters();
+        }
+        msg
+    }
+
+    pub(crate) async fn ready(&self) {
+        self.ready.notified().await;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.ready.notify_one();
+        self.space.notify_waiters();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        QueueStats {
+            label: self.label.clone(),
+            depth: self.len(),
+            peak: self.peak.load(Ordering::Relaxed),
+            capacity: self.config.capacity,
+            policy: self.config.policy,
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Records a message that [`try_push`](Self::try_push) could not queue.
+    pub(crate) fn drop_full(&self) {
+        self.count_dropped(1);
+    }
+
+    fn count_dropped(&self, count: usize) {
+        if count > 0 {
+            self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use message::MessageType;
+
+    use super::*;
+
+    fn msg(topic: &str, priority: Priority) -> Arc<Msg> {
+        let mut msg = Msg::new();
+        msg.set_msg_type(MessageType::Telemetry);
+        msg.set_topic(topic);
+        msg.set_priority(priority);
+        Arc::new(msg)
+    }
+
+    fn queue(capacity: usize, policy: QueuePolicy) -> SubscriberQueue {
+        SubscriberQueue::new("sub".to_string(), QueueConfig::new(capacity, policy))
+    }
+
+    /// Pushes normal priority messages with these topics.
+    fn fill(queue: &SubscriberQueue, topics: &[&str]) {
+        for topic in topics {
+            assert!(matches!(queue.try_push(msg(topic, Priority::Normal)), Push::Queued));
+        }
+    }
+
+    fn drain(queue: &SubscriberQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop()).map(|msg| msg.topic().into_owned()).collect()
+    }
+
+    #[test]
+    fn block_refuses_until_there_is_room() {
+        let queue = queue(2, QueuePolicy::Block);
+        fill(&queue, &["a", "b"]);
+        assert!(matches!(queue.try_push(msg("c", Priority::High)), Push::Full(_)));
+        // Critical 总能入队，哪怕超出容量
+        assert!(matches!(queue.try_push(msg("stop", Priority::Critical)), Push::Queued));
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.peak, stats.dropped), (3, 3, 0));
+        assert_eq!(drain(&queue), ["stop", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn blocked_push_waits_for_a_pop() {
+        let queue = Arc::new(queue(1, QueuePolicy::Block));
+        fill(&queue, &["a"]);
+        let pusher = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push(msg("b", Priority::Normal)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!pusher.is_finished());
+        assert_eq!(queue.pop().unwrap().topic(), "a");
+        pusher.await.unwrap();
+        assert_eq!(drain(&queue), ["b"]);
+        assert_eq!(queue.stats().dropped, 0);
+    }
+
+    #[test]
+    fn drop_oldest_evicts_the_oldest_lowest_priority() {
+        let queue = queue(2, QueuePolicy::DropOldest);
+        fill(&queue, &["a", "b", "c"]);
+        assert_eq!(queue.stats().dropped, 1);
+        assert!(matches!(queue.try_push(msg("d", Priority::High)), Push::Queued));
+        // 新消息优先级低于队中所有消息时丢弃新消息
+        assert!(matches!(queue.try_push(msg("e", Priority::Low)), Push::Dropped));
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.peak, stats.dropped), (2, 2, 3));
+        assert_eq!(drain(&queue), ["d", "c"]);
+    }
+
+    #[test]
+    fn drop_newest_drops_the_newcomer_unless_it_outranks() {
+        let queue = queue(2, QueuePolicy::DropNewest);
+        fill(&queue, &["a", "b"]);
+        assert!(matches!(queue.try_push(msg("c", Priority::Normal)), Push::Dropped));
+        assert!(matches!(queue.try_push(msg("d", Priority::High)), Push::Queued));
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.dropped, stats.policy), (2, 2, QueuePolicy::DropNewest));
+        assert_eq!(drain(&queue), ["d", "a"]);
+    }
+
+    #[test]
+    fn latest_per_key_replaces_the_same_topic() {
+        let queue = queue(3, QueuePolicy::LatestPerKey);
+        fill(&queue, &["motor/1/status", "motor/2/status", "motor/1/status"]);
+        assert_eq!(queue.stats().depth, 2);
+        assert_eq!(queue.stats().dropped, 1);
+        // 满了仍按最旧丢弃
+        fill(&queue, &["motor/3/status", "motor/4/status"]);
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.peak, stats.dropped), (3, 3, 2));
+        assert_eq!(drain(&queue), ["motor/1/status", "motor/3/status", "motor/4/status"]);
+    }
+
+    #[test]
+    fn closed_queues_drop_everything() {
+        let queue = queue(2, QueuePolicy::Block);
+        queue.close();
+        assert!(matches!(queue.try_push(msg("a", Priority::Critical)), Push::Dropped));
+        let stats = queue.stats();
+        assert_eq!((stats.label.as_str(), stats.depth, stats.capacity), ("sub", 0, 2));
+    }
+}
@@ -0,0 +1,196 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use message::{MessageType, Msg};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicError {
+    Empty,
+    /// `#` may only be the last level.
+    MultiLevelNotLast(String),
+    /// `+` and `#` must take up a whole level.
+    WildcardInLevel(String),
+}
+
+impl fmt::Display for TopicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopicError::Empty => write!(f, "topic filter is empty"),
+            TopicError::MultiLevelNotLast(filter) => write!(f, "`#` must be the last level in {}", filter),
+            TopicError::WildcardInLevel(filter) => write!(f, "wildcards must fill a whole level in {}", filter),
+        }
+    }
+}
+
+impl Error for TopicError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Level {
+    Exact(String),
+    /// `+`，匹配恰好一级
+    Single,
+    /// `#`，匹配剩余所有级（包括零级）
+    Multi,
+}
+
+/// A `/`-separated topic pattern such as `motor/+/status` or `alarm/#`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicFilter {
+    raw: String,
+    levels: Vec<Level>,
+}
+
+impl TopicFilter {
+    pub fn new(filter: &str) -> Result<Self, TopicError> {
+        if filter.is_empty() {
+            return Err(TopicError::Empty);
+        }
+        let parts: Vec<&str> = filter.split('/').collect();
+        let mut levels = Vec::with_capacity(parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            let level = match *part {
+                "+" => Level::Single,
+                "#" if i + 1 == parts.len() => Level::Multi,
+                "#" => return Err(TopicError::MultiLevelNotLast(filter.to_string())),
+                part if part.contains(['+', '#']) => {
+                    return Err(TopicError::WildcardInLevel(filter.to_string()))
+                }
+                part => Level::Exact(part.to_string()),
+            };
+            levels.push(level);
+        }
+        Ok(Self {
+            raw: filter.to_string(),
+            levels,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        let mut parts = topic.split('/');
+        for level in &self.levels {
+            match level {
+                Level::Multi => return true,
+                Level::Single => {
+                    if parts.next().is_none() {
+                        return false;
+                    }
+                }
+                Level::Exact(expected) => {
+                    if parts.next() != Some(expected.as_str()) {
+                        return false;
+                    }
+                }
+            }
+        }
+        parts.next().is_none()
+    }
+}
+
+impl FromStr for TopicFilter {
+    type Err = TopicError;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        Self::new(filter)
+    }
+}
+
+impl fmt::Display for TopicFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// What a subscriber wants to receive: every message of a type, or every message on matching topics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Type(MessageType),
+    Topic(TopicFilter),
+}
+
+impl Filter {
+    pub fn topic(filter: &str) -> Result<Self, TopicError> {
+        TopicFilter::new(filter).map(Filter::Topic)
+    }
+
+    pub fn matches(&self, msg: &Msg) -> bool {
+        match self {
+            Filter::Type(msg_type) => *msg_type == msg.get_msg_type(),
+            Filter::Topic(filter) => filter.matches(&msg.topic()),
+        }
+    }
+}
+
+impl From<MessageType> for Filter {
+    fn from(msg_type: MessageType) -> Self {
+        Filter::Type(msg_type)
+    }
+}
+
+impl From<TopicFilter> for Filter {
+    fn from(filter: TopicFilter) -> Self {
+        Filter::Topic(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(filter: &str, topic: &str) -> bool {
+        TopicFilter::new(filter).unwrap().matches(topic)
+    }
+
+    #[test]
+    fn exact_levels_match_the_whole_topic() {
+        assert!(matches("motor/3/status", "motor/3/status"));
+        assert!(!matches("motor/3/status", "motor/3"));
+        assert!(!matches("motor/3", "motor/3/status"));
+        assert!(!matches("motor/3/status", "motor/4/status"));
+    }
+
+    #[test]
+    fn plus_matches_exactly_one_level() {
+        assert!(matches("motor/+/status", "motor/3/status"));
+        assert!(matches("+", "quit"));
+        assert!(!matches("motor/+/status", "motor/status"));
+        assert!(!matches("motor/+/status", "motor/3/4/status"));
+        assert!(!matches("motor/+", "motor/3/status"));
+        // 空的一级也是一级
+        assert!(matches("motor/+/status", "motor//status"));
+    }
+
+    #[test]
+    fn hash_matches_the_rest_including_nothing() {
+        assert!(matches("alarm/#", "alarm/modbus/plc1"));
+        assert!(matches("alarm/#", "alarm/modbus"));
+        assert!(matches("alarm/#", "alarm"));
+        assert!(matches("#", "telemetry/lift/axis"));
+        assert!(matches("+/lift/#", "telemetry/lift/axis"));
+        assert!(!matches("alarm/#", "telemetry/alarm"));
+    }
+
+    #[test]
+    fn empty_levels_are_exact_levels() {
+        assert!(matches("a//b", "a//b"));
+        assert!(!matches("a//b", "a/b"));
+        assert!(matches("/status", "/status"));
+        assert!(!matches("/status", "status"));
+        assert!(matches("motor/", "motor/"));
+        assert!(!matches("motor/", "motor"));
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        assert_eq!(TopicFilter::new(""), Err(TopicError::Empty));
+        for filter in ["#/status", "alarm/#/plc1", "#/#"] {
+            assert_eq!(TopicFilter::new(filter), Err(TopicError::MultiLevelNotLast(filter.to_string())));
+        }
+        for filter in ["motor+/status", "alarm/mod#", "a/+b", "a/b#/c"] {
+            assert_eq!(TopicFilter::new(filter), Err(TopicError::WildcardInLevel(filter.to_string())));
+        }
+        assert_eq!("alarm/#".parse::<TopicFilter>().unwrap().to_string(), "alarm/#");
+    }
+}
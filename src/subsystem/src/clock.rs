@@ -0,0 +1,126 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+
+/// Where time comes from, so anything that waits can be driven by a [`MockClock`] in tests.
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Returns once [`now`](Clock::now) has reached `deadline`.
+    async fn sleep_until(&self, deadline: DateTime<Utc>);
+
+    async fn sleep(&self, duration: Duration) {
+        let deadline = self.now() + duration;
+        self.sleep_until(deadline).await;
+    }
+}
+
+/// The real wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let remaining = (deadline - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(remaining).await;
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    inner: Arc<MockInner>,
+}
+
+#[derive(Debug)]
+struct MockInner {
+    now: Mutex<DateTime<Utc>>,
+    changed: Notify,
+    sleepers: AtomicUsize,
+}
+
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            inner: Arc::new(MockInner {
+                now: Mutex::new(start),
+                changed: Notify::new(),
+                sleepers: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Moves time forward, waking every sleeper whose deadline has passed.
+    pub fn advance(&self, duration: Duration) {
+        *self.inner.now.lock().unwrap() += duration;
+        self.inner.changed.notify_waiters();
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.inner.now.lock().unwrap() = now;
+        self.inner.changed.notify_waiters();
+    }
+
+    /// How many tasks are currently waiting on this clock. Lets a test wait
+    /// until everything it expects to be asleep is, before advancing time.
+    pub fn sleepers(&self) -> usize {
+        self.inner.sleepers.load(Ordering::Acquire)
+    }
+}
+
+impl Default for MockClock {
+    /// Starts at the Unix epoch.
+    fn default() -> Self {
+        Self::new(DateTime::UNIX_EPOCH)
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.inner.now.lock().unwrap()
+    }
+
+    async fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let _sleeping = Sleeping::new(&self.inner.sleepers);
+        loop {
+            let changed = self.inner.changed.notified();
+            tokio::pin!(changed);
+            // 先登记再比较，避免错过 advance 的通知
+            changed.as_mut().enable();
+            if self.now() >= deadline {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
+
+/// Counts a sleeper for as long as its future is alive, including when it is
+/// dropped by a `select!`.
+struct Sleeping<'a>(&'a AtomicUsize);
+
+impl<'a> Sleeping<'a> {
+    fn new(sleepers: &'a AtomicUsize) -> Self {
+        sleepers.fetch_add(1, Ordering::AcqRel);
+        Self(sleepers)
+    }
+}
+
+impl Drop for Sleeping<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Duration, Instant};
+use uuid::Uuid;
+
+/// UDP port servers listen for discovery probes on.
+pub const DISCOVERY_PORT: u16 = 48080;
+
+/// Multicast group servers join when listening on all interfaces.
+pub const DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 80, 80);
+
+/// 用于忽略同一端口上的其它 UDP 流量
+const SERVICE: &str = "csc";
+
+/// 一个 UDP 包的上限
+const MAX_PACKET: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    Tcp,
+    WebSocket,
+    Grpc,
+}
+
+/// One address a server accepts clients on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Advertised {
+    pub transport: TransportKind,
+    /// An unspecified IP such as `0.0.0.0` stands for the address the reply
+    /// came from. Loopback addresses are only sent to probes from this host.
+    pub addr: SocketAddr,
+}
+
+/// What a server tells clients about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub version: String,
+    pub endpoints: Vec<Advertised>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Packet {
+    Probe { service: String, id: Uuid },
+    Reply { id: Uuid, server: ServerInfo },
+}
+
+/// Answers discovery probes sent to its port, by unicast, broadcast or
+/// multicast to [`DISCOVERY_GROUP`]. Probes from other hosts don't hear of
+/// endpoints that only listen on loopback.
+pub struct DiscoveryResponder {
+    socket: UdpSocket,
+    info: ServerInfo,
+}
+
+impl DiscoveryResponder {
+    /// Binds `addr`, joining [`DISCOVERY_GROUP`] when it is `0.0.0.0`.
+    pub async fn bind(addr: SocketAddr, info: ServerInfo) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        if addr.ip() == IpAddr::V4(Ipv4Addr::UNSPECIFIED) {
+            // 没有组播路由时仍可响应单播和广播
+            if let Err(e) = socket.join_multicast_v4(DISCOVERY_GROUP, Ipv4Addr::UNSPECIFIED) {
+                eprintln!("Failed to join discovery group {}: {}", DISCOVERY_GROUP, e);
+            }
+        }
+        Ok(Self { socket, info })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub async fn run(&self) {
+        let mut buf = vec![0; MAX_PACKET];
+        loop {
+            let (len, peer) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("Failed to receive discovery probe: {}", e);
+                    continue;
+                }
+            };
+            let id = match serde_json::from_slice(&buf[..len]) {
+                Ok(Packet::Probe { service, id }) if service == SERVICE => id,
+                _ => continue,
+            };
+            let mut server = self.info.clone();
+            if !peer.ip().is_loopback() {
+                server.endpoints.retain(|endpoint| !endpoint.addr.ip().is_loopback());
+            }
+            let reply = Packet::Reply { id, server };
+            let data = serde_json::to_vec(&reply).expect("discovery reply serializes");
+            if let Err(e) = self.socket.send_to(&data, peer).await {
+                eprintln!("Failed to answer discovery probe from {}: {}", peer, e);
+            }
+        }
+    }
+}
+
+/// A server that answered [`discover`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// Where the reply came from.
+    pub from: SocketAddr,
+    /// Unspecified and loopback endpoint IPs already replaced by the IP of `from`.
+    pub info: ServerInfo,
+}
+
+/// Broadcast and multicast on [`DISCOVERY_PORT`].
+pub fn discovery_targets() -> Vec<SocketAddr> {
+    vec![
+        SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)),
+        SocketAddr::from((DISCOVERY_GROUP, DISCOVERY_PORT)),
+    ]
+}
+
+/// Probes `targets` and collects the servers that answer within `wait`, once
+/// each even if a server hears the probe on several targets.
+///
+/// Fails only if no probe could be sent at all.
+pub async fn discover(targets: &[SocketAddr], wait: Duration) -> io::Result<Vec<DiscoveredServer>> {
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+    socket.set_broadcast(true)?;
+    let id = Uuid::new_v4();
+    let probe = serde_json::to_vec(&Packet::Probe {
+        service: SERVICE.to_string(),
+        id,
+    })?;
+
+    let mut last_error = None;
+    let mut sent = false;
+    for target in targets {
+        match socket.send_to(&probe, target).await {
+            Ok(_) => sent = true,
+            Err(e) => {
+                eprintln!("Failed to send discovery probe to {}: {}", target, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if !sent {
+        if let Some(e) = last_error {
+            return Err(e);
+        }
+    }
+
+    let deadline = Instant::now() + wait;
+    let mut seen = HashSet::new();
+    let mut servers = Vec::new();
+    let mut buf = vec![0; MAX_PACKET];
+    while let Ok(received) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        let mut info = match serde_json::from_slice(&buf[..len]) {
+            Ok(Packet::Reply { id: reply, server }) if reply == id => server,
+            _ => continue,
+        };
+        if !seen.insert(from) {
+            continue;
+        }
+        // 回环地址对本机以外的客户端没用，按应答来源改写
+        for endpoint in &mut info.endpoints {
+            let ip = endpoint.addr.ip();
+            if ip.is_unspecified() || ip.is_loopback() {
+                endpoint.addr.set_ip(from.ip());
+            }
+        }
+        servers.push(DiscoveredServer { from, info });
+    }
+    Ok(servers)
+}
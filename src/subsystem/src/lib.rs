@@ -1,3 +1,12 @@
 mod subsystem;
 
-mod bus;
\ No newline at end of file
+mod bus;
+mod clock;
+mod discovery;
+mod record;
+
+pub use bus::{*};
+pub use clock::{*};
+pub use discovery::{*};
+pub use record::{*};
+pub use subsystem::{*};
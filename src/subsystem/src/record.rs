@@ -0,0 +1,242 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use message::{MessageType, Msg};
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{Bus, Interceptor};
+use crate::clock::{Clock, SystemClock};
+
+/// One line of a recording.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Microseconds since the Unix epoch.
+    pub ts: u64,
+    pub msg: Msg,
+}
+
+impl Record {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(self.ts)
+    }
+}
+
+/// Records waiting for the writer thread. Past this the recorder drops
+/// records rather than hold up dispatch.
+const RECORD_QUEUE_SIZE: usize = 4096;
+
+/// Appends every message dispatched on the bus to a file, one JSON [`Record`] per line.
+///
+/// Install it as an interceptor, usually last so it records what subscribers actually got:
+/// `center.intercept(Recorder::create("bus.rec")?)`. Records are written by a
+/// thread of their own; clones share it, and it finishes the file once the
+/// last clone is dropped.
+#[derive(Clone)]
+pub struct Recorder {
+    // 取时间和入队在同一把锁下，文件里的时间不会倒序
+    queue: Arc<Mutex<Sender<Entry>>>,
+    clock: Arc<dyn Clock>,
+    dropped: Arc<AtomicU64>,
+}
+
+enum Entry {
+    Record(Record),
+    Flush(Sender<io::Result<()>>),
+}
+
+impl Recorder {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (queue, entries) = channel::bounded(RECORD_QUEUE_SIZE);
+        thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || write_records(BufWriter::new(file), entries))?;
+        Ok(Self {
+            queue: Arc::new(Mutex::new(queue)),
+            clock: Arc::new(SystemClock),
+            dropped: Arc::default(),
+        })
+    }
+
+    /// Stamps records with the time of `clock`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Queues `msg` for writing. Fails if the writer has fallen too far behind.
+    pub fn record(&self, msg: &Msg) -> io::Result<()> {
+        let queue = self.queue.lock().unwrap();
+        let ts = self.clock.now().timestamp_micros().max(0) as u64;
+        match queue.try_send(Entry::Record(Record { ts, msg: msg.clone() })) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "recording queue is full"))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(stopped()),
+        }
+    }
+
+    /// Blocks until everything recorded so far is written to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        let (done, result) = channel::bounded(1);
+        self.queue.lock().unwrap().send(Entry::Flush(done)).map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// Records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Interceptor for Recorder {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    fn after(&self, msg: &Msg, _delivered: usize) {
+        if let Err(e) = self.record(msg) {
+            // 队列满时每条都报会刷屏，只报第一条
+            if e.kind() != io::ErrorKind::WouldBlock || self.dropped() == 1 {
+                eprintln!("Failed to record message: {}", e);
+            }
+        }
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "recorder has stopped")
+}
+
+/// Runs on the writer thread until every [`Recorder`] clone is gone.
+fn write_records(mut file: BufWriter<File>, entries: Receiver<Entry>) {
+    while let Ok(entry) = entries.recv() {
+        for entry in std::iter::once(entry).chain(entries.try_iter()) {
+            match entry {
+                Entry::Record(record) => {
+                    let written = serde_json::to_writer(&mut file, &record)
+                        .map_err(io::Error::from)
+                        .and_then(|()| file.write_all(b"\n"));
+                    if let Err(e) = written {
+                        eprintln!("Failed to record message: {}", e);
+                    }
+                }
+                Entry::Flush(done) => {
+                    let _ = done.send(file.flush());
+                }
+            }
+        }
+        // 每批落盘一次，机器崩溃时最多丢最后一批
+        if let Err(e) = file.flush() {
+            eprintln!("Failed to flush recording: {}", e);
+        }
+    }
+}
+
+/// Reads every record from a recording made by [`Recorder`].
+pub fn read_recording(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, e))
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Feeds a recording back onto a bus with the original spacing between messages.
+pub struct Replayer {
+    records: Vec<Record>,
+    clock: Arc<dyn Clock>,
+    speed: f64,
+    types: Vec<MessageType>,
+    from: Option<Duration>,
+    to: Option<Duration>,
+}
+
+impl Replayer {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(read_recording(path)?))
+    }
+
+    pub fn new(records: Vec<Record>) -> Self {
+        Self {
+            records,
+            clock: Arc::new(SystemClock),
+            speed: 1.0,
+            types: Vec::new(),
+            from: None,
+            to: None,
+        }
+    }
+
+    /// Waits out the gaps between messages on `clock`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// `2.0` replays twice as fast as recorded. `0.0` replays as fast as possible.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Only replays messages of these types.
+    pub fn types(mut self, types: impl IntoIterator<Item = MessageType>) -> Self {
+        self.types = types.into_iter().collect();
+        self
+    }
+
+    /// Only replays messages recorded between `from` and `to` after the first record.
+    pub fn between(mut self, from: Option<Duration>, to: Option<Duration>) -> Self {
+        self.from = from;
+        self.to = to;
+        self
+    }
+
+    /// The records that pass the type and time filters, in recorded order.
+    pub fn selected(&self) -> impl Iterator<Item = &Record> {
+        let start = self.records.first().map_or(0, |record| record.ts);
+        self.records.iter().filter(move |record| {
+            let offset = Duration::from_micros(record.ts.saturating_sub(start));
+            (self.types.is_empty() || self.types.contains(&record.msg.info.msg_type))
+                && self.from.is_none_or(|from| offset >= from)
+                && self.to.is_none_or(|to| offset <= to)
+        })
+    }
+
+    /// Publishes the selected records on `bus` and returns how many were replayed.
+    /// Messages keep their recorded uid, source and target.
+    pub async fn replay(&self, bus: &Bus) -> usize {
+        let mut previous = None;
+        let mut count = 0;
+        for record in self.selected() {
+            if let Some(previous) = previous {
+                if self.speed > 0.0 {
+                    let gap = Duration::from_micros(record.ts.saturating_sub(previous));
+                    self.clock.sleep(gap.div_f64(self.speed)).await;
+                }
+            }
+            previous = Some(record.ts);
+            bus.publish(record.msg.clone()).await;
+            count += 1;
+        }
+        count
+    }
+}
@@ -1,42 +1,415 @@
 ﻿
-use std::{collections::HashMap, ops::{Deref, DerefMut}, sync::{Arc, Mutex}};
+use std::{collections::HashMap, fmt, sync::{Arc, RwLock}};
 
+use async_trait::async_trait;
 use crossbeam::channel::{self, Receiver};
-use message::{MessageType, Msg};
+use message::Msg;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
 
+use crate::bus::{Bus, Filter, Interceptor, QueueStats, Subscription};
+use crate::clock::{Clock, SystemClock};
+
+mod context;
+mod error;
+mod lifecycle;
+mod scheduler;
+mod serialsystem;
+mod supervisor;
 mod tcpsystem;
+#[cfg(unix)]
+mod unixsystem;
+mod worker;
+mod wssystem;
+
+pub use context::{Context, Publisher};
+pub use error::{BoxError, CenterError};
+pub use lifecycle::{Health, Registration, SubsystemState};
+pub use scheduler::{Schedule, ScheduleHandle, Scheduler, SchedulerHandle};
+pub use serialsystem::{Codec, CscCodec, DataBits, FlowControl, Parity, SerialConfig, SerialSystem, StopBits};
+pub use supervisor::RestartPolicy;
+pub use tcpsystem::{set_link_state, Backoff, FrameLines, TcpSystem};
+#[cfg(unix)]
+pub use unixsystem::UnixSystem;
+pub use wssystem::WsSystem;
+
+use context::Timers;
+use worker::{Supervisor, Worker};
+
+/// Names a registered subsystem, e.g. `"tcp"` or `"modbus/line1"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SystemId(String);
+
+impl SystemId {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
 
-pub enum SystemType {
-    
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
+impl fmt::Display for SystemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
-pub trait SubSystem {
-    type Msg;
-    fn exec(&mut self, msg:&mut Msg){
-        match msg.get_msg_type() {
-            _ => {}
-        }
+impl From<&str> for SystemId {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+impl From<String> for SystemId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+
+#[async_trait]
+pub trait SubSystem: Send {
+    /// Called once before [`start`](SubSystem::start) with the config given at registration.
+    async fn init(&mut self, _config: &serde_json::Value) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Called after every dependency has started.
+    async fn start(&mut self, _ctx: &mut Context) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Handles one message. Follow-up messages, replies and timers go through `ctx`.
+    ///
+    /// Returning an error or panicking restarts the subsystem under its
+    /// [`RestartPolicy`]; anything put in `ctx` by the failed call is discarded.
+    async fn exec(&mut self, msg: &Msg, ctx: &mut Context) -> Result<(), BoxError>;
+
+    /// Called before any of its dependencies stop.
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        Health::Healthy
     }
+
     fn rollup(&mut self);
 }
 
+#[derive(Debug)]
+enum Command {
+    Health(oneshot::Sender<HashMap<SystemId, Health>>),
+    Shutdown,
+}
+
+type States = Arc<RwLock<HashMap<SystemId, SubsystemState>>>;
+
+/// A cheap, cloneable handle for talking to a running [`CenterSubsystem`] from any task.
+#[derive(Debug, Clone)]
+pub struct CenterHandle {
+    sender: UnboundedSender<Command>,
+    states: States,
+    bus: Bus,
+}
+
+impl CenterHandle {
+    /// Publishes a message on the center's bus, waiting if a subscriber with
+    /// a blocking queue is full.
+    pub async fn publish(&self, msg: Msg) -> Result<(), CenterError> {
+        if self.sender.is_closed() {
+            return Err(CenterError::Stopped);
+        }
+        self.bus.publish(msg).await;
+        Ok(())
+    }
+
+    /// Queue depth and drop counters for every subscriber, labelled by subsystem id.
+    pub fn queue_stats(&self) -> Vec<QueueStats> {
+        self.bus.stats()
+    }
+
+    /// The center's bus, for subscribing from outside a subsystem.
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// Subscribes to messages on the center's bus, e.g. for a remote session.
+    pub fn subscribe<F>(&self, label: impl Into<String>, filters: impl IntoIterator<Item = F>) -> Subscription
+    where
+        F: Into<Filter>,
+    {
+        self.bus.subscribe(label, filters)
+    }
+
+    /// Lifecycle state of every registered subsystem.
+    pub fn states(&self) -> HashMap<SystemId, SubsystemState> {
+        self.states.read().unwrap().clone()
+    }
+
+    /// Asks every running subsystem for its health.
+    pub async fn health(&self) -> Result<HashMap<SystemId, Health>, CenterError> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::Health(sender))
+            .map_err(|_| CenterError::Stopped)?;
+        receiver.await.map_err(|_| CenterError::Stopped)
+    }
+
+    /// Asks the center to stop every subsystem and return from [`CenterSubsystem::run`].
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(Command::Shutdown);
+    }
+}
+
+/// Owns the bus and the registered subsystems. Each running subsystem gets its
+/// own task and inbox; messages are shared between them as `Arc<Msg>`.
+///
+/// A subsystem that fails is restarted on its own task under its
+/// [`RestartPolicy`]. Once it gives up it is marked [`SubsystemState::Failed`]
+/// and an [`AlarmMsg`](message::AlarmMsg) is published on `alarm/subsystem/{id}`.
 pub struct CenterSubsystem{
-    bus:Vec<Arc<Mutex<Msg>>>,
-    subsystems:HashMap<SystemType,(Box<dyn SubSystem<Msg = Msg>>, Vec<MessageType>)>,
+    bus:Bus,
+    subsystems:HashMap<SystemId, Registration>,
+    workers:HashMap<SystemId, Worker>,
+    /// 注册顺序，依赖无先后关系时按此顺序启动
+    registered:Vec<SystemId>,
+    /// 实际启动顺序，停止时倒序
+    started:Vec<SystemId>,
+    states:States,
+    sender:UnboundedSender<Command>,
+    receiver:UnboundedReceiver<Command>,
+    /// 重启退避和重启窗口按此计时
+    clock:Arc<dyn Clock>,
 }
 
 impl CenterSubsystem{
-    async fn dispatch(&mut self){
-        for msg in self.bus.drain(..) {
-            let msg = msg.clone();
-            for (system_type,(subsystem,msg_types)) in self.subsystems.iter_mut() {
-                let mut inner_msg = msg.lock().unwrap();
-                if msg_types.contains(&inner_msg.get_msg_type()) {
-                    subsystem.exec(inner_msg.deref_mut());
+    pub fn new() -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Self {
+            bus: Bus::new(),
+            subsystems: HashMap::new(),
+            workers: HashMap::new(),
+            registered: Vec::new(),
+            started: Vec::new(),
+            states: States::default(),
+            sender,
+            receiver,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Times restart backoffs and windows on `clock` instead of the wall clock.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Registers `subsystem` to receive every message matching one of `filters`,
+    /// which can be message types or topic filters.
+    /// Registering the same id twice replaces the earlier subsystem.
+    pub fn register<S, F>(&mut self, id: impl Into<SystemId>, subsystem: S, filters: impl IntoIterator<Item = F>) -> &mut Registration
+    where
+        S: SubSystem + 'static,
+        F: Into<Filter>,
+    {
+        let id = id.into();
+        if !self.subsystems.contains_key(&id) {
+            self.registered.push(id.clone());
+        }
+        self.set_state(&id, SubsystemState::Registered);
+        let filters = filters.into_iter().map(Into::into).collect();
+        let registration = Registration::new(Box::new(subsystem), filters);
+        self.subsystems.insert(id.clone(), registration);
+        self.subsystems.get_mut(&id).unwrap()
+    }
+
+    /// Adds an interceptor that sees every message published on the bus,
+    /// after the ones added before it. Set these up before [`start`](Self::start).
+    pub fn intercept(&mut self, interceptor: impl Interceptor + 'static) -> &mut Self {
+        self.bus.add_interceptor(interceptor);
+        self
+    }
+
+    /// Removes a subsystem. A running one is aborted and not handed back.
+    pub fn unregister(&mut self, id: &SystemId) -> Option<Box<dyn SubSystem>> {
+        self.registered.retain(|registered| registered != id);
+        self.started.retain(|started| started != id);
+        if let Some(worker) = self.workers.remove(id) {
+            worker.abort();
+        }
+        self.states.write().unwrap().remove(id);
+        self.subsystems.remove(id).and_then(|registration| registration.subsystem)
+    }
+
+    pub fn handle(&self) -> CenterHandle {
+        CenterHandle {
+            sender: self.sender.clone(),
+            states: self.states.clone(),
+            bus: self.bus.clone(),
+        }
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// Lifecycle state of every registered subsystem.
+    pub fn states(&self) -> HashMap<SystemId, SubsystemState> {
+        self.states.read().unwrap().clone()
+    }
+
+    /// Current health of every running subsystem.
+    // 取 &mut self：子系统不要求 Sync，&self 跨 await 会让 run() 的 future 不是 Send
+    pub async fn health(&mut self) -> HashMap<SystemId, Health> {
+        let mut health = HashMap::new();
+        for id in &self.started {
+            let report = match self.workers.get(id) {
+                Some(worker) => worker.health().await,
+                None => None,
+            };
+            let report = report.unwrap_or_else(|| Health::Unhealthy("task is not running".to_string()));
+            health.insert(id.clone(), report);
+        }
+        health
+    }
+
+    /// Publishes a message directly, without going through a [`CenterHandle`].
+    pub async fn publish(&mut self, msg: Msg) {
+        self.bus.publish(msg).await;
+    }
+
+    pub fn queue_stats(&self) -> Vec<QueueStats> {
+        self.bus.stats()
+    }
+
+    /// Initializes and starts every registered subsystem, dependencies first.
+    /// If one fails, everything started so far is stopped again.
+    pub async fn start(&mut self) -> Result<(), CenterError> {
+        let order = lifecycle::start_order(&self.registered, &self.subsystems)?;
+        for id in order {
+            if self.started.contains(&id) {
+                continue;
+            }
+            if let Err(e) = self.start_one(&id).await {
+                self.stop().await;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn start_one(&mut self, id: &SystemId) -> Result<(), CenterError> {
+        let registration = self.subsystems.get_mut(id).unwrap();
+        let Some(mut subsystem) = registration.subsystem.take() else {
+            let reason = "subsystem was lost when its task failed".to_string();
+            self.set_state(id, SubsystemState::Failed(reason.clone()));
+            return Err(CenterError::Start { id: id.clone(), reason });
+        };
+        let filters = registration.filters.clone();
+        let queue = registration.queue;
+        let restart = registration.restart;
+
+        if let Err(e) = subsystem.init(&registration.config).await {
+            registration.subsystem = Some(subsystem);
+            self.set_state(id, SubsystemState::Failed(e.to_string()));
+            return Err(CenterError::Init { id: id.clone(), reason: e.to_string() });
+        }
+        self.set_state(id, SubsystemState::Initialized);
+
+        // 先订阅再启动，启动期间别人发来的消息不会丢
+        let inbox = self.bus.subscribe_with(id.as_str(), filters, queue);
+        let timers = Timers::new(self.clock.clone());
+        let mut ctx = Context::new(id.clone(), self.bus.clone(), timers.clone());
+        if let Err(e) = subsystem.start(&mut ctx).await {
+            self.subsystems.get_mut(id).unwrap().subsystem = Some(subsystem);
+            self.set_state(id, SubsystemState::Failed(e.to_string()));
+            return Err(CenterError::Start { id: id.clone(), reason: e.to_string() });
+        }
+        ctx.flush().await;
+
+        let config = self.subsystems[id].config.clone();
+        let supervisor = Supervisor::new(
+            id.clone(),
+            self.bus.clone(),
+            self.states.clone(),
+            config,
+            restart,
+            self.clock.clone(),
+            timers,
+        );
+        let worker = Worker::spawn(subsystem, inbox, supervisor);
+        self.workers.insert(id.clone(), worker);
+        self.started.push(id.clone());
+        self.set_state(id, SubsystemState::Running);
+        Ok(())
+    }
+
+    /// Stops running subsystems in reverse start order.
+    pub async fn stop(&mut self) {
+        while let Some(id) = self.started.pop() {
+            // 已放弃重启的子系统保持 Failed 状态，不再调用 stop
+            let gave_up = matches!(self.states.read().unwrap().get(&id), Some(SubsystemState::Failed(_)));
+            if !gave_up {
+                self.set_state(&id, SubsystemState::Stopping);
+            }
+            let subsystem = match self.workers.remove(&id) {
+                Some(worker) => worker.stop().await,
+                None => Ok(self.subsystems.get_mut(&id).unwrap().subsystem.take().unwrap()),
+            };
+            let mut subsystem = match subsystem {
+                Ok(subsystem) => subsystem,
+                Err(e) => {
+                    eprintln!("Subsystem {} task failed: {}", id, e);
+                    self.set_state(&id, SubsystemState::Failed(e.to_string()));
+                    continue;
+                }
+            };
+            if gave_up {
+                subsystem.rollup();
+                self.subsystems.get_mut(&id).unwrap().subsystem = Some(subsystem);
+                continue;
+            }
+            let state = match subsystem.stop().await {
+                Ok(()) => SubsystemState::Stopped,
+                Err(e) => {
+                    eprintln!("Failed to stop {}: {}", id, e);
+                    SubsystemState::Failed(e.to_string())
+                }
+            };
+            subsystem.rollup();
+            self.subsystems.get_mut(&id).unwrap().subsystem = Some(subsystem);
+            self.set_state(&id, state);
+        }
+    }
+
+    /// Starts every subsystem and keeps them running until [`CenterHandle::shutdown`]
+    /// is called, then stops them in reverse order.
+    pub async fn run(&mut self) -> Result<(), CenterError> {
+        self.start().await?;
+        while let Some(command) = self.receiver.recv().await {
+            match command {
+                Command::Health(reply) => {
+                    let _ = reply.send(self.health().await);
                 }
+                Command::Shutdown => break,
             }
         }
+        self.stop().await;
+        Ok(())
+    }
+
+    fn set_state(&self, id: &SystemId, state: SubsystemState) {
+        self.states.write().unwrap().insert(id.clone(), state);
     }
 }
 
+impl Default for CenterSubsystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
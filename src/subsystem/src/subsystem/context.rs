@@ -0,0 +1,151 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use message::Msg;
+use tokio::task::AbortHandle;
+
+use crate::bus::Bus;
+use crate::clock::Clock;
+
+use super::{BoxError, SystemId};
+
+/// Handed to [`SubSystem::exec`](super::SubSystem::exec) so a subsystem can emit follow-up messages.
+/// Everything collected here is routed back onto the bus once `exec` returns.
+#[derive(Debug)]
+pub struct Context {
+    source: SystemId,
+    bus: Bus,
+    outbox: Vec<Msg>,
+    scheduled: Vec<(Duration, Msg)>,
+    timers: Timers,
+}
+
+impl Context {
+    pub(crate) fn new(source: SystemId, bus: Bus, timers: Timers) -> Self {
+        Self {
+            source,
+            bus,
+            outbox: Vec::new(),
+            scheduled: Vec::new(),
+            timers,
+        }
+    }
+
+    /// The subsystem this context belongs to.
+    pub fn source(&self) -> &SystemId {
+        &self.source
+    }
+
+    /// A handle that publishes as this subsystem from a background task,
+    /// e.g. a connection task started in [`SubSystem::start`](super::SubSystem::start).
+    pub fn publisher(&self) -> Publisher {
+        Publisher {
+            source: self.source.clone(),
+            bus: self.bus.clone(),
+        }
+    }
+
+    pub fn publish(&mut self, msg: Msg) {
+        self.outbox.push(msg);
+    }
+
+    /// Sends `msg` back to whoever published `request`. Fails without
+    /// sending anything if `request` doesn't say who that was, rather than
+    /// broadcasting the reply.
+    pub fn reply(&mut self, request: &Msg, mut msg: Msg) -> Result<(), BoxError> {
+        let Some(source) = request.info.source.clone() else {
+            return Err(format!("request {} has no source to reply to", request.get_uid()).into());
+        };
+        msg.info.target = Some(source);
+        msg.info.reply_to = Some(request.get_uid());
+        self.outbox.push(msg);
+        Ok(())
+    }
+
+    /// Publishes `msg` after `delay` on the center's clock, unless the
+    /// subsystem stops or restarts first.
+    pub fn schedule(&mut self, delay: Duration, msg: Msg) {
+        self.scheduled.push((delay, msg));
+    }
+
+    /// Puts everything emitted back onto the bus, stamped with this subsystem as the source.
+    pub(crate) async fn flush(self) {
+        let source = self.source.to_string();
+        for mut msg in self.outbox {
+            msg.info.source.get_or_insert_with(|| source.clone());
+            self.bus.publish(msg).await;
+        }
+        for (delay, mut msg) in self.scheduled {
+            msg.info.source.get_or_insert_with(|| source.clone());
+            self.timers.spawn(delay, self.bus.clone(), msg);
+        }
+    }
+}
+
+/// The messages one subsystem scheduled and that are still waiting.
+#[derive(Clone)]
+pub(crate) struct Timers {
+    clock: Arc<dyn Clock>,
+    pending: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+impl Timers {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            pending: Arc::default(),
+        }
+    }
+
+    fn spawn(&self, delay: Duration, bus: Bus, msg: Msg) {
+        let clock = self.clock.clone();
+        let task = tokio::spawn(async move {
+            clock.sleep(delay).await;
+            bus.publish(msg).await;
+        });
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|task| !task.is_finished());
+        pending.push(task.abort_handle());
+    }
+
+    /// Drops every message still waiting.
+    pub(crate) fn cancel(&self) {
+        for task in self.pending.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+impl fmt::Debug for Timers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timers")
+            .field("pending", &self.pending.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Publishes onto the bus as one subsystem, from any task.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    source: SystemId,
+    bus: Bus,
+}
+
+impl Publisher {
+    pub fn source(&self) -> &SystemId {
+        &self.source
+    }
+
+    /// Publishes `msg`, stamped with this subsystem as the source unless it already has one.
+    pub async fn publish(&self, mut msg: Msg) -> usize {
+        msg.info.source.get_or_insert_with(|| self.source.to_string());
+        self.bus.publish(msg).await
+    }
+
+    /// Like [`publish`](Self::publish) but never waits on a full queue.
+    pub fn try_publish(&self, mut msg: Msg) -> usize {
+        msg.info.source.get_or_insert_with(|| self.source.to_string());
+        self.bus.try_publish(msg)
+    }
+}
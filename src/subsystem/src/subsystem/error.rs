@@ -0,0 +1,35 @@
+use std::{error::Error, fmt};
+
+use super::SystemId;
+
+/// Error type returned by subsystem hooks.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CenterError {
+    /// The center is no longer running.
+    Stopped,
+    UnknownDependency { id: SystemId, dependency: SystemId },
+    DependencyCycle(Vec<SystemId>),
+    Init { id: SystemId, reason: String },
+    Start { id: SystemId, reason: String },
+}
+
+impl fmt::Display for CenterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CenterError::Stopped => write!(f, "center has stopped"),
+            CenterError::UnknownDependency { id, dependency } => {
+                write!(f, "{} depends on unregistered subsystem {}", id, dependency)
+            }
+            CenterError::DependencyCycle(ids) => {
+                let ids: Vec<&str> = ids.iter().map(SystemId::as_str).collect();
+                write!(f, "dependency cycle between {}", ids.join(", "))
+            }
+            CenterError::Init { id, reason } => write!(f, "failed to init {}: {}", id, reason),
+            CenterError::Start { id, reason } => write!(f, "failed to start {}: {}", id, reason),
+        }
+    }
+}
+
+impl Error for CenterError {}
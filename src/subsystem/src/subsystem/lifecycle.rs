@@ -0,0 +1,282 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::bus::{Filter, QueueConfig};
+
+use super::{CenterError, RestartPolicy, SubSystem, SystemId};
+
+/// What a subsystem reports from [`SubSystem::health`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Health {
+    #[default]
+    Healthy,
+    Degraded(String),
+    Unhealthy(String),
+}
+
+/// Where a registered subsystem is in its lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SubsystemState {
+    #[default]
+    Registered,
+    Initialized,
+    Running,
+    /// Failed and waiting out its restart backoff.
+    Restarting,
+    Stopping,
+    Stopped,
+    Failed(String),
+}
+
+impl fmt::Display for SubsystemState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubsystemState::Registered => write!(f, "registered"),
+            SubsystemState::Initialized => write!(f, "initialized"),
+            SubsystemState::Running => write!(f, "running"),
+            SubsystemState::Restarting => write!(f, "restarting"),
+            SubsystemState::Stopping => write!(f, "stopping"),
+            SubsystemState::Stopped => write!(f, "stopped"),
+            SubsystemState::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// A subsystem plus everything the center needs to run it.
+pub struct Registration {
+    /// 运行期间由其任务持有
+    pub(crate) subsystem: Option<Box<dyn SubSystem>>,
+    pub(crate) filters: Vec<Filter>,
+    pub(crate) depends_on: Vec<SystemId>,
+    pub(crate) config: serde_json::Value,
+    pub(crate) queue: QueueConfig,
+    pub(crate) restart: RestartPolicy,
+}
+
+impl Registration {
+    pub(crate) fn new(subsystem: Box<dyn SubSystem>, filters: Vec<Filter>) -> Self {
+        Self {
+            subsystem: Some(subsystem),
+            filters,
+            depends_on: Vec::new(),
+            config: serde_json::Value::Null,
+            queue: QueueConfig::default(),
+            restart: RestartPolicy::default(),
+        }
+    }
+
+    /// `id` is started before this subsystem and stopped after it.
+    pub fn depends_on(&mut self, id: impl Into<SystemId>) -> &mut Self {
+        self.depends_on.push(id.into());
+        self
+    }
+
+    /// Bounds this subsystem's inbox and picks what happens when it is full.
+    pub fn queue(&mut self, queue: QueueConfig) -> &mut Self {
+        self.queue = queue;
+        self
+    }
+
+    /// How often and how fast the subsystem is restarted after a failure.
+    pub fn restart(&mut self, policy: RestartPolicy) -> &mut Self {
+        self.restart = policy;
+        self
+    }
+
+    /// Passed to [`SubSystem::init`].
+    pub fn config(&mut self, config: serde_json::Value) -> &mut Self {
+        self.config = config;
+        self
+    }
+}
+
+/// Orders `ids` so every subsystem comes after its dependencies.
+/// Ties keep registration order so startup is deterministic.
+pub(crate) fn start_order(
+    ids: &[SystemId],
+    registrations: &HashMap<SystemId, Registration>,
+) -> Result<Vec<SystemId>, CenterError> {
+    for id in ids {
+        for dependency in &registrations[id].depends_on {
+            if !registrations.contains_key(dependency) {
+                return Err(CenterError::UnknownDependency {
+                    id: id.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+    }
+
+    let mut order = Vec::with_capacity(ids.len());
+    let mut started = HashSet::new();
+    while order.len() < ids.len() {
+        let next = ids.iter().find(|id| {
+            !started.contains(*id)
+                && registrations[*id]
+                    .depends_on
+                    .iter()
+                    .all(|dependency| started.contains(dependency))
+        });
+        match next {
+            Some(id) => {
+                started.insert(id.clone());
+                order.push(id.clone());
+            }
+            None => {
+                let remaining = ids
+                    .iter()
+                    .filter(|id| !started.contains(*id))
+                    .cloned()
+                    .collect();
+                return Err(CenterError::DependencyCycle(remaining));
+            }
+        }
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use message::{MessageType, Msg};
+
+    use super::super::{BoxError, CenterSubsystem, Context};
+    use super::*;
+
+    /// Logs `start:<id>` and `stop:<id>` so tests can check the order.
+    struct Logged {
+        id: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        fail_start: bool,
+    }
+
+    #[async_trait]
+    impl SubSystem for Logged {
+        async fn start(&mut self, _ctx: &mut Context) -> Result<(), BoxError> {
+            if self.fail_start {
+                return Err("no".into());
+            }
+            self.log.lock().unwrap().push(format!("start:{}", self.id));
+            Ok(())
+        }
+
+        async fn exec(&mut self, _msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+            Ok(())
+        }
+
+        async fn stop(&mut self) -> Result<(), BoxError> {
+            self.log.lock().unwrap().push(format!("stop:{}", self.id));
+            Ok(())
+        }
+
+        fn rollup(&mut self) {}
+    }
+
+    fn ids(ids: &[&str]) -> Vec<SystemId> {
+        ids.iter().map(|id| SystemId::from(*id)).collect()
+    }
+
+    /// Registrations for `(id, dependencies)` pairs, in the order given.
+    fn registrations(graph: &[(&'static str, &[&str])]) -> (Vec<SystemId>, HashMap<SystemId, Registration>) {
+        let log = Arc::default();
+        let mut registrations = HashMap::new();
+        for (id, dependencies) in graph {
+            let logged = Logged { id, log: Arc::clone(&log), fail_start: false };
+            let mut registration = Registration::new(Box::new(logged), Vec::new());
+            for dependency in *dependencies {
+                registration.depends_on(*dependency);
+            }
+            registrations.insert(SystemId::from(*id), registration);
+        }
+        let order = graph.iter().map(|(id, _)| SystemId::from(*id)).collect();
+        (order, registrations)
+    }
+
+    #[test]
+    fn dependencies_start_first() {
+        let (order, registrations) =
+            registrations(&[("api", &["db", "bus"]), ("db", &["disk"]), ("bus", &[]), ("disk", &[])]);
+        let started = start_order(&order, &registrations).unwrap();
+        assert_eq!(started, ids(&["bus", "disk", "db", "api"]));
+    }
+
+    #[test]
+    fn independent_subsystems_keep_registration_order() {
+        let (order, registrations) = registrations(&[("c", &[]), ("a", &[]), ("b", &[])]);
+        assert_eq!(start_order(&order, &registrations).unwrap(), ids(&["c", "a", "b"]));
+    }
+
+    #[test]
+    fn cycles_are_reported_with_their_members() {
+        let (order, registrations) =
+            registrations(&[("root", &[]), ("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("leaf", &["a"])]);
+        let error = start_order(&order, &registrations).unwrap_err();
+        // 卡在环上的以及依赖环的都列出来
+        assert_eq!(error, CenterError::DependencyCycle(ids(&["a", "b", "c", "leaf"])));
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let (order, registrations) = registrations(&[("a", &["a"])]);
+        assert_eq!(start_order(&order, &registrations), Err(CenterError::DependencyCycle(ids(&["a"]))));
+    }
+
+    #[test]
+    fn missing_dependencies_are_named() {
+        let (order, registrations) = registrations(&[("a", &[]), ("b", &["a", "ghost"])]);
+        assert_eq!(
+            start_order(&order, &registrations),
+            Err(CenterError::UnknownDependency { id: SystemId::from("b"), dependency: SystemId::from("ghost") })
+        );
+    }
+
+    fn center(log: &Arc<Mutex<Vec<String>>>, graph: &[(&'static str, &[&str], bool)]) -> CenterSubsystem {
+        let mut center = CenterSubsystem::new();
+        for (id, dependencies, fail_start) in graph {
+            let logged = Logged { id, log: Arc::clone(log), fail_start: *fail_start };
+            let registration = center.register(*id, logged, [MessageType::Move]);
+            for dependency in *dependencies {
+                registration.depends_on(*dependency);
+            }
+        }
+        center
+    }
+
+    #[tokio::test]
+    async fn stops_in_reverse_start_order() {
+        let log = Arc::default();
+        let mut center = center(&log, &[("api", &["db"], false), ("db", &[], false), ("cache", &["db"], false)]);
+
+        center.start().await.unwrap();
+        assert!(center.states().values().all(|state| *state == SubsystemState::Running));
+        center.stop().await;
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["start:db", "start:api", "start:cache", "stop:cache", "stop:api", "stop:db"]
+        );
+        assert!(center.states().values().all(|state| *state == SubsystemState::Stopped));
+    }
+
+    #[tokio::test]
+    async fn a_failed_start_stops_what_already_started() {
+        let log = Arc::default();
+        let mut center = center(&log, &[("db", &[], false), ("cache", &["db"], false), ("api", &["cache"], true)]);
+
+        let error = center.start().await.unwrap_err();
+        assert_eq!(error, CenterError::Start { id: SystemId::from("api"), reason: "no".to_string() });
+        assert_eq!(*log.lock().unwrap(), ["start:db", "start:cache", "stop:cache", "stop:db"]);
+    }
+
+    #[tokio::test]
+    async fn nothing_starts_when_the_graph_is_invalid() {
+        let log = Arc::default();
+        let mut center = center(&log, &[("a", &["b"], false), ("b", &["a"], false)]);
+
+        assert_eq!(center.start().await, Err(CenterError::DependencyCycle(ids(&["a", "b"]))));
+        assert!(log.lock().unwrap().is_empty());
+        assert!(center.states().values().all(|state| *state == SubsystemState::Registered));
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use message::{MessageType, Msg, ScheduleKind, ScheduleMsg, UnscheduleMsg};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
+};
+
+use crate::clock::{Clock, SystemClock};
+
+use super::{BoxError, CenterHandle, Context, Health, SubSystem};
+
+/// When a scheduled message is published.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Once(Duration),
+    /// The first time one period from now.
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// Parses a cron expression with seconds, e.g. `0 */5 * * * *`. Times are UTC.
+    pub fn cron(expr: &str) -> Result<Self, BoxError> {
+        Ok(Schedule::Cron(Box::new(cron::Schedule::from_str(expr)?)))
+    }
+
+    fn first(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Once(delay) | Schedule::Every(delay) => Some(now + *delay),
+            Schedule::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+
+    /// Next deadline after one at `fired`, skipping any already missed by `now`.
+    fn next(&self, fired: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let next = match self {
+            Schedule::Once(_) => return None,
+            Schedule::Every(period) => fired + *period,
+            Schedule::Cron(schedule) => schedule.after(&fired).next()?,
+        };
+        // 落后太多时不补发，从现在重新开始计
+        if next > now {
+            Some(next)
+        } else {
+            self.first(now)
+        }
+    }
+}
+
+impl TryFrom<&ScheduleKind> for Schedule {
+    type Error = BoxError;
+
+    fn try_from(kind: &ScheduleKind) -> Result<Self, Self::Error> {
+        match kind {
+            ScheduleKind::Once { delay_ms } => Ok(Schedule::Once(Duration::from_millis(*delay_ms))),
+            ScheduleKind::Every { period_ms: 0 } => Err("period must be greater than zero".into()),
+            ScheduleKind::Every { period_ms } => Ok(Schedule::Every(Duration::from_millis(*period_ms))),
+            ScheduleKind::Cron { expr } => Schedule::cron(expr),
+        }
+    }
+}
+
+enum Request {
+    Add {
+        id: u64,
+        name: Option<String>,
+        schedule: Schedule,
+        msg: Msg,
+    },
+    Cancel(u64),
+    CancelNamed(String),
+}
+
+/// Schedules messages on a running or not yet started [`Scheduler`] from any task.
+#[derive(Debug, Clone)]
+pub struct SchedulerHandle {
+    sender: UnboundedSender<Request>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl SchedulerHandle {
+    /// Publishes `msg` once after `delay`.
+    pub fn once(&self, delay: Duration, msg: Msg) -> ScheduleHandle {
+        self.schedule(Schedule::Once(delay), msg)
+    }
+
+    /// Publishes `msg` every `period`. A zero period is treated as one millisecond.
+    pub fn every(&self, period: Duration, msg: Msg) -> ScheduleHandle {
+        self.schedule(Schedule::Every(period.max(Duration::from_millis(1))), msg)
+    }
+
+    pub fn cron(&self, expr: &str, msg: Msg) -> Result<ScheduleHandle, BoxError> {
+        Ok(self.schedule(Schedule::cron(expr)?, msg))
+    }
+
+    pub fn schedule(&self, schedule: Schedule, msg: Msg) -> ScheduleHandle {
+        self.add(None, schedule, msg)
+    }
+
+    fn add(&self, name: Option<String>, schedule: Schedule, msg: Msg) -> ScheduleHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self.sender.send(Request::Add { id, name, schedule, msg });
+        ScheduleHandle {
+            id,
+            sender: self.sender.clone(),
+        }
+    }
+
+    fn cancel_named(&self, name: String) {
+        let _ = self.sender.send(Request::CancelNamed(name));
+    }
+}
+
+/// Returned for every schedule. Dropping it does not cancel the schedule.
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+    id: u64,
+    sender: UnboundedSender<Request>,
+}
+
+impl ScheduleHandle {
+    pub fn cancel(&self) {
+        let _ = self.sender.send(Request::Cancel(self.id));
+    }
+}
+
+struct Entry {
+    name: Option<String>,
+    schedule: Schedule,
+    msg: Msg,
+    deadline: DateTime<Utc>,
+}
+
+/// 定时任务表，停止时从任务中交还，重启后继续
+struct Timers {
+    requests: UnboundedReceiver<Request>,
+    entries: HashMap<u64, Entry>,
+    names: HashMap<String, u64>,
+}
+
+impl Timers {
+    fn apply(&mut self, request: Request, now: DateTime<Utc>) {
+        match request {
+            Request::Add { id, name, schedule, msg } => {
+                if let Some(name) = &name {
+                    if let Some(previous) = self.names.insert(name.clone(), id) {
+                        self.entries.remove(&previous);
+                    }
+                }
+                let Some(deadline) = schedule.first(now) else {
+                    eprintln!("Schedule {:?} never fires", name);
+                    return;
+                };
+                self.entries.insert(id, Entry { name, schedule, msg, deadline });
+            }
+            Request::Cancel(id) => self.remove(id),
+            Request::CancelNamed(name) => {
+                if let Some(id) = self.names.get(&name).copied() {
+                    self.remove(id);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(entry) = self.entries.remove(&id) {
+            if let Some(name) = entry.name {
+                self.names.remove(&name);
+            }
+        }
+    }
+
+    fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.entries.values().map(|entry| entry.deadline).min()
+    }
+
+    /// Takes every message due at `now`, earliest first, and reschedules repeating ones.
+    fn due(&mut self, now: DateTime<Utc>) -> Vec<Msg> {
+        let mut due: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.deadline <= now)
+            .map(|(id, entry)| (entry.deadline, *id))
+            .collect();
+        due.sort();
+
+        let mut msgs = Vec::with_capacity(due.len());
+        for (deadline, id) in due {
+            let entry = self.entries.get_mut(&id).unwrap();
+            let mut msg = entry.msg.clone();
+            msg.info.uid = uuid::Uuid::new_v4();
+            msgs.push(msg);
+            match entry.schedule.next(deadline, now) {
+                Some(next) => entry.deadline = next,
+                None => self.remove(id),
+            }
+        }
+        msgs
+    }
+}
+
+/// Publishes messages after a delay, periodically or on a cron schedule.
+///
+/// Schedules come from a [`SchedulerHandle`] or as messages: register it for
+/// `MessageType::Schedule` and `MessageType::Unschedule` to accept
+/// [`ScheduleMsg`] and [`UnscheduleMsg`].
+pub struct Scheduler {
+    center: CenterHandle,
+    clock: Arc<dyn Clock>,
+    handle: SchedulerHandle,
+    timers: Option<Timers>,
+    running: Option<(oneshot::Sender<()>, JoinHandle<Timers>)>,
+}
+
+impl Scheduler {
+    pub fn new(center: CenterHandle) -> Self {
+        Self::with_clock(center, SystemClock)
+    }
+
+    pub fn with_clock(center: CenterHandle, clock: impl Clock + 'static) -> Self {
+        let (sender, requests) = unbounded_channel();
+        Self {
+            center,
+            clock: Arc::new(clock),
+            handle: SchedulerHandle {
+                sender,
+                next_id: Arc::new(AtomicU64::new(0)),
+            },
+            timers: Some(Timers {
+                requests,
+                entries: HashMap::new(),
+                names: HashMap::new(),
+            }),
+            running: None,
+        }
+    }
+
+    pub fn handle(&self) -> SchedulerHandle {
+        self.handle.clone()
+    }
+}
+
+#[async_trait]
+impl SubSystem for Scheduler {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let timers = self.timers.take().ok_or("timers were lost when the scheduler task failed")?;
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(run(
+            timers,
+            self.clock.clone(),
+            self.center.clone(),
+            ctx.source().to_string(),
+            stopped,
+        ));
+        self.running = Some((stop, task));
+        Ok(())
+    }
+
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        match msg.get_msg_type() {
+            MessageType::Schedule => {
+                let Some(request) = msg.get_data::<ScheduleMsg>() else {
+                    eprintln!("Malformed schedule request");
+                    return Ok(());
+                };
+                match Schedule::try_from(&request.kind) {
+                    Ok(schedule) => {
+                        self.handle.add(Some(request.name), schedule, request.msg);
+                    }
+                    Err(e) => eprintln!("Rejected schedule {}: {}", request.name, e),
+                }
+            }
+            MessageType::Unschedule => {
+                if let Some(request) = msg.get_data::<UnscheduleMsg>() {
+                    self.handle.cancel_named(request.name);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.running.take() {
+            let _ = stop.send(());
+            self.timers = Some(task.await?);
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &self.running {
+            Some((_, task)) if task.is_finished() => Health::Unhealthy("timer task stopped".to_string()),
+            _ => Health::Healthy,
+        }
+    }
+
+    fn rollup(&mut self) {}
+}
+
+async fn run(
+    mut timers: Timers,
+    clock: Arc<dyn Clock>,
+    center: CenterHandle,
+    source: String,
+    mut stop: oneshot::Receiver<()>,
+) -> Timers {
+    loop {
+        let next = timers.next_deadline();
+        tokio::select! {
+            biased;
+            _ = &mut stop => break,
+            request = timers.requests.recv() => match request {
+                Some(request) => timers.apply(request, clock.now()),
+                None => break,
+            },
+            _ = sleep_until(&*clock, next) => {
+                for mut msg in timers.due(clock.now()) {
+                    msg.info.source.get_or_insert_with(|| source.clone());
+                    if center.publish(msg).await.is_err() {
+                        return timers;
+                    }
+                }
+            }
+        }
+    }
+    timers
+}
+
+async fn sleep_until(clock: &dyn Clock, deadline: Option<DateTime<Utc>>) {
+    match deadline {
+        Some(deadline) => clock.sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,393 @@
+use message::{decode_frame, encode_frame, LinkState, MessageType, Msg, FRAME_DELIMITER, MAX_FRAME_LEN};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf},
+    sync::oneshot,
+    task::JoinHandle,
+    time::Duration,
+};
+use tokio_serial::{SerialPortBuilder, SerialStream};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+pub use tokio_serial::{DataBits, FlowControl, Parity, StopBits};
+
+use crate::bus::{QueueConfig, QueuePolicy, QueueStats, SubscriberQueue};
+use crate::clock::{Clock, SystemClock};
+
+use super::tcpsystem::{set_link_state, Backoff};
+use super::{BoxError, Context, Health, Publisher, SubSystem};
+
+/// Which port to open and how to set up the line. Defaults to 8N1 without
+/// flow control, also when read from a config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerialConfig {
+    /// Device path, e.g. `/dev/ttyUSB0` or a stable `/dev/serial/by-id/...` link.
+    pub path: String,
+    pub baud_rate: u32,
+    #[serde(with = "DataBitsDef", default = "eight_bits")]
+    pub data_bits: DataBits,
+    #[serde(with = "ParityDef", default = "no_parity")]
+    pub parity: Parity,
+    #[serde(with = "StopBitsDef", default = "one_stop_bit")]
+    pub stop_bits: StopBits,
+    #[serde(with = "FlowControlDef", default = "no_flow_control")]
+    pub flow_control: FlowControl,
+}
+
+impl SerialConfig {
+    pub fn new(path: impl Into<String>, baud_rate: u32) -> Self {
+        Self {
+            path: path.into(),
+            baud_rate,
+            data_bits: eight_bits(),
+            parity: no_parity(),
+            stop_bits: one_stop_bit(),
+            flow_control: no_flow_control(),
+        }
+    }
+
+    pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    pub fn builder(&self) -> SerialPortBuilder {
+        tokio_serial::new(&self.path, self.baud_rate)
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .flow_control(self.flow_control)
+    }
+
+    /// Opens the port. Needs a running tokio runtime.
+    pub fn open(&self) -> io::Result<SerialStream> {
+        Ok(SerialStream::open(&self.builder())?)
+    }
+}
+
+// tokio-serial 的枚举没有实现 serde，这里按同名变体映射
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "DataBits")]
+enum DataBitsDef {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Parity")]
+enum ParityDef {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "StopBits")]
+enum StopBitsDef {
+    One,
+    Two,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "FlowControl")]
+enum FlowControlDef {
+    None,
+    Software,
+    Hardware,
+}
+
+fn eight_bits() -> DataBits {
+    DataBits::Eight
+}
+
+fn no_parity() -> Parity {
+    Parity::None
+}
+
+fn one_stop_bit() -> StopBits {
+    StopBits::One
+}
+
+fn no_flow_control() -> FlowControl {
+    FlowControl::None
+}
+
+/// Turns messages into bytes on the wire and back, so a [`SerialSystem`] can
+/// speak CSC frames or a device's own protocol.
+pub trait Codec: Send + 'static {
+    /// Bytes to write for `msg`. An error drops the message.
+    fn encode(&mut self, msg: &Msg) -> Result<Vec<u8>, BoxError>;
+
+    /// Takes the next complete frame off the front of `buf`, or returns `None`
+    /// until more bytes arrive. On error the bad bytes must have been consumed.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Msg>, BoxError>;
+}
+
+/// CSC messages as newline-delimited JSON, the same frames as over TCP.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CscCodec;
+
+impl Codec for CscCodec {
+    fn encode(&mut self, msg: &Msg) -> Result<Vec<u8>, BoxError> {
+        Ok(encode_frame(msg)?)
+    }
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Msg>, BoxError> {
+        loop {
+            let Some(end) = buf.iter().position(|b| *b == FRAME_DELIMITER) else {
+                if buf.len() > MAX_FRAME_LEN {
+                    buf.clear();
+                    return Err(format!("frame longer than {} bytes", MAX_FRAME_LEN).into());
+                }
+                return Ok(None);
+            };
+            let frame: Vec<u8> = buf.drain(..=end).collect();
+            // 串口上常见的 \r\n 和空行
+            if frame.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            return Ok(Some(decode_frame(&frame)?));
+        }
+    }
+}
+
+/// A serial port that stays open.
+///
+/// Every message routed to it on the bus is written to the port through its
+/// [`Codec`], CSC frames by default. While the port is closed they wait in a
+/// bounded buffer. Whatever the codec decodes from the port is published as
+/// coming from this subsystem. When the device goes away, e.g. a USB adapter
+/// is unplugged, the port is reopened with backoff until it comes back. Every
+/// change of link state is published as a [`ConnectionMsg`](message::ConnectionMsg)
+/// on `connection/{id}`.
+pub struct SerialSystem {
+    config: SerialConfig,
+    backoff: Backoff,
+    clock: Arc<dyn Clock>,
+    codec: Option<Box<dyn Codec>>,
+    outbound: Arc<SubscriberQueue>,
+    state: Arc<Mutex<LinkState>>,
+    link: Option<PortTask>,
+}
+
+impl SerialSystem {
+    pub fn new(config: SerialConfig) -> Self {
+        let outbound = Self::outbound(&config, QueueConfig::new(1024, QueuePolicy::DropOldest));
+        SerialSystem {
+            config,
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(10)),
+            clock: Arc::new(SystemClock),
+            codec: Some(Box::new(CscCodec)),
+            outbound,
+            state: Arc::new(Mutex::new(LinkState::Connecting)),
+            link: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Delay between attempts to reopen the port. Defaults to 1 s doubling up to 10 s.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn codec(mut self, codec: impl Codec) -> Self {
+        self.codec = Some(Box::new(codec));
+        self
+    }
+
+    /// Bounds the outbound buffer. Defaults to 1024 messages, dropping the oldest.
+    pub fn buffer(mut self, config: QueueConfig) -> Self {
+        self.outbound = Self::outbound(&self.config, config);
+        self
+    }
+
+    /// Depth and drop count of the outbound buffer.
+    pub fn buffer_stats(&self) -> QueueStats {
+        self.outbound.stats()
+    }
+
+    fn outbound(config: &SerialConfig, queue: QueueConfig) -> Arc<SubscriberQueue> {
+        Arc::new(SubscriberQueue::new(format!("serial/{}", config.path), queue))
+    }
+}
+
+/// Stops the port task, which hands the codec back.
+type PortTask = (oneshot::Sender<()>, JoinHandle<Box<dyn Codec>>);
+
+/// The port task.
+struct Link {
+    config: SerialConfig,
+    backoff: Backoff,
+    clock: Arc<dyn Clock>,
+    outbound: Arc<SubscriberQueue>,
+    state: Arc<Mutex<LinkState>>,
+    publisher: Publisher,
+}
+
+impl Link {
+    async fn run(self, mut codec: Box<dyn Codec>, mut stop: oneshot::Receiver<()>) -> Box<dyn Codec> {
+        let mut attempt = 0;
+        loop {
+            let reason = match self.config.open() {
+                Ok(port) => {
+                    attempt = 0;
+                    self.set_state(LinkState::Connected, None).await;
+                    let result = tokio::select! {
+                        _ = &mut stop => break,
+                        result = self.session(port, &mut *codec) => result,
+                    };
+                    let reason = match result {
+                        Ok(()) => "port closed".to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    eprintln!("Serial port {} lost: {}", self.config.path, reason);
+                    self.set_state(LinkState::Disconnected, Some(reason)).await;
+                    // 设备可能已经插回，先立即重开一次
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            };
+
+            eprintln!("Failed to open serial port {}: {}", self.config.path, reason);
+            self.set_state(LinkState::Disconnected, Some(reason)).await;
+            let delay = self.backoff.delay(attempt);
+            attempt = attempt.saturating_add(1);
+            tokio::select! {
+                _ = &mut stop => break,
+                _ = self.clock.sleep(delay) => {}
+            }
+        }
+        *self.state.lock().unwrap() = LinkState::Disconnected;
+        codec
+    }
+
+    async fn set_state(&self, state: LinkState, reason: Option<String>) {
+        set_link_state(&self.state, &self.publisher, self.config.path.clone(), state, reason).await;
+    }
+
+    /// Runs until the port reports end of file or a read or write fails, which
+    /// is how an unplugged device shows up.
+    async fn session<P>(&self, port: P, codec: &mut dyn Codec) -> io::Result<()>
+    where
+        P: AsyncRead + AsyncWrite,
+    {
+        let (mut reader, mut writer) = tokio::io::split(port);
+        let mut buf = Vec::new();
+        loop {
+            while let Some(msg) = self.outbound.pop() {
+                let bytes = match codec.encode(&msg) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        eprintln!("Failed to encode message for {}: {}", self.config.path, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = write_bytes(&mut writer, &bytes).await {
+                    // 没写出去的消息放回缓冲区，重开后再发
+                    let _ = self.outbound.try_push(msg);
+                    return Err(e);
+                }
+            }
+            tokio::select! {
+                _ = self.outbound.ready() => {}
+                read = reader.read_buf(&mut buf) => {
+                    if read? == 0 {
+                        return Ok(());
+                    }
+                    self.deliver(&mut buf, codec).await;
+                }
+            }
+        }
+    }
+
+    async fn deliver(&self, buf: &mut Vec<u8>, codec: &mut dyn Codec) {
+        loop {
+            let mut msg = match codec.decode(buf) {
+                Ok(Some(msg)) => msg,
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("Failed to decode frame from {}: {}", self.config.path, e);
+                    continue;
+                }
+            };
+            if msg.get_msg_type() == MessageType::Heartbeat {
+                continue;
+            }
+            msg.info.source = Some(self.publisher.source().to_string());
+            self.publisher.publish(msg).await;
+        }
+    }
+}
+
+async fn write_bytes<P: AsyncWrite>(writer: &mut WriteHalf<P>, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(bytes).await?;
+    writer.flush().await
+}
+
+#[async_trait]
+impl SubSystem for SerialSystem {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let codec = self.codec.take().ok_or("codec was lost when the port task failed")?;
+        let link = Link {
+            config: self.config.clone(),
+            backoff: self.backoff,
+            clock: self.clock.clone(),
+            outbound: self.outbound.clone(),
+            state: self.state.clone(),
+            publisher: ctx.publisher(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.link = Some((stop, tokio::spawn(link.run(codec, stopped))));
+        Ok(())
+    }
+
+    /// Queues `msg` for the device.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.outbound.push(Arc::new(msg.clone())).await;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.link.take() {
+            let _ = stop.send(());
+            self.codec = Some(task.await?);
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &*self.state.lock().unwrap() {
+            LinkState::Connected => Health::Healthy,
+            _ => Health::Degraded(format!("serial port {} not open", self.config.path)),
+        }
+    }
+
+    fn rollup(&mut self) {
+        while self.outbound.pop().is_some() {}
+    }
+}
@@ -0,0 +1,123 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use tokio::time::Duration;
+
+use super::BoxError;
+
+/// How the center restarts a subsystem whose `exec` panicked or returned an error.
+///
+/// Restarts are one-for-one: only the failed subsystem is restarted, its
+/// dependencies and dependents keep running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Give up after this many restarts within `window`.
+    pub max_restarts: u32,
+    pub window: Duration,
+    /// Wait before the first restart, doubled for every further restart in the window.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RestartPolicy {
+    /// Never restart; the first failure marks the subsystem failed.
+    pub fn never() -> Self {
+        Self {
+            max_restarts: 0,
+            ..Self::default()
+        }
+    }
+
+    pub fn new(max_restarts: u32, window: Duration) -> Self {
+        Self {
+            max_restarts,
+            window,
+            ..Self::default()
+        }
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Failures seen within the policy's window.
+pub(crate) struct Restarts {
+    policy: RestartPolicy,
+    failures: VecDeque<DateTime<Utc>>,
+}
+
+impl Restarts {
+    pub(crate) fn new(policy: RestartPolicy) -> Self {
+        Self {
+            policy,
+            failures: VecDeque::new(),
+        }
+    }
+
+    /// Records a failure and returns how long to wait before restarting,
+    /// or `None` if the subsystem has used up its restarts.
+    pub(crate) fn failed(&mut self, now: DateTime<Utc>) -> Option<Duration> {
+        while let Some(first) = self.failures.front() {
+            if (now - *first).to_std().unwrap_or_default() > self.policy.window {
+                self.failures.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.failures.push_back(now);
+
+        let attempt = self.failures.len() as u32;
+        if attempt > self.policy.max_restarts {
+            return None;
+        }
+        let backoff = self
+            .policy
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1));
+        Some(backoff.min(self.policy.max_backoff))
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.failures.len()
+    }
+}
+
+/// Runs one subsystem call, turning a panic into an error so it can't take the worker down.
+pub(crate) async fn guard<F>(call: F) -> Result<(), String>
+where
+    F: Future<Output = Result<(), BoxError>>,
+{
+    match AssertUnwindSafe(call).catch_unwind().await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(panic) => Err(format!("panicked: {}", panic_message(&*panic))),
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
@@ -1,146 +1,426 @@
-﻿use message::Msg;
+﻿use message::{
+    decode_frame, encode_frame, ConnectionMsg, LinkState, MessageType, Msg, MsgBuilder, FRAME_DELIMITER, MAX_FRAME_LEN,
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     net::TcpStream,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver},
-    time::{self, Duration},
-    task::spawn,
+    sync::oneshot,
+    task::JoinHandle,
+    time::Duration,
 };
+use std::fmt;
+use std::io;
+use std::mem;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::bus::{QueueConfig, QueuePolicy, QueueStats, SubscriberQueue};
+use crate::clock::{Clock, SystemClock};
+
+use super::wssystem;
+use super::{BoxError, Context, Health, Publisher, SubSystem};
+
+/// Exponential reconnect delay: `initial`, doubled per failed attempt up to `max`,
+/// then randomly shortened or stretched by up to `jitter` (0.2 = ±20%).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub jitter: f64,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            jitter: 0.2,
+        }
+    }
+
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay before retry number `attempt`, counting from 0.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        // 随机抖动，避免多个客户端同时重连
+        let factor = 1.0 + self.jitter * (rand::random::<f64>() * 2.0 - 1.0);
+        delay.mul_f64(factor)
+    }
+}
 
-use super::SubSystem;
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(5), Duration::from_secs(60))
+    }
+}
 
+/// A TCP client that stays connected to `addr`.
+///
+/// Every message routed to it on the bus is sent to the peer, one frame per
+/// message, highest priority first. While disconnected they wait in a bounded
+/// buffer. Messages from the peer are published on the bus as coming from this
+/// subsystem, and every change of link state is published as a [`ConnectionMsg`]
+/// on `connection/{id}`.
 pub struct TcpSystem {
-    receiver: UnboundedReceiver<Msg>,
-    msgs: Vec<Msg>,
-    stream: Option<TcpStream>,
     addr: SocketAddr,
-    reconnect_interval: Duration,
+    backoff: Backoff,
     heartbeat_interval: Duration,
+    clock: Arc<dyn Clock>,
+    /// 断线期间待发送的消息
+    outbound: Arc<SubscriberQueue>,
+    state: Arc<Mutex<LinkState>>,
+    link: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
 }
 
 impl TcpSystem {
-    pub fn new(receiver: UnboundedReceiver<Msg>, addr: SocketAddr) -> Self {
+    pub fn new(addr: SocketAddr) -> Self {
         TcpSystem {
-            receiver,
-            msgs: Vec::new(),
-            stream: None,
             addr,
-            reconnect_interval: Duration::from_secs(5),
+            backoff: Backoff::default(),
             heartbeat_interval: Duration::from_secs(10),
+            clock: Arc::new(SystemClock),
+            outbound: Self::outbound(addr, QueueConfig::new(1024, QueuePolicy::DropOldest)),
+            state: Arc::new(Mutex::new(LinkState::Connecting)),
+            link: None,
         }
     }
 
-    async fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let stream = TcpStream::connect(self.addr).await?;
-        self.stream = Some(stream);
-        Ok(())
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Bounds the outbound buffer. Defaults to 1024 messages, dropping the oldest.
+    pub fn buffer(mut self, config: QueueConfig) -> Self {
+        self.outbound = Self::outbound(self.addr, config);
+        self
     }
 
-    async fn disconnect(&mut self) {
-        if let Some(stream) = self.stream.take() {
-            drop(stream);
+    /// Depth and drop count of the outbound buffer.
+    pub fn buffer_stats(&self) -> QueueStats {
+        self.outbound.stats()
+    }
+
+    fn outbound(addr: SocketAddr, config: QueueConfig) -> Arc<SubscriberQueue> {
+        Arc::new(SubscriberQueue::new(format!("tcp/{}", addr), config))
+    }
+}
+
+/// Where a [`Link`] connects to.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// A `ws://` or `wss://` URL.
+    Ws(String),
+}
+
+/// The receiving half of a connection, one frame at a time.
+#[async_trait]
+pub(super) trait FrameRead: Send {
+    /// The next frame, or `None` once the peer has closed the connection.
+    /// Must be cancel safe.
+    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// The sending half of a connection.
+#[async_trait]
+pub(super) trait FrameWrite: Send {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()>;
+}
+
+pub(super) type Reader = Box<dyn FrameRead>;
+pub(super) type Writer = Box<dyn FrameWrite>;
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> FrameRead for FrameLines<R> {
+    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.next_frame().await
+    }
+}
+
+/// Writes frames as they are, delimiter included, to a byte stream.
+struct StreamWriter<W>(W);
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> FrameWrite for StreamWriter<W> {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        self.0.write_all(&frame).await
+    }
+}
+
+impl Endpoint {
+    async fn connect(&self) -> io::Result<(Reader, Writer)> {
+        match self {
+            Endpoint::Tcp(addr) => {
+                let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+                Ok((Box::new(FrameLines::new(reader)), Box::new(StreamWriter(writer))))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let (reader, writer) = UnixStream::connect(path).await?.into_split();
+                Ok((Box::new(FrameLines::new(reader)), Box::new(StreamWriter(writer))))
+            }
+            Endpoint::Ws(url) => wssystem::connect(url).await,
         }
     }
+}
 
-    async fn send_heartbeat(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(ref mut stream) = self.stream {
-            // let heartbeat_msg = Msg::new(message::MessageType::Heartbeat, "".to_string());
-            // let msg = serde_json::to_vec(&heartbeat_msg).unwrap();
-            // stream.write_all(&msg).await?;
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+            Endpoint::Ws(url) => write!(f, "{}", url),
         }
-        Ok(())
     }
+}
 
-    async fn reconnect(&mut self) {
+/// The connection task, shared by the stream and WebSocket transports.
+pub(super) struct Link {
+    pub(super) endpoint: Endpoint,
+    pub(super) backoff: Backoff,
+    pub(super) heartbeat_interval: Duration,
+    pub(super) clock: Arc<dyn Clock>,
+    pub(super) outbound: Arc<SubscriberQueue>,
+    pub(super) state: Arc<Mutex<LinkState>>,
+    pub(super) publisher: Publisher,
+}
+
+impl Link {
+    pub(super) async fn run(self, mut stop: oneshot::Receiver<()>) {
+        let mut attempt = 0;
         loop {
-            if let Err(e) = self.connect().await {
-                eprintln!("Failed to reconnect: {}", e);
-                time::sleep(self.reconnect_interval).await;
-            } else {
-                break;
+            let connected = tokio::select! {
+                _ = &mut stop => break,
+                connected = self.endpoint.connect() => connected,
+            };
+            let reason = match connected {
+                Ok(stream) => {
+                    attempt = 0;
+                    self.set_state(LinkState::Connected, None).await;
+                    let result = tokio::select! {
+                        _ = &mut stop => break,
+                        result = self.session(stream) => result,
+                    };
+                    let reason = match result {
+                        Ok(()) => "closed by peer".to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    eprintln!("Connection to {} lost: {}", self.endpoint, reason);
+                    self.set_state(LinkState::Disconnected, Some(reason)).await;
+                    // 刚断开时立即重连一次
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            };
+
+            eprintln!("Failed to connect to {}: {}", self.endpoint, reason);
+            self.set_state(LinkState::Disconnected, Some(reason)).await;
+            let delay = self.backoff.delay(attempt);
+            attempt = attempt.saturating_add(1);
+            tokio::select! {
+                _ = &mut stop => break,
+                _ = self.clock.sleep(delay) => {}
             }
         }
+        *self.state.lock().unwrap() = LinkState::Disconnected;
+    }
+
+    async fn set_state(&self, state: LinkState, reason: Option<String>) {
+        set_link_state(&self.state, &self.publisher, self.endpoint.to_string(), state, reason).await;
     }
 
-    async fn process_messages(&mut self) {
-        let mut buffer = [0; 1024];
+    /// Runs until the peer closes the connection or a read or write fails.
+    async fn session(&self, (mut reader, mut writer): (Reader, Writer)) -> io::Result<()> {
+        let mut next_heartbeat = self.clock.now() + self.heartbeat_interval;
         loop {
+            while let Some(msg) = self.outbound.pop() {
+                if let Err(e) = write_msg(&mut writer, &msg).await {
+                    // 没写出去的消息放回缓冲区，重连后再发
+                    let _ = self.outbound.try_push(msg);
+                    return Err(e);
+                }
+            }
             tokio::select! {
-                Some(msg) = self.receiver.recv() => {
-                    self.msgs.push(msg);
+                _ = self.clock.sleep_until(next_heartbeat) => {
+                    let mut heartbeat = Msg::new();
+                    heartbeat.set_msg_type(MessageType::Heartbeat);
+                    write_msg(&mut writer, &heartbeat).await?;
+                    next_heartbeat = self.clock.now() + self.heartbeat_interval;
                 }
-                result = self.stream.as_mut().unwrap().read(&mut buffer) => {
-                    match result {
-                        Ok(n) if n > 0 => {
-                            let received_msg: Msg = serde_json::from_slice(&buffer[..n]).unwrap();
-                            // self.exec(&mut received_msg);
-                        }
-                        _ => {
-                            // Handle disconnection
-                            self.disconnect().await;
-                            self.reconnect().await;
-                            break;
-                        }
-                    }
+                _ = self.outbound.ready() => {}
+                frame = reader.read_frame() => {
+                    let Some(frame) = frame? else {
+                        return Ok(());
+                    };
+                    self.deliver(&frame).await;
                 }
             }
-            let msg_list = &self.msgs;
-            if let Some(ref mut stream) = self.stream {
-                for msg in msg_list.iter() {
-                    let msg = serde_json::to_vec(msg).unwrap();
-                    stream.write_all(&msg).await.unwrap();
-                }
-                self.msgs.clear();
-            }    
         }
     }
+
+    async fn deliver(&self, frame: &[u8]) {
+        if frame.iter().all(u8::is_ascii_whitespace) {
+            return;
+        }
+        let mut msg = match decode_frame(frame) {
+            Ok(msg) => msg,
+            Err(e) => {
+                eprintln!("Failed to deserialize message from {}: {}", self.endpoint, e);
+                return;
+            }
+        };
+        if msg.get_msg_type() == MessageType::Heartbeat {
+            return;
+        }
+        // 以本子系统为来源发布，总线不会再把它转发回对端
+        msg.info.source = Some(self.publisher.source().to_string());
+        self.publisher.publish(msg).await;
+    }
 }
 
-impl SubSystem for TcpSystem {
-    type Msg = Msg;
-    fn exec(&mut self, msg: &mut Msg) {
-        match msg.get_msg_type() {
-            message::MessageType::None => todo!(),
-            message::MessageType::Quit => todo!(),
-            message::MessageType::Move => todo!(),
-            message::MessageType::Join => todo!(),
-            // message::MessageType::Heartbeat => {
-            //     // Handle heartbeat message
-            // }
+/// Reads newline-delimited frames off a stream, like `AsyncBufReadExt::lines`
+/// but refusing frames longer than [`MAX_FRAME_LEN`].
+pub struct FrameLines<R> {
+    reader: BufReader<R>,
+    frame: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> FrameLines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            frame: Vec::new(),
         }
     }
 
-    fn rollup(&mut self) {
-        self.msgs.clear();
+    /// The next frame without its delimiter, or `None` once the peer has
+    /// closed the stream. An overlong frame is an `InvalidData` error, after
+    /// which the stream is out of step and should be closed.
+    ///
+    /// Cancel safe: a partly read frame is kept for the next call.
+    pub async fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                return Ok((!self.frame.is_empty()).then(|| mem::take(&mut self.frame)));
+            }
+            let end = available.iter().position(|b| *b == FRAME_DELIMITER);
+            let take = end.unwrap_or(available.len());
+            self.frame.extend_from_slice(&available[..take]);
+            self.reader.consume(end.map_or(take, |end| end + 1));
+            if self.frame.len() > MAX_FRAME_LEN {
+                self.frame = Vec::new();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame longer than {} bytes", MAX_FRAME_LEN),
+                ));
+            }
+            if end.is_some() {
+                return Ok(Some(mem::take(&mut self.frame)));
+            }
+        }
     }
 }
 
-impl TcpSystem {
-    pub async fn run(&mut self) {
-        self.connect().await.unwrap();
+/// Records the new link state and publishes a [`ConnectionMsg`] on
+/// `connection/{id}` if it changed.
+pub async fn set_link_state(
+    current: &Mutex<LinkState>,
+    publisher: &Publisher,
+    peer: String,
+    state: LinkState,
+    reason: Option<String>,
+) {
+    {
+        let mut current = current.lock().unwrap();
+        if *current == state {
+            return;
+        }
+        *current = state.clone();
+    }
+    let event = ConnectionMsg::new(peer, state, reason);
+    let msg = MsgBuilder::new()
+        .msg_type(MessageType::Connection)
+        .topic(format!("connection/{}", publisher.source()))
+        .data(Box::new(event))
+        .build()
+        .unwrap();
+    publisher.publish(msg).await;
+}
 
-        let mut heartbeat_interval = time::interval(self.heartbeat_interval);
+async fn write_msg(writer: &mut Writer, msg: &Msg) -> io::Result<()> {
+    writer.write_frame(encode_frame(msg)?).await
+}
 
-        // Spawn the process_messages task
+#[async_trait]
+impl SubSystem for TcpSystem {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let link = Link {
+            endpoint: Endpoint::Tcp(self.addr),
+            backoff: self.backoff,
+            heartbeat_interval: self.heartbeat_interval,
+            clock: self.clock.clone(),
+            outbound: self.outbound.clone(),
+            state: self.state.clone(),
+            publisher: ctx.publisher(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.link = Some((stop, tokio::spawn(link.run(stopped))));
+        Ok(())
+    }
 
-        loop {
-            tokio::select! {
-                _ = heartbeat_interval.tick() => {
-                    if let Err(e) = self.send_heartbeat().await {
-                        eprintln!("Heartbeat failed: {}", e);
-                        self.disconnect().await;
-                        self.reconnect().await;
-                    }
-                }
-                _ = self.process_messages() => {
-                    // Handle disconnection
-                    self.disconnect().await;
-                    self.reconnect().await;
-                }
-            }
+    /// Queues `msg` for the peer.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.outbound.push(Arc::new(msg.clone())).await;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.link.take() {
+            let _ = stop.send(());
+            task.await?;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &*self.state.lock().unwrap() {
+            LinkState::Connected => Health::Healthy,
+            _ => Health::Degraded(format!("not connected to {}", self.addr)),
         }
     }
-}
\ No newline at end of file
+
+    fn rollup(&mut self) {
+        while self.outbound.pop().is_some() {}
+    }
+}
@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use message::{LinkState, Msg};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+use crate::bus::{QueueConfig, QueuePolicy, QueueStats, SubscriberQueue};
+use crate::clock::{Clock, SystemClock};
+
+use super::tcpsystem::{Backoff, Endpoint, Link};
+use super::{BoxError, Context, Health, SubSystem};
+
+/// A Unix domain socket client that stays connected to the socket at `path`,
+/// for tools running on the same box as the server.
+///
+/// Same framing, buffering, heartbeat and link state events as
+/// [`TcpSystem`](super::TcpSystem).
+pub struct UnixSystem {
+    path: PathBuf,
+    backoff: Backoff,
+    heartbeat_interval: Duration,
+    clock: Arc<dyn Clock>,
+    outbound: Arc<SubscriberQueue>,
+    state: Arc<Mutex<LinkState>>,
+    link: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+}
+
+impl UnixSystem {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        UnixSystem {
+            backoff: Backoff::default(),
+            heartbeat_interval: Duration::from_secs(10),
+            clock: Arc::new(SystemClock),
+            outbound: Self::outbound(&path, QueueConfig::new(1024, QueuePolicy::DropOldest)),
+            state: Arc::new(Mutex::new(LinkState::Connecting)),
+            link: None,
+            path,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Bounds the outbound buffer. Defaults to 1024 messages, dropping the oldest.
+    pub fn buffer(mut self, config: QueueConfig) -> Self {
+        self.outbound = Self::outbound(&self.path, config);
+        self
+    }
+
+    /// Depth and drop count of the outbound buffer.
+    pub fn buffer_stats(&self) -> QueueStats {
+        self.outbound.stats()
+    }
+
+    fn outbound(path: &Path, config: QueueConfig) -> Arc<SubscriberQueue> {
+        Arc::new(SubscriberQueue::new(format!("unix/{}", path.display()), config))
+    }
+}
+
+#[async_trait]
+impl SubSystem for UnixSystem {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let link = Link {
+            endpoint: Endpoint::Unix(self.path.clone()),
+            backoff: self.backoff,
+            heartbeat_interval: self.heartbeat_interval,
+            clock: self.clock.clone(),
+            outbound: self.outbound.clone(),
+            state: self.state.clone(),
+            publisher: ctx.publisher(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.link = Some((stop, tokio::spawn(link.run(stopped))));
+        Ok(())
+    }
+
+    /// Queues `msg` for the peer.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.outbound.push(Arc::new(msg.clone())).await;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.link.take() {
+            let _ = stop.send(());
+            task.await?;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &*self.state.lock().unwrap() {
+            LinkState::Connected => Health::Healthy,
+            _ => Health::Degraded(format!("not connected to {}", self.path.display())),
+        }
+    }
+
+    fn rollup(&mut self) {
+        while self.outbound.pop().is_some() {}
+    }
+}
@@ -0,0 +1,198 @@
+use message::{AlarmMsg, AlarmSeverity, MessageType, MsgBuilder};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::{JoinError, JoinHandle},
+};
+
+use std::sync::Arc;
+
+use crate::bus::{Bus, Subscription};
+use crate::clock::Clock;
+
+use super::supervisor::{guard, RestartPolicy, Restarts};
+use super::context::Timers;
+use super::{Context, Health, States, SubSystem, SubsystemState, SystemId};
+
+enum Control {
+    Health(oneshot::Sender<Health>),
+    Stop,
+}
+
+/// The task a running subsystem lives on. It owns the subsystem and its inbox,
+/// so a slow subsystem only ever delays its own messages.
+pub(crate) struct Worker {
+    control: UnboundedSender<Control>,
+    task: JoinHandle<Box<dyn SubSystem>>,
+}
+
+impl Worker {
+    pub(crate) fn spawn(subsystem: Box<dyn SubSystem>, inbox: Subscription, supervisor: Supervisor) -> Self {
+        let (control, receiver) = unbounded_channel();
+        let task = tokio::spawn(run(subsystem, inbox, supervisor, receiver));
+        Self { control, task }
+    }
+
+    /// Returns `None` if the task is no longer running.
+    pub(crate) async fn health(&self) -> Option<Health> {
+        let (sender, receiver) = oneshot::channel();
+        self.control.send(Control::Health(sender)).ok()?;
+        receiver.await.ok()
+    }
+
+    /// Lets the message in flight finish, then hands the subsystem back.
+    pub(crate) async fn stop(self) -> Result<Box<dyn SubSystem>, JoinError> {
+        let _ = self.control.send(Control::Stop);
+        self.task.await
+    }
+
+    pub(crate) fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+/// Everything a worker needs to restart its subsystem after a failure.
+pub(crate) struct Supervisor {
+    id: SystemId,
+    bus: Bus,
+    states: States,
+    config: serde_json::Value,
+    restarts: Restarts,
+    clock: Arc<dyn Clock>,
+    timers: Timers,
+}
+
+enum Recovery {
+    Restarted,
+    GaveUp,
+    Stopped,
+}
+
+impl Supervisor {
+    pub(crate) fn new(
+        id: SystemId,
+        bus: Bus,
+        states: States,
+        config: serde_json::Value,
+        policy: RestartPolicy,
+        clock: Arc<dyn Clock>,
+        timers: Timers,
+    ) -> Self {
+        Self {
+            id,
+            bus,
+            states,
+            config,
+            restarts: Restarts::new(policy),
+            clock,
+            timers,
+        }
+    }
+
+    /// Restarts `subsystem` under the restart policy, retrying if `init` or
+    /// `start` fail as well.
+    async fn recover(
+        &mut self,
+        subsystem: &mut Box<dyn SubSystem>,
+        control: &mut UnboundedReceiver<Control>,
+        mut reason: String,
+    ) -> Recovery {
+        loop {
+            eprintln!("Subsystem {} failed: {}", self.id, reason);
+            let Some(backoff) = self.restarts.failed(self.clock.now()) else {
+                self.give_up(reason).await;
+                return Recovery::GaveUp;
+            };
+            self.set_state(SubsystemState::Restarting);
+
+            // 退避期间仍要响应停止请求
+            let mut sleep = self.clock.sleep(backoff);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    command = control.recv() => match command {
+                        Some(Control::Health(reply)) => {
+                            let _ = reply.send(Health::Unhealthy(format!("restarting: {}", reason)));
+                        }
+                        Some(Control::Stop) | None => return Recovery::Stopped,
+                    },
+                }
+            }
+
+            // 重启前安排的消息作废
+            self.timers.cancel();
+            let _ = guard(subsystem.stop()).await;
+            if let Err(e) = guard(subsystem.init(&self.config)).await {
+                reason = e;
+                continue;
+            }
+            let mut ctx = Context::new(self.id.clone(), self.bus.clone(), self.timers.clone());
+            if let Err(e) = guard(subsystem.start(&mut ctx)).await {
+                reason = e;
+                continue;
+            }
+            ctx.flush().await;
+            self.set_state(SubsystemState::Running);
+            return Recovery::Restarted;
+        }
+    }
+
+    async fn give_up(&self, reason: String) {
+        let text = format!(
+            "gave up after {} failures: {}",
+            self.restarts.count(),
+            reason
+        );
+        eprintln!("Subsystem {} {}", self.id, text);
+        let alarm = AlarmMsg::new(self.id.as_str(), AlarmSeverity::Critical, text);
+        let alarm = MsgBuilder::new()
+            .msg_type(MessageType::Alarm)
+            .topic(format!("alarm/subsystem/{}", self.id))
+            .data(Box::new(alarm))
+            .build()
+            .unwrap();
+        self.set_state(SubsystemState::Failed(reason));
+        self.bus.publish(alarm).await;
+    }
+
+    fn set_state(&self, state: SubsystemState) {
+        self.states.write().unwrap().insert(self.id.clone(), state);
+    }
+}
+
+async fn run(
+    mut subsystem: Box<dyn SubSystem>,
+    inbox: Subscription,
+    mut supervisor: Supervisor,
+    mut control: UnboundedReceiver<Control>,
+) -> Box<dyn SubSystem> {
+    loop {
+        tokio::select! {
+            biased;
+            command = control.recv() => match command {
+                Some(Control::Health(reply)) => {
+                    let _ = reply.send(subsystem.health());
+                }
+                Some(Control::Stop) | None => break,
+            },
+            msg = inbox.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                let mut ctx = Context::new(supervisor.id.clone(), supervisor.bus.clone(), supervisor.timers.clone());
+                // panic 和错误都只影响本子系统
+                if let Err(reason) = guard(subsystem.exec(&msg, &mut ctx)).await {
+                    match supervisor.recover(&mut subsystem, &mut control, reason).await {
+                        Recovery::Restarted => continue,
+                        Recovery::GaveUp | Recovery::Stopped => break,
+                    }
+                }
+                ctx.flush().await;
+            }
+        }
+    }
+    supervisor.timers.cancel();
+    subsystem
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures_util::stream::{SplitSink, SplitStream, StreamExt};
+use futures_util::SinkExt;
+use message::{LinkState, Msg, MAX_FRAME_LEN};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use crate::bus::{QueueConfig, QueuePolicy, QueueStats, SubscriberQueue};
+use crate::clock::{Clock, SystemClock};
+
+use super::tcpsystem::{Backoff, Endpoint, FrameRead, FrameWrite, Link, Reader, Writer};
+use super::{BoxError, Context, Health, SubSystem};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A WebSocket client that stays connected to `url`, e.g. `ws://host:8081`,
+/// for sites where only HTTP gets through.
+///
+/// Behaves like [`TcpSystem`](super::TcpSystem): each message is one frame
+/// sent as a text message, buffered while disconnected, and link state
+/// changes are published on `connection/{id}`.
+pub struct WsSystem {
+    url: String,
+    backoff: Backoff,
+    heartbeat_interval: Duration,
+    clock: Arc<dyn Clock>,
+    outbound: Arc<SubscriberQueue>,
+    state: Arc<Mutex<LinkState>>,
+    link: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+}
+
+impl WsSystem {
+    pub fn new(url: impl Into<String>) -> Self {
+        let url = url.into();
+        WsSystem {
+            backoff: Backoff::default(),
+            heartbeat_interval: Duration::from_secs(10),
+            clock: Arc::new(SystemClock),
+            outbound: Self::outbound(&url, QueueConfig::new(1024, QueuePolicy::DropOldest)),
+            state: Arc::new(Mutex::new(LinkState::Connecting)),
+            link: None,
+            url,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Bounds the outbound buffer. Defaults to 1024 messages, dropping the oldest.
+    pub fn buffer(mut self, config: QueueConfig) -> Self {
+        self.outbound = Self::outbound(&self.url, config);
+        self
+    }
+
+    /// Depth and drop count of the outbound buffer.
+    pub fn buffer_stats(&self) -> QueueStats {
+        self.outbound.stats()
+    }
+
+    fn outbound(url: &str, config: QueueConfig) -> Arc<SubscriberQueue> {
+        Arc::new(SubscriberQueue::new(format!("ws/{}", url), config))
+    }
+}
+
+/// Opens a WebSocket connection to `url` for a [`Link`].
+pub(super) async fn connect(url: &str) -> io::Result<(Reader, Writer)> {
+    let (stream, _) = connect_async(url).await.map_err(io::Error::other)?;
+    let (writer, reader) = stream.split();
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+#[async_trait]
+impl FrameRead for SplitStream<WsStream> {
+    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let frame = match self.next().await.transpose().map_err(io::Error::other)? {
+                None | Some(Message::Close(_)) => return Ok(None),
+                Some(Message::Text(text)) => text.into_bytes(),
+                Some(Message::Binary(data)) => data,
+                // ping 由 tungstenite 自动应答
+                Some(_) => continue,
+            };
+            if frame.len() > MAX_FRAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame longer than {} bytes", MAX_FRAME_LEN),
+                ));
+            }
+            return Ok(Some(frame));
+        }
+    }
+}
+
+#[async_trait]
+impl FrameWrite for SplitSink<WsStream, Message> {
+    /// Sends `frame` as one text message.
+    async fn write_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        let text = String::from_utf8(frame).map_err(io::Error::other)?;
+        self.send(Message::Text(text)).await.map_err(io::Error::other)
+    }
+}
+
+#[async_trait]
+impl SubSystem for WsSystem {
+    async fn start(&mut self, ctx: &mut Context) -> Result<(), BoxError> {
+        let link = Link {
+            endpoint: Endpoint::Ws(self.url.clone()),
+            backoff: self.backoff,
+            heartbeat_interval: self.heartbeat_interval,
+            clock: self.clock.clone(),
+            outbound: self.outbound.clone(),
+            state: self.state.clone(),
+            publisher: ctx.publisher(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        self.link = Some((stop, tokio::spawn(link.run(stopped))));
+        Ok(())
+    }
+
+    /// Queues `msg` for the peer.
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.outbound.push(Arc::new(msg.clone())).await;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some((stop, task)) = self.link.take() {
+            let _ = stop.send(());
+            task.await?;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match &*self.state.lock().unwrap() {
+            LinkState::Connected => Health::Healthy,
+            _ => Health::Degraded(format!("not connected to {}", self.url)),
+        }
+    }
+
+    fn rollup(&mut self) {
+        while self.outbound.pop().is_some() {}
+    }
+}
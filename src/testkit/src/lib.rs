@@ -0,0 +1,220 @@
+//! Drives a [`CenterSubsystem`] on a virtual clock so time-based subsystems can
+//! be tested without waiting real seconds.
+//!
+//! Hand [`Harness::clock`] to every subsystem under test, register them, then
+//! inject messages, advance time and look at what each subsystem emitted.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use message::{AlarmMsg, ConnectionMsg, LinkState, Message, MessageType, Msg, MsgBuilder, TelemetryMsg};
+use serde::de::DeserializeOwned;
+use subsystem::{CenterSubsystem, Filter, Interceptor, MockClock, Registration, SubSystem, SystemId};
+
+/// Source stamped on messages injected by the harness.
+pub const HARNESS: &str = "harness";
+
+/// How many times [`Harness::settle`] yields to let other tasks run.
+const SETTLE_ROUNDS: usize = 64;
+
+/// How long the harness waits in real time for something before failing the test.
+pub const EXPECT: Duration = Duration::from_secs(5);
+
+/// Awaits `future` in real time, panicking if it takes longer than [`EXPECT`].
+pub async fn expect<F: Future>(future: F) -> F::Output {
+    tokio::time::timeout(EXPECT, future)
+        .await
+        .expect("nothing happened in time")
+}
+
+/// A message of `msg_type` without data.
+pub fn msg(msg_type: MessageType) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(msg_type);
+    msg
+}
+
+/// Keeps a copy of every message dispatched on the bus.
+#[derive(Clone, Default)]
+struct Tap {
+    msgs: Arc<Mutex<Vec<Msg>>>,
+}
+
+impl Interceptor for Tap {
+    fn name(&self) -> &str {
+        "harness tap"
+    }
+
+    fn after(&self, msg: &Msg, _delivered: usize) {
+        self.msgs.lock().unwrap().push(msg.clone());
+    }
+}
+
+pub struct Harness {
+    center: CenterSubsystem,
+    clock: MockClock,
+    tap: Tap,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let clock = MockClock::default();
+        let mut center = CenterSubsystem::new().with_clock(clock.clone());
+        let tap = Tap::default();
+        center.intercept(tap.clone());
+        Self { center, clock, tap }
+    }
+
+    /// The virtual clock. Subsystems under test must be built with it; the
+    /// center already times restart backoffs on it.
+    pub fn clock(&self) -> MockClock {
+        self.clock.clone()
+    }
+
+    pub fn center(&mut self) -> &mut CenterSubsystem {
+        &mut self.center
+    }
+
+    pub fn register<S, F>(&mut self, id: impl Into<SystemId>, subsystem: S, filters: impl IntoIterator<Item = F>) -> &mut Registration
+    where
+        S: SubSystem + 'static,
+        F: Into<Filter>,
+    {
+        self.center.register(id, subsystem, filters)
+    }
+
+    /// Starts every registered subsystem. Panics if one fails to start.
+    pub async fn start(&mut self) {
+        self.center.start().await.expect("failed to start subsystems");
+        self.settle().await;
+    }
+
+    pub async fn stop(&mut self) {
+        self.center.stop().await;
+    }
+
+    /// Publishes `msg` as if it came from outside, then lets subsystems handle it.
+    pub async fn inject(&mut self, mut msg: Msg) {
+        msg.info.source.get_or_insert_with(|| HARNESS.to_string());
+        self.center.publish(msg).await;
+        self.settle().await;
+    }
+
+    /// Injects a message of `msg_type` carrying `data`, such as a motor command.
+    pub async fn inject_data(&mut self, msg_type: MessageType, data: impl Message + 'static) {
+        let msg = MsgBuilder::new().msg_type(msg_type).data(Box::new(data)).build().unwrap();
+        self.inject(msg).await;
+    }
+
+    /// Lets every task that is ready run until it blocks again.
+    pub async fn settle(&self) {
+        for _ in 0..SETTLE_ROUNDS {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Moves the virtual clock forward and lets woken subsystems run.
+    pub async fn advance(&self, duration: Duration) {
+        self.settle().await;
+        self.clock.advance(duration);
+        self.settle().await;
+    }
+
+    /// Waits in real time until at least `count` tasks are sleeping on the
+    /// virtual clock, so an [`advance`](Self::advance) can't race them.
+    /// Panics if that doesn't happen within a few seconds.
+    pub async fn wait_for_sleepers(&self, count: usize) {
+        let waited = tokio::time::timeout(EXPECT, async {
+            while self.clock.sleepers() < count {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await;
+        assert!(
+            waited.is_ok(),
+            "expected {} sleepers on the clock, found {}",
+            count,
+            self.clock.sleepers()
+        );
+    }
+
+    /// Waits in real time, letting tasks run, until `done` returns true.
+    /// Panics if that doesn't happen within a few seconds.
+    pub async fn wait_until(&self, mut done: impl FnMut(&Self) -> bool) {
+        let waited = tokio::time::timeout(EXPECT, async {
+            while !done(self) {
+                self.settle().await;
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await;
+        assert!(waited.is_ok(), "condition not met in time");
+    }
+
+    /// Every message published on the bus so far, in order.
+    pub fn messages(&self) -> Vec<Msg> {
+        self.tap.msgs.lock().unwrap().clone()
+    }
+
+    /// Messages published by subsystem `id`.
+    pub fn emitted(&self, id: impl Into<SystemId>) -> Vec<Msg> {
+        let id = id.into();
+        self.tap
+            .msgs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|msg| msg.info.source.as_deref() == Some(id.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Messages of `msg_type` published by subsystem `id`.
+    pub fn emitted_of(&self, id: impl Into<SystemId>, msg_type: MessageType) -> Vec<Msg> {
+        self.emitted(id)
+            .into_iter()
+            .filter(|msg| msg.info.msg_type == msg_type)
+            .collect()
+    }
+
+    /// The data of every message of `msg_type` published by subsystem `id`.
+    pub fn emitted_data<T>(&self, id: impl Into<SystemId>, msg_type: MessageType) -> Vec<T>
+    where
+        T: Message + DeserializeOwned,
+    {
+        self.emitted_of(id, msg_type)
+            .iter()
+            .filter_map(|msg| msg.get_data::<T>())
+            .collect()
+    }
+
+    /// Telemetry published by subsystem `id`, in order.
+    pub fn telemetry(&self, id: impl Into<SystemId>) -> Vec<TelemetryMsg> {
+        self.emitted_data(id, MessageType::Telemetry)
+    }
+
+    /// Alarms raised by subsystem `id`, in order.
+    pub fn alarms(&self, id: impl Into<SystemId>) -> Vec<AlarmMsg> {
+        self.emitted_data(id, MessageType::Alarm)
+    }
+
+    /// Link states reported by subsystem `id`, in order.
+    pub fn link_states(&self, id: impl Into<SystemId>) -> Vec<LinkState> {
+        self.emitted_data::<ConnectionMsg>(id, MessageType::Connection)
+            .into_iter()
+            .map(|event| event.state)
+            .collect()
+    }
+
+    /// Forgets every message seen so far.
+    pub fn clear(&self) {
+        self.tap.msgs.lock().unwrap().clear();
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
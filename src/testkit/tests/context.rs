@@ -0,0 +1,102 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use message::{MessageType, Msg};
+use subsystem::{BoxError, Context, SubSystem};
+use testkit::{Harness, HARNESS};
+
+/// Schedules a message on `later`, replies on `ask` and records whether the
+/// reply was accepted.
+struct Responder {
+    replies: Arc<Mutex<Vec<bool>>>,
+}
+
+#[async_trait]
+impl SubSystem for Responder {
+    async fn exec(&mut self, msg: &Msg, ctx: &mut Context) -> Result<(), BoxError> {
+        match &*msg.topic() {
+            "later" => ctx.schedule(Duration::from_secs(5), answer("reminder")),
+            "ask" => {
+                let sent = ctx.reply(msg, answer("answer")).is_ok();
+                self.replies.lock().unwrap().push(sent);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn rollup(&mut self) {}
+}
+
+fn request(topic: &str) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(MessageType::Move);
+    msg.set_topic(topic);
+    msg
+}
+
+fn answer(topic: &str) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(MessageType::Telemetry);
+    msg.set_topic(topic);
+    msg
+}
+
+async fn start() -> (Harness, Arc<Mutex<Vec<bool>>>) {
+    let replies = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::new();
+    harness.register("responder", Responder { replies: replies.clone() }, vec![MessageType::Move]);
+    harness.start().await;
+    (harness, replies)
+}
+
+fn topics(harness: &Harness) -> Vec<String> {
+    harness.emitted("responder").iter().map(|msg| msg.topic().into_owned()).collect()
+}
+
+#[tokio::test]
+async fn scheduled_messages_follow_the_center_clock() {
+    let (mut harness, _) = start().await;
+    harness.inject(request("later")).await;
+    harness.wait_for_sleepers(1).await;
+
+    harness.advance(Duration::from_millis(4999)).await;
+    assert!(topics(&harness).is_empty());
+    harness.advance(Duration::from_millis(1)).await;
+    assert_eq!(topics(&harness), ["reminder"]);
+}
+
+#[tokio::test]
+async fn stopping_cancels_scheduled_messages() {
+    let (mut harness, _) = start().await;
+    harness.inject(request("later")).await;
+    harness.wait_for_sleepers(1).await;
+
+    harness.stop().await;
+    harness.advance(Duration::from_secs(10)).await;
+    assert!(topics(&harness).is_empty());
+    assert_eq!(harness.clock().sleepers(), 0);
+}
+
+#[tokio::test]
+async fn replies_go_back_to_the_source() {
+    let (mut harness, replies) = start().await;
+    harness.inject(request("ask")).await;
+
+    let sent = harness.emitted("responder");
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].info.target.as_deref(), Some(HARNESS));
+    assert_eq!(*replies.lock().unwrap(), [true]);
+}
+
+#[tokio::test]
+async fn a_request_without_a_source_gets_no_reply() {
+    let (mut harness, replies) = start().await;
+    // 不经 inject，保留空的 source
+    harness.center().publish(request("ask")).await;
+    harness.settle().await;
+
+    assert!(harness.emitted("responder").is_empty());
+    assert_eq!(*replies.lock().unwrap(), [false]);
+}
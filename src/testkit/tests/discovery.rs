@@ -0,0 +1,159 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use subsystem::{discover, Advertised, DiscoveryResponder, ServerInfo, TransportKind};
+use tokio::net::UdpSocket;
+
+/// Real time a loopback reply gets to arrive.
+const WAIT: Duration = Duration::from_millis(500);
+
+fn info(name: &str) -> ServerInfo {
+    ServerInfo {
+        name: name.to_string(),
+        version: "1.2.3".to_string(),
+        endpoints: vec![
+            Advertised {
+                transport: TransportKind::Tcp,
+                addr: "0.0.0.0:8080".parse().unwrap(),
+            },
+            Advertised {
+                transport: TransportKind::Grpc,
+                addr: "10.0.0.5:50051".parse().unwrap(),
+            },
+        ],
+    }
+}
+
+async fn responder(name: &str) -> SocketAddr {
+    let responder = DiscoveryResponder::bind("127.0.0.1:0".parse().unwrap(), info(name))
+        .await
+        .unwrap();
+    let addr = responder.local_addr().unwrap();
+    tokio::spawn(async move { responder.run().await });
+    addr
+}
+
+#[tokio::test]
+async fn answers_probe_with_server_info() {
+    let addr = responder("press-1").await;
+
+    let servers = discover(&[addr], WAIT).await.unwrap();
+    assert_eq!(servers.len(), 1);
+    let server = &servers[0];
+    assert_eq!(server.from, addr);
+    assert_eq!(server.info.name, "press-1");
+    assert_eq!(server.info.version, "1.2.3");
+    // 未指定的地址换成应答来源
+    assert_eq!(server.info.endpoints[0].transport, TransportKind::Tcp);
+    assert_eq!(server.info.endpoints[0].addr, "127.0.0.1:8080".parse().unwrap());
+    assert_eq!(server.info.endpoints[1].addr, "10.0.0.5:50051".parse().unwrap());
+}
+
+#[tokio::test]
+async fn lists_each_server_once() {
+    let first = responder("press-1").await;
+    let second = responder("press-2").await;
+
+    let mut servers = discover(&[first, second, first], WAIT).await.unwrap();
+    servers.sort_by(|a, b| a.info.name.cmp(&b.info.name));
+    let names: Vec<_> = servers.iter().map(|server| server.info.name.as_str()).collect();
+    assert_eq!(names, ["press-1", "press-2"]);
+}
+
+#[tokio::test]
+async fn ignores_unrelated_packets() {
+    let addr = responder("press-1").await;
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.send_to(b"hello", addr).await.unwrap();
+    socket
+        .send_to(br#"{"type":"probe","service":"other","id":"6f1c2c4e-8a59-4a0e-9d3e-2f8f5f0b7a11"}"#, addr)
+        .await
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(tokio::time::timeout(WAIT, socket.recv_from(&mut buf)).await.is_err());
+
+    assert_eq!(discover(&[addr], WAIT).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn finds_nothing_without_servers() {
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let servers = discover(&[silent.local_addr().unwrap()], WAIT).await.unwrap();
+    assert!(servers.is_empty());
+}
+
+/// This host's address on its default route, so probes to it don't travel
+/// over loopback.
+fn lan_ip() -> IpAddr {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.connect("10.255.255.255:1").unwrap();
+    let ip = socket.local_addr().unwrap().ip();
+    assert!(!ip.is_loopback(), "needs a non-loopback IPv4 address");
+    ip
+}
+
+/// The server's defaults: TCP and gRPC on loopback, WebSocket on every interface.
+fn loopback_info() -> ServerInfo {
+    let endpoint = |transport, addr: &str| Advertised {
+        transport,
+        addr: addr.parse().unwrap(),
+    };
+    ServerInfo {
+        name: "press-1".to_string(),
+        version: "1.2.3".to_string(),
+        endpoints: vec![
+            endpoint(TransportKind::Tcp, "127.0.0.1:8080"),
+            endpoint(TransportKind::WebSocket, "0.0.0.0:8081"),
+            endpoint(TransportKind::Grpc, "127.0.0.1:50051"),
+        ],
+    }
+}
+
+#[tokio::test]
+async fn loopback_endpoints_stay_on_this_host() {
+    let responder = DiscoveryResponder::bind("0.0.0.0:0".parse().unwrap(), loopback_info())
+        .await
+        .unwrap();
+    let port = responder.local_addr().unwrap().port();
+    tokio::spawn(async move { responder.run().await });
+
+    let local = discover(&[SocketAddr::from(([127, 0, 0, 1], port))], WAIT).await.unwrap();
+    let addrs: Vec<_> = local[0].info.endpoints.iter().map(|endpoint| endpoint.addr).collect();
+    let expected: Vec<SocketAddr> = ["127.0.0.1:8080", "127.0.0.1:8081", "127.0.0.1:50051"]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+    assert_eq!(addrs, expected);
+
+    // 从网卡地址来的探测只拿到对外监听的端点
+    let ip = lan_ip();
+    let remote = discover(&[SocketAddr::new(ip, port)], WAIT).await.unwrap();
+    assert_eq!(remote[0].from.ip(), ip);
+    assert_eq!(
+        remote[0].info.endpoints,
+        [Advertised {
+            transport: TransportKind::WebSocket,
+            addr: SocketAddr::new(ip, 8081),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn loopback_in_a_remote_reply_becomes_the_reply_address() {
+    // 旧版服务端会把回环地址发给其它主机
+    let ip = lan_ip();
+    let server = UdpSocket::bind(SocketAddr::new(ip, 0)).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+        let probe: serde_json::Value = serde_json::from_slice(&buf[..len]).unwrap();
+        let reply = serde_json::json!({ "type": "reply", "id": probe["id"], "server": loopback_info() });
+        server.send_to(reply.to_string().as_bytes(), peer).await.unwrap();
+    });
+
+    let servers = discover(&[addr], WAIT).await.unwrap();
+    let addrs: Vec<_> = servers[0].info.endpoints.iter().map(|endpoint| endpoint.addr).collect();
+    let expected = [8080, 8081, 50051].map(|port| SocketAddr::new(ip, port));
+    assert_eq!(addrs, expected);
+}
@@ -0,0 +1,144 @@
+use std::net::SocketAddr;
+
+use message::{AlarmMsg, AlarmSeverity, MessageType, MotorCommand, MotorMsg, MoveDirection, MsgBuilder, TelemetryMsg};
+use proto::csc::control_client::ControlClient;
+use proto::csc::{
+    AlarmRequest, Direction, EnableRequest, InfoRequest, MoveRequest, SessionsRequest, Severity, StopRequest,
+    TelemetryRequest,
+};
+use proto::ControlService;
+use subsystem::{CenterHandle, CenterSubsystem, Filter, Scheduler, Subscription};
+use testkit::expect;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+
+
+/// A running center with a scheduler, served over gRPC on localhost.
+async fn serve() -> (CenterHandle, ControlClient<Channel>) {
+    let mut center = CenterSubsystem::new();
+    let handle = center.handle();
+    center.register("scheduler", Scheduler::new(handle.clone()), Vec::<Filter>::new());
+    tokio::spawn(async move { center.run().await });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let service = ControlService::new(handle.clone()).into_server();
+    tokio::spawn(Server::builder().add_service(service).serve_with_incoming(TcpListenerStream::new(listener)));
+    let client = ControlClient::connect(format!("http://{}", addr)).await.unwrap();
+    (handle, client)
+}
+
+async fn publish(center: &CenterHandle, msg_type: MessageType, data: Box<dyn message::Message>) {
+    let msg = MsgBuilder::new().msg_type(msg_type).data(data).build().unwrap();
+    center.publish(msg).await.unwrap();
+}
+
+async fn next_motor(subscription: &Subscription) -> (MessageType, MotorMsg) {
+    let msg = expect(subscription.recv()).await.unwrap();
+    (msg.get_msg_type(), msg.get_data::<MotorMsg>().unwrap())
+}
+
+#[tokio::test]
+async fn commands_are_published_on_the_bus() {
+    let (center, mut client) = serve().await;
+    let commands = center.subscribe("test", [MessageType::Move, MessageType::Stop]);
+
+    let request = MoveRequest {
+        motor: 3,
+        direction: Direction::Down.into(),
+    };
+    let reply = client.r#move(request).await.unwrap().into_inner();
+    let msg = expect(commands.recv()).await.unwrap();
+    assert_eq!(msg.get_uid().to_string(), reply.id);
+    assert_eq!(msg.info.source.as_deref(), Some("grpc"));
+    let motor = msg.get_data::<MotorMsg>().unwrap();
+    assert_eq!((motor.id, motor.direction, motor.command), (3, MoveDirection::Down, MotorCommand::Move));
+
+    client.stop(StopRequest { motor: 3 }).await.unwrap();
+    let (msg_type, motor) = next_motor(&commands).await;
+    assert_eq!((msg_type, motor.command), (MessageType::Stop, MotorCommand::Stop));
+
+    client.enable(EnableRequest { motor: 3, enable: false }).await.unwrap();
+    let (msg_type, motor) = next_motor(&commands).await;
+    assert_eq!((msg_type, motor.command), (MessageType::Move, MotorCommand::Disable));
+}
+
+#[tokio::test]
+async fn streams_telemetry_of_one_device() {
+    let (center, mut client) = serve().await;
+    let request = TelemetryRequest {
+        device: "drive1".to_string(),
+    };
+    let mut stream = client.stream_telemetry(request).await.unwrap().into_inner();
+
+    for (device, speed) in [("drive2", 1.0), ("drive1", 2.0)] {
+        let telemetry = TelemetryMsg::new(device, "status").value("speed", speed);
+        publish(&center, MessageType::Telemetry, Box::new(telemetry)).await;
+    }
+    let telemetry = expect(stream.message()).await.unwrap().unwrap();
+    assert_eq!((telemetry.device.as_str(), telemetry.group.as_str()), ("drive1", "status"));
+    assert_eq!(telemetry.values["speed"], 2.0);
+    assert!(telemetry.time.is_some());
+}
+
+#[tokio::test]
+async fn streams_alarms_from_min_severity() {
+    let (center, mut client) = serve().await;
+    let request = AlarmRequest {
+        min_severity: Severity::Warning.into(),
+    };
+    let mut stream = client.stream_alarms(request).await.unwrap().into_inner();
+
+    for severity in [AlarmSeverity::Info, AlarmSeverity::Critical] {
+        let alarm = AlarmMsg::new("drive1", severity, format!("{:?}", severity));
+        publish(&center, MessageType::Alarm, Box::new(alarm)).await;
+    }
+    let alarm = expect(stream.message()).await.unwrap().unwrap();
+    assert_eq!(alarm.severity(), Severity::Critical);
+    assert_eq!((alarm.source.as_str(), alarm.text.as_str()), ("drive1", "Critical"));
+}
+
+#[tokio::test]
+async fn lists_sessions_and_subsystems() {
+    let (center, mut client) = serve().await;
+    let _session = center.subscribe("session/ws/10.0.0.5:40000", Vec::<Filter>::new());
+
+    let sessions = client.list_sessions(SessionsRequest {}).await.unwrap().into_inner().sessions;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].id, "session/ws/10.0.0.5:40000");
+    assert_eq!((sessions[0].transport.as_str(), sessions[0].peer.as_str()), ("ws", "10.0.0.5:40000"));
+
+    let info = client.get_info(InfoRequest {}).await.unwrap().into_inner();
+    assert!(!info.version.is_empty());
+    assert_eq!(info.subsystems.len(), 1);
+    assert_eq!(info.subsystems[0].id, "scheduler");
+    assert_eq!(info.subsystems[0].state, "running");
+    assert_eq!(info.subsystems[0].health, "healthy");
+}
+
+#[tokio::test]
+async fn a_stalled_stream_does_not_hold_up_publishers() {
+    let (center, mut client) = serve().await;
+    let telemetry = client.stream_telemetry(TelemetryRequest::default()).await.unwrap();
+    let alarms = client.stream_alarms(AlarmRequest::default()).await.unwrap();
+
+    // 两个流都不读，发布仍然不会被卡住
+    expect(async {
+        for i in 0..20_000 {
+            let telemetry = TelemetryMsg::new(format!("drive{}", i % 4), "status").value("speed", i as f64);
+            publish(&center, MessageType::Telemetry, Box::new(telemetry)).await;
+            let alarm = AlarmMsg::new("drive1", AlarmSeverity::Critical, format!("alarm {}", i));
+            publish(&center, MessageType::Alarm, Box::new(alarm)).await;
+        }
+    })
+    .await;
+
+    let mut alarms = alarms.into_inner();
+    let first = expect(alarms.message()).await.unwrap().unwrap();
+    assert_ne!(first.text, "alarm 0");
+    let stats = center.queue_stats();
+    let dropped = |label: &str| stats.iter().find(|stats| stats.label == label).unwrap().dropped;
+    assert!(dropped("grpc/telemetry") > 0 && dropped("grpc/alarms") > 0);
+    drop(telemetry);
+}
@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use message::{MessageType, Msg};
+use subsystem::{read_recording, Bus, MockClock, Recorder, Replayer, Subscription};
+use testkit::Harness;
+
+fn recording(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("csc-record-{}-{}.rec", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn msg(msg_type: MessageType, topic: &str) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(msg_type);
+    msg.set_topic(topic);
+    msg
+}
+
+fn topics(subscription: &Subscription) -> Vec<String> {
+    std::iter::from_fn(|| subscription.try_recv()).map(|msg| msg.topic().into_owned()).collect()
+}
+
+/// Records a move, a stop 1.5 s later and telemetry 0.25 s after that.
+async fn record(path: &PathBuf) -> Vec<Msg> {
+    let mut harness = Harness::new();
+    let recorder = Recorder::create(path).unwrap().with_clock(harness.clock());
+    harness.center().intercept(recorder.clone());
+    harness.start().await;
+    harness.clock().advance(Duration::from_secs(100));
+    harness.inject(msg(MessageType::Move, "move/1")).await;
+    harness.advance(Duration::from_millis(1500)).await;
+    harness.inject(msg(MessageType::Stop, "stop/1")).await;
+    harness.advance(Duration::from_millis(250)).await;
+    harness.inject(msg(MessageType::Telemetry, "telemetry/lift/axis")).await;
+    harness.stop().await;
+    recorder.flush().unwrap();
+    harness.messages()
+}
+
+#[tokio::test]
+async fn records_every_message_with_its_time() {
+    let path = recording("times");
+    let published = record(&path).await;
+    let records = read_recording(&path).unwrap();
+    let times: Vec<u64> = records.iter().map(|record| record.ts).collect();
+    assert_eq!(times, [100_000_000, 101_500_000, 101_750_000]);
+    for (record, msg) in records.iter().zip(&published) {
+        assert_eq!(record.msg.info.uid, msg.info.uid);
+        assert_eq!(record.msg.topic(), msg.topic());
+        assert_eq!(record.msg.info.source.as_deref(), Some("harness"));
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn replays_in_recorded_order_and_timing() {
+    let path = recording("replay");
+    let published = record(&path).await;
+
+    let clock = MockClock::default();
+    let bus = Bus::new();
+    let sub = bus.subscribe("replay", [MessageType::Move, MessageType::Stop, MessageType::Telemetry]);
+    let replayer = Replayer::open(&path).unwrap().with_clock(clock.clone());
+    let replay = tokio::spawn({
+        let bus = bus.clone();
+        async move { replayer.replay(&bus).await }
+    });
+    let settle = || async {
+        for _ in 0..64 {
+            tokio::task::yield_now().await;
+        }
+    };
+    let wait_for_sleeper = || async {
+        while clock.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+    };
+
+    wait_for_sleeper().await;
+    assert_eq!(topics(&sub), ["move/1"]);
+    clock.advance(Duration::from_millis(1499));
+    settle().await;
+    assert!(sub.is_empty());
+    clock.advance(Duration::from_millis(1));
+    settle().await;
+    assert_eq!(topics(&sub), ["stop/1"]);
+    clock.advance(Duration::from_millis(250));
+    assert_eq!(replay.await.unwrap(), 3);
+    let replayed = sub.try_recv().unwrap();
+    assert_eq!(replayed.topic(), "telemetry/lift/axis");
+    // 原样重放，uid 和来源不变
+    assert_eq!(replayed.info.uid, published[2].info.uid);
+    assert_eq!(replayed.info.source.as_deref(), Some("harness"));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn replays_faster_and_filtered() {
+    let path = recording("filtered");
+    record(&path).await;
+
+    let clock = MockClock::default();
+    let bus = Bus::new();
+    let sub = bus.subscribe("replay", [MessageType::Move, MessageType::Stop, MessageType::Telemetry]);
+    let replayer = Replayer::open(&path)
+        .unwrap()
+        .with_clock(clock.clone())
+        .speed(2.0)
+        .types([MessageType::Stop, MessageType::Telemetry]);
+    let replay = tokio::spawn({
+        let bus = bus.clone();
+        async move { replayer.replay(&bus).await }
+    });
+    while clock.sleepers() == 0 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(topics(&sub), ["stop/1"]);
+    clock.advance(Duration::from_millis(125));
+    assert_eq!(replay.await.unwrap(), 2);
+    assert_eq!(topics(&sub), ["telemetry/lift/axis"]);
+
+    // 按录制起点的时间窗口选取
+    let window = Replayer::open(&path).unwrap().between(Some(Duration::from_secs(1)), Some(Duration::from_millis(1600)));
+    let selected: Vec<_> = window.selected().map(|record| record.msg.topic().into_owned()).collect();
+    assert_eq!(selected, ["stop/1"]);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn records_from_many_publishers_stay_in_time_order() {
+    let path = recording("concurrent");
+    let recorder = Recorder::create(&path).unwrap();
+    let bus = Bus::new();
+    bus.add_interceptor(recorder.clone());
+    let publishers: Vec<_> = (0..4)
+        .map(|n| {
+            let bus = bus.clone();
+            tokio::spawn(async move {
+                for i in 0..250 {
+                    bus.publish(msg(MessageType::Telemetry, &format!("telemetry/{}/{}", n, i))).await;
+                }
+            })
+        })
+        .collect();
+    for publisher in publishers {
+        publisher.await.unwrap();
+    }
+    recorder.flush().unwrap();
+
+    let records = read_recording(&path).unwrap();
+    assert_eq!(records.len() as u64 + recorder.dropped(), 1000);
+    assert!(records.windows(2).all(|pair| pair[0].ts <= pair[1].ts));
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use message::{MessageType, Msg, MsgBuilder, ScheduleKind, ScheduleMsg, UnscheduleMsg};
+use subsystem::{Scheduler, SchedulerHandle};
+use testkit::Harness;
+
+fn msg(topic: &str) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(MessageType::Move);
+    msg.set_topic(topic);
+    msg
+}
+
+/// Topics the scheduler has published so far, then forgets them.
+fn fired(harness: &Harness) -> Vec<String> {
+    let topics = harness
+        .emitted("scheduler")
+        .iter()
+        .map(|msg| msg.topic().into_owned())
+        .collect();
+    harness.clear();
+    topics
+}
+
+async fn start() -> (Harness, SchedulerHandle) {
+    let mut harness = Harness::new();
+    let scheduler = Scheduler::with_clock(harness.center().handle(), harness.clock());
+    let handle = scheduler.handle();
+    harness.register("scheduler", scheduler, vec![MessageType::Schedule, MessageType::Unschedule]);
+    harness.start().await;
+    (harness, handle)
+}
+
+/// Advances the clock once the timer task is asleep on it.
+async fn advance(harness: &Harness, duration: Duration) {
+    harness.wait_for_sleepers(1).await;
+    harness.advance(duration).await;
+}
+
+#[tokio::test]
+async fn once_fires_after_its_delay_and_only_once() {
+    let (mut harness, handle) = start().await;
+    handle.once(Duration::from_secs(5), msg("once"));
+    harness.settle().await;
+    advance(&harness, Duration::from_millis(4999)).await;
+    assert!(fired(&harness).is_empty());
+    advance(&harness, Duration::from_millis(1)).await;
+    assert_eq!(fired(&harness), ["once"]);
+    harness.advance(Duration::from_secs(60)).await;
+    assert!(fired(&harness).is_empty());
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn every_repeats_without_catching_up() {
+    let (mut harness, handle) = start().await;
+    let tick = handle.every(Duration::from_secs(2), msg("tick"));
+    harness.settle().await;
+    for _ in 0..3 {
+        advance(&harness, Duration::from_secs(2)).await;
+    }
+    assert_eq!(fired(&harness), ["tick", "tick", "tick"]);
+
+    // 落后多个周期只补一次，之后从现在起算
+    advance(&harness, Duration::from_secs(7)).await;
+    assert_eq!(fired(&harness), ["tick"]);
+    advance(&harness, Duration::from_millis(1999)).await;
+    assert!(fired(&harness).is_empty());
+    advance(&harness, Duration::from_millis(1)).await;
+    assert_eq!(fired(&harness), ["tick"]);
+
+    tick.cancel();
+    harness.settle().await;
+    harness.advance(Duration::from_secs(10)).await;
+    assert!(fired(&harness).is_empty());
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn cron_fires_on_its_boundaries() {
+    let (mut harness, handle) = start().await;
+    // 每五分钟的整点，时钟从 1970-01-01 00:00:00 开始
+    handle.cron("0 */5 * * * *", msg("cron")).unwrap();
+    harness.settle().await;
+    advance(&harness, Duration::from_millis(299_999)).await;
+    assert!(fired(&harness).is_empty());
+    advance(&harness, Duration::from_millis(1)).await;
+    assert_eq!(fired(&harness), ["cron"]);
+    advance(&harness, Duration::from_secs(299)).await;
+    assert!(fired(&harness).is_empty());
+    advance(&harness, Duration::from_secs(1)).await;
+    assert_eq!(fired(&harness), ["cron"]);
+    assert!(handle.cron("not a cron line", msg("bad")).is_err());
+    harness.stop().await;
+}
+
+fn schedule(name: &str, kind: ScheduleKind, topic: &str) -> Msg {
+    let request = ScheduleMsg::new(name, kind, msg(topic));
+    MsgBuilder::new()
+        .msg_type(MessageType::Schedule)
+        .data(Box::new(request))
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn named_schedules_are_replaced_and_cancelled_by_message() {
+    let (mut harness, _handle) = start().await;
+    harness
+        .inject(schedule("poll", ScheduleKind::Every { period_ms: 1000 }, "first"))
+        .await;
+    harness
+        .inject(schedule("poll", ScheduleKind::Every { period_ms: 3000 }, "second"))
+        .await;
+    harness
+        .inject(schedule("broken", ScheduleKind::Every { period_ms: 0 }, "never"))
+        .await;
+    advance(&harness, Duration::from_secs(3)).await;
+    assert_eq!(fired(&harness), ["second"]);
+
+    let cancel = MsgBuilder::new()
+        .msg_type(MessageType::Unschedule)
+        .data(Box::new(UnscheduleMsg::new("poll")))
+        .build()
+        .unwrap();
+    harness.inject(cancel).await;
+    harness.clear();
+    harness.advance(Duration::from_secs(30)).await;
+    assert!(fired(&harness).is_empty());
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn schedules_survive_a_restart() {
+    let (mut harness, handle) = start().await;
+    handle.every(Duration::from_secs(2), msg("tick"));
+    harness.settle().await;
+    harness.stop().await;
+    harness.start().await;
+    advance(&harness, Duration::from_secs(2)).await;
+    assert_eq!(fired(&harness), ["tick"]);
+    harness.stop().await;
+}
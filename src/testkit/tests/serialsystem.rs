@@ -0,0 +1,115 @@
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use message::{encode_frame, LinkState, MessageType, Msg};
+use subsystem::{Backoff, Health, SerialConfig, SerialSystem, SystemId};
+use testkit::{expect, msg, Harness};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio_serial::{SerialPort, SerialStream};
+
+
+/// A pseudo-terminal standing in for a USB serial adapter. The subsystem
+/// opens it through a symlink, the way `/dev/serial/by-id` names a device, so
+/// "replugging" can bring it back under the same path.
+struct Device {
+    link: PathBuf,
+}
+
+impl Device {
+    fn new(name: &str) -> Self {
+        let link = std::env::temp_dir().join(format!("csc-serial-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&link);
+        Self { link }
+    }
+
+    fn path(&self) -> &Path {
+        &self.link
+    }
+
+    /// Plugs in a new adapter and returns our end of it.
+    fn plug(&self) -> SerialStream {
+        let (master, slave) = SerialStream::pair().unwrap();
+        let name = slave.name().unwrap();
+        // 只保留主端，从端交给被测子系统打开
+        drop(slave);
+        let _ = std::fs::remove_file(&self.link);
+        std::os::unix::fs::symlink(name, &self.link).unwrap();
+        master
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.link);
+    }
+}
+
+fn serial_system(harness: &mut Harness, device: &Device) {
+    let config = SerialConfig::new(device.path().to_str().unwrap(), 115_200);
+    let serial = SerialSystem::new(config)
+        .with_clock(harness.clock())
+        .backoff(Backoff::new(Duration::from_secs(1), Duration::from_secs(10)).jitter(0.0));
+    harness.register("serial", serial, vec![MessageType::Move]);
+}
+
+#[tokio::test]
+async fn frames_messages_both_ways() {
+    let device = Device::new("frames");
+    let master = device.plug();
+    let mut harness = Harness::new();
+    serial_system(&mut harness, &device);
+    harness.start().await;
+    harness.wait_until(|h| h.link_states("serial") == [LinkState::Connected]).await;
+
+    let (reader, mut writer) = tokio::io::split(master);
+    let mut request = msg(MessageType::Move);
+    request.set_topic("move/1");
+    harness.inject(request).await;
+    let mut lines = BufReader::new(reader).lines();
+    let line = expect(lines.next_line()).await.unwrap().unwrap();
+    let sent: Msg = serde_json::from_str(&line).unwrap();
+    assert_eq!(sent.topic(), "move/1");
+
+    let mut frame = b"\r\n".to_vec();
+    frame.extend(encode_frame(&msg(MessageType::Join)).unwrap());
+    writer.write_all(&frame).await.unwrap();
+    harness.wait_until(|h| !h.emitted_of("serial", MessageType::Join).is_empty()).await;
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn reopens_port_after_replug() {
+    let device = Device::new("replug");
+    let mut harness = Harness::new();
+    serial_system(&mut harness, &device);
+    harness.start().await;
+
+    // 还没插上
+    harness.wait_for_sleepers(1).await;
+    let health = harness.center().health().await;
+    assert!(matches!(health[&SystemId::from("serial")], Health::Degraded(_)));
+
+    let master = device.plug();
+    harness.advance(Duration::from_secs(1)).await;
+    harness.wait_until(|h| h.link_states("serial").last() == Some(&LinkState::Connected)).await;
+
+    // 拔掉
+    drop(master);
+    harness.wait_for_sleepers(1).await;
+    assert_eq!(
+        harness.link_states("serial"),
+        [LinkState::Disconnected, LinkState::Connected, LinkState::Disconnected]
+    );
+
+    harness.inject(msg(MessageType::Move)).await;
+    let master = device.plug();
+    harness.advance(Duration::from_secs(1)).await;
+    harness.wait_until(|h| h.link_states("serial").last() == Some(&LinkState::Connected)).await;
+    let mut lines = BufReader::new(master).lines();
+    let line = expect(lines.next_line()).await.unwrap().unwrap();
+    let sent: Msg = serde_json::from_str(&line).unwrap();
+    assert_eq!(sent.get_msg_type(), MessageType::Move);
+    harness.stop().await;
+}
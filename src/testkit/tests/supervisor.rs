@@ -0,0 +1,127 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use message::{AlarmMsg, AlarmSeverity, MessageType, Msg};
+use subsystem::{BoxError, Context, RestartPolicy, SubSystem, SubsystemState, SystemId};
+use testkit::Harness;
+
+/// Fails on every message with topic `fail` and panics on `panic`.
+struct Flaky {
+    starts: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl SubSystem for Flaky {
+    async fn start(&mut self, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        match &*msg.topic() {
+            "fail" => Err("boom".into()),
+            "panic" => panic!("kaboom"),
+            _ => Ok(()),
+        }
+    }
+
+    fn rollup(&mut self) {}
+}
+
+fn moved(topic: &str) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(MessageType::Move);
+    msg.set_topic(topic);
+    msg
+}
+
+fn state(harness: &mut Harness) -> SubsystemState {
+    harness.center().states()[&SystemId::from("flaky")].clone()
+}
+
+async fn start(policy: RestartPolicy) -> (Harness, Arc<AtomicUsize>) {
+    let starts = Arc::new(AtomicUsize::new(0));
+    let mut harness = Harness::new();
+    let flaky = Flaky { starts: starts.clone() };
+    harness.register("flaky", flaky, vec![MessageType::Move]).restart(policy);
+    harness.start().await;
+    (harness, starts)
+}
+
+/// Fails the subsystem and waits out a backoff of `backoff`, checking that
+/// it isn't restarted a moment early.
+async fn fail_and_restart(harness: &mut Harness, topic: &str, backoff: Duration) {
+    harness.inject(moved(topic)).await;
+    assert_eq!(state(harness), SubsystemState::Restarting);
+    harness.wait_for_sleepers(1).await;
+    harness.advance(backoff - Duration::from_millis(1)).await;
+    assert_eq!(state(harness), SubsystemState::Restarting);
+    harness.advance(Duration::from_millis(1)).await;
+    harness.wait_until(|h| h.clock().sleepers() == 0).await;
+    assert_eq!(state(harness), SubsystemState::Running);
+}
+
+#[tokio::test]
+async fn restarts_with_backoff_then_gives_up() {
+    let policy = RestartPolicy::new(2, Duration::from_secs(60)).backoff(Duration::from_secs(1), Duration::from_secs(10));
+    let (mut harness, starts) = start(policy).await;
+    assert_eq!(starts.load(Ordering::SeqCst), 1);
+
+    fail_and_restart(&mut harness, "fail", Duration::from_secs(1)).await;
+    assert_eq!(starts.load(Ordering::SeqCst), 2);
+    // 窗口内第二次失败，退避翻倍；panic 和返回错误一样处理
+    fail_and_restart(&mut harness, "panic", Duration::from_secs(2)).await;
+    assert_eq!(starts.load(Ordering::SeqCst), 3);
+    harness.inject(moved("ok")).await;
+    assert_eq!(state(&mut harness), SubsystemState::Running);
+
+    harness.inject(moved("fail")).await;
+    assert_eq!(state(&mut harness), SubsystemState::Failed("boom".to_string()));
+    assert_eq!(harness.clock().sleepers(), 0);
+    assert_eq!(starts.load(Ordering::SeqCst), 3);
+    let alarms: Vec<Msg> = harness
+        .messages()
+        .into_iter()
+        .filter(|msg| msg.get_msg_type() == MessageType::Alarm)
+        .collect();
+    assert_eq!(alarms.len(), 1);
+    assert_eq!(alarms[0].topic(), "alarm/subsystem/flaky");
+    let alarm = alarms[0].get_data::<AlarmMsg>().unwrap();
+    assert_eq!(alarm.source, "flaky");
+    assert_eq!(alarm.severity, AlarmSeverity::Critical);
+    assert_eq!(alarm.text, "gave up after 3 failures: boom");
+
+    // 放弃后不再收消息，停止时保持 Failed
+    harness.inject(moved("ok")).await;
+    harness.stop().await;
+    assert_eq!(state(&mut harness), SubsystemState::Failed("boom".to_string()));
+}
+
+#[tokio::test]
+async fn failures_outside_the_window_are_forgotten() {
+    let policy = RestartPolicy::new(1, Duration::from_secs(10)).backoff(Duration::from_secs(1), Duration::from_secs(10));
+    let (mut harness, starts) = start(policy).await;
+
+    fail_and_restart(&mut harness, "fail", Duration::from_secs(1)).await;
+    harness.advance(Duration::from_secs(10)).await;
+    // 上次失败已出窗口，退避从头算，也不会放弃
+    fail_and_restart(&mut harness, "fail", Duration::from_secs(1)).await;
+    assert_eq!(starts.load(Ordering::SeqCst), 3);
+
+    harness.inject(moved("fail")).await;
+    assert_eq!(state(&mut harness), SubsystemState::Failed("boom".to_string()));
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn never_gives_up_on_the_first_failure() {
+    let (mut harness, starts) = start(RestartPolicy::never()).await;
+    harness.inject(moved("fail")).await;
+    assert_eq!(state(&mut harness), SubsystemState::Failed("boom".to_string()));
+    assert_eq!(starts.load(Ordering::SeqCst), 1);
+    harness.stop().await;
+}
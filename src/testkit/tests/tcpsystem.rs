@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use message::{encode_frame, LinkState, MessageType, Msg, MAX_FRAME_LEN};
+use subsystem::{Backoff, FrameLines, Health, SystemId, TcpSystem};
+use testkit::{expect, msg, Harness};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+/// Real time to wait before deciding something didn't happen.
+const QUIET: Duration = Duration::from_millis(100);
+
+fn tcp_system(harness: &mut Harness, addr: std::net::SocketAddr) {
+    let tcp = TcpSystem::new(addr)
+        .with_clock(harness.clock())
+        .backoff(Backoff::new(Duration::from_secs(5), Duration::from_secs(60)).jitter(0.0))
+        .heartbeat_interval(Duration::from_secs(10));
+    harness.register("tcp", tcp, vec![MessageType::Move]);
+}
+
+async fn accept(listener: &TcpListener) -> TcpStream {
+    let (stream, _) = expect(listener.accept()).await.unwrap();
+    stream
+}
+
+#[tokio::test]
+async fn sends_heartbeat_every_interval() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let mut lines = BufReader::new(accept(&listener).await).lines();
+    harness.wait_for_sleepers(1).await;
+
+    harness.advance(Duration::from_secs(9)).await;
+    assert!(timeout(QUIET, lines.next_line()).await.is_err(), "heartbeat sent early");
+
+    for _ in 0..2 {
+        harness.advance(Duration::from_secs(1)).await;
+        let line = expect(lines.next_line()).await.unwrap().unwrap();
+        let msg: Msg = serde_json::from_str(&line).unwrap();
+        assert_eq!(msg.get_msg_type(), MessageType::Heartbeat);
+
+        harness.wait_for_sleepers(1).await;
+        harness.advance(Duration::from_secs(9)).await;
+    }
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn retries_refused_connection_after_backoff() {
+    let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, addr);
+    harness.start().await;
+
+    harness.wait_for_sleepers(1).await;
+    let health = harness.center().health().await;
+    assert!(matches!(health[&SystemId::from("tcp")], Health::Degraded(_)));
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    harness.advance(Duration::from_secs(4)).await;
+    assert!(timeout(QUIET, listener.accept()).await.is_err(), "reconnected early");
+
+    harness.advance(Duration::from_secs(1)).await;
+    accept(&listener).await;
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn reconnects_when_server_closes_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    drop(accept(&listener).await);
+    accept(&listener).await;
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn heartbeat_restarts_after_reconnect() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let first = accept(&listener).await;
+    harness.wait_for_sleepers(1).await;
+    harness.advance(Duration::from_secs(6)).await;
+    drop(first);
+
+    // 重连后从连上的时刻重新计时
+    let mut lines = BufReader::new(accept(&listener).await).lines();
+    harness.wait_for_sleepers(1).await;
+    harness.advance(Duration::from_secs(6)).await;
+    assert!(timeout(QUIET, lines.next_line()).await.is_err(), "heartbeat kept the old schedule");
+
+    harness.advance(Duration::from_secs(4)).await;
+    let line = expect(lines.next_line()).await.unwrap().unwrap();
+    let msg: Msg = serde_json::from_str(&line).unwrap();
+    assert_eq!(msg.get_msg_type(), MessageType::Heartbeat);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn buffers_messages_while_disconnected() {
+    let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, addr);
+    harness.start().await;
+    harness.wait_for_sleepers(1).await;
+
+    let mut first = msg(MessageType::Move);
+    first.set_topic("move/1");
+    harness.inject(first).await;
+    harness.inject(msg(MessageType::Move)).await;
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    harness.advance(Duration::from_secs(5)).await;
+    let mut lines = BufReader::new(accept(&listener).await).lines();
+    for topic in ["move/1", "move"] {
+        let line = expect(lines.next_line()).await.unwrap().unwrap();
+        let msg: Msg = serde_json::from_str(&line).unwrap();
+        assert_eq!(msg.topic(), topic);
+    }
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn publishes_received_messages_and_link_state() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let mut stream = accept(&listener).await;
+    stream.write_all(&encode_frame(&msg(MessageType::Join)).unwrap()).await.unwrap();
+    harness.wait_until(|h| !h.emitted_of("tcp", MessageType::Join).is_empty()).await;
+
+    drop(listener);
+    drop(stream);
+    harness.wait_until(|h| h.link_states("tcp").len() == 2).await;
+    assert_eq!(harness.link_states("tcp"), [LinkState::Connected, LinkState::Disconnected]);
+    let health = harness.center().health().await;
+    assert!(matches!(health[&SystemId::from("tcp")], Health::Degraded(_)));
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn drops_the_link_on_an_overlong_frame() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    tcp_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let mut stream = accept(&listener).await;
+    // 没有换行的超长数据，不能无限缓存
+    stream.write_all(&vec![b'x'; MAX_FRAME_LEN + 1]).await.unwrap();
+    // 断开后立即重连
+    let _stream = accept(&listener).await;
+    harness.wait_until(|h| h.link_states("tcp").len() == 3).await;
+    assert_eq!(harness.link_states("tcp"), [LinkState::Connected, LinkState::Disconnected, LinkState::Connected]);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn frame_lines_caps_frame_length() {
+    let longest = vec![b'x'; MAX_FRAME_LEN];
+    let mut input = [b"first\n".as_slice(), &longest, b"\nlast"].concat();
+    let mut frames = FrameLines::new(input.as_slice());
+    assert_eq!(frames.next_frame().await.unwrap().unwrap(), b"first");
+    assert_eq!(frames.next_frame().await.unwrap().unwrap(), longest);
+    assert_eq!(frames.next_frame().await.unwrap().unwrap(), b"last");
+    assert!(frames.next_frame().await.unwrap().is_none());
+
+    input = [b"x".as_slice(), &longest, b"\n"].concat();
+    let mut frames = FrameLines::new(input.as_slice());
+    let e = frames.next_frame().await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn backoff_doubles_up_to_max() {
+    let backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(30)).jitter(0.0);
+    let delays: Vec<_> = (0..5).map(|attempt| backoff.delay(attempt).as_secs()).collect();
+    assert_eq!(delays, [5, 10, 20, 30, 30]);
+
+    let backoff = backoff.jitter(0.2);
+    for _ in 0..100 {
+        let delay = backoff.delay(0);
+        assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(6));
+    }
+}
@@ -0,0 +1,82 @@
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use message::{encode_frame, LinkState, MessageType, Msg};
+use subsystem::{Backoff, Health, SystemId, UnixSystem};
+use testkit::{expect, msg, Harness};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+
+/// A socket path unique to this test, removed when dropped.
+struct SocketPath(PathBuf);
+
+impl SocketPath {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("csc-unix-{}-{}.sock", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        Self(path)
+    }
+}
+
+impl Drop for SocketPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn unix_system(harness: &mut Harness, path: &SocketPath) {
+    let unix = UnixSystem::new(&path.0)
+        .with_clock(harness.clock())
+        .backoff(Backoff::new(Duration::from_secs(5), Duration::from_secs(60)).jitter(0.0))
+        .heartbeat_interval(Duration::from_secs(10));
+    harness.register("unix", unix, vec![MessageType::Move]);
+}
+
+async fn accept(listener: &UnixListener) -> UnixStream {
+    let (stream, _) = expect(listener.accept()).await.unwrap();
+    stream
+}
+
+#[tokio::test]
+async fn frames_messages_both_ways() {
+    let path = SocketPath::new("frames");
+    let listener = UnixListener::bind(&path.0).unwrap();
+    let mut harness = Harness::new();
+    unix_system(&mut harness, &path);
+    harness.start().await;
+
+    let (reader, mut writer) = accept(&listener).await.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    harness.inject(msg(MessageType::Move)).await;
+    let line = expect(lines.next_line()).await.unwrap().unwrap();
+    let sent: Msg = serde_json::from_str(&line).unwrap();
+    assert_eq!(sent.get_msg_type(), MessageType::Move);
+
+    writer.write_all(&encode_frame(&msg(MessageType::Join)).unwrap()).await.unwrap();
+    harness.wait_until(|h| !h.emitted_of("unix", MessageType::Join).is_empty()).await;
+    assert_eq!(harness.link_states("unix"), [LinkState::Connected]);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn waits_for_socket_and_buffers_messages() {
+    let path = SocketPath::new("buffers");
+    let mut harness = Harness::new();
+    unix_system(&mut harness, &path);
+    harness.start().await;
+    harness.wait_for_sleepers(1).await;
+    let health = harness.center().health().await;
+    assert!(matches!(health[&SystemId::from("unix")], Health::Degraded(_)));
+
+    harness.inject(msg(MessageType::Move)).await;
+    let listener = UnixListener::bind(&path.0).unwrap();
+    harness.advance(Duration::from_secs(5)).await;
+    let mut lines = BufReader::new(accept(&listener).await).lines();
+    let line = expect(lines.next_line()).await.unwrap().unwrap();
+    let sent: Msg = serde_json::from_str(&line).unwrap();
+    assert_eq!(sent.get_msg_type(), MessageType::Move);
+    harness.stop().await;
+}
@@ -0,0 +1,88 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use message::{MessageType, Msg};
+use subsystem::{BoxError, Context, SubSystem};
+use testkit::Harness;
+use tokio::sync::Semaphore;
+
+/// Records the topic of every message it handles.
+#[derive(Clone, Default)]
+struct Seen(Arc<Mutex<Vec<String>>>);
+
+impl Seen {
+    fn topics(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, msg: &Msg) {
+        self.0.lock().unwrap().push(msg.topic().into_owned());
+    }
+}
+
+/// Takes a permit from `gate` for every message, so it is stuck until the test lets it go.
+struct Slow {
+    gate: Arc<Semaphore>,
+    seen: Seen,
+}
+
+#[async_trait]
+impl SubSystem for Slow {
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.gate.acquire().await?.forget();
+        self.seen.push(msg);
+        Ok(())
+    }
+
+    fn rollup(&mut self) {}
+}
+
+struct Fast {
+    seen: Seen,
+}
+
+#[async_trait]
+impl SubSystem for Fast {
+    async fn exec(&mut self, msg: &Msg, _ctx: &mut Context) -> Result<(), BoxError> {
+        self.seen.push(msg);
+        Ok(())
+    }
+
+    fn rollup(&mut self) {}
+}
+
+fn moved(n: usize) -> Msg {
+    let mut msg = Msg::new();
+    msg.set_msg_type(MessageType::Move);
+    msg.set_topic(format!("move/{}", n));
+    msg
+}
+
+#[tokio::test]
+async fn a_slow_subsystem_only_delays_itself() {
+    let gate = Arc::new(Semaphore::new(0));
+    let (slow, fast) = (Seen::default(), Seen::default());
+    let mut harness = Harness::new();
+    let subsystem = Slow {
+        gate: gate.clone(),
+        seen: slow.clone(),
+    };
+    harness.register("slow", subsystem, vec![MessageType::Move]);
+    harness.register("fast", Fast { seen: fast.clone() }, vec![MessageType::Move]);
+    harness.start().await;
+
+    for n in 0..3 {
+        harness.inject(moved(n)).await;
+    }
+    assert_eq!(fast.topics(), ["move/0", "move/1", "move/2"]);
+    assert!(slow.topics().is_empty());
+    // 一条正在处理，其余在自己的队列里等
+    let stats = harness.center().queue_stats();
+    let depth = |label: &str| stats.iter().find(|stats| stats.label == label).unwrap().depth;
+    assert_eq!((depth("slow"), depth("fast")), (2, 0));
+
+    gate.add_permits(3);
+    harness.wait_until(|_| slow.topics().len() == 3).await;
+    assert_eq!(slow.topics(), ["move/0", "move/1", "move/2"]);
+    harness.stop().await;
+}
@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use message::{encode_frame, LinkState, MessageType, Msg};
+use subsystem::{Backoff, WsSystem};
+use testkit::{expect, msg, Harness};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+
+
+fn ws_system(harness: &mut Harness, addr: SocketAddr) {
+    let ws = WsSystem::new(format!("ws://{}", addr))
+        .with_clock(harness.clock())
+        .backoff(Backoff::new(Duration::from_secs(5), Duration::from_secs(60)).jitter(0.0))
+        .heartbeat_interval(Duration::from_secs(10));
+    harness.register("ws", ws, vec![MessageType::Move]);
+}
+
+async fn accept(listener: &TcpListener) -> WebSocketStream<tokio::net::TcpStream> {
+    let (stream, _) = expect(listener.accept()).await.unwrap();
+    accept_async(stream).await.unwrap()
+}
+
+/// The next message frame, skipping heartbeats.
+async fn next_msg(socket: &mut WebSocketStream<tokio::net::TcpStream>) -> Msg {
+    loop {
+        let message = expect(socket.next()).await.unwrap().unwrap();
+        let msg: Msg = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        if msg.get_msg_type() != MessageType::Heartbeat {
+            return msg;
+        }
+    }
+}
+
+#[tokio::test]
+async fn sends_one_frame_per_message() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    ws_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let mut socket = accept(&listener).await;
+    harness.wait_until(|h| h.link_states("ws") == [LinkState::Connected]).await;
+    let mut first = msg(MessageType::Move);
+    first.set_topic("move/1");
+    harness.inject(first).await;
+    harness.inject(msg(MessageType::Move)).await;
+
+    for topic in ["move/1", "move"] {
+        assert_eq!(next_msg(&mut socket).await.topic(), topic);
+    }
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn sends_heartbeat_every_interval() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    ws_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let mut socket = accept(&listener).await;
+    harness.wait_for_sleepers(1).await;
+    harness.advance(Duration::from_secs(10)).await;
+    let message = expect(socket.next()).await.unwrap().unwrap();
+    let msg: Msg = serde_json::from_str(message.to_text().unwrap()).unwrap();
+    assert_eq!(msg.get_msg_type(), MessageType::Heartbeat);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn publishes_received_messages_and_reconnects() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut harness = Harness::new();
+    ws_system(&mut harness, listener.local_addr().unwrap());
+    harness.start().await;
+
+    let mut socket = accept(&listener).await;
+    let frame = String::from_utf8(encode_frame(&msg(MessageType::Join)).unwrap()).unwrap();
+    socket.send(Message::Text(frame)).await.unwrap();
+    harness.wait_until(|h| !h.emitted_of("ws", MessageType::Join).is_empty()).await;
+
+    socket.close(None).await.unwrap();
+    harness.wait_until(|h| h.link_states("ws").len() == 2).await;
+    assert_eq!(harness.link_states("ws"), [LinkState::Connected, LinkState::Disconnected]);
+    // 刚断开时立即重连一次
+    let _socket = accept(&listener).await;
+    harness.wait_until(|h| h.link_states("ws").len() == 3).await;
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn buffers_messages_while_disconnected() {
+    let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let mut harness = Harness::new();
+    ws_system(&mut harness, addr);
+    harness.start().await;
+    harness.wait_for_sleepers(1).await;
+
+    harness.inject(msg(MessageType::Move)).await;
+    let listener = TcpListener::bind(addr).await.unwrap();
+    harness.advance(Duration::from_secs(5)).await;
+    let mut socket = accept(&listener).await;
+    assert_eq!(next_msg(&mut socket).await.get_msg_type(), MessageType::Move);
+    harness.stop().await;
+}